@@ -3,11 +3,15 @@
 //! Provides easy methods to navigate through the epub parts and to get
 //! the content as string.
 
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
 
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
+
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
 
 /// Epub archive struct. Here it's stored the file path and the list of
 /// files in the zip archive.
@@ -101,6 +105,23 @@ impl<R: Read + Seek> EpubArchive<R> {
         Ok(entry)
     }
 
+    /// Returns the uncompressed size of the file by the `name`, as the zip
+    /// records it, without extracting the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name doesn't exists in the zip archive.
+    pub fn get_entry_size<P: AsRef<Path>>(
+        &mut self,
+        name: P,
+    ) -> Result<u64, ArchiveError> {
+        let index = self
+            .entry_name(name)
+            .and_then(|name| self.zip.index_for_name(name))
+            .ok_or(zip::result::ZipError::FileNotFound)?;
+        Ok(self.zip.by_index_raw(index)?.size())
+    }
+
     /// Returns the content of the file by the `name` as `String`.
     ///
     /// # Errors
@@ -114,6 +135,63 @@ impl<R: Read + Seek> EpubArchive<R> {
         String::from_utf8(content).map_err(ArchiveError::from)
     }
 
+    /// Returns the name of the entry at `path`, which may be percent
+    /// encoded.
+    pub(crate) fn entry_name<P: AsRef<Path>>(&self, path: P) -> Option<&str> {
+        let name = path.as_ref().to_str()?;
+        let decoded = percent_encoding::percent_decode(name.as_bytes())
+            .decode_utf8()
+            .ok()?;
+        self.files
+            .iter()
+            .find(|f| *f == name)
+            .or_else(|| self.files.iter().find(|f| **f == decoded))
+            .map(String::as_str)
+    }
+
+    /// Returns the name and the compression of the entry at `index`, in the
+    /// order they are stored in.
+    pub(crate) fn entry_at(
+        &mut self,
+        index: usize,
+    ) -> Result<(String, CompressionMethod), ArchiveError> {
+        let file = self.zip.by_index_raw(index)?;
+        Ok((file.name().to_string(), file.compression()))
+    }
+
+    /// Writes every entry to `zip` as is, without decompressing it, except
+    /// the ones in `replaced`, which get their new content with the same
+    /// compression. Entries in `replaced` that aren't in the archive are
+    /// added at the end.
+    pub(crate) fn copy_to<W: Write + Seek>(
+        &mut self,
+        zip: &mut ZipWriter<W>,
+        mut replaced: BTreeMap<String, Vec<u8>>,
+    ) -> Result<(), ArchiveError> {
+        for i in 0..self.zip.len() {
+            let file = self.zip.by_index_raw(i)?;
+            match replaced.remove(file.name()) {
+                Some(content) => {
+                    let options = SimpleFileOptions::default()
+                        .compression_method(file.compression());
+                    let name = file.name().to_string();
+                    drop(file);
+                    zip.start_file(name, options)?;
+                    zip.write_all(&content)?;
+                }
+                None => zip.raw_copy_file(file)?,
+            }
+        }
+
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated);
+        for (name, content) in replaced {
+            zip.start_file(name, options)?;
+            zip.write_all(&content)?;
+        }
+        Ok(())
+    }
+
     /// Returns the content of container file "META-INF/container.xml".
     ///
     /// # Errors
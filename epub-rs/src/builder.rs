@@ -0,0 +1,693 @@
+//! Writes new epub files.
+//!
+//! [`EpubBuilder`] puts together an EPUB 3 package, with a toc.ncx so EPUB 2
+//! readers can navigate it too.
+//!
+//! # Examples
+//!
+//! ```
+//! use epub::builder::{EpubBuilder, TocEntry};
+//! use epub::doc::EpubDoc;
+//! use epub::metadata::{Metadata, Title};
+//! use std::io::Cursor;
+//!
+//! let mut builder = EpubBuilder::new("urn:uuid:0b2f9a3c-4e1d-4c6b-8f7a-5d3e2c1b0a99");
+//! builder
+//!     .metadata(Metadata {
+//!         titles: vec![Title {
+//!             value: "Release Notes".into(),
+//!             ..Title::default()
+//!         }],
+//!         languages: vec!["en".into()],
+//!         ..Metadata::default()
+//!     })
+//!     .add_content("notes", "notes.xhtml", "<html>...</html>")
+//!     .add_toc_entry(TocEntry::new("Release Notes", "notes.xhtml"));
+//!
+//! let epub = builder.write(Cursor::new(vec![])).unwrap();
+//! let doc = EpubDoc::from_reader(Cursor::new(epub.into_inner())).unwrap();
+//! assert_eq!(doc.mdata("title").unwrap(), "Release Notes");
+//! ```
+
+use std::fmt::Write as _;
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+use crate::metadata::{AlternateScript, Collection, Metadata, Person};
+
+/// The directory of the package in the archive
+const PACKAGE_DIR: &str = "EPUB";
+/// The ids of the resources the builder adds itself
+const NAV_ID: &str = "nav";
+const NCX_ID: &str = "ncx";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BuilderError {
+    #[error("I/O Error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Zip Error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Missing Metadata: {0}")]
+    MissingMetadata(&'static str),
+    #[error("Empty Spine: no resource was added to the reading order")]
+    EmptySpine,
+    #[error("Duplicate Resource: {0}")]
+    DuplicateResource(String),
+    #[error("Unknown Resource Id: {0}")]
+    UnknownId(String),
+}
+
+/// An entry in the table of contents of the built epub
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TocEntry {
+    /// the title of this entry
+    pub label: String,
+    /// the resource href, relative to the package, with a fragment if the
+    /// entry doesn't start at the top of the resource
+    pub href: String,
+    /// nested entries
+    pub children: Vec<Self>,
+}
+
+impl TocEntry {
+    #[must_use]
+    pub fn new(label: &str, href: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            href: href.to_string(),
+            children: vec![],
+        }
+    }
+
+    /// Adds a nested entry.
+    #[must_use]
+    pub fn child(mut self, entry: Self) -> Self {
+        self.children.push(entry);
+        self
+    }
+}
+
+/// A file of the built epub
+#[derive(Clone, Debug)]
+struct Resource {
+    id: String,
+    /// relative to the package
+    href: String,
+    media_type: String,
+    content: Vec<u8>,
+}
+
+/// Builds an EPUB 3 file, see the [module documentation](self)
+#[derive(Clone, Debug, Default)]
+pub struct EpubBuilder {
+    identifier: String,
+    metadata: Metadata,
+    resources: Vec<Resource>,
+    /// resource ids, with whether they are linear
+    spine: Vec<(String, bool)>,
+    toc: Vec<TocEntry>,
+    cover_id: Option<String>,
+}
+
+impl EpubBuilder {
+    /// Starts an epub with `identifier` as its unique identifier, such as a
+    /// `urn:uuid:` or an ISBN.
+    #[must_use]
+    pub fn new(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the metadata. At least a title and a language are needed, and
+    /// the modification date is the time of writing if it isn't set.
+    pub fn metadata(&mut self, metadata: Metadata) -> &mut Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Adds a file at `href`, relative to the package, that isn't part of
+    /// the reading order, such as a stylesheet, an image or a font.
+    pub fn add_resource(
+        &mut self,
+        id: &str,
+        href: &str,
+        media_type: &str,
+        content: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.resources.push(Resource {
+            id: id.to_string(),
+            href: href.to_string(),
+            media_type: media_type.to_string(),
+            content: content.into(),
+        });
+        self
+    }
+
+    /// Adds an XHTML document at `href`, relative to the package, and
+    /// appends it to the reading order.
+    pub fn add_content(
+        &mut self,
+        id: &str,
+        href: &str,
+        content: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        self.add_resource(id, href, "application/xhtml+xml", content);
+        self.add_spine_item(id, true)
+    }
+
+    /// Appends the resource with `id` to the reading order. Non-linear
+    /// items, such as notes, are skipped when reading straight through.
+    pub fn add_spine_item(&mut self, id: &str, linear: bool) -> &mut Self {
+        self.spine.push((id.to_string(), linear));
+        self
+    }
+
+    /// Adds a top level entry to the table of contents.
+    pub fn add_toc_entry(&mut self, entry: TocEntry) -> &mut Self {
+        self.toc.push(entry);
+        self
+    }
+
+    /// Marks the image resource with `id` as the cover.
+    pub fn cover_image(&mut self, id: &str) -> &mut Self {
+        self.cover_id = Some(id.to_string());
+        self
+    }
+
+    /// Writes the epub to `writer`, and returns it.
+    ///
+    /// When there are no table of contents entries, the spine items are
+    /// listed by file name, since the navigation document can't be empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the title or language is missing, if nothing was
+    /// added to the reading order, if two
+    /// resources share an id or an href, if a resource takes an id the
+    /// package uses for the navigation documents or the metadata (`nav`,
+    /// `ncx`, `uid`, `title-1` and so on), if the spine or the cover refer to
+    /// a resource that wasn't added, or if writing fails.
+    pub fn write<W: Write + Seek>(&self, writer: W) -> Result<W, BuilderError> {
+        self.check()?;
+
+        let mut zip = ZipWriter::new(writer);
+        let stored = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated);
+
+        // The mimetype must come first and uncompressed, so the file type can
+        // be told from its first bytes
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(container().as_bytes())?;
+
+        let toc = if self.toc.is_empty() {
+            self.spine_toc()
+        } else {
+            self.toc.clone()
+        };
+        let files = [
+            ("package.opf", self.package()),
+            ("nav.xhtml", nav(&self.title(), &toc)),
+            ("toc.ncx", self.ncx(&toc)),
+        ];
+        for (href, content) in files {
+            zip.start_file(format!("{PACKAGE_DIR}/{href}"), deflated)?;
+            zip.write_all(content.as_bytes())?;
+        }
+
+        for resource in &self.resources {
+            zip.start_file(
+                format!("{PACKAGE_DIR}/{}", resource.href),
+                deflated,
+            )?;
+            zip.write_all(&resource.content)?;
+        }
+
+        Ok(zip.finish()?)
+    }
+
+    fn check(&self) -> Result<(), BuilderError> {
+        if self.identifier.trim().is_empty() {
+            return Err(BuilderError::MissingMetadata("identifier"));
+        }
+        if self.metadata.titles.is_empty() {
+            return Err(BuilderError::MissingMetadata("title"));
+        }
+        if self.metadata.languages.is_empty() {
+            return Err(BuilderError::MissingMetadata("language"));
+        }
+        if self.spine.is_empty() {
+            return Err(BuilderError::EmptySpine);
+        }
+
+        let metadata_ids = self.metadata_ids();
+        let mut ids = vec![NAV_ID, NCX_ID];
+        ids.extend(metadata_ids.iter().map(String::as_str));
+        let mut hrefs = vec!["package.opf", "nav.xhtml", "toc.ncx"];
+        for resource in &self.resources {
+            if ids.contains(&resource.id.as_str()) {
+                return Err(BuilderError::DuplicateResource(
+                    resource.id.clone(),
+                ));
+            }
+            if hrefs.contains(&resource.href.as_str()) {
+                return Err(BuilderError::DuplicateResource(
+                    resource.href.clone(),
+                ));
+            }
+            ids.push(&resource.id);
+            hrefs.push(&resource.href);
+        }
+
+        let unknown = self
+            .spine
+            .iter()
+            .map(|(id, _)| id)
+            .chain(&self.cover_id)
+            .find(|id| !ids.contains(&id.as_str()));
+        unknown.map_or(Ok(()), |id| Err(BuilderError::UnknownId(id.clone())))
+    }
+
+    /// The ids `write_metadata` gives metadata elements, which share the
+    /// package document with the manifest items.
+    fn metadata_ids(&self) -> Vec<String> {
+        fn collection_ids(
+            ids: &mut Vec<String>,
+            id: &str,
+            nested: &[Collection],
+        ) {
+            for (i, collection) in nested.iter().enumerate() {
+                let id = format!("{id}-{}", i + 1);
+                collection_ids(ids, &id, &collection.collections);
+                ids.push(id);
+            }
+        }
+
+        let m = &self.metadata;
+        let mut ids = vec!["uid".to_string()];
+        for (name, count) in [
+            ("title", m.titles.len()),
+            ("creator", m.creators.len()),
+            ("contributor", m.contributors.len()),
+            ("subject", m.subjects.len()),
+        ] {
+            ids.extend((1..=count).map(|i| format!("{name}-{i}")));
+        }
+        collection_ids(&mut ids, "collection", &m.collections);
+        ids
+    }
+
+    fn title(&self) -> String {
+        self.metadata.title().unwrap_or_default().to_string()
+    }
+
+    /// A table of contents with every spine item, named after its file.
+    fn spine_toc(&self) -> Vec<TocEntry> {
+        self.spine
+            .iter()
+            .filter_map(|(id, _)| self.resources.iter().find(|r| &r.id == id))
+            .map(|r| {
+                let stem = Path::new(&r.href).file_stem().map_or_else(
+                    || r.href.clone(),
+                    |s| s.to_string_lossy().into(),
+                );
+                TocEntry::new(&stem, &r.href)
+            })
+            .collect()
+    }
+
+    /// The OPF package document.
+    fn package(&self) -> String {
+        let mut opf = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">"#,
+            "\n",
+            r#"  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">"#,
+            "\n",
+        ));
+        self.write_metadata(&mut opf);
+        opf.push_str("  </metadata>\n  <manifest>\n");
+
+        let _ = writeln!(
+            opf,
+            r#"    <item id="{NAV_ID}" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#
+        );
+        let _ = writeln!(
+            opf,
+            r#"    <item id="{NCX_ID}" href="toc.ncx" media-type="application/x-dtbncx+xml"/>"#
+        );
+        for r in &self.resources {
+            let properties = if self.cover_id.as_ref() == Some(&r.id) {
+                r#" properties="cover-image""#
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                opf,
+                r#"    <item id="{}" href="{}" media-type="{}"{properties}/>"#,
+                escape(&r.id),
+                escape(&r.href),
+                escape(&r.media_type),
+            );
+        }
+
+        let _ = writeln!(opf, "  </manifest>\n  <spine toc=\"{NCX_ID}\">");
+        for (id, linear) in &self.spine {
+            let linear = if *linear { "" } else { r#" linear="no""# };
+            let _ = writeln!(
+                opf,
+                r#"    <itemref idref="{}"{linear}/>"#,
+                escape(id)
+            );
+        }
+        opf.push_str("  </spine>\n</package>\n");
+        opf
+    }
+
+    /// Writes the metadata elements, with the `meta` elements refining them.
+    fn write_metadata(&self, opf: &mut String) {
+        let m = &self.metadata;
+        let _ = writeln!(
+            opf,
+            r#"    <dc:identifier id="uid">{}</dc:identifier>"#,
+            escape(&self.identifier)
+        );
+
+        for (i, title) in m.titles.iter().enumerate() {
+            let id = format!("title-{}", i + 1);
+            let lang = title
+                .language
+                .as_ref()
+                .map(|l| format!(r#" xml:lang="{}""#, escape(l)))
+                .unwrap_or_default();
+            let _ = writeln!(
+                opf,
+                r#"    <dc:title id="{id}"{lang}>{}</dc:title>"#,
+                escape(&title.value)
+            );
+            refine(opf, &id, "title-type", title.kind.as_deref());
+            refine(opf, &id, "file-as", title.file_as.as_deref());
+            let seq = title.display_seq.map(|s| s.to_string());
+            refine(opf, &id, "display-seq", seq.as_deref());
+            refine_scripts(opf, &id, &title.alternate_scripts);
+        }
+
+        for language in &m.languages {
+            element(opf, "language", language);
+        }
+        write_people(opf, "creator", &m.creators);
+        write_people(opf, "contributor", &m.contributors);
+        for (i, collection) in m.collections.iter().enumerate() {
+            let id = format!("collection-{}", i + 1);
+            write_collection(opf, &id, None, collection);
+        }
+        for (i, subject) in m.subjects.iter().enumerate() {
+            let id = format!("subject-{}", i + 1);
+            let _ = writeln!(
+                opf,
+                r#"    <dc:subject id="{id}">{}</dc:subject>"#,
+                escape(&subject.value)
+            );
+            refine(opf, &id, "authority", subject.authority.as_deref());
+            refine(opf, &id, "term", subject.term.as_deref());
+        }
+        for (name, value) in [
+            ("description", &m.description),
+            ("publisher", &m.publisher),
+            ("rights", &m.rights),
+        ] {
+            if let Some(value) = value {
+                element(opf, name, value);
+            }
+        }
+        // EPUB 3 allows a single publication date
+        if let Some(date) = m.dates.first() {
+            element(opf, "date", &date.value);
+        }
+
+        let modified = m.modified.clone().unwrap_or_else(now);
+        let _ = writeln!(
+            opf,
+            r#"    <meta property="dcterms:modified">{}</meta>"#,
+            escape(&modified)
+        );
+        if let Some(cover) = &self.cover_id {
+            // For EPUB 2 readers, which don't know the cover-image property
+            let _ = writeln!(
+                opf,
+                r#"    <meta name="cover" content="{}"/>"#,
+                escape(cover)
+            );
+        }
+    }
+
+    /// The toc.ncx, for EPUB 2 readers.
+    fn ncx(&self, toc: &[TocEntry]) -> String {
+        let mut ncx = String::from(concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            "\n",
+            r#"<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">"#,
+            "\n",
+        ));
+        let _ = writeln!(
+            ncx,
+            r#"  <head>
+    <meta name="dtb:uid" content="{}"/>
+    <meta name="dtb:depth" content="{}"/>
+    <meta name="dtb:totalPageCount" content="0"/>
+    <meta name="dtb:maxPageNumber" content="0"/>
+  </head>
+  <docTitle><text>{}</text></docTitle>
+  <navMap>"#,
+            escape(&self.identifier),
+            depth(toc).max(1),
+            escape(&self.title()),
+        );
+        let mut play_order = 0;
+        write_navpoints(&mut ncx, toc, 2, &mut play_order);
+        ncx.push_str("  </navMap>\n</ncx>\n");
+        ncx
+    }
+}
+
+fn container() -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="{PACKAGE_DIR}/package.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#
+    )
+}
+
+/// The EPUB 3 navigation document.
+fn nav(title: &str, toc: &[TocEntry]) -> String {
+    let mut nav = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <head><title>{}</title></head>
+  <body>
+    <nav epub:type="toc" id="toc">
+"#,
+        escape(title)
+    );
+    write_nav_list(&mut nav, toc, 3);
+    nav.push_str("    </nav>\n  </body>\n</html>\n");
+    nav
+}
+
+fn write_nav_list(nav: &mut String, entries: &[TocEntry], depth: usize) {
+    let indent = "  ".repeat(depth);
+    let _ = writeln!(nav, "{indent}<ol>");
+    for entry in entries {
+        let _ = write!(
+            nav,
+            r#"{indent}  <li><a href="{}">{}</a>"#,
+            escape(&entry.href),
+            escape(&entry.label)
+        );
+        if !entry.children.is_empty() {
+            nav.push('\n');
+            write_nav_list(nav, &entry.children, depth + 2);
+            nav.push_str(&indent);
+            nav.push_str("  ");
+        }
+        nav.push_str("</li>\n");
+    }
+    let _ = writeln!(nav, "{indent}</ol>");
+}
+
+/// Writes the navPoints of `entries`, numbering them depth-first.
+fn write_navpoints(
+    ncx: &mut String,
+    entries: &[TocEntry],
+    depth: usize,
+    play_order: &mut usize,
+) {
+    let indent = "  ".repeat(depth);
+    for entry in entries {
+        *play_order += 1;
+        let _ = writeln!(
+            ncx,
+            r#"{indent}<navPoint id="navpoint-{0}" playOrder="{0}">
+{indent}  <navLabel><text>{1}</text></navLabel>
+{indent}  <content src="{2}"/>"#,
+            play_order,
+            escape(&entry.label),
+            escape(&entry.href),
+        );
+        write_navpoints(ncx, &entry.children, depth + 1, play_order);
+        let _ = writeln!(ncx, "{indent}</navPoint>");
+    }
+}
+
+fn depth(entries: &[TocEntry]) -> usize {
+    entries
+        .iter()
+        .map(|e| depth(&e.children) + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn element(opf: &mut String, name: &str, value: &str) {
+    let _ = writeln!(opf, "    <dc:{name}>{}</dc:{name}>", escape(value));
+}
+
+/// Writes a `meta` element refining the element with `id`, if there is a
+/// `value`.
+fn refine(opf: &mut String, id: &str, property: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        let scheme = if property == "role" {
+            r#" scheme="marc:relators""#
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            opf,
+            r##"    <meta refines="#{id}" property="{property}"{scheme}>{}</meta>"##,
+            escape(value)
+        );
+    }
+}
+
+fn refine_scripts(opf: &mut String, id: &str, scripts: &[AlternateScript]) {
+    for script in scripts {
+        let lang = script
+            .language
+            .as_ref()
+            .map(|l| format!(r#" xml:lang="{}""#, escape(l)))
+            .unwrap_or_default();
+        let _ = writeln!(
+            opf,
+            r##"    <meta refines="#{id}" property="alternate-script"{lang}>{}</meta>"##,
+            escape(&script.value)
+        );
+    }
+}
+
+fn write_people(opf: &mut String, name: &str, people: &[Person]) {
+    for (i, person) in people.iter().enumerate() {
+        write_person(opf, name, &format!("{name}-{}", i + 1), person);
+    }
+}
+
+/// Writes a `creator` or `contributor` element with `id`, with the `meta`
+/// elements refining it.
+pub(crate) fn write_person(
+    opf: &mut String,
+    name: &str,
+    id: &str,
+    person: &Person,
+) {
+    let _ = writeln!(
+        opf,
+        r#"    <dc:{name} id="{id}">{}</dc:{name}>"#,
+        escape(&person.name)
+    );
+    for role in &person.roles {
+        refine(opf, id, "role", Some(role));
+    }
+    refine(opf, id, "file-as", person.file_as.as_deref());
+    let seq = person.display_seq.map(|s| s.to_string());
+    refine(opf, id, "display-seq", seq.as_deref());
+    refine_scripts(opf, id, &person.alternate_scripts);
+}
+
+/// Writes a `belongs-to-collection` element, which refines the collection
+/// with `parent` for collections nested in another.
+pub(crate) fn write_collection(
+    opf: &mut String,
+    id: &str,
+    parent: Option<&str>,
+    collection: &Collection,
+) {
+    let refines = parent
+        .map(|p| format!(r##" refines="#{p}""##))
+        .unwrap_or_default();
+    let _ = writeln!(
+        opf,
+        r#"    <meta property="belongs-to-collection" id="{id}"{refines}>{}</meta>"#,
+        escape(&collection.name)
+    );
+    refine(opf, id, "collection-type", collection.kind.as_deref());
+    refine(opf, id, "group-position", collection.position.as_deref());
+    refine(opf, id, "file-as", collection.file_as.as_deref());
+    refine(
+        opf,
+        id,
+        "dcterms:identifier",
+        collection.identifier.as_deref(),
+    );
+    for (i, nested) in collection.collections.iter().enumerate() {
+        write_collection(opf, &format!("{id}-{}", i + 1), Some(id), nested);
+    }
+}
+
+/// Escapes text for use in XML content and attribute values.
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The current UTC time, formatted as `dcterms:modified` requires.
+pub(crate) fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let days = i64::try_from(secs / 86400).unwrap_or_default();
+    let time = secs % 86400;
+
+    // Howard Hinnant's days_from_civil, inverted
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -0,0 +1,816 @@
+//! EPUB Canonical Fragment Identifiers.
+//!
+//! A CFI such as `epubcfi(/6/4[chap01ref]!/4/10/3:12)` points to a location
+//! in a book. The steps before the `!` lead through the package document to
+//! an `itemref` of the spine, and the steps after it lead through that
+//! chapter's XHTML to an element and, optionally, a character offset into its
+//! text. Even steps select element children, counted from 2, and odd steps
+//! select the text before, between or after them.
+//!
+//! Character offsets count Unicode scalar values, as Rust's [`str::chars`]
+//! does, so they may differ from the UTF-16 offsets of a browser's DOM for
+//! text outside the Basic Multilingual Plane.
+//!
+//! See [`EpubDoc::generate_cfi`](crate::doc::EpubDoc::generate_cfi) and
+//! [`EpubDoc::resolve_cfi`](crate::doc::EpubDoc::resolve_cfi).
+//!
+//! # Examples
+//!
+//! ```
+//! use epub::cfi::Cfi;
+//!
+//! let cfi: Cfi = "epubcfi(/6/4[chap01ref]!/4/10,/2/1:1,/3:4)".parse().unwrap();
+//! assert_eq!(
+//!     cfi.start().to_string(),
+//!     "/6/4[chap01ref]!/4/10/2/1:1"
+//! );
+//! assert_eq!(cfi.end().to_string(), "/6/4[chap01ref]!/4/10/3:4");
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::ParserConfig;
+use xml::reader::XmlEvent as ReaderEvent;
+
+use crate::xmlutils::{self, XMLError};
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CfiError {
+    #[error("Invalid CFI, {1} at character {0}")]
+    Syntax(usize, &'static str),
+    #[error("Spine index {0} is out of range")]
+    SpineIndex(usize),
+    #[error("Element path {0:?} doesn't exist")]
+    NoSuchElement(Vec<usize>),
+    #[error("Step /{0} doesn't exist")]
+    NoSuchStep(usize),
+    #[error("Character offset {0} is out of range")]
+    OffsetOutOfRange(usize),
+    #[error("CFI doesn't lead to an item of the spine")]
+    NotInSpine,
+    #[error("Unsupported CFI: {0}")]
+    Unsupported(&'static str),
+}
+
+/// One step of a CFI path, like `/4[body01]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    /// Even indices are element children, counted from 2, and odd indices
+    /// are the text around them.
+    pub index: usize,
+    /// The id asserted for the element, used to find it again if the
+    /// document changed.
+    pub id: Option<String>,
+    /// Whether the step is preceded by `!`, so it continues into the document
+    /// referenced by the previous step.
+    pub indirect: bool,
+}
+
+impl Step {
+    /// The step to `element`, the `i`th element child of its parent.
+    fn element(i: usize, element: &Element) -> Self {
+        Self {
+            index: 2 * (i + 1),
+            id: element.id().map(String::from),
+            indirect: false,
+        }
+    }
+
+    /// The step to the `i`th piece of text of an element.
+    const fn text(i: usize) -> Self {
+        Self {
+            index: 2 * i + 1,
+            id: None,
+            indirect: false,
+        }
+    }
+}
+
+/// Which side of a location a reading system should prefer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Before,
+    After,
+}
+
+/// The text expected around a character offset, like `[yes,no;s=b]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextAssertion {
+    pub before: String,
+    pub after: Option<String>,
+    pub side: Option<Side>,
+}
+
+/// Where a CFI points inside the node its steps lead to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Offset {
+    /// `:12`, characters into text.
+    Character {
+        offset: usize,
+        assertion: Option<TextAssertion>,
+    },
+    /// `~2.5`, seconds into audio or video, optionally with a point of the
+    /// frame.
+    Temporal {
+        seconds: f64,
+        point: Option<(f64, f64)>,
+    },
+    /// `@50:30`, a point in an image or video, as percentages of its width
+    /// and height.
+    Spatial(f64, f64),
+}
+
+/// A path of steps with an optional offset at its end.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    pub steps: Vec<Step>,
+    pub offset: Option<Offset>,
+}
+
+/// A parsed `epubcfi(...)`, pointing to a location or to a range between two
+/// locations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfi {
+    /// The location, or the common parent of the range.
+    pub path: Location,
+    /// The start and the end of the range, relative to `path`.
+    pub range: Option<(Location, Location)>,
+}
+
+impl Cfi {
+    /// A CFI of the range from `start` to `end`, sharing as many steps as
+    /// possible in its parent path.
+    #[must_use]
+    pub fn range(mut start: Location, mut end: Location) -> Self {
+        let mut common = start
+            .steps
+            .iter()
+            .zip(&end.steps)
+            .take_while(|(a, b)| a == b)
+            .count();
+        // Neither end can be left empty
+        if (common == start.steps.len() && start.offset.is_none())
+            || (common == end.steps.len() && end.offset.is_none())
+        {
+            common = common.saturating_sub(1);
+        }
+
+        let steps = start.steps.drain(..common).collect();
+        end.steps.drain(..common);
+        Self {
+            path: Location {
+                steps,
+                offset: None,
+            },
+            range: Some((start, end)),
+        }
+    }
+
+    /// The location this CFI points to, or the start of its range.
+    #[must_use]
+    pub fn start(&self) -> Location {
+        self.join(self.range.as_ref().map(|(start, _)| start))
+    }
+
+    /// The location this CFI points to, or the end of its range.
+    #[must_use]
+    pub fn end(&self) -> Location {
+        self.join(self.range.as_ref().map(|(_, end)| end))
+    }
+
+    fn join(&self, local: Option<&Location>) -> Location {
+        let Some(local) = local else {
+            return self.path.clone();
+        };
+        Location {
+            steps: [self.path.steps.as_slice(), &local.steps].concat(),
+            offset: local.offset.clone(),
+        }
+    }
+}
+
+impl From<Location> for Cfi {
+    fn from(path: Location) -> Self {
+        Self { path, range: None }
+    }
+}
+
+impl FromStr for Cfi {
+    type Err = CfiError;
+
+    /// Parses a CFI, with or without the `epubcfi(...)` around it.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            chars: s.trim().chars().collect(),
+            pos: 0,
+        };
+        let wrapped = parser.eat_str("epubcfi(");
+
+        let path = parser.location(false)?;
+        let range = if parser.eat(',') {
+            if path.offset.is_some() {
+                return parser.error("offset before a range");
+            }
+            let start = parser.location(true)?;
+            parser.expect(',')?;
+            let end = parser.location(true)?;
+            Some((start, end))
+        } else {
+            None
+        };
+
+        if wrapped {
+            parser.expect(')')?;
+        }
+        if parser.peek().is_some() {
+            return parser.error("unexpected character");
+        }
+
+        Ok(Self { path, range })
+    }
+}
+
+impl fmt::Display for Cfi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "epubcfi({}", self.path)?;
+        if let Some((start, end)) = &self.range {
+            write!(f, ",{start},{end}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            if step.indirect {
+                write!(f, "!")?;
+            }
+            write!(f, "/{}", step.index)?;
+            if let Some(id) = &step.id {
+                write!(f, "[{}]", Escaped(id))?;
+            }
+        }
+        match &self.offset {
+            None => {}
+            Some(Offset::Character { offset, assertion }) => {
+                write!(f, ":{offset}")?;
+                if let Some(assertion) = assertion {
+                    write!(f, "[{}", Escaped(&assertion.before))?;
+                    if let Some(after) = &assertion.after {
+                        write!(f, ",{}", Escaped(after))?;
+                    }
+                    match assertion.side {
+                        None => {}
+                        Some(Side::Before) => write!(f, ";s=b")?,
+                        Some(Side::After) => write!(f, ";s=a")?,
+                    }
+                    write!(f, "]")?;
+                }
+            }
+            Some(Offset::Temporal { seconds, point }) => {
+                write!(f, "~{seconds}")?;
+                if let Some((x, y)) = point {
+                    write!(f, "@{x}:{y}")?;
+                }
+            }
+            Some(Offset::Spatial(x, y)) => write!(f, "@{x}:{y}")?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes a string with the characters that are special in CFIs escaped.
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            if matches!(c, '^' | '[' | ']' | '(' | ')' | ',' | ';' | '=') {
+                write!(f, "^")?;
+            }
+            write!(f, "{c}")?;
+        }
+        Ok(())
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let found = s
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c));
+        if found {
+            self.pos += s.chars().count();
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), CfiError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(match c {
+                ')' => "expected ')'",
+                ',' => "expected ','",
+                ']' => "expected ']'",
+                _ => "unexpected character",
+            })
+        }
+    }
+
+    fn error<T>(&self, message: &'static str) -> Result<T, CfiError> {
+        Err(CfiError::Syntax(self.pos, message))
+    }
+
+    fn digits(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn integer(&mut self) -> Result<usize, CfiError> {
+        let start = self.pos;
+        let digits = self.digits();
+        if digits.is_empty() {
+            return self.error("expected a number");
+        }
+        digits
+            .parse()
+            .map_err(|_| CfiError::Syntax(start, "number too large"))
+    }
+
+    fn decimal(&mut self) -> Result<f64, CfiError> {
+        let start = self.pos;
+        let mut number = self.digits();
+        if self.eat('.') {
+            number.push('.');
+            number.push_str(&self.digits());
+        }
+        number
+            .parse()
+            .map_err(|_| CfiError::Syntax(start, "expected a number"))
+    }
+
+    fn point(&mut self) -> Result<(f64, f64), CfiError> {
+        let x = self.decimal()?;
+        if !self.eat(':') {
+            return self.error("expected ':'");
+        }
+        Ok((x, self.decimal()?))
+    }
+
+    /// Parses steps and an offset. Only the start and end of a range may be
+    /// empty, or be just an offset.
+    fn location(&mut self, local: bool) -> Result<Location, CfiError> {
+        let mut steps = vec![];
+        loop {
+            let indirect = self.eat('!');
+            if !self.eat('/') {
+                if indirect {
+                    return self.error("expected a step");
+                }
+                break;
+            }
+            let index = self.integer()?;
+            let id = if self.eat('[') {
+                let id = self.value();
+                self.parameters()?;
+                self.expect(']')?;
+                Some(id).filter(|id| !id.is_empty())
+            } else {
+                None
+            };
+            steps.push(Step {
+                index,
+                id,
+                indirect,
+            });
+        }
+
+        let offset = self.offset()?;
+        if steps.is_empty() && (!local || offset.is_none()) {
+            return self.error("expected a step");
+        }
+        Ok(Location { steps, offset })
+    }
+
+    fn offset(&mut self) -> Result<Option<Offset>, CfiError> {
+        let offset = if self.eat(':') {
+            let offset = self.integer()?;
+            let assertion = if self.eat('[') {
+                let before = self.value();
+                let after = self.eat(',').then(|| self.value());
+                let side = self.parameters()?;
+                self.expect(']')?;
+                Some(TextAssertion {
+                    before,
+                    after,
+                    side,
+                })
+            } else {
+                None
+            };
+            Offset::Character { offset, assertion }
+        } else if self.eat('~') {
+            let seconds = self.decimal()?;
+            let point = if self.eat('@') {
+                Some(self.point()?)
+            } else {
+                None
+            };
+            Offset::Temporal { seconds, point }
+        } else if self.eat('@') {
+            let (x, y) = self.point()?;
+            Offset::Spatial(x, y)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(offset))
+    }
+
+    /// Parses an escaped value inside an assertion.
+    fn value(&mut self) -> String {
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '^' => {
+                    self.pos += 1;
+                    if let Some(c) = self.peek() {
+                        value.push(c);
+                        self.pos += 1;
+                    }
+                }
+                '[' | ']' | '(' | ')' | ',' | ';' | '=' => break,
+                c => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        value
+    }
+
+    /// Parses the `;name=value` parameters of an assertion, keeping only the
+    /// side bias.
+    fn parameters(&mut self) -> Result<Option<Side>, CfiError> {
+        let mut side = None;
+        while self.eat(';') {
+            let name = self.value();
+            if !self.eat('=') {
+                return self.error("expected '='");
+            }
+            let value = self.value();
+            if name == "s" {
+                side = match value.as_str() {
+                    "b" => Some(Side::Before),
+                    "a" => Some(Side::After),
+                    _ => return self.error("invalid side bias"),
+                };
+            }
+        }
+        Ok(side)
+    }
+}
+
+/// Where a CFI leads to in a chapter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// The index of the chapter in the spine
+    pub spine_index: usize,
+    /// The element, as indices among the element children at each level,
+    /// starting from the root element of the chapter
+    pub path: Vec<usize>,
+    /// The local name of the element
+    pub element: String,
+    /// The id of the element, if any
+    pub id: Option<String>,
+    /// How many characters into the text of the element, including the text
+    /// of its descendants, the CFI points
+    pub offset: Option<usize>,
+}
+
+/// An element of a parsed XML document, keeping the text between its
+/// children, which is what CFI steps count.
+#[derive(Debug)]
+pub(crate) struct Element {
+    pub name: String,
+    pub attributes: Vec<OwnedAttribute>,
+    pub children: Vec<Self>,
+    /// The text before, between and after the children, so there's always
+    /// one more chunk than children.
+    pub chunks: Vec<String>,
+}
+
+impl Element {
+    pub fn parse(content: &[u8]) -> Result<Self, XMLError> {
+        let content = xmlutils::decode(content);
+        let reader = ParserConfig::new()
+            .add_entity("nbsp", "\u{a0}")
+            .add_entity("copy", "©")
+            .add_entity("reg", "®")
+            .replace_unknown_entity_references(true)
+            .cdata_to_characters(true)
+            .whitespace_to_characters(true)
+            .create_reader(&*content);
+
+        let mut stack: Vec<Self> = vec![];
+        for e in reader {
+            match e? {
+                ReaderEvent::StartElement {
+                    name, attributes, ..
+                } => stack.push(Self {
+                    name: name.local_name,
+                    attributes,
+                    children: vec![],
+                    chunks: vec![String::new()],
+                }),
+                ReaderEvent::EndElement { .. } => {
+                    let element = stack.pop().ok_or(XMLError::InvalidState)?;
+                    let Some(parent) = stack.last_mut() else {
+                        return Ok(element);
+                    };
+                    parent.children.push(element);
+                    parent.chunks.push(String::new());
+                }
+                ReaderEvent::Characters(s) => {
+                    if let Some(chunk) =
+                        stack.last_mut().and_then(|e| e.chunks.last_mut())
+                    {
+                        chunk.push_str(&s);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(XMLError::NoElements)
+    }
+
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.name.local_name == name)
+            .map(|a| a.value.as_str())
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.attr("id")
+    }
+
+    /// The length in characters of all the text inside this element.
+    fn text_len(&self) -> usize {
+        self.chunks.iter().map(|c| c.chars().count()).sum::<usize>()
+            + self.children.iter().map(Self::text_len).sum::<usize>()
+    }
+
+    /// The descendant at `path`.
+    fn at(&self, path: &[usize]) -> Option<&Self> {
+        path.iter().try_fold(self, |e, &i| e.children.get(i))
+    }
+
+    /// The path to the descendant with `id`, and that element.
+    fn find(&self, id: &str) -> Option<(Vec<usize>, &Self)> {
+        self.children.iter().enumerate().find_map(|(i, child)| {
+            if child.id() == Some(id) {
+                return Some((vec![i], child));
+            }
+            let (mut path, found) = child.find(id)?;
+            path.insert(0, i);
+            Some((path, found))
+        })
+    }
+
+    /// Follows an element step from `self`, a descendant of `root` at
+    /// `path`. If the step's id assertion doesn't match the element at its
+    /// index, the element with that id is used instead, if there is one.
+    fn step<'a>(
+        &'a self,
+        root: &'a Self,
+        path: &mut Vec<usize>,
+        step: &Step,
+    ) -> Result<&'a Self, CfiError> {
+        let by_index = (step.index / 2)
+            .checked_sub(1)
+            .and_then(|i| Some((i, self.children.get(i)?)));
+        let by_id = step
+            .id
+            .as_deref()
+            .filter(|id| by_index.is_none_or(|(_, e)| e.id() != Some(id)))
+            .and_then(|id| root.find(id));
+
+        if let Some((found, element)) = by_id {
+            *path = found;
+            Ok(element)
+        } else if let Some((i, element)) = by_index {
+            path.push(i);
+            Ok(element)
+        } else {
+            Err(CfiError::NoSuchStep(step.index))
+        }
+    }
+}
+
+/// The steps through the package document `package` to the `occurrence`th
+/// itemref of `idref` in the spine.
+pub(crate) fn package_steps(
+    package: &Element,
+    idref: &str,
+    occurrence: usize,
+) -> Result<Vec<Step>, CfiError> {
+    let (spine_index, spine) = package
+        .children
+        .iter()
+        .enumerate()
+        .find(|(_, e)| e.name == "spine")
+        .ok_or(CfiError::NotInSpine)?;
+    let (item_index, item) = spine
+        .children
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.name == "itemref" && e.attr("idref") == Some(idref))
+        .nth(occurrence)
+        .ok_or(CfiError::NotInSpine)?;
+
+    Ok(vec![
+        Step::element(spine_index, spine),
+        Step::element(item_index, item),
+    ])
+}
+
+/// Follows `steps` through the package document `package` to an itemref,
+/// returning its idref and how many itemrefs of the same idref come before
+/// it.
+pub(crate) fn resolve_package<'a>(
+    package: &'a Element,
+    steps: &[Step],
+) -> Result<(&'a str, usize), CfiError> {
+    let mut path = vec![];
+    let mut parent = package;
+    let mut item = package;
+    for step in steps {
+        if step.index % 2 == 1 {
+            return Err(CfiError::NotInSpine);
+        }
+        let element = item.step(package, &mut path, step)?;
+        item = element;
+        parent = path
+            .split_last()
+            .and_then(|(_, parent)| package.at(parent))
+            .ok_or(CfiError::NotInSpine)?;
+    }
+
+    let idref = item
+        .attr("idref")
+        .filter(|_| item.name == "itemref" && parent.name == "spine")
+        .ok_or(CfiError::NotInSpine)?;
+    let index = path.last().copied().unwrap_or_default();
+    let occurrence = parent.children[..index]
+        .iter()
+        .filter(|e| e.name == "itemref" && e.attr("idref") == Some(idref))
+        .count();
+    Ok((idref, occurrence))
+}
+
+/// The location of the element at `path` in the chapter `root`, or of the
+/// character `offset` characters into its text.
+///
+/// An offset always ends up in a piece of text, in the deepest element that
+/// contains it. One between two pieces of text points to the end of the
+/// first.
+pub(crate) fn content_location(
+    root: &Element,
+    path: &[usize],
+    offset: Option<usize>,
+) -> Result<Location, CfiError> {
+    let mut steps = vec![];
+    let mut element = root;
+    for &i in path {
+        element = element
+            .children
+            .get(i)
+            .ok_or_else(|| CfiError::NoSuchElement(path.to_vec()))?;
+        steps.push(Step::element(i, element));
+    }
+    let Some(mut local) = offset else {
+        return Ok(Location {
+            steps,
+            offset: None,
+        });
+    };
+
+    'element: loop {
+        for (i, chunk) in element.chunks.iter().enumerate() {
+            let len = chunk.chars().count();
+            if local <= len {
+                steps.push(Step::text(i));
+                return Ok(Location {
+                    steps,
+                    offset: Some(Offset::Character {
+                        offset: local,
+                        assertion: None,
+                    }),
+                });
+            }
+            local -= len;
+
+            if let Some(child) = element.children.get(i) {
+                let len = child.text_len();
+                if local < len {
+                    steps.push(Step::element(i, child));
+                    element = child;
+                    continue 'element;
+                }
+                local -= len;
+            }
+        }
+        return Err(CfiError::OffsetOutOfRange(offset.unwrap_or_default()));
+    }
+}
+
+/// Follows `steps`, from the one after the `!`, through the chapter `root`,
+/// returning the path to the element they lead to, that element, and the
+/// character offset into its text if they lead into text.
+///
+/// Temporal and spatial offsets are parsed but not resolved, so they point
+/// to their element.
+pub(crate) fn resolve_content<'a>(
+    root: &'a Element,
+    steps: &[Step],
+    offset: Option<&Offset>,
+) -> Result<(Vec<usize>, &'a Element, Option<usize>), CfiError> {
+    let mut path = vec![];
+    let mut element = root;
+    for (n, step) in steps.iter().enumerate() {
+        if step.indirect && n > 0 {
+            return Err(CfiError::Unsupported("indirection out of a chapter"));
+        }
+        if step.index % 2 == 0 {
+            element = element.step(root, &mut path, step)?;
+            continue;
+        }
+
+        let chunk = (step.index - 1) / 2;
+        if n + 1 != steps.len() || chunk >= element.chunks.len() {
+            return Err(CfiError::NoSuchStep(step.index));
+        }
+        let local = match offset {
+            Some(Offset::Character { offset, .. }) => *offset,
+            _ => 0,
+        };
+        if local > element.chunks[chunk].chars().count() {
+            return Err(CfiError::OffsetOutOfRange(local));
+        }
+        let before = element.chunks[..chunk]
+            .iter()
+            .map(|c| c.chars().count())
+            .sum::<usize>()
+            + element.children[..chunk]
+                .iter()
+                .map(Element::text_len)
+                .sum::<usize>();
+        return Ok((path, element, Some(before + local)));
+    }
+
+    if let Some(Offset::Character { .. }) = offset {
+        return Err(CfiError::Unsupported("character offset into an element"));
+    }
+    Ok((path, element, None))
+}
+
+/// Joins the steps through the package document with a location in a
+/// chapter.
+pub(crate) fn redirect(mut package: Vec<Step>, content: Location) -> Location {
+    let mut steps = content.steps.into_iter();
+    if let Some(mut first) = steps.next() {
+        first.indirect = true;
+        package.push(first);
+    }
+    package.extend(steps);
+    Location {
+        steps: package,
+        offset: content.offset,
+    }
+}
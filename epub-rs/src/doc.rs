@@ -12,6 +12,10 @@ use std::path::{Component, Path, PathBuf};
 use xmlutils::XMLError;
 
 use crate::archive::EpubArchive;
+use crate::cfi::{self, Cfi, CfiError, Location, Target};
+use crate::encryption::{self, Algorithm};
+use crate::metadata::Metadata;
+use crate::search::{ChapterText, SearchMatch, SearchMode};
 
 use crate::xmlutils;
 
@@ -23,8 +27,16 @@ pub enum DocError {
     XmlError(#[from] crate::xmlutils::XMLError),
     #[error("I/O Error: {0}")]
     IOError(#[from] std::io::Error),
+    #[error("Regex Error: {0}")]
+    RegexError(#[from] regex::Error),
+    #[error("CFI Error: {0}")]
+    CfiError(#[from] crate::cfi::CfiError),
     #[error("Invalid EPub")]
     InvalidEpub,
+    /// The resource at the path is encrypted with the algorithm, and can't
+    /// be decrypted
+    #[error("Encrypted Resource: {} with {1}", .0.display())]
+    Encrypted(PathBuf, String),
 }
 
 /// Struct that represent a navigation point in a table of content
@@ -58,6 +70,20 @@ impl PartialEq for NavPoint {
     }
 }
 
+/// A labeled location in the book, such as a landmark, a guide reference or a
+/// print page
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NavTarget {
+    /// the label of this target, which is the page number for print pages
+    pub label: String,
+    /// the resource path, with the fragment if there is one
+    pub content: PathBuf,
+    /// what the target points at: the `epub:type` of a landmark, such as
+    /// `bodymatter` or `toc`, the `type` of a guide reference, such as `text`
+    /// or `cover`, or the `type` of a toc.ncx page target, such as `front`
+    pub kind: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct SpineItem {
     pub idref: String,
@@ -70,7 +96,7 @@ pub struct SpineItem {
 #[derive(Clone, Debug)]
 pub struct EpubDoc<R: Read + Seek> {
     /// the zip archive
-    archive: EpubArchive<R>,
+    pub(crate) archive: EpubArchive<R>,
 
     /// The current chapter, is an spine index
     current: usize,
@@ -81,9 +107,21 @@ pub struct EpubDoc<R: Read + Seek> {
     /// resource id -> (path, mime)
     pub resources: HashMap<String, (PathBuf, String)>,
 
-    /// table of content, list of `NavPoint` in the toc.ncx
+    /// table of content, list of `NavPoint` in the EPUB 3 navigation
+    /// document, or in the toc.ncx if there isn't one
     pub toc: Vec<NavPoint>,
 
+    /// landmarks in the EPUB 3 navigation document, such as the cover, the
+    /// table of contents or the start of the body matter
+    pub landmarks: Vec<NavTarget>,
+
+    /// references in the OPF 2 `<guide>`, which landmarks replace in EPUB 3
+    pub guide: Vec<NavTarget>,
+
+    /// print page numbers, in the page list of the EPUB 3 navigation
+    /// document, or in the toc.ncx if there isn't one
+    pub page_list: Vec<NavTarget>,
+
     /// The epub metadata stored as key -> value
     ///
     /// # Examples
@@ -97,6 +135,20 @@ pub struct EpubDoc<R: Read + Seek> {
     /// ```
     pub metadata: HashMap<String, Vec<String>>,
 
+    /// The epub metadata with the relations between elements kept: creator
+    /// roles, sort names, title types, series and so on
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use epub::doc::EpubDoc;
+    /// # let doc = EpubDoc::new("test.epub");
+    /// # let doc = doc.unwrap();
+    /// let creator = &doc.book_metadata.creators[0];
+    /// assert_eq!(creator.roles, vec!["aut".to_string()]);
+    /// ```
+    pub book_metadata: Metadata,
+
     /// root file base path
     pub root_base: PathBuf,
 
@@ -111,6 +163,13 @@ pub struct EpubDoc<R: Read + Seek> {
 
     /// The id of the cover, if any
     pub cover_id: Option<String>,
+
+    /// The id of the EPUB 3 navigation document, if any
+    pub nav_id: Option<String>,
+
+    /// The resources listed in `META-INF/encryption.xml`, by full path, with
+    /// how they are encrypted
+    pub encryption: HashMap<PathBuf, Algorithm>,
 }
 
 impl EpubDoc<BufReader<File>> {
@@ -168,8 +227,13 @@ impl<R: Read + Seek> EpubDoc<R> {
     ///
     /// Returns an error if the epub is broken.
     pub fn from_reader(reader: R) -> Result<Self, DocError> {
-        let mut archive = EpubArchive::from_reader(reader)?;
+        Self::from_archive(EpubArchive::from_reader(reader)?)
+    }
 
+    /// Opens the epub in `archive`.
+    pub(crate) fn from_archive(
+        mut archive: EpubArchive<R>,
+    ) -> Result<Self, DocError> {
         let container = archive.get_container_file()?;
         let root_file = get_root_file(&container)?;
         let base_path = root_file.parent().expect("All files have a parent");
@@ -177,16 +241,26 @@ impl<R: Read + Seek> EpubDoc<R> {
             archive,
             spine: vec![],
             toc: vec![],
+            landmarks: vec![],
+            guide: vec![],
+            page_list: vec![],
             resources: HashMap::new(),
             metadata: HashMap::new(),
+            book_metadata: Metadata::default(),
             root_file: root_file.clone(),
             root_base: base_path.to_path_buf(),
             current: 0,
             extra_css: vec![],
             unique_identifier: None,
             cover_id: None,
+            nav_id: None,
+            encryption: HashMap::new(),
         };
         doc.fill_resources()?;
+        // A broken encryption.xml is as good as none
+        if let Ok(content) = doc.archive.get_entry("META-INF/encryption.xml") {
+            doc.encryption = encryption::parse(&content).unwrap_or_default();
+        }
         Ok(doc)
     }
 
@@ -270,12 +344,70 @@ impl<R: Read + Seek> EpubDoc<R> {
 
     /// Returns the resource content by full path in the epub archive
     ///
-    /// Returns [`None`] if the path doesn't exist in the epub
+    /// Returns [`None`] if the path doesn't exist in the epub, or if the
+    /// resource can't be decrypted
     pub fn get_resource_by_path<P: AsRef<Path>>(
         &mut self,
         path: P,
     ) -> Option<Vec<u8>> {
-        self.archive.get_entry(path).ok()
+        self.read_resource_by_path(path).ok()
+    }
+
+    /// Returns the resource content by full path in the epub archive, with
+    /// obfuscated fonts restored
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DocError::Encrypted`] if the resource is encrypted with an
+    /// algorithm other than font obfuscation, or if the book has no
+    /// identifier to derive the key from. Returns an archive error if the
+    /// path doesn't exist in the epub.
+    pub fn read_resource_by_path<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Vec<u8>, DocError> {
+        let path = path.as_ref();
+        let mut content = self.archive.get_entry(path)?;
+
+        let decoded = path.to_str().map(|p| {
+            percent_encoding::percent_decode_str(p)
+                .decode_utf8_lossy()
+                .into_owned()
+        });
+        let algorithm = self.encryption.get(path).or_else(|| {
+            decoded.and_then(|p| self.encryption.get(Path::new(&p)))
+        });
+        if let Some(algorithm) = algorithm {
+            let decrypted =
+                self.obfuscation_identifier(algorithm).and_then(|id| {
+                    encryption::deobfuscate(&mut content, algorithm, id)
+                });
+            if decrypted.is_none() {
+                return Err(DocError::Encrypted(
+                    path.to_path_buf(),
+                    algorithm.uri().to_string(),
+                ));
+            }
+        }
+
+        Ok(content)
+    }
+
+    /// Returns the identifier the key of font obfuscation with `algorithm`
+    /// is derived from.
+    pub(crate) fn obfuscation_identifier(
+        &self,
+        algorithm: &Algorithm,
+    ) -> Option<&str> {
+        let identifiers = self
+            .metadata
+            .get("identifier")
+            .map_or(&[][..], Vec::as_slice);
+        encryption::key_identifier(
+            algorithm,
+            self.unique_identifier.as_deref(),
+            identifiers,
+        )
     }
 
     /// Returns the resource content and mime-type by the id defined in the
@@ -290,6 +422,15 @@ impl<R: Read + Seek> EpubDoc<R> {
         Some((content, mime))
     }
 
+    /// Returns the size of the resource content by the id defined in the
+    /// spine, read from the archive without extracting the resource
+    ///
+    /// Returns [`None`] if the id doesn't exists in the epub
+    pub fn get_resource_size(&mut self, id: &str) -> Option<u64> {
+        let (path, _) = self.resources.get(id)?;
+        self.archive.get_entry_size(path).ok()
+    }
+
     /// Returns the resource content by full path in the epub archive, as String
     ///
     /// Returns [`None`] if the path doesn't exists in the epub
@@ -297,7 +438,8 @@ impl<R: Read + Seek> EpubDoc<R> {
         &mut self,
         path: P,
     ) -> Option<String> {
-        self.archive.get_entry_as_str(path).ok()
+        let content = self.read_resource_by_path(path).ok()?;
+        String::from_utf8(content).ok()
     }
 
     /// Returns the resource content and mime-type by the id defined in the
@@ -595,6 +737,263 @@ impl<R: Read + Seek> EpubDoc<R> {
         self.extra_css.push(String::from(css));
     }
 
+    /// Searches the text of every chapter in the spine for `query`.
+    ///
+    /// Chapters that aren't XHTML, or that can't be parsed, are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use epub::doc::EpubDoc;
+    /// use epub::search::SearchMode;
+    ///
+    /// let mut doc = EpubDoc::new("test.epub").unwrap();
+    /// let matches = doc.search("EL PRIMER", SearchMode::Text).unwrap();
+    /// assert!(!matches.is_empty());
+    ///
+    /// let matches = doc.search(r"\bprimer[oa]?\b", SearchMode::Regex).unwrap();
+    /// assert!(!matches.is_empty());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DocError::RegexError`] if `query` isn't a valid regular
+    /// expression in [`SearchMode::Regex`].
+    pub fn search(
+        &mut self,
+        query: &str,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchMatch>, DocError> {
+        let regex = match mode {
+            SearchMode::Text => regex::RegexBuilder::new(&regex::escape(query))
+                .case_insensitive(true)
+                .build()?,
+            SearchMode::Regex => regex::Regex::new(query)?,
+        };
+
+        let mut matches = vec![];
+        for chapter in 0..self.spine.len() {
+            let id = self.spine[chapter].idref.clone();
+            let Some((content, mime)) = self.get_resource(&id) else {
+                continue;
+            };
+            if mime != "application/xhtml+xml" && mime != "text/html" {
+                continue;
+            }
+            let Ok(text) = ChapterText::parse(&content) else {
+                continue;
+            };
+
+            let mut offset = 0;
+            let mut last_end = 0;
+            for m in regex.find_iter(&text.text) {
+                if m.is_empty() {
+                    continue;
+                }
+                offset += text.text[last_end..m.start()].chars().count();
+                last_end = m.start();
+                matches.push(SearchMatch {
+                    chapter,
+                    offset,
+                    anchor: text.anchor_at(m.start()).map(String::from),
+                    matched: m.as_str().to_string(),
+                    snippet: text.snippet(m.start(), m.end()),
+                });
+            }
+        }
+
+        Ok(matches)
+    }
+
+    /// Returns the readable text of the chapter at `spine_index`, with a
+    /// line break between block elements, as [`search`](Self::search) sees
+    /// it.
+    ///
+    /// Returns [`None`] if there's no such chapter, or it isn't a readable
+    /// XHTML document.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use epub::doc::EpubDoc;
+    ///
+    /// let mut doc = EpubDoc::new("test.epub").unwrap();
+    /// let text = doc.chapter_text(1).unwrap();
+    /// assert!(text.starts_with("Todo es mío\nDaniel García\n"));
+    /// ```
+    pub fn chapter_text(&mut self, spine_index: usize) -> Option<String> {
+        let id = self.spine.get(spine_index)?.idref.clone();
+        let (content, mime) = self.get_resource(&id)?;
+        if mime != "application/xhtml+xml" && mime != "text/html" {
+            return None;
+        }
+        ChapterText::parse(&content).ok().map(|t| t.text)
+    }
+
+    /// Generates a CFI pointing into the chapter at `spine_index`.
+    ///
+    /// `path` leads from the root element of the chapter to an element, as
+    /// indices among the element children at each level, so `&[1]` is
+    /// usually `<body>`. With an `offset`, the CFI points that many
+    /// characters into the text of the element, including the text of its
+    /// descendants.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use epub::doc::EpubDoc;
+    ///
+    /// let mut doc = EpubDoc::new("test.epub").unwrap();
+    /// let cfi = doc.generate_cfi(1, &[1, 0], Some(3)).unwrap();
+    /// assert_eq!(cfi.to_string(), "epubcfi(/6/4!/4/2/1:3)");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DocError::CfiError`] if there's no such chapter, element or
+    /// offset, and other errors if the chapter can't be read.
+    pub fn generate_cfi(
+        &mut self,
+        spine_index: usize,
+        path: &[usize],
+        offset: Option<usize>,
+    ) -> Result<Cfi, DocError> {
+        let (package_steps, root) = self.cfi_chapter(spine_index)?;
+        let content = cfi::content_location(&root, path, offset)?;
+        Ok(cfi::redirect(package_steps, content).into())
+    }
+
+    /// Generates a CFI of the characters in `range` of the text of an
+    /// element, like [`EpubDoc::generate_cfi`].
+    ///
+    /// # Errors
+    ///
+    /// See [`EpubDoc::generate_cfi`].
+    pub fn generate_cfi_range(
+        &mut self,
+        spine_index: usize,
+        path: &[usize],
+        range: std::ops::Range<usize>,
+    ) -> Result<Cfi, DocError> {
+        let (package_steps, root) = self.cfi_chapter(spine_index)?;
+        let start = cfi::content_location(&root, path, Some(range.start))?;
+        let end = cfi::content_location(&root, path, Some(range.end))?;
+        Ok(Cfi::range(
+            cfi::redirect(package_steps.clone(), start),
+            cfi::redirect(package_steps, end),
+        ))
+    }
+
+    /// Finds where `cfi` points to, or where its range starts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use epub::doc::EpubDoc;
+    ///
+    /// let mut doc = EpubDoc::new("test.epub").unwrap();
+    /// let cfi = "epubcfi(/6/4!/4/2/1:3)".parse().unwrap();
+    /// let target = doc.resolve_cfi(&cfi).unwrap();
+    /// assert_eq!(target.spine_index, 1);
+    /// assert_eq!(target.path, [1, 0]);
+    /// assert_eq!(target.element, "h1");
+    /// assert_eq!(target.offset, Some(3));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DocError::CfiError`] if `cfi` doesn't lead anywhere in the
+    /// book, and other errors if the chapter can't be read.
+    pub fn resolve_cfi(&mut self, cfi: &Cfi) -> Result<Target, DocError> {
+        self.resolve_cfi_location(&cfi.start())
+    }
+
+    /// Finds where the range of `cfi` starts and ends. For a CFI without a
+    /// range, both are the location it points to.
+    ///
+    /// # Errors
+    ///
+    /// See [`EpubDoc::resolve_cfi`].
+    pub fn resolve_cfi_range(
+        &mut self,
+        cfi: &Cfi,
+    ) -> Result<(Target, Target), DocError> {
+        Ok((
+            self.resolve_cfi_location(&cfi.start())?,
+            self.resolve_cfi_location(&cfi.end())?,
+        ))
+    }
+
+    fn resolve_cfi_location(
+        &mut self,
+        location: &Location,
+    ) -> Result<Target, DocError> {
+        let split = location
+            .steps
+            .iter()
+            .position(|s| s.indirect)
+            .unwrap_or(location.steps.len());
+        let (package_steps, content_steps) = location.steps.split_at(split);
+
+        let package = self.cfi_package()?;
+        let (idref, occurrence) =
+            cfi::resolve_package(&package, package_steps)?;
+        let spine_index = self
+            .spine
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.idref == idref)
+            .nth(occurrence)
+            .map(|(i, _)| i)
+            .ok_or(CfiError::NotInSpine)?;
+
+        let (content, _) =
+            self.get_resource(idref).ok_or(DocError::InvalidEpub)?;
+        let root = cfi::Element::parse(&content)?;
+        let (path, element, offset) = cfi::resolve_content(
+            &root,
+            content_steps,
+            location.offset.as_ref(),
+        )?;
+
+        Ok(Target {
+            spine_index,
+            path,
+            element: element.name.clone(),
+            id: element.id().map(String::from),
+            offset,
+        })
+    }
+
+    /// The steps to the chapter at `spine_index` in the package document,
+    /// and the parsed chapter.
+    fn cfi_chapter(
+        &mut self,
+        spine_index: usize,
+    ) -> Result<(Vec<cfi::Step>, cfi::Element), DocError> {
+        let idref = self
+            .spine
+            .get(spine_index)
+            .ok_or(CfiError::SpineIndex(spine_index))?
+            .idref
+            .clone();
+        let occurrence = self.spine[..spine_index]
+            .iter()
+            .filter(|item| item.idref == idref)
+            .count();
+
+        let package = self.cfi_package()?;
+        let steps = cfi::package_steps(&package, &idref, occurrence)?;
+        let (content, _) =
+            self.get_resource(&idref).ok_or(DocError::InvalidEpub)?;
+        Ok((steps, cfi::Element::parse(&content)?))
+    }
+
+    fn cfi_package(&mut self) -> Result<cfi::Element, DocError> {
+        let content = self.archive.get_entry(&self.root_file)?;
+        Ok(cfi::Element::parse(&content)?)
+    }
+
     /// Function to convert a resource path to a chapter number in the spine
     /// If the resource isn't in the spine list, None will be returned
     ///
@@ -610,6 +1009,36 @@ impl<R: Read + Seek> EpubDoc<R> {
         None
     }
 
+    /// Returns the chapter number where the text of the book starts, after
+    /// the cover and front matter, from the `bodymatter` landmark or else
+    /// the `text` guide reference
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use epub::doc::EpubDoc;
+    /// let mut doc = EpubDoc::new("tests/docs/Metamorphosis-jackson.epub").unwrap();
+    /// let start = doc.start_of_text().unwrap();
+    /// doc.set_current_page(start);
+    /// assert_eq!("pressbooks-promo", doc.get_current_id().unwrap());
+    /// ```
+    pub fn start_of_text(&self) -> Option<usize> {
+        let landmark = self
+            .landmarks
+            .iter()
+            .find(|l| l.kind.as_deref() == Some("bodymatter"));
+        let reference = || {
+            self.guide
+                .iter()
+                .find(|r| r.kind.as_deref() == Some("text"))
+        };
+        let target = landmark.or_else(reference)?;
+        // The target may point at a fragment of the chapter
+        let path = target.content.to_string_lossy();
+        let path = path.split('#').next().unwrap_or_default();
+        self.resource_uri_to_chapter(&PathBuf::from(path))
+    }
+
     /// Function to convert a resource id to a chapter number in the spine
     /// If the resourse isn't in the spine list, None will be returned
     pub fn resource_id_to_chapter(&self, uri: &str) -> Option<usize> {
@@ -640,6 +1069,13 @@ impl<R: Read + Seek> EpubDoc<R> {
                     }
                 }
             }
+            if self.nav_id.is_none()
+                && let (Some(id), Some(properties)) =
+                    (item.get_attr("id"), item.get_attr("properties"))
+                && properties.split_whitespace().any(|p| p == "nav")
+            {
+                self.nav_id = Some(id);
+            }
             let _ = self.insert_resource(&item);
         }
 
@@ -650,16 +1086,27 @@ impl<R: Read + Seek> EpubDoc<R> {
             let _ = self.insert_spine(&item);
         }
 
-        // toc.ncx
-        if let Some(toc) = spine.borrow().get_attr("toc") {
+        // nav.xhtml, falling back to toc.ncx
+        if let Some(nav) = self.nav_id.clone() {
+            let _ = self.fill_nav(&nav);
+        }
+        if (self.toc.is_empty() || self.page_list.is_empty())
+            && let Some(toc) = spine.borrow().get_attr("toc")
+        {
             let _ = self.fill_toc(&toc);
         }
 
+        // guide, only in OPF 2 but often kept in EPUB 3
+        if let Some(guide) = root.borrow().find("guide") {
+            self.fill_guide(&guide.borrow());
+        }
+
         // metadata
         let metadata = root
             .borrow()
             .find("metadata")
             .ok_or(DocError::InvalidEpub)?;
+        self.book_metadata = Metadata::from_node(&metadata.borrow());
         for r in &metadata.borrow().children {
             let item = r.borrow();
             if item.name.local_name == "meta" {
@@ -750,19 +1197,50 @@ impl<R: Read + Seek> EpubDoc<R> {
         Ok(())
     }
 
+    fn fill_guide(&mut self, guide: &xmlutils::XMLNode) {
+        for r in &guide.children {
+            let item = r.borrow();
+            if item.name.local_name != "reference" {
+                continue;
+            }
+            let Some(href) = item.get_attr("href") else {
+                continue;
+            };
+            let kind = item.get_attr("type");
+            self.guide.push(NavTarget {
+                label: item
+                    .get_attr("title")
+                    .or_else(|| kind.clone())
+                    .unwrap_or_default(),
+                content: resolve_href(&self.root_file, &href),
+                kind,
+            });
+        }
+    }
+
     fn fill_toc(&mut self, id: &str) -> Result<(), DocError> {
         let toc_res = self.resources.get(id).ok_or(DocError::InvalidEpub)?; // this should be turned into it's own error type, but
 
-        let container = self.archive.get_entry(&toc_res.0)?;
+        let toc_path = toc_res.0.clone();
+
+        let container = self.archive.get_entry(&toc_path)?;
         let root = xmlutils::XMLReader::parse(container.as_slice())?;
 
-        let mapnode = root
-            .borrow()
-            .find("navMap")
-            .ok_or_else(|| XMLError::AttrNotFound("navMap".into()))?;
+        if self.page_list.is_empty()
+            && let Some(list) = root.borrow().find("pageList")
+        {
+            self.page_list = get_page_targets(&list.borrow(), &toc_path);
+        }
 
-        self.toc.append(&mut self.get_navpoints(&mapnode.borrow()));
-        self.toc.sort();
+        if self.toc.is_empty() {
+            let mapnode = root
+                .borrow()
+                .find("navMap")
+                .ok_or_else(|| XMLError::AttrNotFound("navMap".into()))?;
+
+            self.toc.append(&mut self.get_navpoints(&mapnode.borrow()));
+            self.toc.sort();
+        }
 
         Ok(())
     }
@@ -807,6 +1285,202 @@ impl<R: Read + Seek> EpubDoc<R> {
         navpoints.sort();
         navpoints
     }
+
+    fn fill_nav(&mut self, id: &str) -> Result<(), DocError> {
+        let nav_path = self
+            .resources
+            .get(id)
+            .map(|r| r.0.clone())
+            .ok_or(DocError::InvalidEpub)?;
+
+        let container = self.archive.get_entry(&nav_path)?;
+        let root = xmlutils::XMLReader::parse(container.as_slice())?;
+
+        let list = |kind| {
+            find_nav(&root.borrow(), kind)
+                .and_then(|nav| nav.borrow().find("ol"))
+        };
+        if let Some(list) = list("landmarks") {
+            self.landmarks = get_nav_targets(&list.borrow(), &nav_path);
+        }
+        if let Some(list) = list("page-list") {
+            self.page_list = get_nav_targets(&list.borrow(), &nav_path);
+        }
+
+        let list =
+            list("toc").ok_or_else(|| XMLError::AttrNotFound("nav".into()))?;
+        let mut play_order = 0;
+        self.toc =
+            get_nav_list_points(&list.borrow(), &nav_path, &mut play_order);
+
+        Ok(())
+    }
+}
+
+/// Extract the links of a flat navigation document `<ol>`, such as the
+/// landmarks or the page list.
+fn get_nav_targets(
+    list: &xmlutils::XMLNode,
+    nav_path: &Path,
+) -> Vec<NavTarget> {
+    let mut targets = Vec::new();
+
+    for li in &list.children {
+        let item = li.borrow();
+        if item.name.local_name != "li" {
+            continue;
+        }
+        let Some(a) = item.find("a") else {
+            continue;
+        };
+        let a = a.borrow();
+        let Some(href) = a.get_attr("href") else {
+            continue;
+        };
+        let label = a.text_content();
+        targets.push(NavTarget {
+            label: label.split_whitespace().collect::<Vec<_>>().join(" "),
+            content: resolve_href(nav_path, &href),
+            kind: a.get_attr("type"),
+        });
+    }
+
+    targets
+}
+
+/// Extract the page targets of a toc.ncx `<pageList>`, in play order.
+fn get_page_targets(
+    list: &xmlutils::XMLNode,
+    ncx_path: &Path,
+) -> Vec<NavTarget> {
+    let mut targets = Vec::new();
+
+    for target in &list.children {
+        let item = target.borrow();
+        if item.name.local_name != "pageTarget" {
+            continue;
+        }
+        let play_order = item
+            .get_attr("playOrder")
+            .and_then(|n| n.parse::<usize>().ok());
+        let content = item
+            .find("content")
+            .and_then(|c| c.borrow().get_attr("src"))
+            .map(|src| resolve_href(ncx_path, &src));
+        let label = item
+            .find("navLabel")
+            .map(|l| l.borrow().text_content().trim().to_string())
+            .or_else(|| item.get_attr("value"));
+
+        if let (Some(c), Some(l)) = (content, label) {
+            targets.push((
+                play_order,
+                NavTarget {
+                    label: l,
+                    content: c,
+                    kind: item.get_attr("type"),
+                },
+            ));
+        }
+    }
+
+    // Targets without a play order keep their place after the others
+    targets.sort_by_key(|(order, _)| order.unwrap_or(usize::MAX));
+    targets.into_iter().map(|(_, t)| t).collect()
+}
+
+/// Finds the `<nav>` element whose `epub:type` contains `kind`.
+fn find_nav(
+    root: &xmlutils::XMLNode,
+    kind: &str,
+) -> Option<std::rc::Rc<std::cell::RefCell<xmlutils::XMLNode>>> {
+    root.find_where(&|n: &xmlutils::XMLNode| {
+        n.name.local_name == "nav"
+            && n.get_attr("type")
+                .is_some_and(|t| t.split_whitespace().any(|t| t == kind))
+    })
+}
+
+/// Recursively extract all navpoints from the `<li>` children of a navigation
+/// document `<ol>`. Play order is assigned depth-first, starting from 1.
+fn get_nav_list_points(
+    list: &xmlutils::XMLNode,
+    nav_path: &Path,
+    play_order: &mut usize,
+) -> Vec<NavPoint> {
+    let mut navpoints = Vec::new();
+
+    for li in &list.children {
+        let item = li.borrow();
+        if item.name.local_name != "li" {
+            continue;
+        }
+
+        let mut label = None;
+        let mut content = None;
+        let mut sublist = None;
+        for c in &item.children {
+            let child = c.borrow();
+            match child.name.local_name.as_str() {
+                "a" if label.is_none() => {
+                    label = Some(child.text_content());
+                    content = child
+                        .get_attr("href")
+                        .map(|href| resolve_href(nav_path, &href));
+                }
+                "span" if label.is_none() => {
+                    label = Some(child.text_content());
+                }
+                "ol" => sublist = Some(c.clone()),
+                _ => {}
+            }
+        }
+
+        *play_order += 1;
+        let order = *play_order;
+        let children = sublist.map_or_else(Vec::new, |l| {
+            get_nav_list_points(&l.borrow(), nav_path, play_order)
+        });
+
+        // Headings without a link of their own point at their first child
+        let content =
+            content.or_else(|| children.first().map(|c| c.content.clone()));
+        if let (Some(l), Some(c)) = (label, content) {
+            navpoints.push(NavPoint {
+                label: l.split_whitespace().collect::<Vec<_>>().join(" "),
+                content: c,
+                children,
+                play_order: order,
+            });
+        }
+    }
+
+    navpoints
+}
+
+/// Resolves `href`, relative to the archive file `base`, to a full path in
+/// the archive.
+fn resolve_href(base: &Path, href: &str) -> PathBuf {
+    let mut path = base.parent().map(Path::to_path_buf).unwrap_or_default();
+    for p in Path::new(href).components() {
+        match p {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(s) => {
+                path.push(s);
+            }
+            _ => {}
+        }
+    }
+
+    // If on Windows, replace all Windows path separators with Unix path
+    // separators
+    if cfg!(windows) {
+        PathBuf::from(path.to_string_lossy().replace('\\', "/"))
+    } else {
+        path
+    }
 }
 
 fn get_root_file(container: &[u8]) -> Result<PathBuf, DocError> {
@@ -830,30 +1504,6 @@ fn build_epub_uri<P: AsRef<Path>>(path: P, append: &str) -> String {
         return String::from(append);
     }
 
-    let path = path.as_ref();
-    let mut cpath = path.to_path_buf();
-
-    // current file base dir
-    cpath.pop();
-    for p in Path::new(append).components() {
-        match p {
-            Component::ParentDir => {
-                cpath.pop();
-            }
-            Component::Normal(s) => {
-                cpath.push(s);
-            }
-            _ => {}
-        };
-    }
-
-    // If on Windows, replace all Windows path separators with Unix path
-    // separators
-    let path = if cfg!(windows) {
-        cpath.to_string_lossy().replace('\\', "/")
-    } else {
-        cpath.to_string_lossy().to_string()
-    };
-
-    format!("epub://{}", path)
+    let path = resolve_href(path.as_ref(), append);
+    format!("epub://{}", path.to_string_lossy())
 }
@@ -0,0 +1,833 @@
+//! Edits the metadata and the cover of existing epub files.
+//!
+//! [`EpubEditor`] rewrites the package document with the changes, and every
+//! other entry of the archive is copied as is, without being decompressed.
+//!
+//! # Examples
+//!
+//! ```
+//! use epub::doc::EpubDoc;
+//! use epub::editor::EpubEditor;
+//! use std::io::Cursor;
+//!
+//! let doc = EpubDoc::new("test.epub").unwrap();
+//! let mut editor = EpubEditor::new(doc);
+//! editor
+//!     .title("Todo es tuyo")
+//!     .add_identifier("isbn:9780000000000");
+//!
+//! let epub = editor.write(Cursor::new(vec![])).unwrap();
+//! let doc = EpubDoc::from_reader(Cursor::new(epub.into_inner())).unwrap();
+//! assert_eq!(doc.mdata("title").unwrap(), "Todo es tuyo");
+//! ```
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::io::{Read, Seek, Write};
+use std::rc::Rc;
+
+use xml::attribute::OwnedAttribute;
+use xml::reader::{ParserConfig, XmlEvent as ReaderEvent};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent as WriterEvent};
+use zip::write::ZipWriter;
+
+use crate::archive::ArchiveError;
+use crate::builder::{escape, now, write_collection, write_person};
+use crate::doc::{DocError, EpubDoc};
+use crate::encryption;
+use crate::metadata::{Collection, Person};
+use crate::xmlutils::{self, XMLError, XMLNode, XMLReader};
+
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+const OPF: &str = "http://www.idpf.org/2007/opf";
+
+/// Changes to the metadata and the cover of an [`EpubDoc`], see the
+/// [module documentation](self)
+///
+/// Each change replaces what the book had: setting the creators removes
+/// the previous ones, along with the `meta` elements refining them.
+pub struct EpubEditor<R: Read + Seek> {
+    doc: EpubDoc<R>,
+    title: Option<String>,
+    creators: Option<Vec<Person>>,
+    /// `Some(None)` removes the series
+    #[allow(clippy::option_option)]
+    series: Option<Option<Collection>>,
+    identifier: Option<String>,
+    identifiers: Vec<String>,
+    /// media type and content
+    cover: Option<(String, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> EpubEditor<R> {
+    /// Starts editing `doc`. Nothing changes until it's written.
+    #[must_use]
+    pub fn new(doc: EpubDoc<R>) -> Self {
+        Self {
+            doc,
+            title: None,
+            creators: None,
+            series: None,
+            identifier: None,
+            identifiers: vec![],
+            cover: None,
+        }
+    }
+
+    /// Returns the document being edited, as it was opened.
+    pub fn doc(&self) -> &EpubDoc<R> {
+        &self.doc
+    }
+
+    /// Sets the main title. Its sort title and alternate scripts are
+    /// removed, since they don't match it anymore.
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    /// Replaces all the creators. OPF 2 packages only keep the first role
+    /// of each.
+    pub fn creators(&mut self, creators: Vec<Person>) -> &mut Self {
+        self.creators = Some(creators);
+        self
+    }
+
+    /// Replaces the series, or removes it with [`None`]. The collection type
+    /// is `series` if it has none. OPF 2 packages get calibre's series
+    /// metadata instead, without nested collections.
+    pub fn series(&mut self, series: Option<Collection>) -> &mut Self {
+        self.series = Some(series);
+        self
+    }
+
+    /// Replaces the unique identifier. Obfuscated fonts are obfuscated again
+    /// with the key derived from the new one.
+    pub fn identifier(&mut self, identifier: &str) -> &mut Self {
+        self.identifier = Some(identifier.to_string());
+        self
+    }
+
+    /// Adds an identifier, after the ones the book has.
+    pub fn add_identifier(&mut self, identifier: &str) -> &mut Self {
+        self.identifiers.push(identifier.to_string());
+        self
+    }
+
+    /// Sets the cover image. The current cover keeps its path and gets the
+    /// new content, or else the image is added to the package.
+    pub fn cover_image<C: Into<Vec<u8>>>(
+        &mut self,
+        media_type: &str,
+        content: C,
+    ) -> &mut Self {
+        self.cover = Some((media_type.to_string(), content.into()));
+        self
+    }
+
+    /// Writes the edited epub to `writer`, and returns it.
+    ///
+    /// The modification date of EPUB 3 packages is set to the time of
+    /// writing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the package document can't be parsed, or if
+    /// reading or writing an entry fails.
+    pub fn write<W: Write + Seek>(&mut self, writer: W) -> Result<W, DocError> {
+        let root_file = self
+            .doc
+            .archive
+            .entry_name(&self.doc.root_file)
+            .ok_or(DocError::InvalidEpub)?
+            .to_string();
+        let opf = self.doc.archive.get_entry(&root_file)?;
+        let root = XMLReader::parse(&opf)?;
+        let mut package =
+            Package::new(&root.borrow()).ok_or(DocError::InvalidEpub)?;
+        let mut replaced = BTreeMap::new();
+
+        if let Some(title) = &self.title {
+            package.set_title(title);
+        }
+        if let Some(creators) = &self.creators {
+            package.set_creators(creators);
+        }
+        if let Some(series) = &self.series {
+            package.set_series(series.as_ref());
+        }
+        if let Some(identifier) = &self.identifier {
+            package.set_identifier(identifier);
+        }
+        for identifier in &self.identifiers {
+            package.add_identifier(identifier);
+        }
+        if let Some((media_type, content)) = &self.cover {
+            let path = self.set_cover(&mut package, media_type);
+            replaced.insert(path, content.clone());
+        }
+        package.set_modified();
+
+        self.reobfuscate(&mut replaced)?;
+        replaced.insert(root_file, package.rewrite(&opf)?);
+
+        let mut zip = ZipWriter::new(writer);
+        self.doc.archive.copy_to(&mut zip, replaced)?;
+        Ok(zip.finish().map_err(ArchiveError::from)?)
+    }
+
+    /// Points the package to the new cover, and returns the path its content
+    /// goes to.
+    fn set_cover(&self, package: &mut Package, media_type: &str) -> String {
+        let doc = &self.doc;
+        let current = doc.cover_id.as_ref().and_then(|id| {
+            let (path, mime) = doc.resources.get(id)?;
+            Some((id, doc.archive.entry_name(path)?, mime))
+        });
+        if let Some((id, name, mime)) = current {
+            if mime != media_type {
+                package.set_media_type(id, media_type);
+            }
+            return name.to_string();
+        }
+
+        let extension = match media_type {
+            "image/jpeg" => "jpg",
+            "image/svg+xml" => "svg",
+            other => other.strip_prefix("image/").unwrap_or("img"),
+        };
+        let mut href = format!("cover.{extension}");
+        for i in 2.. {
+            if doc.archive.entry_name(doc.root_base.join(&href)).is_none() {
+                break;
+            }
+            href = format!("cover-{i}.{extension}");
+        }
+        package.add_cover(&href, media_type);
+        doc.root_base.join(href).to_string_lossy().into_owned()
+    }
+
+    /// Obfuscates the fonts again if their key changes with the
+    /// identifiers.
+    fn reobfuscate(
+        &mut self,
+        replaced: &mut BTreeMap<String, Vec<u8>>,
+    ) -> Result<(), DocError> {
+        let old_unique = self.doc.unique_identifier.as_deref();
+        let unique = self.identifier.as_deref().or(old_unique);
+        let mut identifiers = self
+            .doc
+            .metadata
+            .get("identifier")
+            .cloned()
+            .unwrap_or_default();
+        if let (Some(new), Some(old)) = (&self.identifier, old_unique) {
+            for id in identifiers.iter_mut().filter(|id| *id == old) {
+                id.clone_from(new);
+            }
+        }
+        identifiers.extend(self.identifiers.iter().cloned());
+
+        let mut fonts = vec![];
+        for (path, algorithm) in &self.doc.encryption {
+            let old = self.doc.obfuscation_identifier(algorithm);
+            let new =
+                encryption::key_identifier(algorithm, unique, &identifiers);
+            if let (Some(old), Some(new), Some(name)) =
+                (old, new, self.doc.archive.entry_name(path))
+                && old != new
+            {
+                fonts.push((
+                    name.to_string(),
+                    algorithm.clone(),
+                    old.to_string(),
+                    new.to_string(),
+                ));
+            }
+        }
+
+        for (name, algorithm, old, new) in fonts {
+            let mut content = self.doc.archive.get_entry(&name)?;
+            // Obfuscation is its own inverse
+            if encryption::deobfuscate(&mut content, &algorithm, &old)
+                .and_then(|()| {
+                    encryption::deobfuscate(&mut content, &algorithm, &new)
+                })
+                .is_some()
+            {
+                replaced.insert(name, content);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Changes to the children of the `metadata` or the `manifest` element, by
+/// their index
+#[derive(Debug, Default)]
+struct Edits {
+    removed: HashSet<usize>,
+    /// Elements to insert before the child at each index, or at the end for
+    /// the number of children
+    inserted: BTreeMap<usize, Vec<String>>,
+}
+
+/// The package document, with the changes to make to it
+struct Package {
+    /// OPF 2 packages have attributes instead of refines
+    opf2: bool,
+    unique_identifier: Option<String>,
+    metadata: Vec<Rc<RefCell<XMLNode>>>,
+    manifest: Vec<Rc<RefCell<XMLNode>>>,
+    /// Every id in the package, so new ones don't clash
+    ids: HashSet<String>,
+    /// Opening tag declaring the namespaces new elements are parsed in
+    wrapper: String,
+    metadata_edits: Edits,
+    manifest_edits: Edits,
+}
+
+impl Package {
+    fn new(root: &XMLNode) -> Option<Self> {
+        let metadata = root.find("metadata")?;
+        let manifest = root.find("manifest")?;
+
+        let mut wrapper = format!(
+            r#"<metadata xmlns="{OPF}" xmlns:dc="{DC}" xmlns:opf="{OPF}""#
+        );
+        for (prefix, uri) in &metadata.borrow().namespace.0 {
+            if !matches!(prefix.as_str(), "" | "dc" | "opf" | "xml" | "xmlns")
+                && !uri.is_empty()
+            {
+                let _ = write!(wrapper, r#" xmlns:{prefix}="{}""#, escape(uri));
+            }
+        }
+        wrapper.push('>');
+
+        let mut ids = HashSet::new();
+        collect_ids(root, &mut ids);
+
+        Some(Self {
+            opf2: root
+                .get_attr("version")
+                .is_none_or(|v| v.trim().starts_with('2')),
+            unique_identifier: root.get_attr("unique-identifier"),
+            metadata: metadata.borrow().children.clone(),
+            manifest: manifest.borrow().children.clone(),
+            ids,
+            wrapper,
+            metadata_edits: Edits::default(),
+            manifest_edits: Edits::default(),
+        })
+    }
+
+    /// Returns the indexes of the metadata elements matching `pred`.
+    fn find<F: Fn(&XMLNode) -> bool>(&self, pred: F) -> Vec<usize> {
+        self.metadata
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| pred(&n.borrow()))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the indexes of the `meta` elements refining the metadata
+    /// element at `i`.
+    fn refines(&self, i: usize) -> Vec<usize> {
+        let Some(id) = self.metadata[i].borrow().get_attr("id") else {
+            return vec![];
+        };
+        self.find(|n| {
+            n.get_attr("refines")
+                .is_some_and(|r| r.strip_prefix('#').unwrap_or(&r) == id)
+        })
+    }
+
+    /// Returns the value of the `property` refining the metadata element at
+    /// `i`.
+    fn refinement(&self, i: usize, property: &str) -> Option<String> {
+        self.refines(i).into_iter().find_map(|r| {
+            let meta = self.metadata[r].borrow();
+            (meta.get_attr("property").as_deref() == Some(property))
+                .then(|| meta.text.as_deref().unwrap_or_default().trim().into())
+        })
+    }
+
+    /// Removes the metadata element at `i`, and everything refining it.
+    fn remove(&mut self, i: usize) {
+        if self.metadata_edits.removed.insert(i) {
+            for r in self.refines(i) {
+                self.remove(r);
+            }
+        }
+    }
+
+    fn insert(&mut self, i: usize, element: String) {
+        self.metadata_edits
+            .inserted
+            .entry(i)
+            .or_default()
+            .push(element);
+    }
+
+    /// Returns an id starting with `prefix` that isn't used yet.
+    fn new_id(&mut self, prefix: &str) -> String {
+        let mut n = 1;
+        loop {
+            let id = format!("{prefix}-{n}");
+            if self.ids.insert(id.clone()) {
+                return id;
+            }
+            n += 1;
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        let titles = self.find(|n| is_dc(n, "title"));
+        let main = titles
+            .iter()
+            .copied()
+            .find(|&i| {
+                self.refinement(i, "title-type").as_deref() == Some("main")
+            })
+            .or_else(|| titles.first().copied());
+        let Some(i) = main else {
+            let element = format!("<dc:title>{}</dc:title>", escape(title));
+            self.insert(self.metadata.len(), element);
+            return;
+        };
+
+        for r in self.refines(i) {
+            let property = self.metadata[r].borrow().get_attr("property");
+            if matches!(
+                property.as_deref(),
+                Some("file-as" | "alternate-script")
+            ) {
+                self.remove(r);
+            }
+        }
+        self.metadata_edits.removed.insert(i);
+        let element =
+            element(&self.metadata[i].borrow(), &[("file-as", None)], title);
+        self.insert(i, element);
+    }
+
+    fn set_creators(&mut self, creators: &[Person]) {
+        let old = self.find(|n| is_dc(n, "creator"));
+        let at = old.first().copied().unwrap_or(self.metadata.len());
+        for i in old {
+            self.remove(i);
+        }
+
+        for person in creators {
+            let mut element = String::new();
+            if self.opf2 {
+                element.push_str("<dc:creator");
+                if let Some(role) = person.roles.first() {
+                    let _ = write!(element, r#" opf:role="{}""#, escape(role));
+                }
+                if let Some(file_as) = &person.file_as {
+                    let _ = write!(
+                        element,
+                        r#" opf:file-as="{}""#,
+                        escape(file_as)
+                    );
+                }
+                let _ =
+                    write!(element, ">{}</dc:creator>", escape(&person.name));
+            } else {
+                let id = self.new_id("creator");
+                write_person(&mut element, "creator", &id, person);
+            }
+            self.insert(at, element);
+        }
+    }
+
+    fn set_series(&mut self, series: Option<&Collection>) {
+        let collections = self.find(|n| {
+            n.get_attr("property").as_deref() == Some("belongs-to-collection")
+                && n.get_attr("refines").is_none()
+        });
+        let kind = |i| self.refinement(i, "collection-type");
+        let typed: Vec<_> = collections
+            .iter()
+            .copied()
+            .filter(|&i| kind(i).as_deref() == Some("series"))
+            .collect();
+        // Like `Metadata::series`, an untyped collection is the series if
+        // there is no other
+        let mut old = if typed.is_empty() {
+            collections
+                .into_iter()
+                .filter(|&i| kind(i).is_none())
+                .take(1)
+                .collect()
+        } else {
+            typed
+        };
+        old.extend(self.find(|n| {
+            matches!(
+                n.get_attr("name").as_deref(),
+                Some("calibre:series" | "calibre:series_index")
+            )
+        }));
+        old.sort_unstable();
+        let at = old.first().copied().unwrap_or(self.metadata.len());
+        for i in old {
+            self.remove(i);
+        }
+
+        let Some(series) = series else {
+            return;
+        };
+        let mut element = String::new();
+        if self.opf2 {
+            let _ = writeln!(
+                element,
+                r#"<meta name="calibre:series" content="{}"/>"#,
+                escape(&series.name)
+            );
+            if let Some(position) = &series.position {
+                let _ = writeln!(
+                    element,
+                    r#"<meta name="calibre:series_index" content="{}"/>"#,
+                    escape(position)
+                );
+            }
+        } else {
+            let mut series = series.clone();
+            series.kind.get_or_insert_with(|| "series".into());
+            let id = self.new_id("collection");
+            write_collection(&mut element, &id, None, &series);
+        }
+        self.insert(at, element);
+    }
+
+    fn set_identifier(&mut self, identifier: &str) {
+        let unique = self.unique_identifier.clone();
+        let current = self
+            .find(|n| {
+                is_dc(n, "identifier")
+                    && unique.is_some()
+                    && n.get_attr("id") == unique
+            })
+            .first()
+            .copied();
+
+        if let Some(i) = current {
+            // The scheme and the refines describe the old identifier
+            self.remove(i);
+            let element = element(
+                &self.metadata[i].borrow(),
+                &[("scheme", None)],
+                identifier,
+            );
+            self.insert(i, element);
+        } else {
+            let id = unique.unwrap_or_else(|| self.new_id("uid"));
+            let element = format!(
+                r#"<dc:identifier id="{}">{}</dc:identifier>"#,
+                escape(&id),
+                escape(identifier)
+            );
+            self.insert(0, element);
+        }
+    }
+
+    fn add_identifier(&mut self, identifier: &str) {
+        let at = self
+            .find(|n| is_dc(n, "identifier"))
+            .last()
+            .map_or(self.metadata.len(), |i| i + 1);
+        let element =
+            format!("<dc:identifier>{}</dc:identifier>", escape(identifier));
+        self.insert(at, element);
+    }
+
+    /// Sets the EPUB 3 modification date to now.
+    fn set_modified(&mut self) {
+        if self.opf2 {
+            return;
+        }
+        let old = self.find(|n| {
+            n.get_attr("property").as_deref() == Some("dcterms:modified")
+                && n.get_attr("refines").is_none()
+        });
+        let at = old.first().copied().unwrap_or(self.metadata.len());
+        for i in old {
+            self.remove(i);
+        }
+        let element =
+            format!(r#"<meta property="dcterms:modified">{}</meta>"#, now());
+        self.insert(at, element);
+    }
+
+    fn set_media_type(&mut self, id: &str, media_type: &str) {
+        let item = self
+            .manifest
+            .iter()
+            .position(|n| n.borrow().get_attr("id").as_deref() == Some(id));
+        if let Some(i) = item {
+            let element = element(
+                &self.manifest[i].borrow(),
+                &[("media-type", Some(media_type))],
+                "",
+            );
+            self.manifest_edits.removed.insert(i);
+            self.manifest_edits
+                .inserted
+                .entry(i)
+                .or_default()
+                .push(element);
+        }
+    }
+
+    /// Adds a cover image at `href`, in place of the one the package
+    /// refers to.
+    fn add_cover(&mut self, href: &str, media_type: &str) {
+        let id = self.new_id("cover-image");
+        let properties = if self.opf2 {
+            ""
+        } else {
+            r#" properties="cover-image""#
+        };
+        let item = format!(
+            r#"<item id="{}" href="{}" media-type="{}"{properties}/>"#,
+            escape(&id),
+            escape(href),
+            escape(media_type)
+        );
+        self.manifest_edits
+            .inserted
+            .entry(self.manifest.len())
+            .or_default()
+            .push(item);
+
+        for i in self.find(|n| n.get_attr("name").as_deref() == Some("cover")) {
+            self.remove(i);
+        }
+        // For EPUB 2 readers, which don't know the cover-image property
+        let meta = format!(r#"<meta name="cover" content="{}"/>"#, escape(&id));
+        self.insert(self.metadata.len(), meta);
+    }
+
+    /// Writes the package document `opf` again with the changes.
+    fn rewrite(&self, opf: &[u8]) -> Result<Vec<u8>, XMLError> {
+        let content = xmlutils::decode(opf);
+        let reader = ParserConfig::new()
+            .ignore_comments(false)
+            .create_reader(&*content);
+        let mut out = vec![];
+        let mut writer = EmitterConfig::new()
+            .autopad_comments(false)
+            .create_writer(&mut out);
+
+        let mut depth = 0;
+        let mut edits: Option<&Edits> = None;
+        let mut index = 0;
+        // depth in an element being removed
+        let mut skipped = 0;
+        // whitespace between the children of the edited elements, dropped
+        // with the element after it
+        let mut pending = String::new();
+        let mut indent = String::from("\n    ");
+
+        for event in reader {
+            let event = event?;
+            if skipped > 0 {
+                match event {
+                    ReaderEvent::StartElement { .. } => skipped += 1,
+                    ReaderEvent::EndElement { .. } => skipped -= 1,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match &event {
+                ReaderEvent::StartDocument {
+                    version,
+                    standalone,
+                    ..
+                } => {
+                    // The content is UTF-8 once decoded
+                    writer.write(WriterEvent::StartDocument {
+                        version: *version,
+                        encoding: Some("UTF-8"),
+                        standalone: *standalone,
+                    })?;
+                    writer.write(WriterEvent::characters("\n"))?;
+                    continue;
+                }
+                ReaderEvent::Whitespace(space)
+                    if depth == 2 && edits.is_some() =>
+                {
+                    pending.push_str(space);
+                    continue;
+                }
+                ReaderEvent::StartElement { name, .. } if depth == 1 => {
+                    edits = match name.local_name.as_str() {
+                        "metadata" => Some(&self.metadata_edits),
+                        "manifest" => Some(&self.manifest_edits),
+                        _ => None,
+                    };
+                    index = 0;
+                }
+                ReaderEvent::StartElement { .. } if depth == 2 => {
+                    if let Some(edits) = edits {
+                        if !pending.is_empty() {
+                            indent.clone_from(&pending);
+                        }
+                        if let Some(elements) = edits.inserted.get(&index) {
+                            self.write_elements(
+                                &mut writer,
+                                &indent,
+                                elements,
+                            )?;
+                        }
+                        index += 1;
+                        if edits.removed.contains(&(index - 1)) {
+                            pending.clear();
+                            skipped = 1;
+                            continue;
+                        }
+                    }
+                }
+                ReaderEvent::EndElement { .. } if depth == 2 => {
+                    if let Some(edits) = edits.take() {
+                        for elements in edits.inserted.range(index..) {
+                            self.write_elements(
+                                &mut writer,
+                                &indent,
+                                elements.1,
+                            )?;
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if !pending.is_empty() {
+                writer.write(WriterEvent::characters(&pending))?;
+                pending.clear();
+            }
+            match event {
+                ReaderEvent::StartElement { .. } => depth += 1,
+                ReaderEvent::EndElement { .. } => depth -= 1,
+                _ => {}
+            }
+            if let Some(e) = event.as_writer_event() {
+                writer.write(e)?;
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Writes each of the elements in `elements` after `indent`. An entry
+    /// may hold several elements, such as a creator and its refinements.
+    fn write_elements<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        indent: &str,
+        elements: &[String],
+    ) -> Result<(), XMLError> {
+        for element in elements {
+            // Parsed whole, since text may span lines
+            let wrapped = format!("{}{element}</metadata>", self.wrapper);
+            let mut depth = 0;
+            for event in ParserConfig::new().create_reader(wrapped.as_bytes()) {
+                match event? {
+                    ReaderEvent::StartElement {
+                        name,
+                        attributes,
+                        mut namespace,
+                    } => {
+                        depth += 1;
+                        if depth == 1 {
+                            continue;
+                        }
+                        if depth == 2 {
+                            writer.write(WriterEvent::characters(indent))?;
+                        }
+                        // Only the prefixes this element uses, the package
+                        // declares the others
+                        namespace.0.retain(|prefix, _| {
+                            if prefix.is_empty() {
+                                name.prefix.is_none()
+                            } else {
+                                name.prefix.as_ref() == Some(prefix)
+                                    || attributes.iter().any(|a| {
+                                        a.name.prefix.as_ref() == Some(prefix)
+                                    })
+                            }
+                        });
+                        writer.write(WriterEvent::StartElement {
+                            name: name.borrow(),
+                            attributes: attributes
+                                .iter()
+                                .map(OwnedAttribute::borrow)
+                                .collect(),
+                            namespace: Cow::Owned(namespace),
+                        })?;
+                    }
+                    ReaderEvent::EndElement { .. } => {
+                        depth -= 1;
+                        if depth > 0 {
+                            writer.write(WriterEvent::end_element())?;
+                        }
+                    }
+                    event => {
+                        if depth > 1
+                            && let Some(e) = event.as_writer_event()
+                        {
+                            writer.write(e)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_dc(node: &XMLNode, name: &str) -> bool {
+    node.name.local_name == name && node.name.namespace.as_deref() == Some(DC)
+}
+
+fn collect_ids(node: &XMLNode, ids: &mut HashSet<String>) {
+    if let Some(id) = node.get_attr("id") {
+        ids.insert(id);
+    }
+    for c in &node.children {
+        collect_ids(&c.borrow(), ids);
+    }
+}
+
+/// Writes `node` again with `text` as its content, and with the attributes
+/// in `changed` set to a new value or removed.
+fn element(
+    node: &XMLNode,
+    changed: &[(&str, Option<&str>)],
+    text: &str,
+) -> String {
+    let name = node.name.borrow().to_repr();
+    let mut element = format!("<{name}");
+    for attr in &node.attrs {
+        let value = changed
+            .iter()
+            .find(|(n, _)| *n == attr.name.local_name)
+            .map_or(Some(attr.value.as_str()), |(_, v)| *v);
+        if let Some(value) = value {
+            let _ = write!(
+                element,
+                r#" {}="{}""#,
+                attr.name.borrow().to_repr(),
+                escape(value)
+            );
+        }
+    }
+    let _ = write!(element, ">{}</{name}>", escape(text));
+    element
+}
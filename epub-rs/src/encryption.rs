@@ -0,0 +1,161 @@
+//! Encrypted resources, as listed in `META-INF/encryption.xml`.
+//!
+//! Fonts are often obfuscated so they can't be copied out of the epub as is.
+//! Both the IDPF and the Adobe obfuscation algorithms are undone when the
+//! fonts are read with [`EpubDoc`](crate::doc::EpubDoc), with a key derived
+//! from the book's identifier. Other algorithms are real encryption, which
+//! can't be undone.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use sha1::{Digest, Sha1};
+
+use crate::xmlutils::{XMLError, XMLReader};
+
+/// The IDPF font obfuscation algorithm
+pub const IDPF: &str = "http://www.idpf.org/2008/embedding";
+/// The Adobe font obfuscation algorithm
+pub const ADOBE: &str = "http://ns.adobe.com/pdf/enc#RC";
+
+/// How many leading bytes the IDPF algorithm obfuscates
+const IDPF_LENGTH: usize = 1040;
+/// How many leading bytes the Adobe algorithm obfuscates
+const ADOBE_LENGTH: usize = 1024;
+
+/// How a resource is encrypted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Obfuscated with the IDPF algorithm, keyed by the SHA-1 of the unique
+    /// identifier
+    Idpf,
+    /// Obfuscated with the Adobe algorithm, keyed by the book's UUID
+    Adobe,
+    /// Encrypted with any other algorithm, by its URI
+    Other(String),
+}
+
+impl Algorithm {
+    fn from_uri(uri: &str) -> Self {
+        match uri {
+            IDPF => Self::Idpf,
+            ADOBE => Self::Adobe,
+            _ => Self::Other(uri.to_string()),
+        }
+    }
+
+    /// Returns the URI that identifies the algorithm.
+    #[must_use]
+    pub fn uri(&self) -> &str {
+        match self {
+            Self::Idpf => IDPF,
+            Self::Adobe => ADOBE,
+            Self::Other(uri) => uri,
+        }
+    }
+}
+
+/// Reads the encrypted resources in the content of `encryption.xml`, by
+/// their full path in the archive.
+pub(crate) fn parse(
+    content: &[u8],
+) -> Result<HashMap<PathBuf, Algorithm>, XMLError> {
+    let root = XMLReader::parse(content)?;
+    let mut encrypted = HashMap::new();
+
+    for r in &root.borrow().children {
+        let data = r.borrow();
+        if data.name.local_name != "EncryptedData" {
+            continue;
+        }
+        let algorithm = data
+            .find("EncryptionMethod")
+            .and_then(|m| m.borrow().get_attr("Algorithm"));
+        let uri = data
+            .find("CipherReference")
+            .and_then(|c| c.borrow().get_attr("URI"));
+        if let (Some(algorithm), Some(uri)) = (algorithm, uri) {
+            let path = percent_encoding::percent_decode_str(&uri)
+                .decode_utf8_lossy()
+                .into_owned();
+            encrypted
+                .insert(PathBuf::from(path), Algorithm::from_uri(&algorithm));
+        }
+    }
+
+    Ok(encrypted)
+}
+
+/// Returns the identifier the key of `algorithm` is derived from, among the
+/// `unique` identifier and all the `identifiers` of the book.
+pub(crate) fn key_identifier<'a>(
+    algorithm: &Algorithm,
+    unique: Option<&'a str>,
+    identifiers: &'a [String],
+) -> Option<&'a str> {
+    match algorithm {
+        Algorithm::Idpf => unique,
+        // Adobe keys come from the UUID, which isn't always the unique
+        // identifier
+        Algorithm::Adobe => unique
+            .into_iter()
+            .chain(identifiers.iter().map(String::as_str))
+            .find(|id| id.trim().starts_with("urn:uuid:")),
+        Algorithm::Other(_) => None,
+    }
+}
+
+/// Undoes the obfuscation of `content` with `algorithm`, using the key
+/// derived from `identifier`, which is the unique identifier for the IDPF
+/// algorithm and a `urn:uuid:` identifier for the Adobe one.
+///
+/// Obfuscation is its own inverse, so this obfuscates plain content as well.
+///
+/// Returns [`None`] if the algorithm can't be undone or if the identifier
+/// can't be a key for it.
+pub(crate) fn deobfuscate(
+    content: &mut [u8],
+    algorithm: &Algorithm,
+    identifier: &str,
+) -> Option<()> {
+    match algorithm {
+        Algorithm::Idpf => {
+            // Whitespace isn't part of the key
+            let identifier: String = identifier
+                .chars()
+                .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+                .collect();
+            let key = Sha1::digest(identifier.as_bytes());
+            xor(content, &key, IDPF_LENGTH);
+        }
+        Algorithm::Adobe => {
+            let key = uuid_bytes(identifier)?;
+            xor(content, &key, ADOBE_LENGTH);
+        }
+        Algorithm::Other(_) => return None,
+    }
+    Some(())
+}
+
+/// XORs the first `length` bytes of `content` with `key`, repeated.
+fn xor(content: &mut [u8], key: &[u8], length: usize) {
+    for (byte, k) in content.iter_mut().take(length).zip(key.iter().cycle()) {
+        *byte ^= k;
+    }
+}
+
+/// Returns the 16 bytes of a `urn:uuid:` identifier, or of a bare UUID.
+fn uuid_bytes(identifier: &str) -> Option<[u8; 16]> {
+    let identifier = identifier.trim();
+    let uuid = identifier.strip_prefix("urn:uuid:").unwrap_or(identifier);
+    let hex: Vec<u8> = uuid.bytes().filter(|&b| b != b'-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks_exact(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(bytes)
+}
@@ -36,6 +36,17 @@
 //! assert_eq!(title.unwrap(), "Todo es mío");
 //! ```
 //!
+//! The same metadata is also available with its structure kept, see
+//! [`metadata::Metadata`]
+//!
+//! ```
+//! # use epub::doc::EpubDoc;
+//! # let doc = EpubDoc::new("test.epub");
+//! # let doc = doc.unwrap();
+//! let author = &doc.book_metadata.authors()[0];
+//! assert_eq!(author.file_as.as_deref(), Some("Garcia, Daniel"));
+//! ```
+//!
 //! ## Accessing resources
 //!
 //! In the resources var is stored each resource defined
@@ -114,4 +125,11 @@
 mod xmlutils;
 
 pub mod archive;
+pub mod builder;
+pub mod cfi;
 pub mod doc;
+pub mod editor;
+pub mod encryption;
+pub mod metadata;
+pub mod search;
+pub mod validate;
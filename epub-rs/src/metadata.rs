@@ -0,0 +1,414 @@
+//! Structured package metadata.
+//!
+//! [`EpubDoc::metadata`](crate::doc::EpubDoc::metadata) keeps every element
+//! as a plain string, which loses how they relate to each other. This module
+//! reads the same elements into typed values, applying the EPUB 3 `meta`
+//! elements that refine them, and the OPF 2 attributes that did the same job
+//! before.
+//!
+//! See [`EpubDoc::book_metadata`](crate::doc::EpubDoc::book_metadata).
+
+use std::collections::{HashMap, HashSet};
+
+use crate::xmlutils::XMLNode;
+
+/// A title of the publication
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Title {
+    pub value: String,
+    /// The `title-type`, such as `main`, `subtitle` or `collection`
+    pub kind: Option<String>,
+    /// The title as it should be sorted
+    pub file_as: Option<String>,
+    /// The language of the title
+    pub language: Option<String>,
+    /// The position in which to show the title, among titles that have one
+    pub display_seq: Option<u32>,
+    /// The title written in other scripts
+    pub alternate_scripts: Vec<AlternateScript>,
+}
+
+/// A creator or contributor
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Person {
+    pub name: String,
+    /// The roles, usually MARC relator codes such as `aut` or `ill`
+    pub roles: Vec<String>,
+    /// The name as it should be sorted, such as `Kafka, Franz`
+    pub file_as: Option<String>,
+    /// The position in which to show the name, among names that have one
+    pub display_seq: Option<u32>,
+    /// The name written in other scripts
+    pub alternate_scripts: Vec<AlternateScript>,
+}
+
+/// A value written in another script, with the language it is written in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AlternateScript {
+    pub value: String,
+    pub language: Option<String>,
+}
+
+/// A collection the publication belongs to, such as a series
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Collection {
+    pub name: String,
+    /// The `collection-type`, such as `series` or `set`
+    pub kind: Option<String>,
+    /// The position of the publication in the collection, which isn't
+    /// always a whole number
+    pub position: Option<String>,
+    /// The name as it should be sorted
+    pub file_as: Option<String>,
+    /// An identifier of the collection itself
+    pub identifier: Option<String>,
+    /// The collections this collection is part of in turn
+    pub collections: Vec<Self>,
+}
+
+/// A subject of the publication
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Subject {
+    pub value: String,
+    /// The scheme the subject comes from, such as `BISAC`
+    pub authority: Option<String>,
+    /// The code of the subject in its scheme
+    pub term: Option<String>,
+}
+
+/// A date of the publication
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Date {
+    pub value: String,
+    /// What happened on the date, such as `publication`, from the OPF 2
+    /// `opf:event` attribute
+    pub event: Option<String>,
+}
+
+/// The metadata of a publication
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub titles: Vec<Title>,
+    /// The people primarily responsible for the publication, in display
+    /// order
+    pub creators: Vec<Person>,
+    /// The people that contributed to the publication, in display order
+    pub contributors: Vec<Person>,
+    pub collections: Vec<Collection>,
+    pub subjects: Vec<Subject>,
+    pub description: Option<String>,
+    pub publisher: Option<String>,
+    pub dates: Vec<Date>,
+    /// When the publication was last modified, from `dcterms:modified`
+    pub modified: Option<String>,
+    pub rights: Option<String>,
+    pub languages: Vec<String>,
+}
+
+/// A `meta` element that refines another element
+#[derive(Debug)]
+struct Refinement {
+    id: Option<String>,
+    property: String,
+    value: String,
+    language: Option<String>,
+}
+
+/// The refinements in a metadata element, by the id of what they refine.
+struct Refinements(HashMap<String, Vec<Refinement>>);
+
+impl Refinements {
+    fn new(metadata: &XMLNode) -> Self {
+        let mut map: HashMap<String, Vec<Refinement>> = HashMap::new();
+        for r in &metadata.children {
+            let item = r.borrow();
+            if item.name.local_name != "meta" {
+                continue;
+            }
+            let (Some(refines), Some(property)) =
+                (item.get_attr("refines"), item.get_attr("property"))
+            else {
+                continue;
+            };
+            let Some(value) = text(&item) else {
+                continue;
+            };
+            let refines = refines.strip_prefix('#').unwrap_or(&refines);
+            map.entry(refines.to_string())
+                .or_default()
+                .push(Refinement {
+                    id: item.get_attr("id"),
+                    property,
+                    value,
+                    language: item.get_attr("lang"),
+                });
+        }
+        Self(map)
+    }
+
+    /// The refinements of the element with `id`.
+    fn of(&self, id: Option<&str>) -> &[Refinement] {
+        id.and_then(|id| self.0.get(id)).map_or(&[], Vec::as_slice)
+    }
+
+    /// The value of the first refinement of `id` with `property`.
+    fn first(&self, id: Option<&str>, property: &str) -> Option<String> {
+        self.of(id)
+            .iter()
+            .find(|r| r.property == property)
+            .map(|r| r.value.clone())
+    }
+
+    fn alternate_scripts(&self, id: Option<&str>) -> Vec<AlternateScript> {
+        self.of(id)
+            .iter()
+            .filter(|r| r.property == "alternate-script")
+            .map(|r| AlternateScript {
+                value: r.value.clone(),
+                language: r.language.clone(),
+            })
+            .collect()
+    }
+
+    fn display_seq(&self, id: Option<&str>) -> Option<u32> {
+        self.first(id, "display-seq")
+            .and_then(|seq| seq.trim().parse().ok())
+    }
+
+    /// The title `value` of the `dc:title` element `item`.
+    fn title(&self, item: &XMLNode, value: String) -> Title {
+        let id = item.get_attr("id");
+        let id = id.as_deref();
+        Title {
+            value,
+            kind: self.first(id, "title-type"),
+            file_as: self
+                .first(id, "file-as")
+                .or_else(|| item.get_attr("file-as")),
+            language: item.get_attr("lang"),
+            display_seq: self.display_seq(id),
+            alternate_scripts: self.alternate_scripts(id),
+        }
+    }
+
+    /// The person called `name` of the `dc:creator` or `dc:contributor`
+    /// element `item`. Refinements take precedence over OPF 2 attributes.
+    fn person(&self, item: &XMLNode, name: String) -> Person {
+        let id = item.get_attr("id");
+        let id = id.as_deref();
+        let mut roles: Vec<String> = self
+            .of(id)
+            .iter()
+            .filter(|r| r.property == "role")
+            .map(|r| r.value.clone())
+            .collect();
+        if roles.is_empty() {
+            roles.extend(item.get_attr("role"));
+        }
+        Person {
+            name,
+            roles,
+            file_as: self
+                .first(id, "file-as")
+                .or_else(|| item.get_attr("file-as")),
+            display_seq: self.display_seq(id),
+            alternate_scripts: self.alternate_scripts(id),
+        }
+    }
+
+    /// The collection called `name` whose `meta` element has `id`,
+    /// including the collections refining it.
+    fn collection(&self, name: String, id: Option<&str>) -> Collection {
+        self.collection_once(name, id, &mut HashSet::new())
+    }
+
+    /// Like [`collection`](Self::collection), without reading the
+    /// refinements of the collections in `seen` again, so that collections
+    /// refining each other in a cycle don't recurse forever.
+    fn collection_once(
+        &self,
+        name: String,
+        id: Option<&str>,
+        seen: &mut HashSet<String>,
+    ) -> Collection {
+        let mut collections = vec![];
+        if let Some(id) = id
+            && seen.insert(id.to_string())
+        {
+            for r in self.of(Some(id)) {
+                if r.property == "belongs-to-collection" {
+                    collections.push(self.collection_once(
+                        r.value.clone(),
+                        r.id.as_deref(),
+                        seen,
+                    ));
+                }
+            }
+        }
+        Collection {
+            name,
+            kind: self.first(id, "collection-type"),
+            position: self.first(id, "group-position"),
+            file_as: self.first(id, "file-as"),
+            identifier: self.first(id, "dcterms:identifier"),
+            collections,
+        }
+    }
+}
+
+impl Metadata {
+    /// Reads the children of the package's `metadata` element.
+    pub(crate) fn from_node(metadata: &XMLNode) -> Self {
+        let refinements = Refinements::new(metadata);
+        let mut data = Self::default();
+        // Calibre's series, from OPF 2 `meta` elements
+        let mut series = None;
+        let mut series_index = None;
+
+        for r in &metadata.children {
+            let item = r.borrow();
+            let id = item.get_attr("id");
+            let id = id.as_deref();
+            if item.name.local_name == "meta" {
+                if let (Some(name), Some(content)) =
+                    (item.get_attr("name"), item.get_attr("content"))
+                {
+                    match name.as_str() {
+                        "calibre:series" => series = Some(content),
+                        "calibre:series_index" => series_index = Some(content),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if item.get_attr("refines").is_some() {
+                    continue;
+                }
+                let (Some(property), Some(value)) =
+                    (item.get_attr("property"), text(&item))
+                else {
+                    continue;
+                };
+                match property.as_str() {
+                    "belongs-to-collection" => {
+                        data.collections
+                            .push(refinements.collection(value, id));
+                    }
+                    "dcterms:modified" => data.modified = Some(value),
+                    _ => {}
+                }
+                continue;
+            }
+
+            let Some(value) = text(&item) else {
+                continue;
+            };
+            match item.name.local_name.as_str() {
+                "title" => data.titles.push(refinements.title(&item, value)),
+                "creator" => {
+                    data.creators.push(refinements.person(&item, value));
+                }
+                "contributor" => {
+                    data.contributors.push(refinements.person(&item, value));
+                }
+                "subject" => data.subjects.push(Subject {
+                    value,
+                    authority: refinements.first(id, "authority"),
+                    term: refinements.first(id, "term"),
+                }),
+                "description" => {
+                    data.description.get_or_insert(value);
+                }
+                "publisher" => {
+                    data.publisher.get_or_insert(value);
+                }
+                "date" => data.dates.push(Date {
+                    value,
+                    event: item.get_attr("event"),
+                }),
+                "rights" => {
+                    data.rights.get_or_insert(value);
+                }
+                "language" => data.languages.push(value),
+                _ => {}
+            }
+        }
+
+        if data.series().is_none()
+            && let Some(name) = series
+        {
+            data.collections.push(Collection {
+                name,
+                kind: Some("series".to_string()),
+                position: series_index,
+                ..Collection::default()
+            });
+        }
+        // Stable, so names without a display-seq keep their order at the end
+        data.titles
+            .sort_by_key(|t| t.display_seq.unwrap_or(u32::MAX));
+        data.creators
+            .sort_by_key(|p| p.display_seq.unwrap_or(u32::MAX));
+        data.contributors
+            .sort_by_key(|p| p.display_seq.unwrap_or(u32::MAX));
+        data
+    }
+
+    /// Returns the main title: the first with the `main` title-type, or else
+    /// the first title.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use epub::doc::EpubDoc;
+    /// # let doc = EpubDoc::new("test.epub").unwrap();
+    /// assert_eq!(doc.book_metadata.title(), Some("Todo es mío"));
+    /// ```
+    #[must_use]
+    pub fn title(&self) -> Option<&str> {
+        self.titles
+            .iter()
+            .find(|t| t.kind.as_deref() == Some("main"))
+            .or_else(|| self.titles.first())
+            .map(|t| t.value.as_str())
+    }
+
+    /// Returns the creators that have the `aut` role, or all of them if
+    /// none has a role.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use epub::doc::EpubDoc;
+    /// # let doc = EpubDoc::new("test.epub").unwrap();
+    /// let authors = doc.book_metadata.authors();
+    /// assert_eq!(authors[0].name, "Daniel Garcia");
+    /// assert_eq!(authors[0].file_as.as_deref(), Some("Garcia, Daniel"));
+    /// ```
+    #[must_use]
+    pub fn authors(&self) -> Vec<&Person> {
+        if self.creators.iter().all(|p| p.roles.is_empty()) {
+            return self.creators.iter().collect();
+        }
+        self.creators
+            .iter()
+            .filter(|p| p.roles.iter().any(|r| r == "aut"))
+            .collect()
+    }
+
+    /// Returns the series the publication belongs to: the first collection
+    /// with the `series` collection-type, or else the first collection
+    /// without a type.
+    #[must_use]
+    pub fn series(&self) -> Option<&Collection> {
+        self.collections
+            .iter()
+            .find(|c| c.kind.as_deref() == Some("series"))
+            .or_else(|| self.collections.iter().find(|c| c.kind.is_none()))
+    }
+}
+
+/// The trimmed text of `node`, if there is any.
+fn text(node: &XMLNode) -> Option<String> {
+    let text = node.text.as_deref()?.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
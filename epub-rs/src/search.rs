@@ -0,0 +1,191 @@
+//! Full-text search through the epub spine.
+//!
+//! See [`EpubDoc::search`](crate::doc::EpubDoc::search).
+
+use xml::reader::ParserConfig;
+use xml::reader::XmlEvent as ReaderEvent;
+
+use crate::xmlutils::XMLError;
+
+/// How a search query is interpreted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchMode {
+    /// The query is matched as literal text, ignoring case
+    Text,
+    /// The query is a regular expression, as understood by the `regex` crate
+    Regex,
+}
+
+/// A single search hit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// The spine index of the chapter containing the match
+    pub chapter: usize,
+    /// The offset of the match, in characters, from the start of the chapter
+    /// text
+    pub offset: usize,
+    /// The id of the closest element at or before the match, if any
+    pub anchor: Option<String>,
+    /// The text that matched
+    pub matched: String,
+    /// The match with some of its surrounding text
+    pub snippet: String,
+}
+
+/// The readable text of a chapter.
+pub(crate) struct ChapterText {
+    /// The body text, with whitespace between block elements.
+    pub text: String,
+    /// Element ids, with the byte offset in `text` where each element starts.
+    pub anchors: Vec<(usize, String)>,
+}
+
+impl ChapterText {
+    /// Extracts the body text of an XHTML document.
+    pub fn parse(content: &[u8]) -> Result<Self, XMLError> {
+        let reader = ParserConfig::new()
+            .add_entity("nbsp", " ")
+            .add_entity("copy", "©")
+            .add_entity("reg", "®")
+            .replace_unknown_entity_references(true)
+            .create_reader(content);
+
+        let mut text = String::new();
+        let mut anchors = Vec::new();
+        let mut in_body = false;
+        // Depth of elements whose content isn't readable text
+        let mut skip_depth = 0usize;
+
+        for e in reader {
+            match e? {
+                ReaderEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let tag = name.local_name.to_lowercase();
+                    if tag == "body" {
+                        in_body = true;
+                    } else if matches!(tag.as_str(), "script" | "style") {
+                        skip_depth += 1;
+                    }
+                    if is_block(&tag) {
+                        push_break(&mut text);
+                    }
+                    if let Some(id) = attributes
+                        .iter()
+                        .find(|a| a.name.local_name == "id")
+                        .filter(|_| in_body)
+                    {
+                        anchors.push((text.len(), id.value.clone()));
+                    }
+                }
+                ReaderEvent::EndElement { name } => {
+                    let tag = name.local_name.to_lowercase();
+                    if tag == "body" {
+                        in_body = false;
+                    } else if matches!(tag.as_str(), "script" | "style") {
+                        skip_depth = skip_depth.saturating_sub(1);
+                    }
+                    if is_block(&tag) {
+                        push_break(&mut text);
+                    }
+                }
+                ReaderEvent::Characters(s) | ReaderEvent::CData(s)
+                    if in_body && skip_depth == 0 =>
+                {
+                    text.push_str(&s);
+                }
+                ReaderEvent::Whitespace(_)
+                    if in_body
+                        && skip_depth == 0
+                        && !text.is_empty()
+                        && !text.ends_with(char::is_whitespace) =>
+                {
+                    text.push(' ');
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { text, anchors })
+    }
+
+    /// The id of the last element starting at or before `offset`.
+    pub fn anchor_at(&self, offset: usize) -> Option<&str> {
+        self.anchors
+            .iter()
+            .take_while(|(start, _)| *start <= offset)
+            .last()
+            .map(|(_, id)| id.as_str())
+    }
+
+    /// The text around `start..end`, with its whitespace collapsed.
+    pub fn snippet(&self, start: usize, end: usize) -> String {
+        const CONTEXT: usize = 40;
+
+        let before = self.text[..start]
+            .char_indices()
+            .rev()
+            .nth(CONTEXT - 1)
+            .map_or(0, |(i, _)| i);
+        let after = self.text[end..]
+            .char_indices()
+            .nth(CONTEXT)
+            .map_or(self.text.len(), |(i, _)| end + i);
+
+        let mut snippet = self.text[before..after]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if before > 0 {
+            snippet.insert(0, '…');
+        }
+        if after < self.text.len() {
+            snippet.push('…');
+        }
+        snippet
+    }
+}
+
+/// Whether `tag` is an element that separates the text around it.
+fn is_block(tag: &str) -> bool {
+    matches!(
+        tag,
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "br"
+            | "dd"
+            | "div"
+            | "dl"
+            | "dt"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "header"
+            | "hr"
+            | "li"
+            | "ol"
+            | "p"
+            | "pre"
+            | "section"
+            | "table"
+            | "td"
+            | "th"
+            | "tr"
+            | "ul"
+    )
+}
+
+fn push_break(text: &mut String) {
+    text.truncate(text.trim_end_matches(' ').len());
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
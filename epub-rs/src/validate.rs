@@ -0,0 +1,511 @@
+//! Checks epub files for structural problems.
+//!
+//! [`EpubDoc`] stops at the first problem it can't get past and skips the
+//! ones it can. A [`Report`] collects all of them instead, so a broken book
+//! can be fixed.
+//!
+//! # Examples
+//!
+//! ```
+//! use epub::validate::{Problem, Report, Severity};
+//!
+//! let report = Report::new("test.epub").unwrap();
+//! assert!(!report.is_valid());
+//! assert!(report.problems.contains(&Problem::DuplicateId("000.xhtml".into())));
+//! assert_eq!(Severity::Error, report.problems[0].severity());
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+
+use zip::CompressionMethod;
+
+use crate::archive::EpubArchive;
+use crate::doc::{EpubDoc, NavPoint};
+use crate::encryption::{self, Algorithm};
+use crate::xmlutils::{XMLNode, XMLReader};
+
+const MIMETYPE: &str = "application/epub+zip";
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+
+/// How bad a [`Problem`] is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Readers usually cope with it
+    Warning,
+    /// The book breaks the EPUB specification, and parts of it may not be
+    /// readable
+    Error,
+}
+
+/// A problem found in an epub
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Problem {
+    #[error("Not a zip archive: {0}")]
+    Archive(String),
+    #[error("There is no mimetype file")]
+    MissingMimetype,
+    #[error("The mimetype file isn't the first in the archive")]
+    MimetypeNotFirst,
+    #[error("The mimetype file is compressed")]
+    CompressedMimetype,
+    #[error("The mimetype is \"{0}\" instead of \"{MIMETYPE}\"")]
+    WrongMimetype(String),
+    #[error("There is no META-INF/container.xml")]
+    MissingContainer,
+    #[error("The container doesn't point to a package document")]
+    MissingRootFile,
+    #[error("The package document {} is missing", .0.display())]
+    MissingPackage(PathBuf),
+    /// A file that isn't XHTML can't be parsed, with the parser's error
+    #[error("Unparsable XML in {}: {1}", .0.display())]
+    InvalidXml(PathBuf, String),
+    #[error("The package document has no {0} element")]
+    MissingElement(&'static str),
+    /// An element, by name, is missing a required attribute
+    #[error("A <{0}> element has no {1} attribute")]
+    MissingAttribute(&'static str, &'static str),
+    #[error("The id \"{0}\" is used more than once")]
+    DuplicateId(String),
+    /// The manifest item with the id refers to a file that isn't in the
+    /// archive
+    #[error("The manifest item \"{0}\" refers to a missing file: {}", .1.display())]
+    MissingResource(String, PathBuf),
+    #[error("The spine refers to \"{0}\", which isn't in the manifest")]
+    DanglingSpineItem(String),
+    #[error("The spine is empty")]
+    EmptySpine,
+    /// Required metadata, by property name
+    #[error("There is no {0} metadata")]
+    MissingMetadata(&'static str),
+    /// No identifier has the id the package says is the unique one
+    #[error("No identifier has the unique identifier's id \"{0}\"")]
+    MissingUniqueIdentifier(String),
+    #[error("Unparsable XHTML in {}: {1}", .0.display())]
+    InvalidXhtml(PathBuf, String),
+    #[error("There is neither a navigation document nor a toc.ncx")]
+    MissingToc,
+    /// A navigation entry, by label, points to a file that isn't in the
+    /// archive
+    #[error("The navigation entry \"{0}\" points to a missing file: {}", .1.display())]
+    BrokenLink(String, PathBuf),
+    /// The book can't be opened, for a reason the other checks missed
+    #[error("The book can't be opened: {0}")]
+    Unreadable(String),
+}
+
+impl Problem {
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::MissingMetadata("dcterms:modified") | Self::MissingToc => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// Every problem found in an epub, see the [module documentation](self)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The problems, in the order they were found
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Checks the epub file in `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be opened. Problems with its
+    /// content are in the report.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+        let file = File::open(path)?;
+        Ok(Self::from_reader(BufReader::new(file)))
+    }
+
+    /// Checks the epub contained in `reader`.
+    pub fn from_reader<R: Read + Seek>(reader: R) -> Self {
+        let mut report = Self::default();
+        match EpubArchive::from_reader(reader) {
+            Ok(archive) => report.check(archive),
+            Err(e) => report.problems.push(Problem::Archive(e.to_string())),
+        }
+        report
+    }
+
+    /// Whether there are no errors, only warnings if anything.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Problem> {
+        self.problems
+            .iter()
+            .filter(|p| p.severity() == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Problem> {
+        self.problems
+            .iter()
+            .filter(|p| p.severity() == Severity::Warning)
+    }
+
+    fn push(&mut self, problem: Problem) {
+        self.problems.push(problem);
+    }
+
+    fn check<R: Read + Seek>(&mut self, mut archive: EpubArchive<R>) {
+        self.check_mimetype(&mut archive);
+
+        let Some(root_file) = self.check_container(&mut archive) else {
+            return;
+        };
+        if archive.entry_name(&root_file).is_none() {
+            self.push(Problem::MissingPackage(root_file));
+            return;
+        }
+        let Some(root) =
+            self.parse(&mut archive, &root_file, Problem::InvalidXml)
+        else {
+            return;
+        };
+        let base = root_file.parent().unwrap_or_else(|| Path::new(""));
+        self.check_package(&mut archive, &root.borrow(), base);
+
+        match EpubDoc::from_archive(archive) {
+            Ok(doc) => self.check_navigation(&doc),
+            // Only if no problem explains it already
+            Err(e) if self.is_valid() => {
+                self.push(Problem::Unreadable(e.to_string()));
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn check_mimetype<R: Read + Seek>(&mut self, archive: &mut EpubArchive<R>) {
+        if archive.entry_name("mimetype").is_none() {
+            self.push(Problem::MissingMimetype);
+            return;
+        }
+        match archive.entry_at(0) {
+            Ok((name, _)) if name != "mimetype" => {
+                self.push(Problem::MimetypeNotFirst);
+            }
+            Ok((_, compression))
+                if compression != CompressionMethod::Stored =>
+            {
+                self.push(Problem::CompressedMimetype);
+            }
+            _ => {}
+        }
+        let content = archive.get_entry_as_str("mimetype").unwrap_or_default();
+        if content != MIMETYPE {
+            self.push(Problem::WrongMimetype(content));
+        }
+    }
+
+    /// Returns the path of the package document.
+    fn check_container<R: Read + Seek>(
+        &mut self,
+        archive: &mut EpubArchive<R>,
+    ) -> Option<PathBuf> {
+        let path = Path::new("META-INF/container.xml");
+        if archive.entry_name(path).is_none() {
+            self.push(Problem::MissingContainer);
+            return None;
+        }
+        let root = self.parse(archive, path, Problem::InvalidXml)?;
+        let root_file = root
+            .borrow()
+            .find("rootfile")
+            .and_then(|r| r.borrow().get_attr("full-path"));
+        if root_file.is_none() {
+            self.push(Problem::MissingRootFile);
+        }
+        root_file.map(PathBuf::from)
+    }
+
+    /// Parses the XML file at `path`, which must exist, or reports it as
+    /// `invalid`.
+    fn parse<R: Read + Seek>(
+        &mut self,
+        archive: &mut EpubArchive<R>,
+        path: &Path,
+        invalid: fn(PathBuf, String) -> Problem,
+    ) -> Option<RefCell<XMLNode>> {
+        let parsed = archive
+            .get_entry(path)
+            .map_err(|e| e.to_string())
+            .and_then(|c| XMLReader::parse(&c).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(root) => Some(root),
+            Err(e) => {
+                self.push(invalid(path.to_path_buf(), e));
+                None
+            }
+        }
+    }
+
+    fn check_package<R: Read + Seek>(
+        &mut self,
+        archive: &mut EpubArchive<R>,
+        root: &XMLNode,
+        base: &Path,
+    ) {
+        let mut ids = HashSet::new();
+        let mut duplicates = vec![];
+        find_duplicate_ids(root, &mut ids, &mut duplicates);
+        for id in duplicates {
+            self.push(Problem::DuplicateId(id));
+        }
+
+        let epub3 = root
+            .get_attr("version")
+            .is_some_and(|v| v.trim().starts_with('3'));
+        match root.find("metadata") {
+            Some(metadata) => {
+                let unique = root.get_attr("unique-identifier");
+                self.check_metadata(&metadata.borrow(), unique, epub3);
+            }
+            None => self.push(Problem::MissingElement("metadata")),
+        }
+
+        let Some(manifest) = root.find("manifest") else {
+            self.push(Problem::MissingElement("manifest"));
+            return;
+        };
+        let resources = self.check_manifest(archive, &manifest.borrow(), base);
+
+        let Some(spine) = root.find("spine") else {
+            self.push(Problem::MissingElement("spine"));
+            return;
+        };
+        self.check_spine(&spine.borrow(), &resources);
+
+        let nav = resources.values().any(|(_, _, properties)| {
+            properties.split_whitespace().any(|p| p == "nav")
+        });
+        let ncx = spine
+            .borrow()
+            .get_attr("toc")
+            .is_some_and(|id| resources.contains_key(&id));
+        if !nav && !ncx {
+            self.push(Problem::MissingToc);
+        }
+    }
+
+    fn check_metadata(
+        &mut self,
+        metadata: &XMLNode,
+        unique: Option<String>,
+        epub3: bool,
+    ) {
+        let dc = |name: &str| {
+            metadata.children.iter().any(|c| {
+                let c = c.borrow();
+                c.name.local_name == name
+                    && c.name.namespace.as_deref() == Some(DC)
+                    && c.text.as_deref().is_some_and(|t| !t.trim().is_empty())
+            })
+        };
+        for name in ["identifier", "title", "language"] {
+            if !dc(name) {
+                self.push(Problem::MissingMetadata(name));
+            }
+        }
+        if epub3
+            && metadata
+                .find_where(&|n| {
+                    n.get_attr("property").as_deref()
+                        == Some("dcterms:modified")
+                })
+                .is_none()
+        {
+            self.push(Problem::MissingMetadata("dcterms:modified"));
+        }
+
+        match unique {
+            None => {
+                self.push(Problem::MissingAttribute(
+                    "package",
+                    "unique-identifier",
+                ));
+            }
+            Some(id)
+                if metadata
+                    .find_where(&|n| {
+                        n.name.local_name == "identifier"
+                            && n.get_attr("id").as_ref() == Some(&id)
+                    })
+                    .is_none() =>
+            {
+                self.push(Problem::MissingUniqueIdentifier(id));
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Returns the path, media type and properties of each manifest item
+    /// by id.
+    fn check_manifest<R: Read + Seek>(
+        &mut self,
+        archive: &mut EpubArchive<R>,
+        manifest: &XMLNode,
+        base: &Path,
+    ) -> HashMap<String, (PathBuf, String, String)> {
+        // Encrypted content can't be parsed
+        let encrypted = archive
+            .get_entry("META-INF/encryption.xml")
+            .ok()
+            .and_then(|c| encryption::parse(&c).ok())
+            .unwrap_or_default();
+
+        let mut resources = HashMap::new();
+        for item in &manifest.children {
+            let item = item.borrow();
+            if item.name.local_name != "item" {
+                continue;
+            }
+            let mut attr = |name| {
+                let value = item.get_attr(name);
+                if value.is_none() {
+                    self.push(Problem::MissingAttribute("item", name));
+                }
+                value
+            };
+            let (Some(id), Some(href), Some(media_type)) =
+                (attr("id"), attr("href"), attr("media-type"))
+            else {
+                continue;
+            };
+            let properties = item.get_attr("properties").unwrap_or_default();
+
+            let path = base.join(&href);
+            // Remote resources are allowed for audio, video and fonts
+            if !href.contains("://") {
+                self.check_resource(
+                    archive,
+                    &id,
+                    &path,
+                    &media_type,
+                    &encrypted,
+                );
+            }
+            resources.insert(id, (path, media_type, properties));
+        }
+        resources
+    }
+
+    fn check_resource<R: Read + Seek>(
+        &mut self,
+        archive: &mut EpubArchive<R>,
+        id: &str,
+        path: &Path,
+        media_type: &str,
+        encrypted: &HashMap<PathBuf, Algorithm>,
+    ) {
+        let Some(name) = archive.entry_name(path).map(PathBuf::from) else {
+            self.push(Problem::MissingResource(id.into(), path.into()));
+            return;
+        };
+        if encrypted.contains_key(&name) {
+            return;
+        }
+        match media_type {
+            "application/xhtml+xml" => {
+                self.parse(archive, &name, Problem::InvalidXhtml);
+            }
+            "application/x-dtbncx+xml" => {
+                self.parse(archive, &name, Problem::InvalidXml);
+            }
+            _ => {}
+        }
+    }
+
+    fn check_spine<V>(
+        &mut self,
+        spine: &XMLNode,
+        resources: &HashMap<String, V>,
+    ) {
+        let mut items = 0;
+        for item in &spine.children {
+            let item = item.borrow();
+            if item.name.local_name != "itemref" {
+                continue;
+            }
+            items += 1;
+            match item.get_attr("idref") {
+                Some(idref) if !resources.contains_key(&idref) => {
+                    self.push(Problem::DanglingSpineItem(idref));
+                }
+                Some(_) => {}
+                None => {
+                    self.push(Problem::MissingAttribute("itemref", "idref"));
+                }
+            }
+        }
+        if items == 0 {
+            self.push(Problem::EmptySpine);
+        }
+    }
+
+    fn check_navigation<R: Read + Seek>(&mut self, doc: &EpubDoc<R>) {
+        let mut entries = vec![];
+        flatten(&doc.toc, &mut entries);
+        entries.extend(
+            doc.landmarks
+                .iter()
+                .chain(&doc.guide)
+                .chain(&doc.page_list)
+                .map(|t| (t.label.as_str(), t.content.as_path())),
+        );
+
+        let mut broken = HashSet::new();
+        for (label, content) in entries {
+            let content = content.to_string_lossy();
+            let path = content.split_once('#').map_or(&*content, |(p, _)| p);
+            if path.contains("://") {
+                continue;
+            }
+            if doc.archive.entry_name(path).is_none()
+                && broken.insert((label, path.to_string()))
+            {
+                self.push(Problem::BrokenLink(
+                    label.to_string(),
+                    PathBuf::from(path),
+                ));
+            }
+        }
+    }
+}
+
+/// Collects the ids used more than once in `node` and its descendants.
+fn find_duplicate_ids(
+    node: &XMLNode,
+    ids: &mut HashSet<String>,
+    duplicates: &mut Vec<String>,
+) {
+    if let Some(id) = node.get_attr("id")
+        && !ids.insert(id.clone())
+        && !duplicates.contains(&id)
+    {
+        duplicates.push(id);
+    }
+    for c in &node.children {
+        find_duplicate_ids(&c.borrow(), ids, duplicates);
+    }
+}
+
+/// Collects the label and content of every entry in `toc`, depth first.
+fn flatten<'a>(toc: &'a [NavPoint], entries: &mut Vec<(&'a str, &'a Path)>) {
+    for point in toc {
+        entries.push((&point.label, &point.content));
+        flatten(&point.children, entries);
+    }
+}
@@ -40,6 +40,32 @@ pub struct XMLReader<'a> {
     reader: EventReader<&'a [u8]>,
 }
 
+/// Converts XML content with a byte order mark to UTF-8 without one.
+pub fn decode(content: &[u8]) -> Cow<'_, [u8]> {
+    //If there is a UTF-8 BOM marker, ignore it
+    if content.starts_with(&[0xefu8, 0xbbu8, 0xbfu8]) {
+        Cow::Borrowed(&content[3..])
+    } else if content.starts_with(&[0xfeu8, 0xffu8])
+        || content.starts_with(&[0xffu8, 0xfeu8])
+    {
+        //handle utf-16
+        let (big_byte, small_byte) = if content[0] == 0xfeu8 {
+            (1, 0) //big endian utf-16
+        } else {
+            (0, 1) //little endian utf-16
+        };
+        let content_u16: Vec<u16> = content[2..]
+            .chunks_exact(2)
+            .map(|a| u16::from_ne_bytes([a[big_byte], a[small_byte]]))
+            .collect();
+        Cow::Owned(
+            String::from_utf16_lossy(content_u16.as_slice()).into_bytes(),
+        )
+    } else {
+        Cow::Borrowed(content)
+    }
+}
+
 impl<'a> XMLReader<'a> {
     pub fn parse(content: &[u8]) -> Result<RefCell<XMLNode>, XMLError> {
         // The operations below require at least 4 bytes to not panic
@@ -47,36 +73,13 @@ impl<'a> XMLReader<'a> {
             return Err(XMLError::NoContent);
         }
 
-        let content_str;
-        //If there is a UTF-8 BOM marker, ignore it
-        let content_slice = if content[0..3] == [0xefu8, 0xbbu8, 0xbfu8] {
-            &content[3..]
-        } else if content[0..2] == [0xfeu8, 0xffu8]
-            || content[0..2] == [0xffu8, 0xfeu8]
-        {
-            //handle utf-16
-            let (big_byte, small_byte) = if content[0] == 0xfeu8 {
-                (1, 0) //big endian utf-16
-            } else {
-                (0, 1) //little endian utf-16
-            };
-            let content_u16: Vec<u16> = content[2..]
-                .chunks_exact(2)
-                .into_iter()
-                .map(|a| u16::from_ne_bytes([a[big_byte], a[small_byte]]))
-                .collect();
-            content_str = String::from_utf16_lossy(content_u16.as_slice());
-            content_str.as_bytes()
-        } else {
-            content
-        };
-
+        let content = decode(content);
         let reader = XMLReader {
             reader: ParserConfig::new()
                 .add_entity("nbsp", " ")
                 .add_entity("copy", "©")
                 .add_entity("reg", "®")
-                .create_reader(content_slice),
+                .create_reader(&*content),
         };
 
         reader.parse_xml()
@@ -96,9 +99,10 @@ impl<'a> XMLReader<'a> {
                     let node = XMLNode {
                         name,
                         attrs: attributes,
-                        _namespace: namespace,
+                        namespace,
                         parent: None,
                         text: None,
+                        tail: None,
                         cdata: None,
                         children: vec![],
                     };
@@ -125,7 +129,29 @@ impl<'a> XMLReader<'a> {
                 Ok(ReaderEvent::Characters(text)) => {
                     let current = parents.last();
                     if let Some(c) = current {
-                        c.borrow_mut().text = Some(text);
+                        // Text after a child element is that child's tail, so
+                        // the text stays in document order
+                        let mut c = c.borrow_mut();
+                        if let Some(last) = c.children.last() {
+                            last.borrow_mut()
+                                .tail
+                                .get_or_insert_default()
+                                .push_str(&text);
+                        } else {
+                            c.text.get_or_insert_default().push_str(&text);
+                        }
+                    }
+                }
+                Ok(ReaderEvent::Whitespace(text)) => {
+                    // Whitespace only separates words between elements
+                    let last = parents
+                        .last()
+                        .and_then(|c| c.borrow().children.last().cloned());
+                    if let Some(last) = last {
+                        last.borrow_mut()
+                            .tail
+                            .get_or_insert_default()
+                            .push_str(&text);
                     }
                 }
                 Ok(ReaderEvent::CData(text)) => {
@@ -153,8 +179,12 @@ impl<'a> XMLReader<'a> {
 pub struct XMLNode {
     pub name: xml::name::OwnedName,
     pub attrs: Vec<xml::attribute::OwnedAttribute>,
-    pub _namespace: xml::namespace::Namespace,
+    pub namespace: xml::namespace::Namespace,
+    /// The text before the first child element
     pub text: Option<String>,
+    /// The text after this element, up to the next sibling element or the
+    /// end of the parent
+    pub tail: Option<String>,
     pub cdata: Option<String>,
     pub parent: Option<ParentNodeRef>,
     pub children: Vec<ChildNodeRef>,
@@ -180,6 +210,34 @@ impl XMLNode {
 
         None
     }
+
+    /// Depth-first search for the first descendant matching `pred`.
+    pub fn find_where<F>(&self, pred: &F) -> Option<ChildNodeRef>
+    where
+        F: Fn(&Self) -> bool, {
+        for r in &self.children {
+            let c = r.borrow();
+            if pred(&c) {
+                return Some(r.clone());
+            } else if let Some(n) = c.find_where(pred) {
+                return Some(n);
+            }
+        }
+
+        None
+    }
+
+    /// Returns the text of this node and all its descendants, in document
+    /// order.
+    pub fn text_content(&self) -> String {
+        let mut text = self.text.clone().unwrap_or_default();
+        for c in &self.children {
+            let c = c.borrow();
+            text.push_str(&c.text_content());
+            text.push_str(c.tail.as_deref().unwrap_or_default());
+        }
+        text
+    }
 }
 
 impl fmt::Display for XMLNode {
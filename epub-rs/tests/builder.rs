@@ -0,0 +1,215 @@
+use epub::builder::{BuilderError, EpubBuilder, TocEntry};
+use epub::doc::EpubDoc;
+use epub::metadata::{Collection, Metadata, Person, Subject, Title};
+use std::io::Cursor;
+use std::path::Path;
+
+const CHAPTER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+  <head><title>Chapter</title><link rel="stylesheet" href="../style.css"/></head>
+  <body><h1 id="top">Chapter</h1><p id="fixes">Fixed &amp; improved.</p></body>
+</html>
+"#;
+
+fn metadata() -> Metadata {
+    Metadata {
+        titles: vec![
+            Title {
+                value: "Release Notes & Changes".into(),
+                kind: Some("main".into()),
+                ..Title::default()
+            },
+            Title {
+                value: "Version 2".into(),
+                kind: Some("subtitle".into()),
+                ..Title::default()
+            },
+        ],
+        creators: vec![Person {
+            name: "Docs Team".into(),
+            roles: vec!["aut".into()],
+            file_as: Some("Team, Docs".into()),
+            ..Person::default()
+        }],
+        collections: vec![Collection {
+            name: "Release Notes".into(),
+            kind: Some("series".into()),
+            position: Some("2".into()),
+            collections: vec![Collection {
+                name: "Internal Docs".into(),
+                ..Collection::default()
+            }],
+            ..Collection::default()
+        }],
+        subjects: vec![Subject {
+            value: "Software".into(),
+            ..Subject::default()
+        }],
+        publisher: Some("Example Corp".into()),
+        modified: Some("2024-06-01T12:00:00Z".into()),
+        languages: vec!["en".into()],
+        ..Metadata::default()
+    }
+}
+
+fn release_notes() -> EpubBuilder {
+    let mut builder =
+        EpubBuilder::new("urn:uuid:0b2f9a3c-4e1d-4c6b-8f7a-5d3e2c1b0a99");
+    builder
+        .metadata(metadata())
+        .add_resource("style", "style.css", "text/css", "p { margin: 0 }")
+        .add_resource(
+            "cover",
+            "images/cover.png",
+            "image/png",
+            vec![0x89, b'P'],
+        )
+        .cover_image("cover")
+        .add_content("ch1", "text/ch1.xhtml", CHAPTER)
+        .add_content("ch2", "text/ch2.xhtml", CHAPTER)
+        .add_resource(
+            "notes",
+            "text/notes.xhtml",
+            "application/xhtml+xml",
+            CHAPTER,
+        )
+        .add_spine_item("notes", false)
+        .add_toc_entry(TocEntry::new("Chapter One", "text/ch1.xhtml"))
+        .add_toc_entry(
+            TocEntry::new("Chapter Two", "text/ch2.xhtml")
+                .child(TocEntry::new("Fixes", "text/ch2.xhtml#fixes")),
+        );
+    builder
+}
+
+#[test]
+fn builder_round_trip() {
+    let epub = release_notes()
+        .write(Cursor::new(vec![]))
+        .unwrap()
+        .into_inner();
+
+    // The mimetype is the first entry, stored without compression
+    assert_eq!(b"mimetypeapplication/epub+zip", &epub[30..58]);
+
+    let mut doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    assert_eq!(Path::new("EPUB/package.opf"), doc.root_file);
+    assert_eq!(
+        Some("urn:uuid:0b2f9a3c-4e1d-4c6b-8f7a-5d3e2c1b0a99"),
+        doc.unique_identifier.as_deref()
+    );
+    assert_eq!(Some("nav".to_string()), doc.nav_id);
+    assert_eq!(Some("cover".to_string()), doc.get_cover_id());
+    assert_eq!(vec![0x89, b'P'], doc.get_cover().unwrap().0);
+
+    assert_eq!(metadata(), doc.book_metadata);
+    assert_eq!("Release Notes & Changes", doc.mdata("title").unwrap());
+
+    let spine: Vec<_> = doc
+        .spine
+        .iter()
+        .map(|s| (s.idref.as_str(), s.linear))
+        .collect();
+    assert_eq!(vec![("ch1", true), ("ch2", true), ("notes", false)], spine);
+    assert_eq!(
+        ("p { margin: 0 }".to_string(), "text/css".to_string()),
+        doc.get_resource_str("style").unwrap()
+    );
+    assert_eq!(CHAPTER, doc.get_resource_str("ch2").unwrap().0);
+
+    assert_eq!(2, doc.toc.len());
+    assert_eq!("Chapter One", doc.toc[0].label);
+    assert_eq!(Path::new("EPUB/text/ch1.xhtml"), doc.toc[0].content);
+    let fixes = &doc.toc[1].children[0];
+    assert_eq!("Fixes", fixes.label);
+    assert_eq!(Path::new("EPUB/text/ch2.xhtml#fixes"), fixes.content);
+
+    // The toc.ncx fallback has the same entries
+    let (ncx, _) = doc.get_resource_str("ncx").unwrap();
+    assert!(ncx.contains(r#"<navPoint id="navpoint-3" playOrder="3">"#));
+    assert!(ncx.contains(r#"<content src="text/ch2.xhtml#fixes"/>"#));
+    assert!(ncx.contains(r#"<meta name="dtb:depth" content="2"/>"#));
+}
+
+#[test]
+fn builder_spine_toc() {
+    let mut builder = EpubBuilder::new("isbn:9780000000000");
+    builder
+        .metadata(Metadata {
+            titles: vec![Title {
+                value: "Notes".into(),
+                ..Title::default()
+            }],
+            languages: vec!["en".into()],
+            ..Metadata::default()
+        })
+        .add_content("a", "a.xhtml", CHAPTER)
+        .add_content("b", "b.xhtml", CHAPTER);
+    let epub = builder.write(Cursor::new(vec![])).unwrap().into_inner();
+
+    let doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    let labels: Vec<_> = doc.toc.iter().map(|n| n.label.as_str()).collect();
+    assert_eq!(vec!["a", "b"], labels);
+    // The modification date is set when it's missing
+    let modified = doc.book_metadata.modified.unwrap();
+    assert_eq!(20, modified.len());
+    assert!(modified.ends_with('Z'));
+}
+
+#[test]
+fn builder_errors() {
+    let mut builder = release_notes();
+    builder.metadata(Metadata::default());
+    assert!(matches!(
+        builder.write(Cursor::new(vec![])),
+        Err(BuilderError::MissingMetadata("title"))
+    ));
+
+    let mut builder = release_notes();
+    builder.add_resource("ch1", "text/other.xhtml", "text/css", "");
+    assert!(matches!(
+        builder.write(Cursor::new(vec![])),
+        Err(BuilderError::DuplicateResource(id)) if id == "ch1"
+    ));
+
+    let mut builder = release_notes();
+    builder.add_resource("nav2", "nav.xhtml", "application/xhtml+xml", "");
+    assert!(matches!(
+        builder.write(Cursor::new(vec![])),
+        Err(BuilderError::DuplicateResource(href)) if href == "nav.xhtml"
+    ));
+
+    // Metadata ids share the package document with the manifest
+    for id in ["uid", "title-2", "creator-1", "collection-1-1"] {
+        let mut builder = release_notes();
+        builder.add_resource(id, "extra.css", "text/css", "");
+        assert!(matches!(
+            builder.write(Cursor::new(vec![])),
+            Err(BuilderError::DuplicateResource(dup)) if dup == id
+        ));
+    }
+    let mut builder = release_notes();
+    builder.add_resource("creator-2", "extra.css", "text/css", "");
+    assert!(builder.write(Cursor::new(vec![])).is_ok());
+
+    // An EPUB 3 needs something to read
+    let mut builder =
+        EpubBuilder::new("urn:uuid:0b2f9a3c-4e1d-4c6b-8f7a-5d3e2c1b0a99");
+    builder.metadata(metadata()).add_resource(
+        "style",
+        "style.css",
+        "text/css",
+        "p { margin: 0 }",
+    );
+    assert!(matches!(
+        builder.write(Cursor::new(vec![])),
+        Err(BuilderError::EmptySpine)
+    ));
+
+    let mut builder = release_notes();
+    builder.add_spine_item("missing", true);
+    assert!(matches!(
+        builder.write(Cursor::new(vec![])),
+        Err(BuilderError::UnknownId(id)) if id == "missing"
+    ));
+}
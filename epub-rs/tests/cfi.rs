@@ -0,0 +1,224 @@
+use epub::cfi::{Cfi, CfiError, Offset, Side};
+use epub::doc::{DocError, EpubDoc};
+
+#[test]
+fn cfi_parse() {
+    for s in [
+        "epubcfi(/6/4[chap01ref]!/4[body01]/10[para05]/3:10)",
+        "epubcfi(/6/4!/4/2/1:3[Ye^[s^],we;s=b])",
+        "epubcfi(/6/4[chap01ref]!/4[body01]/10[para05],/2/1:1,/3:4)",
+        "epubcfi(/6/4!/4/2/1,:0,:5)",
+        "epubcfi(/6/14!/4/2~23.5@50:30.5)",
+        "epubcfi(/6/14!/4/2@10:20)",
+        "epubcfi(/6/4[a^,b^(c^)])",
+    ] {
+        let cfi: Cfi = s.parse().unwrap();
+        assert_eq!(s, cfi.to_string());
+    }
+
+    let cfi: Cfi = "/6/4!/4/2/1:3[Ye^[s^],we;s=b]".parse().unwrap();
+    assert_eq!(5, cfi.path.steps.len());
+    assert!(cfi.path.steps[2].indirect);
+    let Some(Offset::Character {
+        offset: 3,
+        assertion: Some(assertion),
+    }) = &cfi.path.offset
+    else {
+        panic!("expected a character offset: {cfi:?}");
+    };
+    assert_eq!("Ye[s]", assertion.before);
+    assert_eq!(Some("we"), assertion.after.as_deref());
+    assert_eq!(Some(Side::Before), assertion.side);
+
+    let cfi: Cfi = "epubcfi(/6/4[a^,b^(c^)])".parse().unwrap();
+    assert_eq!(Some("a,b(c)"), cfi.path.steps[1].id.as_deref());
+
+    for s in [
+        "",
+        "epubcfi()",
+        "epubcfi(/6/4",
+        "epubcfi(6/4)",
+        "epubcfi(/6/x)",
+        "epubcfi(/6/4!)",
+        "epubcfi(/6/4:1,/2,/4)",
+        "epubcfi(/6/4,/2)",
+        "epubcfi(/6/4[id)",
+        "epubcfi(/6/4) trailing",
+    ] {
+        assert!(
+            matches!(s.parse::<Cfi>(), Err(CfiError::Syntax(..))),
+            "{s:?} should be invalid"
+        );
+    }
+}
+
+#[test]
+fn cfi_range() {
+    let cfi: Cfi = "epubcfi(/6/4!/4/10,/2/1:1,/3:4)".parse().unwrap();
+    assert_eq!("/6/4!/4/10/2/1:1", cfi.start().to_string());
+    assert_eq!("/6/4!/4/10/3:4", cfi.end().to_string());
+    assert_eq!(cfi, Cfi::range(cfi.start(), cfi.end()));
+
+    // A point isn't a range
+    let cfi: Cfi = "epubcfi(/6/4!/4/10/3:4)".parse().unwrap();
+    assert_eq!(cfi.start(), cfi.end());
+    assert_eq!(
+        "epubcfi(/6/4!/4/10/3,:4,:4)",
+        Cfi::range(cfi.start(), cfi.end()).to_string()
+    );
+    let cfi: Cfi = "epubcfi(/6/4!/4/10)".parse().unwrap();
+    assert_eq!(
+        "epubcfi(/6/4!/4,/10,/10)",
+        Cfi::range(cfi.start(), cfi.end()).to_string()
+    );
+}
+
+#[test]
+fn cfi_generate() {
+    let mut doc = EpubDoc::new("test.epub").unwrap();
+
+    // <body> starts with a newline and two spaces, then <h1>Todo es mío</h1>
+    let cfi = |doc: &mut EpubDoc<_>, path: &[usize], offset| {
+        doc.generate_cfi(1, path, offset).unwrap().to_string()
+    };
+    assert_eq!("epubcfi(/6/4)", cfi(&mut doc, &[], None));
+    assert_eq!("epubcfi(/6/4!/4)", cfi(&mut doc, &[1], None));
+    assert_eq!("epubcfi(/6/4!/4/2)", cfi(&mut doc, &[1, 0], None));
+    assert_eq!("epubcfi(/6/4!/4/2/1:3)", cfi(&mut doc, &[1, 0], Some(3)));
+    assert_eq!("epubcfi(/6/4!/4/1:3)", cfi(&mut doc, &[1], Some(3)));
+    assert_eq!("epubcfi(/6/4!/4/2/1:2)", cfi(&mut doc, &[1], Some(5)));
+    assert_eq!("epubcfi(/6/4!/4/3:0)", cfi(&mut doc, &[1], Some(14)));
+    assert_eq!(
+        "epubcfi(/6/4!/4/2/1,:2,:5)",
+        doc.generate_cfi_range(1, &[1], 5..8).unwrap().to_string()
+    );
+    assert_eq!(
+        "epubcfi(/6/4!/4,/2/1:2,/3:2)",
+        doc.generate_cfi_range(1, &[1], 5..16).unwrap().to_string()
+    );
+
+    assert!(matches!(
+        doc.generate_cfi(17, &[1], None),
+        Err(DocError::CfiError(CfiError::SpineIndex(17)))
+    ));
+    assert!(matches!(
+        doc.generate_cfi(1, &[1, 40], None),
+        Err(DocError::CfiError(CfiError::NoSuchElement(_)))
+    ));
+    assert!(matches!(
+        doc.generate_cfi(1, &[1, 0], Some(12)),
+        Err(DocError::CfiError(CfiError::OffsetOutOfRange(12)))
+    ));
+
+    // Ids are added as assertions
+    let mut doc = EpubDoc::new("tests/docs/nav.epub").unwrap();
+    assert_eq!(
+        "epubcfi(/6/8!/4/6[section-2-1]/1:4)",
+        doc.generate_cfi(3, &[1, 2], Some(4)).unwrap().to_string()
+    );
+    // <p>Nobody expected the <em>bright</em> morning.</p>
+    assert_eq!(
+        "epubcfi(/6/8!/4/8/2/1:1)",
+        doc.generate_cfi(3, &[1, 3], Some(21)).unwrap().to_string()
+    );
+}
+
+#[test]
+fn cfi_resolve() {
+    let mut doc = EpubDoc::new("tests/docs/nav.epub").unwrap();
+
+    let cfi = "epubcfi(/6/8!/4/8/2/1:1)".parse().unwrap();
+    let target = doc.resolve_cfi(&cfi).unwrap();
+    assert_eq!(3, target.spine_index);
+    assert_eq!([1, 3, 0], *target.path);
+    assert_eq!("em", target.element);
+    assert_eq!(Some(1), target.offset);
+
+    // Text after a child element counts the text inside it
+    let cfi = "epubcfi(/6/8!/4/8/3:2)".parse().unwrap();
+    let target = doc.resolve_cfi(&cfi).unwrap();
+    assert_eq!([1, 3], *target.path);
+    assert_eq!(Some(28), target.offset);
+
+    // A wrong index is corrected by the id assertion
+    let cfi = "epubcfi(/6/8!/4/2[section-2-1]/1:4)".parse().unwrap();
+    let target = doc.resolve_cfi(&cfi).unwrap();
+    assert_eq!([1, 2], *target.path);
+    assert_eq!(Some("section-2-1"), target.id.as_deref());
+    assert_eq!(Some(4), target.offset);
+
+    let cfi = "epubcfi(/6/8!/4/8,/1:4,/3:2)".parse().unwrap();
+    let (start, end) = doc.resolve_cfi_range(&cfi).unwrap();
+    assert_eq!(Some(4), start.offset);
+    assert_eq!(Some(28), end.offset);
+    assert_eq!(start.path, end.path);
+
+    for s in [
+        "epubcfi(/6/40!/4)",
+        "epubcfi(/4/2!/4)",
+        "epubcfi(/6/8!/4/40)",
+        "epubcfi(/6/8!/4/8/1:100)",
+        "epubcfi(/6/8!/4/8/1/2)",
+    ] {
+        let cfi = s.parse().unwrap();
+        assert!(
+            matches!(doc.resolve_cfi(&cfi), Err(DocError::CfiError(_))),
+            "{s} shouldn't resolve"
+        );
+    }
+}
+
+/// Every location generated in a book resolves back to where it was
+/// generated from.
+#[test]
+fn cfi_round_trip() {
+    for path in [
+        "test.epub",
+        "tests/docs/Metamorphosis-jackson.epub",
+        "tests/docs/book2.epub",
+        "tests/docs/nav.epub",
+    ] {
+        let mut doc = EpubDoc::new(path).unwrap();
+        let mut checked = 0;
+        for spine_index in 0..doc.spine.len() {
+            let cfi = match doc.generate_cfi(spine_index, &[1], None) {
+                Ok(cfi) => cfi,
+                // Chapters that aren't XML can't be pointed into
+                Err(DocError::XmlError(_)) => continue,
+                Err(e) => panic!("{path} {spine_index}: {e}"),
+            };
+            let target = doc.resolve_cfi(&cfi).unwrap();
+            assert_eq!(spine_index, target.spine_index);
+            assert_eq!([1], *target.path);
+            assert_eq!("body", target.element);
+
+            // Spread out, as every location reparses the chapter
+            for offset in
+                std::iter::successors(Some(0), |o| Some(o * 3 / 2 + 1))
+            {
+                let cfi =
+                    match doc.generate_cfi(spine_index, &[1], Some(offset)) {
+                        Ok(cfi) => cfi,
+                        Err(DocError::CfiError(
+                            CfiError::OffsetOutOfRange(_),
+                        )) => {
+                            break;
+                        }
+                        Err(e) => panic!("{path} {spine_index} {offset}: {e}"),
+                    };
+                let parsed = cfi.to_string().parse().unwrap();
+                assert_eq!(cfi, parsed);
+
+                let target = doc.resolve_cfi(&parsed).unwrap();
+                assert_eq!(spine_index, target.spine_index);
+                assert_eq!(Some(&1), target.path.first());
+                let again = doc
+                    .generate_cfi(spine_index, &target.path, target.offset)
+                    .unwrap();
+                assert_eq!(cfi, again, "{path}");
+                checked += 1;
+            }
+        }
+        assert!(checked > 0, "nothing checked in {path}");
+    }
+}
@@ -79,3 +79,192 @@ fn toc_test() {
         assert_eq!(nav.play_order, chapter.unwrap());
     }
 }
+
+#[test]
+fn nav_toc_test() {
+    let doc = EpubDoc::new("tests/docs/nav.epub").unwrap();
+    assert_eq!(Some("nav".to_string()), doc.nav_id);
+
+    // The navigation document is preferred over the toc.ncx
+    assert_eq!(2, doc.toc.len());
+    assert_eq!("Chapter One", doc.toc[0].label);
+    assert_eq!(Path::new("EPUB/text/ch1.xhtml"), doc.toc[0].content);
+
+    let part = &doc.toc[1];
+    assert_eq!("Part Two", part.label);
+    assert_eq!(Path::new("EPUB/text/ch2.xhtml"), part.content);
+    assert_eq!(2, part.children.len());
+    assert_eq!("Chapter Three", part.children[1].label);
+    assert_eq!(
+        Path::new("EPUB/text/ch3.xhtml#start"),
+        part.children[1].content
+    );
+
+    let section = &part.children[0].children[0];
+    assert_eq!("Section 2.1", section.label);
+    assert_eq!(
+        Path::new("EPUB/text/ch2.xhtml#section-2-1"),
+        section.content
+    );
+
+    let orders: Vec<usize> = [
+        &doc.toc[0],
+        part,
+        &part.children[0],
+        section,
+        &part.children[1],
+    ]
+    .iter()
+    .map(|n| n.play_order)
+    .collect();
+    assert_eq!(vec![1, 2, 3, 4, 5], orders);
+}
+
+#[test]
+fn landmarks_test() {
+    let doc = EpubDoc::new("tests/docs/nav.epub").unwrap();
+
+    let kinds: Vec<_> =
+        doc.landmarks.iter().map(|l| l.kind.as_deref()).collect();
+    assert_eq!(vec![Some("cover"), Some("toc"), Some("bodymatter")], kinds);
+    assert_eq!("Start of Content", doc.landmarks[2].label);
+    assert_eq!(
+        Path::new("EPUB/nav/nav.xhtml#toc"),
+        doc.landmarks[1].content
+    );
+    assert_eq!(Some(2), doc.start_of_text());
+    assert!(doc.guide.is_empty());
+
+    // The navigation document's page list is preferred over the toc.ncx
+    let labels: Vec<_> =
+        doc.page_list.iter().map(|p| p.label.as_str()).collect();
+    assert_eq!(vec!["1", "2", "3"], labels);
+    assert_eq!(
+        Path::new("EPUB/text/ch2.xhtml#section-2-1"),
+        doc.page_list[1].content
+    );
+}
+
+#[test]
+fn nav_label_markup_test() {
+    let doc = EpubDoc::new("tests/docs/labels.epub").unwrap();
+
+    // Text around inline elements stays in document order
+    let part = &doc.toc[0];
+    assert_eq!("Part One of Two", part.label);
+    let labels: Vec<_> =
+        part.children.iter().map(|c| c.label.as_str()).collect();
+    assert_eq!(vec!["Chapter 1: Intro", "The Middle Bit Ends"], labels);
+
+    assert_eq!("Start of Content", doc.landmarks[0].label);
+    let pages: Vec<_> =
+        doc.page_list.iter().map(|p| p.label.as_str()).collect();
+    assert_eq!(vec!["iv", "vii"], pages);
+}
+
+#[test]
+fn guide_page_list_test() {
+    let doc = EpubDoc::new("tests/docs/ncx.epub").unwrap();
+
+    assert!(doc.landmarks.is_empty());
+    assert_eq!(2, doc.guide.len());
+    assert_eq!("Cover", doc.guide[0].label);
+    assert_eq!(Some("cover"), doc.guide[0].kind.as_deref());
+    // References without a title are labeled by their type
+    assert_eq!("text", doc.guide[1].label);
+    assert_eq!(
+        Path::new("OEBPS/text/ch1.xhtml#start"),
+        doc.guide[1].content
+    );
+    assert_eq!(Some(1), doc.start_of_text());
+
+    // Page targets are in play order
+    let labels: Vec<_> =
+        doc.page_list.iter().map(|p| p.label.as_str()).collect();
+    assert_eq!(vec!["i", "1", "2"], labels);
+    assert_eq!(Some("front"), doc.page_list[0].kind.as_deref());
+    assert_eq!(
+        Path::new("OEBPS/text/ch2.xhtml#p2"),
+        doc.page_list[2].content
+    );
+    assert_eq!(2, doc.toc.len());
+
+    let doc = EpubDoc::new("test.epub").unwrap();
+    assert_eq!(
+        Path::new("OEBPS/Text/titlepage.xhtml"),
+        doc.guide[0].content
+    );
+    assert_eq!(None, doc.start_of_text());
+    assert!(doc.page_list.is_empty());
+}
+
+#[test]
+fn obfuscated_fonts_test() {
+    use epub::doc::DocError;
+    use epub::encryption::Algorithm;
+
+    let mut doc = EpubDoc::new("tests/docs/fonts.epub").unwrap();
+    let font: Vec<u8> = (0..=255).cycle().take(2000).collect();
+
+    assert_eq!(
+        Some(&Algorithm::Idpf),
+        doc.encryption.get(Path::new("EPUB/fonts/idpf.otf"))
+    );
+    assert_eq!(font, doc.get_resource("idpf").unwrap().0);
+    // The path is percent-encoded in both the manifest and encryption.xml
+    assert_eq!(
+        Some(&Algorithm::Adobe),
+        doc.encryption.get(Path::new("EPUB/fonts/adobe font.otf"))
+    );
+    assert_eq!(font, doc.get_resource("adobe").unwrap().0);
+    assert_eq!(font, doc.get_resource("plain").unwrap().0);
+    // Obfuscation doesn't change the size the zip records
+    assert_eq!(Some(font.len() as u64), doc.get_resource_size("adobe"));
+    assert_eq!(None, doc.get_resource_size("missing"));
+
+    assert!(doc.get_resource("secret").is_none());
+    match doc.read_resource_by_path("EPUB/secret.xhtml") {
+        Err(DocError::Encrypted(path, algorithm)) => {
+            assert_eq!(Path::new("EPUB/secret.xhtml"), path);
+            assert_eq!(
+                "http://www.w3.org/2001/04/xmlenc#aes128-cbc",
+                algorithm
+            );
+        }
+        other => panic!("expected an encryption error, got {other:?}"),
+    }
+
+    let doc = EpubDoc::new("test.epub").unwrap();
+    assert!(doc.encryption.is_empty());
+}
+
+#[test]
+fn search_test() {
+    use epub::search::SearchMode;
+
+    let mut doc = EpubDoc::new("tests/docs/nav.epub").unwrap();
+
+    let matches = doc.search("BRIGHT", SearchMode::Text).unwrap();
+    assert_eq!(2, matches.len());
+    assert_eq!(2, matches[0].chapter);
+    assert_eq!("bright", matches[0].matched);
+    assert_eq!(None, matches[0].anchor);
+    assert_eq!(3, matches[1].chapter);
+    assert_eq!(Some("section-2-1".to_string()), matches[1].anchor);
+    assert_eq!(
+        "…irteen. Section 2.1 Nobody expected the bright morning.",
+        matches[1].snippet
+    );
+
+    let matches = doc.search(r"str\w+", SearchMode::Regex).unwrap();
+    assert_eq!(1, matches.len());
+    assert_eq!("striking", matches[0].matched);
+    assert_eq!(
+        "Chapter Two\nThe clocks were ".chars().count(),
+        matches[0].offset
+    );
+
+    // Regex syntax is literal text in text mode
+    assert!(doc.search(r"str\w+", SearchMode::Text).unwrap().is_empty());
+    assert!(doc.search("(", SearchMode::Regex).is_err());
+}
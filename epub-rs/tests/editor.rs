@@ -0,0 +1,188 @@
+use epub::doc::EpubDoc;
+use epub::editor::EpubEditor;
+use epub::metadata::{Collection, Person};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+
+/// The name, crc32, compressed size and compression of every entry.
+fn entries<R: Read + Seek>(zip: R) -> Vec<(String, u32, u64, String)> {
+    let mut zip = zip::ZipArchive::new(zip).unwrap();
+    (0..zip.len())
+        .map(|i| {
+            let file = zip.by_index_raw(i).unwrap();
+            (
+                file.name().to_string(),
+                file.crc32(),
+                file.compressed_size(),
+                file.compression().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn edit(
+    path: &str,
+    edit: impl Fn(&mut EpubEditor<std::io::BufReader<File>>),
+) -> Vec<u8> {
+    let mut editor = EpubEditor::new(EpubDoc::new(path).unwrap());
+    edit(&mut editor);
+    editor.write(Cursor::new(vec![])).unwrap().into_inner()
+}
+
+#[test]
+fn editor_opf2() {
+    let epub = edit("test.epub", |editor| {
+        editor
+            .title("Todo es tuyo")
+            .creators(vec![Person {
+                name: "Ana López".into(),
+                roles: vec!["aut".into()],
+                file_as: Some("López, Ana".into()),
+                ..Person::default()
+            }])
+            .series(Some(Collection {
+                name: "Todo".into(),
+                position: Some("1".into()),
+                ..Collection::default()
+            }))
+            .add_identifier("isbn:9780000000000");
+    });
+
+    // Only the package document changed
+    let before = entries(File::open("test.epub").unwrap());
+    let after = entries(Cursor::new(&epub));
+    assert_eq!(before.len(), after.len());
+    for (old, new) in before.iter().zip(&after) {
+        if old.0 == "OEBPS/content.opf" {
+            assert_ne!(old.1, new.1);
+        } else {
+            assert_eq!(old, new);
+        }
+    }
+
+    let doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    let m = &doc.book_metadata;
+    assert_eq!(Some("Todo es tuyo"), m.title());
+    assert_eq!(1, m.creators.len());
+    assert_eq!("Ana López", m.creators[0].name);
+    assert_eq!(vec!["aut".to_string()], m.creators[0].roles);
+    assert_eq!(Some("López, Ana"), m.creators[0].file_as.as_deref());
+    let series = m.series().unwrap();
+    assert_eq!("Todo", series.name);
+    assert_eq!(Some("1"), series.position.as_deref());
+
+    // The rest is kept
+    assert_eq!(
+        Some("urn:uuid:09132750-3601-4d19-b3a4-55fdf8639849"),
+        doc.unique_identifier.as_deref()
+    );
+    assert_eq!(
+        vec![
+            "urn:uuid:09132750-3601-4d19-b3a4-55fdf8639849".to_string(),
+            "isbn:9780000000000".to_string()
+        ],
+        doc.metadata["identifier"]
+    );
+    assert_eq!(Some("portada.png".to_string()), doc.get_cover_id());
+    assert_eq!(vec!["es".to_string()], m.languages);
+    assert_eq!(23, doc.resources.len());
+    assert_eq!(17, doc.spine.len());
+}
+
+#[test]
+fn editor_refines() {
+    let epub = edit("tests/docs/metadata.epub", |editor| {
+        editor
+            .title("The Empty Crown")
+            .creators(vec![Person {
+                name: "Ada Marsh".into(),
+                roles: vec!["aut".into()],
+                ..Person::default()
+            }])
+            .series(None);
+    });
+
+    let doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    let m = &doc.book_metadata;
+    // The main title keeps its type and order, but not its sort title or
+    // alternate script
+    let titles: Vec<_> = m.titles.iter().map(|t| t.value.as_str()).collect();
+    assert_eq!(
+        vec!["The Chronicles of the North", "The Empty Crown", "Book Two"],
+        titles
+    );
+    assert_eq!(Some("main"), m.titles[1].kind.as_deref());
+    assert_eq!(None, m.titles[1].file_as);
+    assert!(m.titles[1].alternate_scripts.is_empty());
+
+    // The refines of the old creators are gone
+    assert_eq!(1, m.creators.len());
+    assert_eq!(vec!["aut".to_string()], m.creators[0].roles);
+    assert_eq!(None, m.creators[0].display_seq);
+    assert!(m.creators[0].alternate_scripts.is_empty());
+    assert_eq!(1, m.contributors.len());
+
+    // So are the series, the set it belongs to, and calibre's series
+    assert!(m.collections.is_empty());
+    assert!(m.series().is_none());
+
+    assert_eq!(2, m.subjects.len());
+    assert_eq!(Some("FIC009000"), m.subjects[0].term.as_deref());
+    assert_ne!(Some("2024-01-01T00:00:00Z"), m.modified.as_deref());
+}
+
+#[test]
+fn editor_multiline() {
+    for path in ["test.epub", "tests/docs/metadata.epub"] {
+        let epub = edit(path, |editor| {
+            editor.title("Line one\nLine two").creators(vec![Person {
+                name: "A\nB".into(),
+                roles: vec!["aut".into()],
+                file_as: Some("B,\nA".into()),
+                ..Person::default()
+            }]);
+        });
+
+        let doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+        let m = &doc.book_metadata;
+        assert_eq!(Some("Line one\nLine two"), m.title(), "{path}");
+        assert_eq!(1, m.creators.len(), "{path}");
+        assert_eq!("A\nB", m.creators[0].name, "{path}");
+        assert_eq!(vec!["aut".to_string()], m.creators[0].roles, "{path}");
+        assert!(m.creators[0].file_as.is_some(), "{path}");
+    }
+}
+
+#[test]
+fn editor_cover_and_fonts() {
+    let epub = edit("tests/docs/fonts.epub", |editor| {
+        editor
+            .identifier("urn:uuid:00000000-1111-2222-3333-444444444444")
+            .cover_image("image/png", vec![0x89, b'P', b'N', b'G']);
+    });
+
+    let after = entries(Cursor::new(&epub));
+    assert_eq!("mimetype", after[0].0);
+    assert_eq!("Stored", after[0].3);
+    assert_eq!("EPUB/cover.png", after.last().unwrap().0);
+
+    let mut doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    assert_eq!(
+        Some("urn:uuid:00000000-1111-2222-3333-444444444444"),
+        doc.unique_identifier.as_deref()
+    );
+    let (cover, mime) = doc.get_cover().unwrap();
+    assert_eq!(vec![0x89, b'P', b'N', b'G'], cover);
+    assert_eq!("image/png", mime);
+    assert_eq!(
+        Some(&(Path::new("EPUB/cover.png").to_path_buf(), mime)),
+        doc.resources.get(&doc.get_cover_id().unwrap())
+    );
+
+    // The fonts are obfuscated with the new identifier
+    let font: Vec<u8> = (0..=255).cycle().take(2000).collect();
+    assert_eq!(font, doc.get_resource("idpf").unwrap().0);
+    assert_eq!(font, doc.get_resource("adobe").unwrap().0);
+    assert_eq!(font, doc.get_resource("plain").unwrap().0);
+}
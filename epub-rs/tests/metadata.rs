@@ -0,0 +1,158 @@
+use epub::doc::EpubDoc;
+use epub::metadata::AlternateScript;
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+
+/// An epub whose `metadata` element holds `metadata`.
+fn with_metadata(metadata: &str) -> Vec<u8> {
+    let package = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:1</dc:identifier>
+    <dc:title>Cycles</dc:title>
+    {metadata}
+  </metadata>
+  <manifest>
+    <item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="one"/>
+  </spine>
+</package>
+"#
+    );
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let files = [
+        ("mimetype", "application/epub+zip"),
+        (
+            "META-INF/container.xml",
+            r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#,
+        ),
+        ("OEBPS/content.opf", &package),
+        ("OEBPS/one.xhtml", "<html><body><p>One</p></body></html>"),
+    ];
+    for (name, content) in files {
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+fn metadata_refines() {
+    let doc = EpubDoc::new("tests/docs/metadata.epub").unwrap();
+    let m = &doc.book_metadata;
+
+    assert_eq!(Some("The Hollow Crown"), m.title());
+    let titles: Vec<_> = m.titles.iter().map(|t| t.value.as_str()).collect();
+    assert_eq!(
+        vec![
+            "The Chronicles of the North",
+            "The Hollow Crown",
+            "Book Two"
+        ],
+        titles
+    );
+    let main = &m.titles[1];
+    assert_eq!(Some("main"), main.kind.as_deref());
+    assert_eq!(Some("Hollow Crown, The"), main.file_as.as_deref());
+    assert_eq!(
+        vec![AlternateScript {
+            value: "虚ろな王冠".into(),
+            language: Some("ja".into()),
+        }],
+        main.alternate_scripts
+    );
+    assert_eq!(Some("subtitle"), m.titles[2].kind.as_deref());
+
+    // display-seq puts the illustrator first
+    assert_eq!(2, m.creators.len());
+    assert_eq!("Ben Ito", m.creators[0].name);
+    assert_eq!(vec!["ill".to_string()], m.creators[0].roles);
+    assert_eq!(1, m.creators[0].alternate_scripts.len());
+    assert_eq!("Ada Marsh", m.creators[1].name);
+    assert_eq!(Some("Marsh, Ada"), m.creators[1].file_as.as_deref());
+    let authors = m.authors();
+    assert_eq!(1, authors.len());
+    assert_eq!("Ada Marsh", authors[0].name);
+
+    // OPF 2 attributes
+    assert_eq!(1, m.contributors.len());
+    assert_eq!(vec!["trl".to_string()], m.contributors[0].roles);
+    assert_eq!(Some("Lane, Clara"), m.contributors[0].file_as.as_deref());
+
+    let series = m.series().unwrap();
+    assert_eq!("The Chronicles of the North", series.name);
+    assert_eq!(Some("2"), series.position.as_deref());
+    assert_eq!(1, series.collections.len());
+    assert_eq!("Northern Tales", series.collections[0].name);
+    assert_eq!(Some("set"), series.collections[0].kind.as_deref());
+    assert_eq!(1, m.collections.len());
+
+    assert_eq!(2, m.subjects.len());
+    assert_eq!(Some("BISAC"), m.subjects[0].authority.as_deref());
+    assert_eq!(Some("FIC009000"), m.subjects[0].term.as_deref());
+    assert_eq!(None, m.subjects[1].authority);
+
+    assert_eq!(Some("Lantern House"), m.publisher.as_deref());
+    assert_eq!("2023-05-01", m.dates[0].value);
+    assert_eq!(Some("2024-01-01T00:00:00Z"), m.modified.as_deref());
+    assert_eq!(Some("All rights reserved"), m.rights.as_deref());
+    assert_eq!(vec!["en".to_string(), "ja".to_string()], m.languages);
+}
+
+#[test]
+fn metadata_opf2() {
+    let doc = EpubDoc::new("tests/docs/Metamorphosis-jackson.epub").unwrap();
+    let m = &doc.book_metadata;
+
+    assert_eq!(Some("Metamorphosis"), m.title());
+    assert_eq!(1, m.creators.len());
+    assert_eq!("Franz Kafka", m.creators[0].name);
+    assert_eq!(vec!["aut".to_string()], m.creators[0].roles);
+    assert_eq!(Some("PressBooks.com"), m.publisher.as_deref());
+    assert_eq!(vec!["en".to_string()], m.languages);
+    assert!(m.series().is_none());
+    assert!(m.description.is_some());
+}
+
+#[test]
+fn metadata_collection_cycles() {
+    // A collection refining itself
+    let epub = with_metadata(
+        r##"<meta property="belongs-to-collection" id="c1">S</meta>
+    <meta refines="#c1" property="belongs-to-collection" id="c1">L</meta>"##,
+    );
+    let doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    let collections = &doc.book_metadata.collections;
+    assert_eq!(1, collections.len());
+    assert_eq!("S", collections[0].name);
+    assert_eq!(1, collections[0].collections.len());
+    assert_eq!("L", collections[0].collections[0].name);
+    assert!(collections[0].collections[0].collections.is_empty());
+
+    // Two collections refining each other
+    let epub = with_metadata(
+        r##"<meta property="belongs-to-collection" id="a">A</meta>
+    <meta refines="#a" property="belongs-to-collection" id="b">B</meta>
+    <meta refines="#b" property="belongs-to-collection" id="a">A2</meta>
+    <meta refines="#b" property="group-position">2</meta>"##,
+    );
+    let doc = EpubDoc::from_reader(Cursor::new(epub)).unwrap();
+    let collections = &doc.book_metadata.collections;
+    assert_eq!(1, collections.len());
+    let b = &collections[0].collections;
+    assert_eq!(1, b.len());
+    assert_eq!("B", b[0].name);
+    assert_eq!(Some("2"), b[0].position.as_deref());
+    assert_eq!(1, b[0].collections.len());
+    assert_eq!("A2", b[0].collections[0].name);
+    assert!(b[0].collections[0].collections.is_empty());
+}
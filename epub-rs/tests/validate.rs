@@ -0,0 +1,110 @@
+use epub::validate::{Problem, Report, Severity};
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use zip::write::SimpleFileOptions;
+
+const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+const PACKAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="isbn">urn:isbn:9780000000000</dc:identifier>
+    <dc:language>en</dc:language>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="one" href="one.xhtml" media-type="application/xhtml+xml"/>
+    <item id="two" href="two.xhtml" media-type="application/xhtml+xml"/>
+    <item id="cover" href="cover.jpg" media-type="image/jpeg"/>
+  </manifest>
+  <spine>
+    <itemref idref="one"/>
+    <itemref idref="two"/>
+    <itemref idref="three"/>
+  </spine>
+</package>
+"#;
+
+const NAV: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <body>
+    <nav epub:type="toc"><ol>
+      <li><a href="one.xhtml">One</a></li>
+      <li><a href="three.xhtml#start">Three</a></li>
+    </ol></nav>
+  </body>
+</html>
+"#;
+
+/// An epub with a problem of almost every kind.
+fn broken() -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let files = [
+        ("mimetype", "application/epub+zip"),
+        ("META-INF/container.xml", CONTAINER),
+        ("OEBPS/content.opf", PACKAGE),
+        ("OEBPS/nav.xhtml", NAV),
+        ("OEBPS/one.xhtml", "<html><body><p>One</body></html>"),
+        ("OEBPS/two.xhtml", "<html><body><p>Two</p></body></html>"),
+    ];
+    for (name, content) in files {
+        // The mimetype is deflated like the rest
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+#[test]
+fn validate_valid() {
+    for path in ["tests/docs/nav.epub", "tests/docs/ncx.epub"] {
+        let report = Report::new(path).unwrap();
+        assert_eq!(Vec::<Problem>::new(), report.problems, "{path}");
+        assert!(report.is_valid());
+    }
+}
+
+#[test]
+fn validate_broken() {
+    let report = Report::from_reader(Cursor::new(broken()));
+    assert!(!report.is_valid());
+
+    let errors: Vec<_> = report.errors().cloned().collect();
+    assert!(errors.contains(&Problem::CompressedMimetype));
+    assert!(errors.contains(&Problem::MissingResource(
+        "cover".into(),
+        PathBuf::from("OEBPS/cover.jpg")
+    )));
+    assert!(errors.contains(&Problem::DanglingSpineItem("three".into())));
+    assert!(errors.contains(&Problem::MissingMetadata("title")));
+    assert!(errors.contains(&Problem::MissingUniqueIdentifier("uid".into())));
+    assert!(errors.contains(&Problem::BrokenLink(
+        "Three".into(),
+        PathBuf::from("OEBPS/three.xhtml")
+    )));
+    // Only the unclosed paragraph is unparsable
+    let invalid: Vec<_> = errors
+        .iter()
+        .filter_map(|p| match p {
+            Problem::InvalidXhtml(path, _) => Some(path.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec![PathBuf::from("OEBPS/one.xhtml")], invalid);
+
+    let warnings: Vec<_> = report.warnings().cloned().collect();
+    assert_eq!(vec![Problem::MissingMetadata("dcterms:modified")], warnings);
+    assert_eq!(Severity::Warning, warnings[0].severity());
+}
+
+#[test]
+fn validate_not_a_zip() {
+    let report = Report::from_reader(Cursor::new(b"not an epub".to_vec()));
+    assert!(matches!(report.problems[..], [Problem::Archive(_)]));
+}
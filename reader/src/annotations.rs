@@ -0,0 +1,322 @@
+//! Highlights in EPUB chapters, and exporting them.
+//!
+//! A highlight is anchored by the character offsets of its passage in the
+//! text of the chapter's body, which is the concatenation of every text node
+//! in `<body>`, the same as `Range.toString()` in the browser. The passage's
+//! text is kept too, so a highlight can still be found if the chapter changes.
+
+use crate::store::Highlight;
+use crate::{Book, BookState};
+use epub::doc::NavPoint;
+use std::fmt::Write as _;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Json => crate::JSON,
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown export format \"{s}\", expected \"markdown\" or \"json\""
+            )),
+        }
+    }
+}
+
+/// The highlights of a chapter in the table of contents.
+#[derive(Debug, serde::Serialize)]
+struct Chapter<'a> {
+    title: String,
+    highlights: Vec<&'a Highlight>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Export<'a> {
+    title: &'a str,
+    chapters: Vec<Chapter<'a>>,
+}
+
+/// Write all the highlights of `book` as `format`, grouped by the table of
+/// contents entry they fall under.
+pub fn export(book: &BookState, format: ExportFormat) -> String {
+    let mut highlights = book.store.data.highlights.iter().collect::<Vec<_>>();
+    highlights.sort_by_key(|h| (h.page, h.start));
+
+    let toc = toc_pages(&book.book);
+    let mut chapters: Vec<Chapter> = vec![];
+    for highlight in highlights {
+        let title = title_at(&toc, highlight.page).map_or_else(
+            || format!("Page {}", highlight.page + 1),
+            String::from,
+        );
+        match chapters.last_mut() {
+            Some(chapter) if chapter.title == title => {
+                chapter.highlights.push(highlight);
+            }
+            _ => chapters.push(Chapter {
+                title,
+                highlights: vec![highlight],
+            }),
+        }
+    }
+
+    let export = Export {
+        title: &book.title,
+        chapters,
+    };
+    match format {
+        ExportFormat::Json => serde_json::to_string_pretty(&export)
+            .expect("serializable annotations"),
+        ExportFormat::Markdown => markdown(&export),
+    }
+}
+
+/// The title of the table of contents entry `page` falls under. If `anchor`
+/// is given, an entry pointing right at it in `page` is preferred.
+pub fn toc_title(
+    book: &Book,
+    page: usize,
+    anchor: Option<&str>,
+) -> Option<String> {
+    let Book::Epub(epub) = book else {
+        return None;
+    };
+    if let Some(anchor) = anchor {
+        let mut found = None;
+        flatten_toc(&epub.toc, &mut |nav| {
+            let content = nav.content.to_string_lossy();
+            if found.is_none()
+                && let Some((path, fragment)) = content.split_once('#')
+                && fragment == anchor
+                && epub.resource_uri_to_chapter(&PathBuf::from(path))
+                    == Some(page)
+            {
+                found = Some(nav.label.clone());
+            }
+        });
+        if found.is_some() {
+            return found;
+        }
+    }
+    title_at(&toc_pages(book), page).map(String::from)
+}
+
+/// The first table of contents entry of each page, in page order.
+fn toc_pages(book: &Book) -> Vec<(usize, String)> {
+    let mut toc = vec![];
+    if let Book::Epub(epub) = book {
+        flatten_toc(&epub.toc, &mut |nav| {
+            let content = nav.content.to_string_lossy();
+            let path = content.split('#').next().unwrap_or_default();
+            if let Some(page) =
+                epub.resource_uri_to_chapter(&PathBuf::from(path))
+                && !toc.iter().any(|(p, _)| *p == page)
+            {
+                toc.push((page, nav.label.clone()));
+            }
+        });
+    }
+    toc.sort_by_key(|(page, _)| *page);
+    toc
+}
+
+/// The title of the last entry of `toc` at or before `page`.
+fn title_at(toc: &[(usize, String)], page: usize) -> Option<&str> {
+    toc.iter()
+        .rev()
+        .find(|(p, _)| *p <= page)
+        .map(|(_, label)| label.as_str())
+}
+
+fn flatten_toc(navpoints: &[NavPoint], f: &mut impl FnMut(&NavPoint)) {
+    for nav in navpoints {
+        f(nav);
+        flatten_toc(&nav.children, f);
+    }
+}
+
+fn markdown(export: &Export) -> String {
+    let mut md = format!("# {}\n", export.title);
+    for chapter in &export.chapters {
+        let _ = write!(md, "\n## {}\n", chapter.title);
+        for highlight in &chapter.highlights {
+            md.push('\n');
+            for line in highlight.text.trim().lines() {
+                let _ = writeln!(md, "> {}", line.trim());
+            }
+            if let Some(note) = &highlight.note {
+                let _ = write!(md, "\n{}\n", note.trim());
+            }
+        }
+    }
+    md
+}
+
+/// Split raw XML text into the pieces that each make up one character of the
+/// parsed text, so an entity reference is a single piece.
+pub fn text_units(raw: &str) -> impl Iterator<Item = &str> {
+    let mut rest = raw;
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+        let len = if c == '&'
+            && let Some((end, _)) =
+                rest.char_indices().take(12).find(|(_, c)| *c == ';')
+        {
+            end + 1
+        } else {
+            c.len_utf8()
+        };
+        let (unit, tail) = rest.split_at(len);
+        rest = tail;
+        Some(unit)
+    })
+}
+
+/// The character a piece from [`text_units`] stands for.
+fn decode_unit(unit: &str) -> char {
+    let Some(entity) = unit.strip_prefix('&').and_then(|u| u.strip_suffix(';'))
+    else {
+        return unit.chars().next().unwrap_or_default();
+    };
+    let code = if let Some(hex) = entity
+        .strip_prefix("#x")
+        .or_else(|| entity.strip_prefix("#X"))
+    {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(dec) = entity.strip_prefix('#') {
+        dec.parse().ok()
+    } else {
+        match entity {
+            "amp" => Some(0x26),
+            "lt" => Some(0x3c),
+            "gt" => Some(0x3e),
+            "quot" => Some(0x22),
+            "apos" => Some(0x27),
+            "nbsp" => Some(0xa0),
+            _ => None,
+        }
+    };
+    code.and_then(char::from_u32)
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// The text of the body of the XHTML document `src`.
+fn body_text(src: &str) -> String {
+    use xmlparser::{ElementEnd, Token};
+    let mut text = String::new();
+    let mut in_body = false;
+    for token in xmlparser::Tokenizer::from(src) {
+        match token {
+            Ok(Token::ElementStart { local, .. }) if local == "body" => {
+                in_body = true;
+            }
+            Ok(Token::ElementEnd {
+                end: ElementEnd::Close(_, local),
+                ..
+            }) if local == "body" => in_body = false,
+            Ok(Token::Text { text: t }) if in_body => {
+                text.extend(text_units(t.as_str()).map(decode_unit));
+            }
+            Ok(Token::Cdata { text: t, .. }) if in_body => {
+                text.push_str(t.as_str());
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    text
+}
+
+/// Find where each of `highlights` is in the body text of `src`, as character
+/// ranges. Highlights that can't be found anymore are left out.
+pub fn resolve<'a>(
+    src: &str,
+    highlights: impl IntoIterator<Item = &'a Highlight>,
+) -> Vec<(Range<usize>, &'a Highlight)> {
+    let text = body_text(src).chars().collect::<Vec<_>>();
+    let quote_at = |start: usize, quote: &[char]| {
+        text.get(start..start + quote.len()) == Some(quote)
+    };
+
+    highlights
+        .into_iter()
+        .filter_map(|h| {
+            let quote = h.text.chars().collect::<Vec<_>>();
+            if quote.is_empty() {
+                return None;
+            }
+            let start = if quote_at(h.start, &quote) {
+                h.start
+            } else {
+                (0..text.len()).find(|&i| quote_at(i, &quote))?
+            };
+            Some((start..start + quote.len(), h))
+        })
+        .collect()
+}
+
+/// Write the raw XML `text`, which starts `*pos` characters into the body
+/// text, to `output`, wrapping the parts inside `highlights` in `<mark>`s.
+pub fn push_marked_text(
+    output: &mut String,
+    text: &str,
+    pos: &mut usize,
+    highlights: &[(Range<usize>, &Highlight)],
+) {
+    let mut open = None;
+    for unit in text_units(text) {
+        let highlight = highlights
+            .iter()
+            .find(|(range, _)| range.contains(pos))
+            .map(|(_, h)| *h);
+        if open != highlight.map(|h| h.id) {
+            if open.is_some() {
+                output.push_str("</mark>");
+            }
+            if let Some(h) = highlight {
+                let _ = write!(
+                    output,
+                    r#"<mark class="highlight" data-highlight="{}""#,
+                    h.id
+                );
+                if let Some(note) = &h.note {
+                    let _ = write!(output, r#" title="{}""#, escape(note));
+                }
+                output.push('>');
+            }
+            open = highlight.map(|h| h.id);
+        }
+        output.push_str(unit);
+        *pos += 1;
+    }
+    if open.is_some() {
+        output.push_str("</mark>");
+    }
+}
+
+/// Escape `s` for use in an XML attribute value.
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,5 +1,20 @@
+use log::warn;
+use std::cmp::Ordering;
 use std::io::{BufRead, BufReader, Read};
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, mpsc};
+
+mod cache;
+mod dir;
+mod metadata;
+mod rar;
+mod sevenz;
+mod tar;
+
+pub use cache::{Cache, CacheConfig};
+pub use metadata::Metadata;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -7,6 +22,10 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("ZIP: {0}")]
     Zip(#[from] zip::result::ZipError),
+    #[error("RAR: {0}")]
+    Rar(#[from] rar::Error),
+    #[error("7z: {0}")]
+    SevenZ(#[from] sevenz_rust::Error),
     #[error("Unknown file type: failed to detect file type")]
     UnknownFileType,
     #[error("Invalid File Type: detected file type is unsupported: \"{0}\"")]
@@ -15,20 +34,293 @@ pub enum Error {
     IndexOutOfBounds(usize),
 }
 
+/// How the pages of a comic are ordered.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PageOrder {
+    /// The order the comic's [`Metadata`] lists the pages in if it does, and
+    /// otherwise the file names in natural order (see [`natural_cmp`]).
+    #[default]
+    Natural,
+    /// The file names in plain string order.
+    Lexical,
+}
+
+impl FromStr for PageOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "natural" => Ok(Self::Natural),
+            "lexical" => Ok(Self::Lexical),
+            _ => Err(format!(
+                "unknown page order \"{s}\", expected \"natural\" or \"lexical\""
+            )),
+        }
+    }
+}
+
+/// How comic pages are sized to the view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    /// The whole page is in view.
+    #[default]
+    Page,
+    /// The page is as wide as the view.
+    Width,
+    /// The page is as tall as the view.
+    Height,
+    /// The page is shown at its own size.
+    Original,
+}
+
+impl FitMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Page => "page",
+            Self::Width => "width",
+            Self::Height => "height",
+            Self::Original => "original",
+        }
+    }
+}
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "page" => Ok(Self::Page),
+            "width" => Ok(Self::Width),
+            "height" => Ok(Self::Height),
+            "original" => Ok(Self::Original),
+            _ => Err(format!(
+                "unknown fit mode \"{s}\", expected \"page\", \"width\", \"height\" or \"original\""
+            )),
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
 enum InnerArchive {
     Zip(zip::ZipArchive<BufReader<std::fs::File>>),
+    Rar(rar::Archive<BufReader<std::fs::File>>),
+    SevenZ(sevenz::Archive<BufReader<std::fs::File>>),
+    Tar(tar::Archive<BufReader<std::fs::File>>),
+    Dir(dir::Directory),
+}
+
+impl InnerArchive {
+    /// The names of the files in the archive, with the indices to read them
+    /// by.
+    fn names(&self) -> Vec<(usize, String)> {
+        match self {
+            Self::Zip(zip) => (0..zip.len())
+                .filter_map(|i| Some((i, zip.name_for_index(i)?)))
+                .filter(|(_, name)| !name.ends_with('/'))
+                .map(|(i, name)| (i, name.to_string()))
+                .collect(),
+            Self::Rar(rar) => rar
+                .entries()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| !entry.is_dir)
+                .map(|(i, entry)| (i, entry.name.clone()))
+                .collect(),
+            Self::SevenZ(sevenz) => sevenz
+                .entries()
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| !entry.is_directory)
+                .map(|(i, entry)| (i, entry.name.clone()))
+                .collect(),
+            Self::Tar(tar) => tar
+                .entries()
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (i, entry.name.clone()))
+                .collect(),
+            Self::Dir(dir) => dir.names().iter().cloned().enumerate().collect(),
+        }
+    }
+
+    /// Read the file at `index`.
+    fn read(&mut self, index: usize) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Zip(zip) => {
+                let mut contents = vec![];
+                zip.by_index(index)?.read_to_end(&mut contents)?;
+                Ok(contents)
+            }
+            Self::Rar(rar) => Ok(rar.read(index)?),
+            Self::SevenZ(sevenz) => {
+                Ok(sevenz.read(index, 0, &mut |_, _| {})?)
+            }
+            Self::Tar(tar) => Ok(tar.read(index)?),
+            Self::Dir(dir) => Ok(dir.read(index)?),
+        }
+    }
+
+    /// Read the file at `index`, keeping the other files that are
+    /// decompressed along with it in `cache`.
+    fn read_cached(
+        &mut self,
+        index: usize,
+        cache: &Mutex<Cache>,
+    ) -> Result<Vec<u8>, Error> {
+        let lock = || cache.lock().unwrap_or_else(PoisonError::into_inner);
+        match self {
+            // Reaching a file in a solid block decompresses the ones before
+            // it, so keep those and the next ones rather than decompressing
+            // the block again for each page. Half of the cache is left for
+            // the pages already read.
+            Self::SevenZ(sevenz) => {
+                let ahead = lock().capacity() / 2;
+                Ok(sevenz.read(index, ahead, &mut |i, data| {
+                    lock().insert(i, data.into());
+                })?)
+            }
+            _ => self.read(index),
+        }
+    }
+
+    /// The size of the file at `index` once read, from the archive's
+    /// headers rather than by decompressing it.
+    fn size(&mut self, index: usize) -> Option<u64> {
+        match self {
+            Self::Zip(zip) => Some(zip.by_index_raw(index).ok()?.size()),
+            Self::Rar(rar) => rar.entries()[index].size,
+            Self::SevenZ(sevenz) => Some(sevenz.entries()[index].size),
+            Self::Tar(tar) => Some(tar.entries()[index].size),
+            Self::Dir(dir) => dir.size(index).ok(),
+        }
+    }
+}
+
+/// Whether the file `name` is an image, going by its extension.
+pub fn is_image(name: impl AsRef<Path>) -> bool {
+    mime_guess::from_path(name)
+        .first()
+        .is_some_and(|mime| mime.type_() == mime::IMAGE)
+}
+
+/// Compare file names so that runs of digits are ordered by their value,
+/// putting `page2.jpg` before `page10.jpg`, and letters regardless of case.
+pub fn natural_cmp(left: &str, right: &str) -> Ordering {
+    fn number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+            number.push(c);
+        }
+        number
+    }
+
+    let mut left_chars = left.chars().peekable();
+    let mut right_chars = right.chars().peekable();
+    loop {
+        let ordering = match (left_chars.peek(), right_chars.peek()) {
+            // Names that only differ in case or zero-padding still need an
+            // order
+            (None, None) => return left.cmp(right),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+                let l = number(&mut left_chars);
+                let r = number(&mut right_chars);
+                let (l, r) =
+                    (l.trim_start_matches('0'), r.trim_start_matches('0'));
+                l.len().cmp(&r.len()).then_with(|| l.cmp(r))
+            }
+            (Some(&l), Some(&r)) => {
+                left_chars.next();
+                right_chars.next();
+                l.to_lowercase().cmp(r.to_lowercase())
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
 }
 
 pub struct CBAReader {
+    path: PathBuf,
     inner: InnerArchive,
-    /// An ordered list of indices
-    indices: Vec<usize>,
+    /// The images in the archive, with the indices to read them by
+    images: Vec<(usize, String)>,
+    /// Indices into `images`, in natural order
+    natural: Vec<usize>,
+    metadata: Option<Metadata>,
+    /// Whether each of `images` spans two pages
+    double: Vec<bool>,
+    /// Indices into `images`, in reading order
+    pages: Vec<usize>,
 
     position: usize,
+
+    /// Decoded pages, shared with the thread reading ahead
+    cache: Arc<Mutex<Cache>>,
+    /// How many pages after the current one to read ahead
+    prefetch: usize,
+    /// Sends the archive indices of pages to read ahead, once there is a
+    /// thread to do it.
+    prefetcher: Option<mpsc::Sender<usize>>,
 }
 
 impl CBAReader {
+    /// Open the comic at `path`, which is an archive or a directory of
+    /// images, with its pages in the default [`PageOrder`].
     pub fn read(path: &Path) -> Result<Self, Error> {
+        let mut inner = Self::open(path)?;
+        let names = inner.names();
+        let images = names
+            .iter()
+            .filter(|(_, name)| is_image(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut natural = (0..images.len()).collect::<Vec<_>>();
+        natural.sort_by(|&l, &r| natural_cmp(&images[l].1, &images[r].1));
+        let metadata = Self::read_metadata(&mut inner, &names);
+        let mut double = vec![false; images.len()];
+        for page in metadata.iter().flat_map(|metadata| &metadata.pages) {
+            if let Some(&image) = natural.get(page.image) {
+                double[image] |= page.double;
+            }
+        }
+
+        let cache = CacheConfig::default();
+        let mut reader = Self {
+            path: path.to_path_buf(),
+            inner,
+            images,
+            natural,
+            metadata,
+            double,
+            pages: vec![],
+            position: 0,
+            cache: Arc::new(Mutex::new(Cache::new(cache.size))),
+            prefetch: cache.prefetch,
+            prefetcher: None,
+        };
+        reader.set_page_order(PageOrder::default());
+        Ok(reader)
+    }
+
+    fn open(path: &Path) -> Result<InnerArchive, Error> {
+        if path.is_dir() {
+            return Ok(InnerArchive::Dir(dir::Directory::new(path)?));
+        }
+
         let mut file = BufReader::new(std::fs::File::open(path)?);
 
         let buffer = file.fill_buf().unwrap();
@@ -42,50 +334,114 @@ impl CBAReader {
             .map_err(|_| Error::UnknownFileType)?;
         match (mime.type_().as_str(), mime.subtype().as_str()) {
             ("application", "zip") => {
-                let mut zip = zip::ZipArchive::new(file)?;
-
-                let mut indices = Vec::with_capacity(zip.len());
-                for i in 0..zip.len() {
-                    let name = zip.by_index(i)?.name().to_string();
-                    let mime = {
-                        let name: &Path = name.as_ref();
-                        let Some(ext) =
-                            name.extension().and_then(|x| x.to_str())
-                        else {
-                            continue;
-                        };
-                        let Some(mime) = mime_guess::from_ext(ext).first()
-                        else {
-                            continue;
-                        };
-                        mime
-                    };
-                    if mime.type_() == mime::IMAGE {
-                        indices.push((i, name));
+                Ok(InnerArchive::Zip(zip::ZipArchive::new(file)?))
+            }
+            ("application", "vnd.rar") => {
+                Ok(InnerArchive::Rar(rar::Archive::new(file)?))
+            }
+            ("application", "x-7z-compressed") => {
+                Ok(InnerArchive::SevenZ(sevenz::Archive::new(file)?))
+            }
+            ("application", "x-tar") => {
+                Ok(InnerArchive::Tar(tar::Archive::new(file)?))
+            }
+            _invalid => Err(Error::InvalidFileType(mime)),
+        }
+    }
+
+    /// Read the `ComicInfo.xml` or, failing that, the `CoMet` file among
+    /// `names`, if there is one.
+    fn read_metadata(
+        inner: &mut InnerArchive,
+        names: &[(usize, String)],
+    ) -> Option<Metadata> {
+        [metadata::COMIC_INFO, metadata::COMET]
+            .into_iter()
+            .find_map(|file_name| {
+                let &(i, _) = names.iter().find(|(_, name)| {
+                    Path::new(name).file_name().is_some_and(|name| {
+                        name.eq_ignore_ascii_case(file_name)
+                    })
+                })?;
+                match inner.read(i) {
+                    Ok(data) => Some(Metadata::parse(
+                        file_name,
+                        &String::from_utf8_lossy(&data),
+                    )),
+                    Err(e) => {
+                        warn!("Ignoring unreadable {file_name}: {e}");
+                        None
                     }
                 }
+            })
+    }
 
-                indices.sort_by(|(_, left), (_, right)| left.cmp(right));
-                let indices =
-                    indices.into_iter().map(|(i, _)| i).collect::<Vec<_>>();
+    /// Change how many pages are kept in memory and read ahead.
+    pub fn set_cache(&mut self, config: CacheConfig) {
+        self.cache().set_capacity(config.size);
+        self.prefetch = config.prefetch;
+    }
 
-                dbg!(&indices);
-                let inner = InnerArchive::Zip(zip);
-                Ok(Self {
-                    inner,
-                    indices,
-                    position: 0,
-                })
-            }
-            ("application", "vnd.rar") => {
-                todo!("Process RAR");
+    fn cache(&self) -> MutexGuard<'_, Cache> {
+        // The cache is still consistent if the other thread panicked
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Decode the pages after `pos` in the background, so they're ready by
+    /// the time they're turned to.
+    fn read_ahead(&mut self, pos: usize) {
+        let wanted = {
+            let cache = self.cache();
+            self.pages
+                .iter()
+                .skip(pos + 1)
+                .take(self.prefetch)
+                .map(|&image| self.images[image].0)
+                .filter(|index| !cache.contains(index))
+                .collect::<Vec<_>>()
+        };
+        if wanted.is_empty() {
+            return;
+        }
+
+        let prefetcher = self.prefetcher.get_or_insert_with(|| {
+            spawn_prefetcher(self.path.clone(), Arc::clone(&self.cache))
+        });
+        for index in wanted {
+            if prefetcher.send(index).is_err() {
+                // It couldn't open the archive, so try again next time
+                self.prefetcher = None;
+                break;
             }
-            _invalid => Err(Error::InvalidFileType(mime)),
         }
     }
 
+    /// The comic's metadata, if it has any.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Put the pages in `order`. The current page stays at the same
+    /// position, if there still are that many pages.
+    pub fn set_page_order(&mut self, order: PageOrder) {
+        let pages = match order {
+            PageOrder::Natural => match &self.metadata {
+                Some(metadata) => metadata.order(&self.natural),
+                None => self.natural.clone(),
+            },
+            PageOrder::Lexical => {
+                let names = &self.images;
+                let mut pages = (0..names.len()).collect::<Vec<_>>();
+                pages.sort_by(|&l, &r| names[l].1.cmp(&names[r].1));
+                pages
+            }
+        };
+        self.pages = pages;
+        self.position = self.position.min(self.pages.len().saturating_sub(1));
+    }
+
     pub fn page_count(&self) -> usize {
-        self.indices.len()
+        self.pages.len()
     }
 
     pub fn get_current_page(&self) -> usize {
@@ -94,28 +450,278 @@ impl CBAReader {
 
     /// Try to seek to `pos`, returning the previous position if successful.
     pub fn set_current_page(&mut self, pos: usize) -> Option<usize> {
-        if pos >= self.indices.len() {
+        if pos >= self.pages.len() {
             return None;
         }
         let old = self.position;
         self.position = pos;
+        self.read_ahead(pos);
         Some(old)
     }
 
+    /// The pages shown together with the one at `pos` when showing two pages
+    /// side by side. The first page, normally the cover, and double-width
+    /// pages are shown alone, and the rest in pairs between them.
+    pub fn spread(&self, pos: usize) -> Range<usize> {
+        let alone = |pos: usize| pos == 0 || self.double[self.pages[pos]];
+        let mut start = 0;
+        while start < self.pages.len() {
+            let end = if alone(start)
+                || start + 1 == self.pages.len()
+                || alone(start + 1)
+            {
+                start + 1
+            } else {
+                start + 2
+            };
+            if pos < end {
+                return start..end;
+            }
+            start = end;
+        }
+        pos..pos + 1
+    }
+
     /// Returns the data for the page at `pos` as an image as well as the type
     /// of image it is.
     pub fn page(&mut self, pos: usize) -> Result<(Vec<u8>, mime::Mime), Error> {
-        let mut contents = vec![];
-        let mime: mime::Mime;
+        let image = *self.pages.get(pos).ok_or(Error::IndexOutOfBounds(pos))?;
+        self.image(image)
+    }
+
+    /// The file name of the page at `pos`.
+    pub fn page_name(&self, pos: usize) -> Option<&str> {
+        let &image = self.pages.get(pos)?;
+        Some(&self.images[image].1)
+    }
+
+    /// The size of the page at `pos` in bytes, if the archive records it,
+    /// without reading the page.
+    pub fn page_size(&mut self, pos: usize) -> Option<u64> {
+        let &image = self.pages.get(pos)?;
+        self.inner.size(self.images[image].0)
+    }
+
+    /// Returns the cover image: the page the metadata marks as the front
+    /// cover or names as the cover image, or otherwise the first page.
+    pub fn cover(&mut self) -> Result<(Vec<u8>, mime::Mime), Error> {
+        let image = self.cover_image().ok_or(Error::IndexOutOfBounds(0))?;
+        self.image(image)
+    }
+
+    /// The file name of the [`cover`](Self::cover).
+    pub fn cover_name(&self) -> Option<&str> {
+        let image = self.cover_image()?;
+        Some(&self.images[image].1)
+    }
+
+    /// The index in `images` of the cover.
+    fn cover_image(&self) -> Option<usize> {
+        let marked = self.metadata.as_ref().and_then(|metadata| {
+            let front = metadata
+                .pages
+                .iter()
+                .find(|page| page.kind.as_deref() == Some("FrontCover"))
+                .and_then(|page| self.natural.get(page.image).copied());
+            front.or_else(|| {
+                let cover = metadata.cover_image.as_deref()?;
+                self.images.iter().position(|(_, name)| {
+                    name == cover
+                        || Path::new(name)
+                            .file_name()
+                            .is_some_and(|name| name == cover)
+                })
+            })
+        });
+        marked.or_else(|| self.pages.first().copied())
+    }
+
+    /// Returns the data and type of the image at `image` in `images`.
+    fn image(&mut self, image: usize) -> Result<(Vec<u8>, mime::Mime), Error> {
+        let (idx, name) = &self.images[image];
+        let mime = mime_guess::from_path(name).first().unwrap();
+        let cached = self.cache().get(idx);
+        let contents = if let Some(data) = cached {
+            data.to_vec()
+        } else {
+            let data = self.inner.read_cached(*idx, &self.cache)?;
+            self.cache().insert(*idx, data.as_slice().into());
+            data
+        };
+        Ok((contents, mime))
+    }
+}
 
-        let idx = *self.indices.get(pos).ok_or(Error::IndexOutOfBounds(pos))?;
-        match &mut self.inner {
-            InnerArchive::Zip(zip) => {
-                let mut file = zip.by_index(idx)?;
-                mime = mime_guess::from_path(file.name()).first().unwrap();
-                file.read_to_end(&mut contents)?;
+/// Start a thread that decodes the files it's sent the indices of from its
+/// own handle on the archive at `path`, and keeps them in `cache`. It stops
+/// once the sender is dropped.
+fn spawn_prefetcher(
+    path: PathBuf,
+    cache: Arc<Mutex<Cache>>,
+) -> mpsc::Sender<usize> {
+    let (sender, receiver) = mpsc::channel::<usize>();
+    std::thread::spawn(move || {
+        let mut inner = match CBAReader::open(&path) {
+            Ok(inner) => inner,
+            Err(e) => {
+                warn!("Can't read ahead in \"{}\": {e}", path.display());
+                return;
+            }
+        };
+        let lock = || cache.lock().unwrap_or_else(PoisonError::into_inner);
+        for index in receiver {
+            if lock().contains(&index) {
+                continue;
+            }
+            match inner.read_cached(index, &cache) {
+                Ok(data) => lock().insert(index, data.into()),
+                Err(e) => warn!("Failed to read ahead file {index}: {e}"),
             }
         }
-        Ok((contents, mime))
+    });
+    sender
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn natural_numbers() {
+        assert_eq!(Ordering::Less, natural_cmp("page2.jpg", "page10.jpg"));
+        assert_eq!(Ordering::Greater, natural_cmp("page10.jpg", "page9.jpg"));
+        assert_eq!(Ordering::Less, natural_cmp("1-2.jpg", "1-10.jpg"));
+        assert_eq!(Ordering::Less, natural_cmp("v2/p10.jpg", "v10/p2.jpg"));
+    }
+
+    #[test]
+    fn natural_leading_zeros() {
+        assert_eq!(Ordering::Less, natural_cmp("page002.jpg", "page10.jpg"));
+        assert_eq!(Ordering::Greater, natural_cmp("page010.jpg", "page9.jpg"));
+        // Equal values are still ordered, by their padding
+        assert_eq!(Ordering::Less, natural_cmp("page002.jpg", "page2.jpg"));
+        assert_eq!(Ordering::Greater, natural_cmp("page2.jpg", "page002.jpg"));
+        assert_eq!(Ordering::Less, natural_cmp("page0.jpg", "page1.jpg"));
+        assert_eq!(Ordering::Less, natural_cmp("page00.jpg", "page1.jpg"));
+    }
+
+    #[test]
+    fn natural_case() {
+        assert_eq!(Ordering::Less, natural_cmp("Page2.jpg", "page10.jpg"));
+        assert_eq!(Ordering::Less, natural_cmp("a.jpg", "B.jpg"));
+        assert_eq!(Ordering::Greater, natural_cmp("b.jpg", "A.jpg"));
+        // Equal but for case is still an order
+        assert_eq!(Ordering::Less, natural_cmp("Page1.jpg", "page1.jpg"));
+        assert_eq!(Ordering::Greater, natural_cmp("page1.jpg", "Page1.jpg"));
+    }
+
+    #[test]
+    fn natural_long_numbers() {
+        let long = "p123456789012345678901234567890.jpg";
+        let longer = "p1234567890123456789012345678901.jpg";
+        assert_eq!(Ordering::Less, natural_cmp(long, longer));
+        assert_eq!(Ordering::Greater, natural_cmp(longer, long));
+        assert_eq!(
+            Ordering::Less,
+            natural_cmp(long, "p123456789012345678901234567891.jpg")
+        );
+        assert_eq!(Ordering::Less, natural_cmp("p99.jpg", long));
+        assert_eq!(
+            Ordering::Less,
+            natural_cmp("p0000000000000000000000000000002.jpg", "p10.jpg")
+        );
+    }
+
+    #[test]
+    fn natural_prefix() {
+        assert_eq!(Ordering::Equal, natural_cmp("page1.jpg", "page1.jpg"));
+        assert_eq!(Ordering::Equal, natural_cmp("", ""));
+        assert_eq!(Ordering::Less, natural_cmp("", "page"));
+        assert_eq!(Ordering::Less, natural_cmp("page", "page1"));
+        assert_eq!(Ordering::Less, natural_cmp("page1", "page1a"));
+        assert_eq!(Ordering::Greater, natural_cmp("page12", "page1"));
+        assert_eq!(Ordering::Less, natural_cmp("page1", "page1.jpg"));
+    }
+
+    /// The spread of each page of `reader`.
+    fn spreads(reader: &CBAReader) -> Vec<Range<usize>> {
+        (0..reader.page_count())
+            .map(|pos| reader.spread(pos))
+            .collect()
+    }
+
+    #[test]
+    fn spread() {
+        let mut reader = CBAReader::read(Path::new("tests/comic")).unwrap();
+        assert_eq!(8, reader.page_count());
+        // The cover, the double page and the page left before it are alone,
+        // and so is the last page once the rest are paired
+        assert_eq!(
+            vec![0..1, 1..2, 2..3, 3..5, 3..5, 5..7, 5..7, 7..8],
+            spreads(&reader)
+        );
+
+        reader.double.fill(false);
+        assert_eq!(
+            vec![0..1, 1..3, 1..3, 3..5, 3..5, 5..7, 5..7, 7..8],
+            spreads(&reader)
+        );
+        reader.pages.pop();
+        assert_eq!(
+            vec![0..1, 1..3, 1..3, 3..5, 3..5, 5..7, 5..7],
+            spreads(&reader)
+        );
+
+        // A double page at the end is alone, not paired with the one before
+        reader.double[reader.pages[6]] = true;
+        assert_eq!(
+            vec![0..1, 1..3, 1..3, 3..5, 3..5, 5..6, 6..7],
+            spreads(&reader)
+        );
+
+        // Past the end, a page is its own spread
+        assert_eq!(20..21, reader.spread(20));
+    }
+
+    #[test]
+    fn spread_previous() {
+        let reader = CBAReader::read(Path::new("tests/comic")).unwrap();
+        // Going back from a spread lands on the start of the one before it,
+        // which ends where it starts
+        let starts = spreads(&reader)
+            .into_iter()
+            .map(|spread| spread.start)
+            .collect::<Vec<_>>();
+        for &start in starts.iter().filter(|&&start| start > 0) {
+            let previous = reader.spread(start - 1);
+            assert_eq!(start, previous.end);
+            assert!(starts.contains(&previous.start));
+        }
+    }
+
+    #[test]
+    fn natural_sort() {
+        let mut names = vec![
+            "Page10.jpg",
+            "page9.jpg",
+            "page1.jpg",
+            "cover.jpg",
+            "page02.jpg",
+            "page2.jpg",
+            "page100.jpg",
+        ];
+        names.sort_by(|l, r| natural_cmp(l, r));
+        assert_eq!(
+            vec![
+                "cover.jpg",
+                "page1.jpg",
+                "page02.jpg",
+                "page2.jpg",
+                "page9.jpg",
+                "Page10.jpg",
+                "page100.jpg",
+            ],
+            names
+        );
     }
 }
@@ -0,0 +1,193 @@
+//! Decoded pages kept in memory, so that turning to a page that was read
+//! ahead or recently shown doesn't wait for it to be decompressed.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// How many decoded pages are kept, and how many are read ahead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// The most bytes of decoded pages to keep in memory.
+    pub size: usize,
+    /// How many pages after the current one to decode in the background.
+    pub prefetch: usize,
+}
+
+impl CacheConfig {
+    pub const DEFAULT_SIZE_MB: usize = 64;
+    pub const DEFAULT_PREFETCH: usize = 3;
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            size: Self::DEFAULT_SIZE_MB * 1024 * 1024,
+            prefetch: Self::DEFAULT_PREFETCH,
+        }
+    }
+}
+
+/// A least recently used cache of file contents, by their index in the
+/// archive or some other key, holding at most `capacity` bytes.
+pub struct Cache<K = usize> {
+    /// The least recently used first
+    entries: VecDeque<(K, Arc<[u8]>)>,
+    /// The total length of the cached contents
+    size: usize,
+    capacity: usize,
+}
+
+impl<K: PartialEq> Cache<K> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    pub fn contains(&self, index: &K) -> bool {
+        self.entries.iter().any(|(i, _)| i == index)
+    }
+
+    /// Get the contents of the file at `index`, marking them as used.
+    pub fn get(&mut self, index: &K) -> Option<Arc<[u8]>> {
+        let position = self.entries.iter().position(|(i, _)| i == index)?;
+        let entry = self.entries.remove(position)?;
+        let data = Arc::clone(&entry.1);
+        self.entries.push_back(entry);
+        Some(data)
+    }
+
+    /// Keep `data` as the contents of the file at `index`, dropping the least
+    /// recently used files to make room. Files bigger than the whole cache
+    /// aren't kept.
+    pub fn insert(&mut self, index: K, data: Arc<[u8]>) {
+        let old = self
+            .entries
+            .iter()
+            .position(|(i, _)| *i == index)
+            .and_then(|position| self.entries.remove(position));
+        if let Some((_, old)) = old {
+            self.size -= old.len();
+        }
+        if data.len() > self.capacity {
+            return;
+        }
+        self.size += data.len();
+        self.entries.push_back((index, data));
+        self.shrink();
+    }
+
+    /// The most bytes the cache holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change how many bytes the cache holds, dropping files if it's
+    /// smaller now.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.shrink();
+    }
+
+    fn shrink(&mut self) {
+        while self.size > self.capacity {
+            let Some((_, data)) = self.entries.pop_front() else {
+                break;
+            };
+            self.size -= data.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Arc<[u8]> {
+        vec![0; len].into()
+    }
+
+    fn keys(cache: &Cache) -> Vec<usize> {
+        cache.entries.iter().map(|&(i, _)| i).collect()
+    }
+
+    #[test]
+    fn eviction() {
+        let mut cache = Cache::new(10);
+        cache.insert(0, data(4));
+        cache.insert(1, data(4));
+        assert_eq!(vec![0, 1], keys(&cache));
+        // Using it makes 0 the most recent, so 1 makes room for 2
+        assert_eq!(4, cache.get(&0).unwrap().len());
+        cache.insert(2, data(4));
+        assert_eq!(vec![0, 2], keys(&cache));
+        assert!(!cache.contains(&1));
+        assert_eq!(None, cache.get(&1));
+        assert_eq!(8, cache.size);
+
+        // As many as it takes are dropped
+        cache.insert(3, data(10));
+        assert_eq!(vec![3], keys(&cache));
+        assert_eq!(10, cache.size);
+        // Empty files fit in a full cache
+        cache.insert(4, data(0));
+        assert_eq!(vec![3, 4], keys(&cache));
+    }
+
+    #[test]
+    fn too_big() {
+        let mut cache = Cache::new(10);
+        cache.insert(0, data(4));
+        cache.insert(1, data(11));
+        assert_eq!(vec![0], keys(&cache));
+        assert_eq!(4, cache.size);
+
+        // Nothing fits in an empty cache
+        let mut cache = Cache::new(0);
+        cache.insert(0, data(1));
+        assert!(!cache.contains(&0));
+        assert_eq!(0, cache.size);
+    }
+
+    #[test]
+    fn reinsert() {
+        let mut cache = Cache::new(10);
+        cache.insert(0, data(4));
+        cache.insert(1, data(4));
+        // The new contents replace the old ones as the most recent
+        cache.insert(0, data(6));
+        assert_eq!(vec![1, 0], keys(&cache));
+        assert_eq!(10, cache.size);
+        assert_eq!(6, cache.get(&0).unwrap().len());
+
+        // Even if they're too big, the old ones don't stay
+        cache.insert(1, data(11));
+        assert_eq!(vec![0], keys(&cache));
+        assert_eq!(6, cache.size);
+    }
+
+    #[test]
+    fn set_capacity() {
+        let mut cache = Cache::new(10);
+        for i in 0..5 {
+            cache.insert(i, data(2));
+        }
+        cache.set_capacity(5);
+        assert_eq!(vec![3, 4], keys(&cache));
+        assert_eq!(5, cache.capacity());
+        cache.set_capacity(0);
+        assert!(keys(&cache).is_empty());
+        assert_eq!(0, cache.size);
+    }
+
+    #[test]
+    fn string_keys() {
+        let mut cache = Cache::new(10);
+        cache.insert("a".to_string(), data(6));
+        cache.insert("b".to_string(), data(6));
+        assert!(!cache.contains(&"a".to_string()));
+        assert_eq!(6, cache.get(&"b".to_string()).unwrap().len());
+    }
+}
@@ -0,0 +1,63 @@
+//! Reading a plain directory of images as a comic.
+//!
+//! Files in subdirectories are included too, named by their path relative
+//! to the directory, so that a directory of chapters reads as one book.
+
+use std::path::{Path, PathBuf};
+
+pub struct Directory {
+    root: PathBuf,
+    /// Paths relative to `root`, separated by `/` like archive entries
+    names: Vec<String>,
+}
+
+impl Directory {
+    pub fn new(root: &Path) -> std::io::Result<Self> {
+        let mut names = vec![];
+        collect(root, "", &mut names)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            names,
+        })
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Read the file at `index`.
+    pub fn read(&self, index: usize) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.root.join(&self.names[index]))
+    }
+
+    /// The size of the file at `index`.
+    pub fn size(&self, index: usize) -> std::io::Result<u64> {
+        Ok(std::fs::metadata(self.root.join(&self.names[index]))?.len())
+    }
+}
+
+/// Add the files under `dir`, whose path relative to the root is `prefix`,
+/// to `names`. Hidden files and directories are skipped.
+fn collect(
+    dir: &Path,
+    prefix: &str,
+    names: &mut Vec<String>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        let name = format!("{prefix}{name}");
+        if entry.file_type()?.is_dir() {
+            collect(&entry.path(), &format!("{name}/"), names)?;
+        } else {
+            names.push(name);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,461 @@
+//! The metadata files that comic book archives often carry alongside their
+//! pages: `ComicInfo.xml`, and the older `CoMet` format.
+
+/// The name of the `ComicInfo` metadata file in an archive.
+pub const COMIC_INFO: &str = "ComicInfo.xml";
+/// The name of the `CoMet` metadata file in an archive.
+pub const COMET: &str = "CoMet.xml";
+
+/// A page as listed in the metadata.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Page {
+    /// The index of the page's image among the archive's images, in natural
+    /// order.
+    pub image: usize,
+    /// What kind of page it is, such as `FrontCover` or `Deleted`.
+    pub kind: Option<String>,
+    /// Whether the image spans two pages.
+    pub double: bool,
+}
+
+/// What's known about a comic.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct Metadata {
+    pub series: Option<String>,
+    /// The issue number, which isn't always a number.
+    pub number: Option<String>,
+    pub title: Option<String>,
+    pub writers: Vec<String>,
+    pub pencillers: Vec<String>,
+    pub summary: Option<String>,
+    pub language: Option<String>,
+    /// Whether the pages are read right to left, like manga.
+    pub right_to_left: bool,
+    /// The file name of the cover image, for formats that name it rather
+    /// than marking its page.
+    pub cover_image: Option<String>,
+    /// The pages in reading order.
+    pub pages: Vec<Page>,
+}
+
+impl Metadata {
+    /// Parse `src` as the metadata file `file_name`, which is one of
+    /// [`COMIC_INFO`] and [`COMET`].
+    pub fn parse(file_name: &str, src: &str) -> Self {
+        if file_name == COMET {
+            Self::parse_comet(src)
+        } else {
+            Self::parse_comic_info(src)
+        }
+    }
+
+    /// Parse a `ComicInfo.xml` file, ignoring anything malformed or unknown.
+    fn parse_comic_info(src: &str) -> Self {
+        let mut metadata = Self::default();
+        for element in elements(src) {
+            let text = element.text();
+            match element.name.as_str() {
+                "Series" => metadata.series = text,
+                "Number" => metadata.number = text,
+                "Title" => metadata.title = text,
+                "Writer" => metadata.writers = list(text.as_deref()),
+                "Penciller" => metadata.pencillers = list(text.as_deref()),
+                "Summary" => metadata.summary = text,
+                "LanguageISO" => metadata.language = text,
+                "Manga" => {
+                    metadata.right_to_left =
+                        text.as_deref() == Some("YesAndRightToLeft");
+                }
+                "Page" => {
+                    let Some(image) = element
+                        .attribute("Image")
+                        .and_then(|image| image.trim().parse().ok())
+                    else {
+                        continue;
+                    };
+                    metadata.pages.push(Page {
+                        image,
+                        kind: element.attribute("Type").map(str::to_string),
+                        double: element
+                            .attribute("DoublePage")
+                            .is_some_and(|b| b.eq_ignore_ascii_case("true")),
+                    });
+                }
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    /// Parse a `CoMet` file, ignoring anything malformed or unknown.
+    fn parse_comet(src: &str) -> Self {
+        let mut metadata = Self::default();
+        for element in elements(src) {
+            let text = element.text();
+            match element.name.as_str() {
+                "series" => metadata.series = text,
+                "issue" => metadata.number = text,
+                "title" => metadata.title = text,
+                "writer" => metadata.writers.extend(text),
+                "penciller" => metadata.pencillers.extend(text),
+                "description" => metadata.summary = text,
+                "language" => metadata.language = text,
+                "readingDirection" => {
+                    metadata.right_to_left = text.as_deref() == Some("rtl");
+                }
+                "coverImage" => metadata.cover_image = text,
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    /// The title to show for the comic, made up of its series, number and
+    /// own title, whichever of them are known.
+    pub fn display_title(&self) -> Option<String> {
+        let series = match (&self.series, &self.number) {
+            (Some(series), Some(number)) => Some(format!("{series} #{number}")),
+            (Some(series), None) => Some(series.clone()),
+            (None, _) => None,
+        };
+        match (series, &self.title) {
+            (Some(series), Some(title)) => Some(format!("{series}: {title}")),
+            (Some(series), None) => Some(series),
+            (None, title) => title.clone(),
+        }
+    }
+
+    /// The writers followed by the pencillers, without repeats.
+    pub fn authors(&self) -> Vec<String> {
+        let mut authors = self.writers.clone();
+        for penciller in &self.pencillers {
+            if !authors.contains(penciller) {
+                authors.push(penciller.clone());
+            }
+        }
+        authors
+    }
+
+    /// Put `images`, which are sorted in natural order, in the order of the
+    /// pages listed here, leaving out deleted ones. Images that aren't
+    /// listed follow in their original order.
+    pub fn order(&self, images: &[usize]) -> Vec<usize> {
+        let mut listed = vec![false; images.len()];
+        let mut ordered = Vec::with_capacity(images.len());
+        for page in &self.pages {
+            let Some(seen) = listed.get_mut(page.image) else {
+                continue;
+            };
+            if *seen {
+                continue;
+            }
+            *seen = true;
+            if page.kind.as_deref() != Some("Deleted") {
+                ordered.push(images[page.image]);
+            }
+        }
+        ordered.extend(
+            images
+                .iter()
+                .zip(listed)
+                .filter(|(_, listed)| !listed)
+                .map(|(&image, _)| image),
+        );
+        ordered
+    }
+}
+
+/// An element of a metadata file. Both formats are flat enough that the
+/// nesting doesn't matter.
+#[derive(Default)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    /// The text directly inside the element
+    text: String,
+}
+
+impl Element {
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The trimmed text, if there is any.
+    fn text(&self) -> Option<String> {
+        let text = self.text.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+/// Every element in `src`, in document order, up to the first syntax error.
+fn elements(src: &str) -> Vec<Element> {
+    use xmlparser::{ElementEnd, Token};
+    let mut elements: Vec<Element> = vec![];
+    // Indices of the elements that are open
+    let mut open = vec![];
+    for token in xmlparser::Tokenizer::from(src) {
+        match token {
+            Ok(Token::ElementStart { local, .. }) => {
+                open.push(elements.len());
+                elements.push(Element {
+                    name: local.to_string(),
+                    ..Element::default()
+                });
+            }
+            Ok(Token::Attribute { local, value, .. }) => {
+                if let Some(&i) = open.last() {
+                    elements[i]
+                        .attributes
+                        .push((local.to_string(), unescape(&value)));
+                }
+            }
+            Ok(Token::ElementEnd {
+                end: ElementEnd::Empty | ElementEnd::Close(..),
+                ..
+            }) => {
+                open.pop();
+            }
+            Ok(Token::Text { text }) => {
+                if let Some(&i) = open.last() {
+                    elements[i].text.push_str(&unescape(&text));
+                }
+            }
+            Ok(Token::Cdata { text, .. }) => {
+                if let Some(&i) = open.last() {
+                    elements[i].text.push_str(&text);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    elements
+}
+
+/// Replace the predefined entities and character references in `s`.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let c = match &rest[1..end] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            reference => reference
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| reference.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        if let Some(c) = c {
+            out.push(c);
+            rest = &rest[end + 1..];
+        } else {
+            out.push('&');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Split a comma separated list of names.
+fn list(text: Option<&str>) -> Vec<String> {
+    text.iter()
+        .flat_map(|text| text.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMIC_INFO_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <Series>Night &amp; Day</Series>
+  <Number>3</Number>
+  <Title>The &#8220;Long&#x201D; Walk</Title>
+  <Writer>Ana López, Kim Park</Writer>
+  <Penciller>Kim Park,  Lee Chen ,</Penciller>
+  <Summary><![CDATA[A <walk>.]]></Summary>
+  <LanguageISO>es</LanguageISO>
+  <Manga>YesAndRightToLeft</Manga>
+  <Pages>
+    <Page Image="1" Type="FrontCover"/>
+    <Page Image="0" DoublePage="True"/>
+    <Page Image="2" Type="Deleted"/>
+    <Page Image="x"/>
+  </Pages>
+</ComicInfo>"#;
+
+    #[test]
+    fn comic_info() {
+        let metadata = Metadata::parse(COMIC_INFO, COMIC_INFO_XML);
+        assert_eq!(Some("Night & Day"), metadata.series.as_deref());
+        assert_eq!(Some("3"), metadata.number.as_deref());
+        assert_eq!(
+            Some("The \u{201c}Long\u{201d} Walk"),
+            metadata.title.as_deref()
+        );
+        assert_eq!(vec!["Ana López", "Kim Park"], metadata.writers);
+        assert_eq!(vec!["Kim Park", "Lee Chen"], metadata.pencillers);
+        assert_eq!(
+            vec!["Ana López", "Kim Park", "Lee Chen"],
+            metadata.authors()
+        );
+        assert_eq!(Some("A <walk>."), metadata.summary.as_deref());
+        assert_eq!(Some("es"), metadata.language.as_deref());
+        assert!(metadata.right_to_left);
+        assert_eq!(
+            Some("Night & Day #3: The \u{201c}Long\u{201d} Walk"),
+            metadata.display_title().as_deref()
+        );
+
+        // The page without an image index is skipped
+        let pages = metadata
+            .pages
+            .iter()
+            .map(|page| (page.image, page.kind.as_deref(), page.double))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, Some("FrontCover"), false),
+                (0, None, true),
+                (2, Some("Deleted"), false)
+            ],
+            pages
+        );
+    }
+
+    #[test]
+    fn manga() {
+        let manga = |value: &str| {
+            let src = format!("<ComicInfo><Manga>{value}</Manga></ComicInfo>");
+            Metadata::parse(COMIC_INFO, &src).right_to_left
+        };
+        assert!(manga("YesAndRightToLeft"));
+        // Manga, but not necessarily read right to left
+        assert!(!manga("Yes"));
+        assert!(!manga("No"));
+        assert!(!manga("Unknown"));
+    }
+
+    #[test]
+    fn comet() {
+        let metadata = Metadata::parse(
+            COMET,
+            r#"<?xml version="1.0"?>
+<comet xmlns="http://www.denvog.com/comet/">
+  <title>Walk</title>
+  <series>Night</series>
+  <issue>1b</issue>
+  <writer>Ana López</writer>
+  <writer>Kim Park</writer>
+  <penciller>Kim Park</penciller>
+  <description>Out &lt;there&gt;</description>
+  <language>en</language>
+  <readingDirection>rtl</readingDirection>
+  <coverImage>covers/front.jpg</coverImage>
+</comet>"#,
+        );
+        assert_eq!(
+            Some("Night #1b: Walk"),
+            metadata.display_title().as_deref()
+        );
+        assert_eq!(vec!["Ana López", "Kim Park"], metadata.authors());
+        assert_eq!(Some("Out <there>"), metadata.summary.as_deref());
+        assert_eq!(Some("en"), metadata.language.as_deref());
+        assert!(metadata.right_to_left);
+        assert_eq!(Some("covers/front.jpg"), metadata.cover_image.as_deref());
+        assert!(metadata.pages.is_empty());
+    }
+
+    #[test]
+    fn malformed() {
+        // Everything before the syntax error is kept
+        let metadata = Metadata::parse(
+            COMIC_INFO,
+            "<ComicInfo><Series>Night</Series><Title>Walk</Title <Number>",
+        );
+        assert_eq!(Some("Night"), metadata.series.as_deref());
+        assert_eq!(None, metadata.number);
+        assert_eq!(None, Metadata::parse(COMIC_INFO, "").display_title());
+    }
+
+    #[test]
+    fn order() {
+        let images = [10, 11, 12, 13, 14];
+        let page = |image, kind: Option<&str>| Page {
+            image,
+            kind: kind.map(str::to_string),
+            double: false,
+        };
+        let order = |pages| {
+            Metadata {
+                pages,
+                ..Metadata::default()
+            }
+            .order(&images)
+        };
+
+        assert_eq!(images.to_vec(), order(vec![]));
+        // Unlisted images follow the listed ones, deleted ones are left out
+        assert_eq!(
+            vec![13, 10, 11, 14],
+            order(vec![
+                page(3, Some("FrontCover")),
+                page(0, None),
+                page(2, Some("Deleted")),
+            ])
+        );
+        // Out of range indices are ignored, and only the first listing of an
+        // image counts
+        assert_eq!(
+            vec![11, 10, 12, 13, 14],
+            order(vec![
+                page(1, None),
+                page(5, None),
+                page(usize::MAX, None),
+                page(1, None),
+                page(0, None),
+                page(0, Some("Deleted")),
+            ])
+        );
+        assert_eq!(
+            vec![11, 12, 13, 14],
+            order(vec![page(0, Some("Deleted")), page(0, None)])
+        );
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!("plain", unescape("plain"));
+        assert_eq!(
+            r#"<a & 'b'> "c""#,
+            unescape("&lt;a &amp; &apos;b&apos;&gt; &quot;c&quot;")
+        );
+        assert_eq!("é é €", unescape("&#233; &#xE9; &#x20AC;"));
+        // Unknown or broken references are kept as they are
+        assert_eq!(
+            "&nbsp; &#xZZ; &#1114112;",
+            unescape("&nbsp; &#xZZ; &#1114112;")
+        );
+        assert_eq!("a & b", unescape("a & b"));
+        assert_eq!("&amp", unescape("&amp"));
+        assert_eq!("&&", unescape("&&amp;"));
+    }
+}
@@ -0,0 +1,888 @@
+//! Reading RAR archives, both the RAR 1.5-4.x format ("RAR4") and RAR 5.0.
+//!
+//! Only what a comic book reader needs is supported: listing the entries
+//! and extracting them one at a time. Files are either stored or compressed
+//! with RAR 2.9 (LZ and `PPMd`) or RAR 5.0 compression. Encryption, files split
+//! over several volumes and the older compression formats are reported as
+//! errors when an affected entry is read.
+
+// Decompression is arithmetic on integers of known ranges
+#![allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_sign_loss
+)]
+
+use std::io::{Read, Seek, SeekFrom};
+
+mod bits;
+mod ppm;
+mod unpack3;
+mod unpack5;
+
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x01\x00";
+
+/// The largest dictionary we are willing to allocate a window for.
+const MAX_DICTIONARY: u64 = 1 << 30;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("IO: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Not a RAR archive")]
+    NotRar,
+    #[error("Corrupt archive: {0}")]
+    Corrupt(&'static str),
+    #[error("Encrypted archives are unsupported")]
+    Encrypted,
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("Checksum mismatch for \"{0}\"")]
+    Checksum(String),
+}
+
+/// How an entry's data is stored.
+#[derive(Debug, Clone)]
+enum Method {
+    Store,
+    /// RAR 2.9 compression
+    Rar3,
+    /// RAR 5.0 compression
+    Rar5,
+    Unsupported(String),
+}
+
+/// A file or directory in the archive.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Entry {
+    pub name: String,
+    /// Unpacked size in bytes, if the archive records it.
+    pub size: Option<u64>,
+    pub is_dir: bool,
+    offset: u64,
+    packed_size: u64,
+    crc: Option<u32>,
+    method: Method,
+    dictionary: u64,
+    /// The entry continues the compressed stream of the previous one.
+    solid: bool,
+    encrypted: bool,
+    split: bool,
+}
+
+impl Entry {
+    fn is_compressed(&self) -> bool {
+        !self.is_dir && !matches!(self.method, Method::Store)
+    }
+}
+
+enum Unpack {
+    Rar3(Box<unpack3::Unpack>),
+    Rar5(Box<unpack5::Unpack>),
+}
+
+/// The decoder of a solid stream, kept so that reading the entries in order
+/// doesn't decompress everything before each of them again.
+struct SolidState {
+    unpack: Unpack,
+    /// The last entry decoded
+    last: usize,
+}
+
+pub struct Archive<R> {
+    reader: R,
+    entries: Vec<Entry>,
+    solid: Option<SolidState>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut signature = [0; 8];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut signature)?;
+        let entries = if signature.starts_with(RAR5_SIGNATURE) {
+            read_rar5_entries(&mut reader)?
+        } else if signature.starts_with(RAR4_SIGNATURE) {
+            read_rar4_entries(&mut reader)?
+        } else {
+            return Err(Error::NotRar);
+        };
+        Ok(Self {
+            reader,
+            entries,
+            solid: None,
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Extract the entry at `index`.
+    pub fn read(&mut self, index: usize) -> Result<Vec<u8>, Error> {
+        let entry = &self.entries[index];
+        if entry.encrypted {
+            return Err(Error::Encrypted);
+        }
+        if entry.split {
+            return Err(Error::Unsupported(
+                "files split across volumes".to_string(),
+            ));
+        }
+        if entry.is_dir {
+            return Ok(vec![]);
+        }
+        let data = match &entry.method {
+            Method::Store => self.packed(index)?,
+            Method::Unsupported(method) => {
+                return Err(Error::Unsupported(method.clone()));
+            }
+            Method::Rar3 | Method::Rar5 => self.unpack(index)?,
+        };
+
+        let entry = &self.entries[index];
+        if entry.size.is_some_and(|size| data.len() as u64 != size) {
+            return Err(Error::Corrupt("wrong unpacked size"));
+        }
+        if entry.crc.is_some_and(|crc| crc != crc32fast::hash(&data)) {
+            return Err(Error::Checksum(entry.name.clone()));
+        }
+        Ok(data)
+    }
+
+    /// The packed data of the entry at `index`.
+    fn packed(&mut self, index: usize) -> Result<Vec<u8>, Error> {
+        let entry = &self.entries[index];
+        let mut data = vec![];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        (&mut self.reader)
+            .take(entry.packed_size)
+            .read_to_end(&mut data)?;
+        if (data.len() as u64) < entry.packed_size {
+            return Err(Error::Corrupt("truncated archive"));
+        }
+        Ok(data)
+    }
+
+    /// Decompress the entry at `index`, along with every entry before it in
+    /// the same solid stream that hasn't been decoded yet.
+    fn unpack(&mut self, index: usize) -> Result<Vec<u8>, Error> {
+        // Stored entries and directories aren't part of solid streams
+        let stream = |i: usize| self.entries[i].is_compressed();
+        let mut first = index;
+        while self.entries[first].solid {
+            match (0..first).rev().find(|&i| stream(i)) {
+                Some(previous) => first = previous,
+                None => break,
+            }
+        }
+        let last = (index + 1..self.entries.len())
+            .take_while(|&i| !stream(i) || self.entries[i].solid)
+            .last()
+            .unwrap_or(index);
+
+        let (mut unpack, start) = match self.solid.take() {
+            Some(solid) if (first..index).contains(&solid.last) => {
+                (solid.unpack, solid.last + 1)
+            }
+            _ => {
+                let entries = &self.entries[first..=last];
+                let mut window = entries
+                    .iter()
+                    .filter(|entry| entry.is_compressed())
+                    .map(|entry| entry.dictionary)
+                    .max()
+                    .unwrap_or(0);
+                // Nothing before or after this entry uses the window, so it
+                // needn't be larger than the entry if we know its size
+                if first == last
+                    && let Some(size) = entries[0].size.and_then(|size| {
+                        size.max(1).checked_next_power_of_two()
+                    })
+                {
+                    window = window.min(size);
+                }
+                if window > MAX_DICTIONARY {
+                    return Err(Error::Unsupported(format!(
+                        "a {} MiB dictionary",
+                        window >> 20
+                    )));
+                }
+                let window = window as usize;
+                let unpack = match self.entries[index].method {
+                    Method::Rar3 => {
+                        Unpack::Rar3(Box::new(unpack3::Unpack::new(window)))
+                    }
+                    _ => Unpack::Rar5(Box::new(unpack5::Unpack::new(window))),
+                };
+                (unpack, first)
+            }
+        };
+
+        let chain = (start..=index).filter(|&i| stream(i)).collect::<Vec<_>>();
+        let mut data = vec![];
+        for i in chain {
+            let packed = self.packed(i)?;
+            let entry = &self.entries[i];
+            let solid = i != first;
+            data = match (&mut unpack, &entry.method) {
+                (Unpack::Rar3(unpack), Method::Rar3) => {
+                    unpack.unpack(&packed, entry.size, solid)?
+                }
+                (Unpack::Rar5(unpack), Method::Rar5) => {
+                    unpack.unpack(&packed, entry.size, solid)?
+                }
+                (_, Method::Unsupported(method)) => {
+                    return Err(Error::Unsupported(method.clone()));
+                }
+                _ => return Err(Error::Corrupt("mixed compression formats")),
+            };
+        }
+        if last > index {
+            self.solid = Some(SolidState {
+                unpack,
+                last: index,
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// Read a little endian integer of `N` bytes at `pos`.
+fn le<const N: usize>(buf: &[u8], pos: usize) -> Result<u64, Error> {
+    let bytes = buf
+        .get(pos..pos + N)
+        .ok_or(Error::Corrupt("header too short"))?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte)))
+}
+
+/// Read the RAR 5 variable length integer at `*pos`, advancing past it.
+fn vint(buf: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.get(*pos).ok_or(Error::Corrupt("header too short"))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Corrupt("invalid number"))
+}
+
+/// Read `len` bytes, or fewer at the end of the file.
+fn read_up_to<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(len);
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_rar4_entries<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<Vec<Entry>, Error> {
+    let mut entries = vec![];
+    let mut pos = RAR4_SIGNATURE.len() as u64;
+    loop {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = read_up_to(reader, 7)?;
+        if header.len() < 7 {
+            // Archives don't need to end with an end block
+            break;
+        }
+        let kind = header[2];
+        let flags = le::<2>(&header, 3)?;
+        let size = le::<2>(&header, 5)? as usize;
+        if size < 7 {
+            return Err(Error::Corrupt("invalid header size"));
+        }
+        header.extend(read_up_to(reader, size - 7)?);
+        if header.len() < size {
+            return Err(Error::Corrupt("truncated archive"));
+        }
+        let long_block = flags & 0x8000 != 0;
+        // File headers and their newer service headers always have data
+        let data_size = if long_block || matches!(kind, 0x74 | 0x7a) {
+            le::<4>(&header, 7)?
+        } else {
+            0
+        };
+
+        match kind {
+            // Archive header
+            0x73 => {
+                if u64::from(crc32fast::hash(&header[2..]) & 0xffff)
+                    != le::<2>(&header, 0)?
+                {
+                    return Err(Error::Corrupt("header checksum mismatch"));
+                }
+                if flags & 0x80 != 0 {
+                    return Err(Error::Encrypted);
+                }
+            }
+            // File header
+            0x74 => {
+                if u64::from(crc32fast::hash(&header[2..]) & 0xffff)
+                    != le::<2>(&header, 0)?
+                {
+                    return Err(Error::Corrupt("header checksum mismatch"));
+                }
+                let mut entry = read_rar4_file(&header, flags)?;
+                entry.offset = pos + size as u64;
+                entries.push(entry);
+            }
+            // End of archive
+            0x7b => break,
+            _ => {}
+        }
+        pos += size as u64 + data_size;
+    }
+    Ok(entries)
+}
+
+fn read_rar4_file(header: &[u8], flags: u64) -> Result<Entry, Error> {
+    let mut packed_size = le::<4>(header, 7)?;
+    let mut size = le::<4>(header, 11)?;
+    let crc = le::<4>(header, 16)? as u32;
+    let version = le::<1>(header, 24)?;
+    let method = le::<1>(header, 25)?;
+    let name_size = le::<2>(header, 26)? as usize;
+    let mut name_start = 32;
+    if flags & 0x100 != 0 {
+        packed_size |= le::<4>(header, 32)? << 32;
+        size |= le::<4>(header, 36)? << 32;
+        name_start = 40;
+    }
+    let name = header
+        .get(name_start..name_start + name_size)
+        .ok_or(Error::Corrupt("header too short"))?;
+    let name = if flags & 0x200 != 0 {
+        match name.iter().position(|&byte| byte == 0) {
+            Some(end) => decode_rar4_name(&name[..end], &name[end + 1..]),
+            None => String::from_utf8_lossy(name).into_owned(),
+        }
+    } else {
+        // In the OEM code page, which for file names is generally ASCII
+        name.iter().map(|&byte| char::from(byte)).collect()
+    };
+
+    let dictionary_bits = (flags >> 5) & 7;
+    let method = match (method, version) {
+        (0x30, _) => Method::Store,
+        (_, 29 | 36) => Method::Rar3,
+        (_, version) => Method::Unsupported(format!(
+            "RAR {}.{} compression",
+            version / 10,
+            version % 10
+        )),
+    };
+    Ok(Entry {
+        name: name.replace('\\', "/"),
+        size: Some(size),
+        is_dir: dictionary_bits == 7,
+        offset: 0,
+        packed_size,
+        crc: Some(crc),
+        method,
+        dictionary: 0x10000 << dictionary_bits.min(6),
+        solid: flags & 0x10 != 0,
+        encrypted: flags & 0x04 != 0,
+        split: flags & 0x03 != 0,
+    })
+}
+
+/// Decode a RAR4 Unicode file name, stored as a compact encoding relative to
+/// the `ascii` version of the name.
+fn decode_rar4_name(ascii: &[u8], encoded: &[u8]) -> String {
+    let mut name = vec![];
+    let mut bytes = encoded.iter().copied();
+    let high_byte = u16::from(bytes.next().unwrap_or(0));
+    let mut flags = 0;
+    let mut flag_bits = 0;
+    while let Some(first) = bytes.next() {
+        if flag_bits == 0 {
+            flags = first;
+            flag_bits = 8;
+            continue;
+        }
+        match flags >> 6 {
+            0 => name.push(u16::from(first)),
+            1 => name.push(u16::from(first) | (high_byte << 8)),
+            2 => {
+                let Some(second) = bytes.next() else { break };
+                name.push(u16::from(first) | (u16::from(second) << 8));
+            }
+            _ => {
+                let length = usize::from(first & 0x7f) + 2;
+                if first & 0x80 != 0 {
+                    let Some(correction) = bytes.next() else {
+                        break;
+                    };
+                    for _ in 0..length {
+                        let Some(&byte) = ascii.get(name.len()) else {
+                            break;
+                        };
+                        let low = byte.wrapping_add(correction);
+                        name.push(u16::from(low) | (high_byte << 8));
+                    }
+                } else {
+                    for _ in 0..length {
+                        let Some(&byte) = ascii.get(name.len()) else {
+                            break;
+                        };
+                        name.push(u16::from(byte));
+                    }
+                }
+            }
+        }
+        flags <<= 2;
+        flag_bits -= 2;
+    }
+    String::from_utf16_lossy(&name)
+}
+
+fn read_rar5_entries<R: Read + Seek>(
+    reader: &mut R,
+) -> Result<Vec<Entry>, Error> {
+    let mut entries = vec![];
+    let mut pos = RAR5_SIGNATURE.len() as u64;
+    loop {
+        reader.seek(SeekFrom::Start(pos))?;
+        // The CRC and a header size of at most three bytes
+        let start = read_up_to(reader, 7)?;
+        if start.len() < 5 {
+            break;
+        }
+        let mut offset = 4;
+        let size = vint(&start, &mut offset)? as usize;
+        if size > 2 << 20 {
+            return Err(Error::Corrupt("invalid header size"));
+        }
+        reader.seek(SeekFrom::Start(pos + offset as u64))?;
+        let header = read_up_to(reader, size)?;
+        if header.len() < size {
+            return Err(Error::Corrupt("truncated archive"));
+        }
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&start[4..offset]);
+        hasher.update(&header);
+        if u64::from(hasher.finalize()) != le::<4>(&start, 0)? {
+            return Err(Error::Corrupt("header checksum mismatch"));
+        }
+
+        let mut field = 0;
+        let kind = vint(&header, &mut field)?;
+        let flags = vint(&header, &mut field)?;
+        let extra_size = if flags & 0x01 != 0 {
+            vint(&header, &mut field)? as usize
+        } else {
+            0
+        };
+        let data_size = if flags & 0x02 != 0 {
+            vint(&header, &mut field)?
+        } else {
+            0
+        };
+        let extra = header
+            .len()
+            .checked_sub(extra_size)
+            .filter(|&extra| extra >= field)
+            .ok_or(Error::Corrupt("invalid extra area size"))?;
+        let data_offset = pos + (offset + size) as u64;
+        let next = data_offset
+            .checked_add(data_size)
+            .ok_or(Error::Corrupt("invalid data size"))?;
+
+        match kind {
+            // File header
+            2 => {
+                let mut entry =
+                    read_rar5_file(&header[..extra], &header[extra..], field)?;
+                entry.offset = data_offset;
+                entry.packed_size = data_size;
+                entry.split = flags & 0x18 != 0;
+                entries.push(entry);
+            }
+            // Archive encryption header
+            4 => return Err(Error::Encrypted),
+            // End of archive
+            5 => break,
+            _ => {}
+        }
+        pos = next;
+    }
+    Ok(entries)
+}
+
+fn read_rar5_file(
+    header: &[u8],
+    extra: &[u8],
+    mut field: usize,
+) -> Result<Entry, Error> {
+    let flags = vint(header, &mut field)?;
+    let size = vint(header, &mut field)?;
+    let _attributes = vint(header, &mut field)?;
+    if flags & 0x02 != 0 {
+        field += 4;
+    }
+    let crc = if flags & 0x04 != 0 {
+        let crc = le::<4>(header, field)? as u32;
+        field += 4;
+        Some(crc)
+    } else {
+        None
+    };
+    let compression = vint(header, &mut field)?;
+    let _host = vint(header, &mut field)?;
+    let name_size = vint(header, &mut field)? as usize;
+    let name = field
+        .checked_add(name_size)
+        .and_then(|end| header.get(field..end))
+        .ok_or(Error::Corrupt("header too short"))?;
+
+    let mut encrypted = false;
+    let mut record = 0;
+    while record < extra.len() {
+        let record_size = vint(extra, &mut record)? as usize;
+        let next = record
+            .checked_add(record_size)
+            .ok_or(Error::Corrupt("invalid extra record size"))?;
+        // File encryption record
+        encrypted |= vint(extra, &mut record)? == 0x01;
+        record = next;
+    }
+
+    let version = compression & 0x3f;
+    let dictionary_bits = (compression >> 10) & 0x1f;
+    let method = match ((compression >> 7) & 7, version) {
+        (0, _) => Method::Store,
+        (_, 0) if dictionary_bits <= 15 => Method::Rar5,
+        (_, 0) => Method::Unsupported("dictionaries over 4 GiB".to_string()),
+        (_, version) => Method::Unsupported(format!(
+            "RAR 5 compression algorithm version {version}"
+        )),
+    };
+    Ok(Entry {
+        name: String::from_utf8_lossy(name).into_owned(),
+        size: (flags & 0x08 == 0).then_some(size),
+        is_dir: flags & 0x01 != 0,
+        offset: 0,
+        packed_size: 0,
+        crc,
+        method,
+        dictionary: 0x20000 << dictionary_bits.min(15),
+        solid: compression & 0x40 != 0,
+        encrypted,
+        split: false,
+    })
+}
+
+/// The sliding dictionary shared by both compression formats.
+///
+/// Everything unpacked goes through the window, and is also collected in the
+/// output for the current file.
+struct Window {
+    data: Vec<u8>,
+    mask: usize,
+    pos: usize,
+}
+
+impl Window {
+    fn new(size: usize) -> Self {
+        let size = size.max(1).next_power_of_two();
+        Self {
+            data: vec![0; size],
+            mask: size - 1,
+            pos: 0,
+        }
+    }
+
+    fn put(&mut self, out: &mut Vec<u8>, byte: u8) {
+        self.data[self.pos & self.mask] = byte;
+        self.pos = self.pos.wrapping_add(1);
+        out.push(byte);
+    }
+
+    fn copy(&mut self, out: &mut Vec<u8>, distance: usize, length: usize) {
+        for _ in 0..length {
+            let byte = self.data[self.pos.wrapping_sub(distance) & self.mask];
+            self.put(out, byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Cursor;
+
+    const SOLID: &[(&str, usize, u32)] = &[
+        ("01.txt", 4000, 0x35f4_36dc),
+        ("notes.txt", 29, 0xa914_8874),
+        ("02.txt", 3500, 0x9691_7d63),
+        ("03.bin", 3000, 0xf69e_7c1a),
+    ];
+
+    fn open(name: &str) -> Archive<File> {
+        Archive::new(File::open(format!("tests/rar/{name}")).unwrap()).unwrap()
+    }
+
+    /// Extract every entry in order, checking the names, sizes and CRCs.
+    fn check(name: &str, expected: &[(&str, usize, u32)]) {
+        let mut archive = open(name);
+        let entries = archive.entries().to_vec();
+        assert_eq!(expected.len(), entries.len(), "{name}");
+        for (i, (entry, &(path, size, crc))) in
+            entries.iter().zip(expected).enumerate()
+        {
+            let data = archive.read(i).unwrap();
+            assert_eq!(path, entry.name, "{name}");
+            assert_eq!(size, data.len(), "{name}: {path}");
+            assert_eq!(crc, crc32fast::hash(&data), "{name}: {path}");
+            if !entry.is_dir {
+                assert_eq!(Some(crc), entry.crc, "{name}: {path}");
+            }
+        }
+    }
+
+    #[test]
+    fn stored() {
+        check(
+            "stored.cbr",
+            &[
+                ("pages", 0, 0),
+                ("pages/01.txt", 300, 0xabf9_9e62),
+                ("pages/02.bin", 200, 0xb850_65b6),
+                ("empty.txt", 0, 0),
+            ],
+        );
+        assert!(open("stored.cbr").entries()[0].is_dir);
+    }
+
+    #[test]
+    fn rar4_lz() {
+        check(
+            "lz.cbr",
+            &[
+                ("text.txt", 6000, 0xd3f6_1ce6),
+                ("binary.bin", 4000, 0x92f3_2454),
+                ("records.bin", 6000, 0x2f98_91b5),
+            ],
+        );
+    }
+
+    #[test]
+    fn rar4_ppmd() {
+        check("ppmd.cbr", &[("text.txt", 5304, 0xe842_9c8f)]);
+        // The first block is PPMd, not LZ
+        assert_ne!(0, open("ppmd.cbr").packed(0).unwrap()[0] & 0x80);
+    }
+
+    #[test]
+    fn rar4_solid() {
+        check("solid.cbr", SOLID);
+    }
+
+    #[test]
+    fn rar5() {
+        check(
+            "rar5.cbr",
+            &[
+                ("pages", 0, 0),
+                ("pages/text.txt", 6000, 0x1209_cfe8),
+                ("pages/binary.bin", 5000, 0xef98_f6c9),
+                ("pages/stored.txt", 200, 0x54e0_86a6),
+            ],
+        );
+    }
+
+    #[test]
+    fn rar5_filters() {
+        check(
+            "rar5_filters.cbr",
+            &[
+                ("x86.exe", 5000, 0x7763_f198),
+                ("arm.bin", 5000, 0x8b03_991e),
+                ("delta.bin", 5000, 0x1d6f_4b6b),
+            ],
+        );
+    }
+
+    #[test]
+    fn rar5_solid() {
+        check("rar5_solid.cbr", SOLID);
+    }
+
+    #[test]
+    fn solid_out_of_order() {
+        for name in ["solid.cbr", "rar5_solid.cbr"] {
+            let mut archive = open(name);
+            for i in [3, 0, 2, 1, 3] {
+                let data = archive.read(i).unwrap();
+                assert_eq!(SOLID[i].2, crc32fast::hash(&data), "{name}: {i}");
+            }
+        }
+    }
+
+    fn encode_vint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    /// A RAR 5 archive with a single header of `kind`, followed by `data`.
+    fn rar5_archive(
+        kind: u64,
+        flags: u64,
+        fields: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut header = encode_vint(kind);
+        header.extend(encode_vint(flags));
+        header.extend(fields);
+        let mut sized = encode_vint(header.len() as u64);
+        sized.extend(header);
+        let mut archive = RAR5_SIGNATURE.to_vec();
+        archive.extend(crc32fast::hash(&sized).to_le_bytes());
+        archive.extend(sized);
+        archive.extend(data);
+        archive
+    }
+
+    /// The fields of a RAR 5 file header for a stored file.
+    fn rar5_file(size: u64, name_size: u64, name: &[u8]) -> Vec<u8> {
+        let mut fields = encode_vint(0);
+        fields.extend(encode_vint(size));
+        fields.extend(encode_vint(0x20));
+        fields.extend(encode_vint(0));
+        fields.extend(encode_vint(0));
+        fields.extend(encode_vint(name_size));
+        fields.extend(name);
+        fields
+    }
+
+    fn rar5_error(archive: Vec<u8>) -> Error {
+        Archive::new(Cursor::new(archive)).err().unwrap()
+    }
+
+    #[test]
+    fn rar5_malformed_headers() {
+        let file = rar5_archive(2, 0, &rar5_file(1, 1, b"a"), b"");
+        assert_eq!(1, Archive::new(Cursor::new(file)).unwrap().entries().len());
+
+        let error =
+            rar5_error(rar5_archive(2, 0, &rar5_file(1, u64::MAX, b"a"), b""));
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+
+        // A data area past the end of the address space
+        let mut fields = encode_vint(u64::MAX);
+        fields.extend(rar5_file(1, 1, b"a"));
+        let error = rar5_error(rar5_archive(2, 0x02, &fields, b""));
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+
+        // An extra area record longer than anything
+        let record = encode_vint(u64::MAX);
+        let mut fields = encode_vint(record.len() as u64);
+        fields.extend(rar5_file(1, 1, b"a"));
+        fields.extend(record);
+        let error = rar5_error(rar5_archive(2, 0x01, &fields, b""));
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+
+        let mut archive = rar5_archive(2, 0, &rar5_file(1, 1, b"a"), b"");
+        archive[RAR5_SIGNATURE.len()] ^= 1;
+        let error = rar5_error(archive);
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+
+        let error = rar5_error(rar5_archive(4, 0, &[], b""));
+        assert!(matches!(error, Error::Encrypted), "{error}");
+    }
+
+    #[test]
+    fn rar5_unknown_size() {
+        let mut archive = open("rar5.cbr");
+        let expected = archive.read(1).unwrap();
+        let packed = archive.packed(1).unwrap();
+
+        let mut fields = encode_vint(packed.len() as u64);
+        // Flags: the size is unknown, the CRC is present
+        fields.extend(encode_vint(0x0c));
+        fields.extend(encode_vint(0));
+        fields.extend(encode_vint(0x20));
+        fields.extend(crc32fast::hash(&expected).to_le_bytes());
+        // Compressed, with the fixture's 1 MiB dictionary
+        fields.extend(encode_vint((3 << 7) | (3 << 10)));
+        fields.extend(encode_vint(0));
+        fields.extend(encode_vint(1));
+        fields.extend(b"a");
+        let mut archive =
+            Archive::new(Cursor::new(rar5_archive(2, 0x02, &fields, &packed)))
+                .unwrap();
+        assert_eq!(None, archive.entries()[0].size);
+        assert_eq!(expected, archive.read(0).unwrap());
+    }
+
+    #[test]
+    fn rar4_malformed_headers() {
+        let mut archive = RAR4_SIGNATURE.to_vec();
+        archive.extend([0, 0, 0x73, 0, 0, 3, 0]);
+        let error = Archive::new(Cursor::new(archive)).err().unwrap();
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+
+        let mut archive = std::fs::read("tests/rar/lz.cbr").unwrap();
+        archive[RAR4_SIGNATURE.len()] ^= 1;
+        let error = Archive::new(Cursor::new(archive)).err().unwrap();
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+
+        let error = Archive::new(Cursor::new(b"PK\x03\x04".repeat(4)))
+            .err()
+            .unwrap();
+        assert!(matches!(error, Error::NotRar), "{error}");
+    }
+
+    #[test]
+    fn truncated() {
+        let archive = std::fs::read("tests/rar/lz.cbr").unwrap();
+        let mut archive =
+            Archive::new(Cursor::new(&archive[..archive.len() / 2])).unwrap();
+        let error = archive.read(archive.entries().len() - 1).err().unwrap();
+        assert!(matches!(error, Error::Corrupt(_)), "{error}");
+    }
+
+    /// Damaged packed data is an error, never a panic.
+    #[test]
+    fn corrupt_data() {
+        for name in [
+            "lz.cbr",
+            "ppmd.cbr",
+            "solid.cbr",
+            "rar5.cbr",
+            "rar5_filters.cbr",
+            "rar5_solid.cbr",
+        ] {
+            let original = std::fs::read(format!("tests/rar/{name}")).unwrap();
+            let entries = open(name).entries().to_vec();
+            for entry in entries.iter().filter(|entry| entry.is_compressed()) {
+                let start = entry.offset as usize;
+                let end = start + entry.packed_size as usize;
+                for pos in (start..end).step_by(61) {
+                    let mut archive = original.clone();
+                    archive[pos] ^= 0x5a;
+                    let mut archive =
+                        Archive::new(Cursor::new(archive)).unwrap();
+                    for i in 0..entries.len() {
+                        let _ = archive.read(i);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Bit input and Huffman decoding shared by both compression formats.
+
+/// Reads the packed data most significant bit first. Reading past the end
+/// yields zeros, which callers detect with [`BitReader::exhausted`].
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit: 0,
+        }
+    }
+
+    fn byte_at(&self, pos: usize) -> u32 {
+        self.data.get(pos).copied().map_or(0, u32::from)
+    }
+
+    /// The next 16 bits, without consuming them.
+    pub fn peek(&self) -> u32 {
+        let bits = (self.byte_at(self.pos) << 16)
+            | (self.byte_at(self.pos + 1) << 8)
+            | self.byte_at(self.pos + 2);
+        (bits >> (8 - self.bit)) & 0xffff
+    }
+
+    /// The next 32 bits, without consuming them.
+    pub fn peek32(&self) -> u32 {
+        let bits = (self.byte_at(self.pos) << 24)
+            | (self.byte_at(self.pos + 1) << 16)
+            | (self.byte_at(self.pos + 2) << 8)
+            | self.byte_at(self.pos + 3);
+        (bits << self.bit) | (self.byte_at(self.pos + 4) >> (8 - self.bit))
+    }
+
+    pub fn skip(&mut self, count: u32) {
+        let bits = self.bit + count;
+        self.pos += (bits / 8) as usize;
+        self.bit = bits % 8;
+    }
+
+    /// Read `count` bits, at most 16.
+    pub fn bits(&mut self, count: u32) -> u32 {
+        let value = self.peek() >> (16 - count);
+        self.skip(count);
+        value
+    }
+
+    /// Skip to the start of the next byte, unless already there.
+    pub fn align(&mut self) {
+        if self.bit != 0 {
+            self.pos += 1;
+            self.bit = 0;
+        }
+    }
+
+    /// Read a whole byte, which must start at a byte boundary.
+    pub fn byte(&mut self) -> u8 {
+        let byte = self.byte_at(self.pos) as u8;
+        self.pos += 1;
+        byte
+    }
+
+    /// The position in bits.
+    pub fn position(&self) -> usize {
+        self.pos * 8 + self.bit as usize
+    }
+
+    /// Whether more bits have been consumed than there are.
+    pub fn exhausted(&self) -> bool {
+        self.position() > self.data.len() * 8
+    }
+}
+
+/// A canonical Huffman code, with codes of up to 15 bits.
+pub struct Huffman {
+    /// The first code longer than each length, left aligned to 16 bits
+    limits: [u32; 16],
+    /// The index in `symbols` of the first code of each length
+    positions: [u32; 16],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code from the code length of every symbol, where 0 means
+    /// that the symbol isn't used.
+    pub fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u32; 16];
+        for &length in lengths {
+            counts[usize::from(length & 0xf)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut limits = [0; 16];
+        let mut positions = [0; 16];
+        let mut upper = 0;
+        for length in 1..16 {
+            upper += counts[length];
+            limits[length] = upper << (16 - length);
+            upper *= 2;
+            positions[length] = positions[length - 1] + counts[length - 1];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        let mut next = positions;
+        for (symbol, &length) in lengths.iter().enumerate() {
+            let length = usize::from(length & 0xf);
+            if length != 0 {
+                symbols[next[length] as usize] = symbol as u16;
+                next[length] += 1;
+            }
+        }
+        Self {
+            limits,
+            positions,
+            symbols,
+        }
+    }
+
+    pub fn decode(&self, bits: &mut BitReader) -> usize {
+        let field = bits.peek() & 0xfffe;
+        let length = (1..15)
+            .find(|&length| field < self.limits[length])
+            .unwrap_or(15);
+        bits.skip(length as u32);
+        let offset = (field - self.limits[length - 1]) >> (16 - length);
+        let pos = (self.positions[length] + offset) as usize;
+        usize::from(self.symbols.get(pos).copied().unwrap_or(0))
+    }
+}
+
+/// Read the code lengths of several Huffman tables, in the run length
+/// encoding both formats use after the lengths of the code length code
+/// itself. `previous` holds the lengths of the last tables, which RAR 2.9
+/// encodes the new lengths relative to.
+pub fn read_lengths(
+    bits: &mut BitReader,
+    lengths: &mut [u8],
+    previous: Option<&[u8]>,
+) -> bool {
+    let mut length_lengths = [0u8; 20];
+    let mut i = 0;
+    while i < length_lengths.len() {
+        let length = bits.bits(4) as u8;
+        if length == 15 {
+            let zeros = bits.bits(4) as usize;
+            if zeros != 0 {
+                let end = (i + zeros + 2).min(length_lengths.len());
+                length_lengths[i..end].fill(0);
+                i = end;
+                continue;
+            }
+        }
+        length_lengths[i] = length;
+        i += 1;
+    }
+
+    let code = Huffman::new(&length_lengths);
+    let mut i = 0;
+    while i < lengths.len() {
+        if bits.exhausted() {
+            return false;
+        }
+        let number = code.decode(bits);
+        match number {
+            0..16 => {
+                let base = previous.map_or(0, |previous| previous[i]);
+                lengths[i] = (number as u8 + base) & 0xf;
+                i += 1;
+            }
+            16..20 => {
+                let count = if number.is_multiple_of(2) {
+                    bits.bits(3) as usize + 3
+                } else {
+                    bits.bits(7) as usize + 11
+                };
+                let end = (i + count).min(lengths.len());
+                if number < 18 {
+                    // Repeat the previous length
+                    if i == 0 {
+                        return false;
+                    }
+                    let length = lengths[i - 1];
+                    lengths[i..end].fill(length);
+                } else {
+                    lengths[i..end].fill(0);
+                }
+                i = end;
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_reader() {
+        let mut bits = BitReader::new(&[0b1011_0011, 0x5a, 0xff]);
+        assert_eq!(0b101, bits.bits(3));
+        assert_eq!(0b1_0011_0101, bits.bits(9));
+        assert_eq!(0xaff0_0000, bits.peek32());
+        bits.align();
+        assert_eq!(0xff, bits.byte());
+        assert!(!bits.exhausted());
+        // Past the end are zeros
+        assert_eq!(0, bits.bits(16));
+        assert!(bits.exhausted());
+    }
+
+    #[test]
+    fn huffman() {
+        // The codes are 10, 0, 110 and 111, and the last symbol is unused
+        let code = Huffman::new(&[2, 1, 3, 3, 0]);
+        let mut bits = BitReader::new(&[0b0101_1011, 0b1000_0000]);
+        let symbols: Vec<_> = (0..5).map(|_| code.decode(&mut bits)).collect();
+        assert_eq!(vec![1, 0, 2, 3, 1], symbols);
+        assert_eq!(10, bits.position());
+    }
+}
@@ -0,0 +1,1306 @@
+//! `PPMd` variant H, as RAR 2.9 uses it for text.
+//!
+//! The model lives in one block of memory handed out in 12 byte units, as in
+//! the original implementation. That isn't only for speed: the model is
+//! restarted when the memory runs out, so how it's allocated decides what
+//! the decompressed data is.
+
+use super::bits::BitReader;
+
+const MAX_ORDER: usize = 64;
+const MAX_FREQ: u32 = 124;
+const INTERVAL: u32 = 1 << 7;
+const BIN_SCALE: u32 = 1 << 14;
+const PERIOD_BITS: u8 = 7;
+const TOP: u32 = 1 << 24;
+const BOTTOM: u32 = 1 << 15;
+
+const UNIT_SIZE: u32 = 12;
+const INDEXES: usize = 38;
+/// Offset of the heap in `Memory::data`, so that 0 can mean null.
+const HEAP_START: u32 = 1;
+
+const INIT_BIN_ESCAPE: [u32; 8] = [
+    0x3cdd, 0x1f3f, 0x59bf, 0x48f3, 0x64a1, 0x5abc, 0x6632, 0x6051,
+];
+const EXP_ESCAPE: [u8; 16] = [25, 14, 9, 7, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2];
+
+/// Memory for contexts, their symbol statistics and the text seen so far,
+/// addressed by offsets.
+///
+/// A context is 12 bytes: the number of symbols (u16), the sum of their
+/// frequencies (u16), a pointer to the array of states (u32) and a pointer
+/// to the suffix context (u32). A context with one symbol keeps its state
+/// in place of the sum and pointer. A state is 6 bytes: the symbol, its
+/// frequency and a pointer to the successor context.
+struct Memory {
+    data: Vec<u8>,
+    size: u32,
+    free_lists: [u32; INDEXES],
+    index_units: [u8; INDEXES],
+    units_index: [u8; 128],
+    glue_count: u32,
+    text: u32,
+    units_start: u32,
+    fake_units_start: u32,
+    low_unit: u32,
+    high_unit: u32,
+}
+
+impl Memory {
+    fn new(size: u32) -> Self {
+        let mut index_units = [0; INDEXES];
+        let mut units = 0;
+        for (i, count) in index_units.iter_mut().enumerate() {
+            units += match i {
+                0..4 => 1,
+                4..8 => 2,
+                8..12 => 3,
+                _ => 4,
+            };
+            *count = units;
+        }
+        let mut units_index = [0; 128];
+        let mut i = 0;
+        for (units, index) in units_index.iter_mut().enumerate() {
+            if usize::from(index_units[i]) < units + 1 {
+                i += 1;
+            }
+            *index = i as u8;
+        }
+        // A unit past the end stays zero, to stop merging free blocks, and
+        // one more holds the head of the list used while merging
+        let end = HEAP_START + size;
+        Self {
+            data: vec![0; (end + 2 * UNIT_SIZE) as usize],
+            size,
+            free_lists: [0; INDEXES],
+            index_units,
+            units_index,
+            glue_count: 0,
+            text: 0,
+            units_start: 0,
+            fake_units_start: 0,
+            low_unit: 0,
+            high_unit: 0,
+        }
+    }
+
+    fn u8(&self, at: u32) -> u8 {
+        self.data[at as usize]
+    }
+
+    fn set_u8(&mut self, at: u32, value: u8) {
+        self.data[at as usize] = value;
+    }
+
+    fn u16(&self, at: u32) -> u16 {
+        let at = at as usize;
+        u16::from_le_bytes([self.data[at], self.data[at + 1]])
+    }
+
+    fn set_u16(&mut self, at: u32, value: u16) {
+        let at = at as usize;
+        self.data[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&self, at: u32) -> u32 {
+        let at = at as usize;
+        u32::from_le_bytes(self.data[at..at + 4].try_into().unwrap())
+    }
+
+    fn set_u32(&mut self, at: u32, value: u32) {
+        let at = at as usize;
+        self.data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn copy(&mut self, to: u32, from: u32, len: u32) {
+        let from = from as usize;
+        self.data
+            .copy_within(from..from + len as usize, to as usize);
+    }
+
+    fn init(&mut self) {
+        self.free_lists = [0; INDEXES];
+        let units_size = UNIT_SIZE * (self.size / 8 / UNIT_SIZE * 7);
+        let text_size = self.size - units_size;
+        self.text = HEAP_START;
+        self.units_start = HEAP_START + text_size;
+        self.fake_units_start = self.units_start;
+        self.low_unit = self.units_start;
+        self.high_unit = self.low_unit + units_size;
+        self.glue_count = 0;
+    }
+
+    fn units(&self, index: usize) -> u32 {
+        u32::from(self.index_units[index])
+    }
+
+    fn index(&self, units: u32) -> usize {
+        usize::from(self.units_index[units as usize - 1])
+    }
+
+    fn insert_node(&mut self, at: u32, index: usize) {
+        self.set_u32(at, self.free_lists[index]);
+        self.free_lists[index] = at;
+    }
+
+    fn remove_node(&mut self, index: usize) -> u32 {
+        let at = self.free_lists[index];
+        self.free_lists[index] = self.u32(at);
+        at
+    }
+
+    fn split_block(&mut self, at: u32, old_index: usize, new_index: usize) {
+        let mut difference = self.units(old_index) - self.units(new_index);
+        let mut at = at + self.units(new_index) * UNIT_SIZE;
+        let mut index = self.index(difference);
+        if self.units(index) != difference {
+            index -= 1;
+            self.insert_node(at, index);
+            let units = self.units(index);
+            at += units * UNIT_SIZE;
+            difference -= units;
+        }
+        let index = self.index(difference);
+        self.insert_node(at, index);
+    }
+
+    /// Merge adjacent free blocks, and sort them into the free lists again.
+    ///
+    /// While merging, a free block has 0xffff where a used block has a
+    /// context's symbol count or a state's symbol and frequency, then the
+    /// number of units, and the next and previous blocks in a list.
+    fn glue_free_blocks(&mut self) {
+        const STAMP: u16 = 0xffff;
+        let head = HEAP_START + self.size + UNIT_SIZE;
+        let nu = |at: u32| at + 2;
+        let next = |at: u32| at + 4;
+        let previous = |at: u32| at + 8;
+
+        if self.low_unit != self.high_unit {
+            self.set_u16(self.low_unit, 0);
+        }
+        self.set_u32(next(head), head);
+        self.set_u32(previous(head), head);
+        for index in 0..INDEXES {
+            while self.free_lists[index] != 0 {
+                let block = self.remove_node(index);
+                let after = self.u32(next(head));
+                self.set_u32(previous(block), head);
+                self.set_u32(next(block), after);
+                self.set_u32(previous(after), block);
+                self.set_u32(next(head), block);
+                self.set_u16(block, STAMP);
+                self.set_u16(nu(block), u16::from(self.index_units[index]));
+            }
+        }
+
+        let mut block = self.u32(next(head));
+        while block != head {
+            loop {
+                let units = u32::from(self.u16(nu(block)));
+                let neighbour = block + units * UNIT_SIZE;
+                let neighbour_units = u32::from(self.u16(nu(neighbour)));
+                if self.u16(neighbour) != STAMP
+                    || units + neighbour_units >= 0x10000
+                {
+                    break;
+                }
+                let (before, after) =
+                    (self.u32(previous(neighbour)), self.u32(next(neighbour)));
+                self.set_u32(next(before), after);
+                self.set_u32(previous(after), before);
+                self.set_u16(nu(block), (units + neighbour_units) as u16);
+            }
+            block = self.u32(next(block));
+        }
+
+        loop {
+            let mut block = self.u32(next(head));
+            if block == head {
+                break;
+            }
+            let (before, after) =
+                (self.u32(previous(block)), self.u32(next(block)));
+            self.set_u32(next(before), after);
+            self.set_u32(previous(after), before);
+
+            let mut units = u32::from(self.u16(nu(block)));
+            while units > 128 {
+                self.insert_node(block, INDEXES - 1);
+                units -= 128;
+                block += 128 * UNIT_SIZE;
+            }
+            let mut index = self.index(units);
+            if self.units(index) != units {
+                index -= 1;
+                let rest = units - self.units(index);
+                self.insert_node(
+                    block + (units - rest) * UNIT_SIZE,
+                    rest as usize - 1,
+                );
+            }
+            self.insert_node(block, index);
+        }
+    }
+
+    fn alloc_units_rare(&mut self, index: usize) -> u32 {
+        if self.glue_count == 0 {
+            self.glue_count = 255;
+            self.glue_free_blocks();
+            if self.free_lists[index] != 0 {
+                return self.remove_node(index);
+            }
+        }
+        let mut i = index;
+        loop {
+            i += 1;
+            if i == INDEXES {
+                // Take the units from the end of the text instead
+                self.glue_count -= 1;
+                let size = self.units(index) * UNIT_SIZE;
+                if self.fake_units_start - self.text > size {
+                    self.fake_units_start -= size;
+                    self.units_start -= size;
+                    return self.units_start;
+                }
+                return 0;
+            }
+            if self.free_lists[i] != 0 {
+                break;
+            }
+        }
+        let block = self.remove_node(i);
+        self.split_block(block, i, index);
+        block
+    }
+
+    fn alloc_units(&mut self, units: u32) -> u32 {
+        let index = self.index(units);
+        if self.free_lists[index] != 0 {
+            return self.remove_node(index);
+        }
+        let size = self.units(index) * UNIT_SIZE;
+        if self.high_unit - self.low_unit >= size {
+            let block = self.low_unit;
+            self.low_unit += size;
+            return block;
+        }
+        self.alloc_units_rare(index)
+    }
+
+    fn alloc_context(&mut self) -> u32 {
+        if self.high_unit != self.low_unit {
+            self.high_unit -= UNIT_SIZE;
+            return self.high_unit;
+        }
+        if self.free_lists[0] != 0 {
+            return self.remove_node(0);
+        }
+        self.alloc_units_rare(0)
+    }
+
+    fn expand_units(&mut self, old: u32, old_units: u32) -> u32 {
+        let old_index = self.index(old_units);
+        if old_index == self.index(old_units + 1) {
+            return old;
+        }
+        let new = self.alloc_units(old_units + 1);
+        if new != 0 {
+            self.copy(new, old, old_units * UNIT_SIZE);
+            self.insert_node(old, old_index);
+        }
+        new
+    }
+
+    fn shrink_units(
+        &mut self,
+        old: u32,
+        old_units: u32,
+        new_units: u32,
+    ) -> u32 {
+        let old_index = self.index(old_units);
+        let new_index = self.index(new_units);
+        if old_index == new_index {
+            return old;
+        }
+        if self.free_lists[new_index] != 0 {
+            let new = self.remove_node(new_index);
+            self.copy(new, old, new_units * UNIT_SIZE);
+            self.insert_node(old, old_index);
+            return new;
+        }
+        self.split_block(old, old_index, new_index);
+        old
+    }
+
+    fn free_units(&mut self, at: u32, units: u32) {
+        let index = self.index(units);
+        self.insert_node(at, index);
+    }
+}
+
+/// Secondary escape estimation
+#[derive(Clone, Copy, Default)]
+struct See {
+    summ: u16,
+    shift: u8,
+    count: u8,
+}
+
+impl See {
+    fn new(value: u16) -> Self {
+        Self {
+            summ: value << (PERIOD_BITS - 4),
+            shift: PERIOD_BITS - 4,
+            count: 4,
+        }
+    }
+
+    fn mean(&mut self) -> u32 {
+        let mean = self.summ >> self.shift;
+        self.summ = self.summ.wrapping_sub(mean);
+        u32::from(mean) + u32::from(mean == 0)
+    }
+
+    fn update(&mut self) {
+        if self.shift < PERIOD_BITS {
+            self.count = self.count.wrapping_sub(1);
+            if self.count == 0 {
+                self.summ = self.summ.wrapping_add(self.summ);
+                self.count = 3 << self.shift;
+                self.shift += 1;
+            }
+        }
+    }
+}
+
+/// The range decoder `PPMd`'s probabilities drive.
+#[derive(Default)]
+struct Coder {
+    low: u32,
+    code: u32,
+    range: u32,
+    low_count: u32,
+    high_count: u32,
+    scale: u32,
+}
+
+impl Coder {
+    fn init(&mut self, bits: &mut BitReader) {
+        self.low = 0;
+        self.code = 0;
+        self.range = u32::MAX;
+        for _ in 0..4 {
+            self.code = (self.code << 8) | u32::from(bits.byte());
+        }
+    }
+
+    fn count(&mut self) -> u32 {
+        self.range /= self.scale;
+        self.code
+            .wrapping_sub(self.low)
+            .checked_div(self.range)
+            .unwrap_or(u32::MAX)
+    }
+
+    fn shift_count(&mut self, shift: u32) -> u32 {
+        self.range >>= shift;
+        self.code
+            .wrapping_sub(self.low)
+            .checked_div(self.range)
+            .unwrap_or(u32::MAX)
+    }
+
+    fn decode(&mut self) {
+        self.low = self
+            .low
+            .wrapping_add(self.range.wrapping_mul(self.low_count));
+        self.range = self
+            .range
+            .wrapping_mul(self.high_count.wrapping_sub(self.low_count));
+    }
+
+    fn normalize(&mut self, bits: &mut BitReader) {
+        loop {
+            if (self.low ^ self.low.wrapping_add(self.range)) >= TOP {
+                if self.range >= BOTTOM {
+                    break;
+                }
+                self.range = self.low.wrapping_neg() & (BOTTOM - 1);
+            }
+            self.code = (self.code << 8) | u32::from(bits.byte());
+            self.range <<= 8;
+            self.low <<= 8;
+        }
+    }
+}
+
+/// Accessors for the fields of contexts and states in memory.
+impl Memory {
+    fn num_stats(&self, context: u32) -> u32 {
+        u32::from(self.u16(context))
+    }
+
+    fn set_num_stats(&mut self, context: u32, value: u32) {
+        self.set_u16(context, value as u16);
+    }
+
+    fn summ_freq(&self, context: u32) -> u32 {
+        u32::from(self.u16(context + 2))
+    }
+
+    fn set_summ_freq(&mut self, context: u32, value: u32) {
+        self.set_u16(context + 2, value as u16);
+    }
+
+    fn stats(&self, context: u32) -> u32 {
+        self.u32(context + 4)
+    }
+
+    fn set_stats(&mut self, context: u32, value: u32) {
+        self.set_u32(context + 4, value);
+    }
+
+    fn suffix(&self, context: u32) -> u32 {
+        self.u32(context + 8)
+    }
+
+    fn set_suffix(&mut self, context: u32, value: u32) {
+        self.set_u32(context + 8, value);
+    }
+
+    fn symbol(&self, state: u32) -> u8 {
+        self.u8(state)
+    }
+
+    fn freq(&self, state: u32) -> u32 {
+        u32::from(self.u8(state + 1))
+    }
+
+    fn set_freq(&mut self, state: u32, value: u32) {
+        self.set_u8(state + 1, value as u8);
+    }
+
+    fn successor(&self, state: u32) -> u32 {
+        self.u32(state + 2)
+    }
+
+    fn set_successor(&mut self, state: u32, value: u32) {
+        self.set_u32(state + 2, value);
+    }
+
+    fn state(&self, state: u32) -> [u8; 6] {
+        let at = state as usize;
+        self.data[at..at + 6].try_into().unwrap()
+    }
+
+    fn set_state(&mut self, state: u32, value: [u8; 6]) {
+        let at = state as usize;
+        self.data[at..at + 6].copy_from_slice(&value);
+    }
+
+    fn swap_states(&mut self, a: u32, b: u32) {
+        let (first, second) = (self.state(a), self.state(b));
+        self.set_state(a, second);
+        self.set_state(b, first);
+    }
+}
+
+fn one_state(context: u32) -> u32 {
+    context + 2
+}
+
+const STATE_SIZE: u32 = 6;
+
+pub struct Model {
+    memory: Option<Memory>,
+    coder: Coder,
+    min_context: u32,
+    max_context: u32,
+    found_state: u32,
+    num_masked: u32,
+    init_escape: u32,
+    order_fall: u32,
+    max_order: u32,
+    run_length: i32,
+    init_run_length: i32,
+    escape_count: u8,
+    previous_success: u32,
+    high_bits_flag: u32,
+    char_mask: [u8; 256],
+    ns_to_index: [u8; 256],
+    ns_to_bs_index: [u8; 256],
+    high_bits_flags: [u8; 256],
+    see: [[See; 16]; 25],
+    bin_summ: [[u16; 64]; 128],
+}
+
+impl Model {
+    pub fn new() -> Self {
+        Self {
+            memory: None,
+            coder: Coder::default(),
+            min_context: 0,
+            max_context: 0,
+            found_state: 0,
+            num_masked: 0,
+            init_escape: 0,
+            order_fall: 0,
+            max_order: 0,
+            run_length: 0,
+            init_run_length: 0,
+            escape_count: 0,
+            previous_success: 0,
+            high_bits_flag: 0,
+            char_mask: [0; 256],
+            ns_to_index: [0; 256],
+            ns_to_bs_index: [0; 256],
+            high_bits_flags: [0; 256],
+            see: [[See::default(); 16]; 25],
+            bin_summ: [[0; 64]; 128],
+        }
+    }
+
+    /// Start a `PPMd` block: read its parameters, and either reset the model
+    /// or continue with the one from the previous block.
+    pub fn decode_init(
+        &mut self,
+        bits: &mut BitReader,
+        escape: &mut u8,
+    ) -> bool {
+        let flags = bits.byte();
+        let reset = flags & 0x20 != 0;
+        let megabytes = if reset {
+            u32::from(bits.byte())
+        } else if self.memory.is_none() {
+            return false;
+        } else {
+            0
+        };
+        if flags & 0x40 != 0 {
+            *escape = bits.byte();
+        }
+        self.coder.init(bits);
+        if reset {
+            let mut max_order = u32::from(flags & 0x1f) + 1;
+            if max_order > 16 {
+                max_order = 16 + (max_order - 16) * 3;
+            }
+            if max_order == 1 {
+                self.memory = None;
+                return false;
+            }
+            let size = (megabytes + 1) << 20;
+            if self
+                .memory
+                .as_ref()
+                .is_none_or(|memory| memory.size != size)
+            {
+                self.memory = Some(Memory::new(size));
+            }
+            self.start_model(max_order);
+        }
+        self.min_context != 0
+    }
+
+    fn memory(&self) -> &Memory {
+        self.memory.as_ref().unwrap()
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory {
+        self.memory.as_mut().unwrap()
+    }
+
+    fn start_model(&mut self, max_order: u32) {
+        self.escape_count = 1;
+        self.max_order = max_order;
+        self.restart_model();
+
+        self.ns_to_bs_index[0] = 0;
+        self.ns_to_bs_index[1] = 2;
+        self.ns_to_bs_index[2..11].fill(4);
+        self.ns_to_bs_index[11..].fill(6);
+        for i in 0..3 {
+            self.ns_to_index[i] = i as u8;
+        }
+        let (mut m, mut k, mut step) = (3, 1, 1);
+        for i in 3..256 {
+            self.ns_to_index[i] = m;
+            k -= 1;
+            if k == 0 {
+                step += 1;
+                k = step;
+                m += 1;
+            }
+        }
+        self.high_bits_flags[..0x40].fill(0);
+        self.high_bits_flags[0x40..].fill(8);
+    }
+
+    fn restart_model(&mut self) {
+        self.char_mask = [0; 256];
+        let max_order = self.max_order;
+        let memory = self.memory_mut();
+        memory.init();
+        let context = memory.alloc_context();
+        memory.set_suffix(context, 0);
+        memory.set_num_stats(context, 256);
+        memory.set_summ_freq(context, 257);
+        let stats = memory.alloc_units(128);
+        memory.set_stats(context, stats);
+        for symbol in 0..256 {
+            let slot = stats + symbol * STATE_SIZE;
+            memory.set_state(slot, [symbol as u8, 1, 0, 0, 0, 0]);
+        }
+
+        self.init_run_length = -(max_order.min(12) as i32) - 1;
+        self.min_context = context;
+        self.max_context = context;
+        self.found_state = stats;
+        self.order_fall = max_order;
+        self.run_length = self.init_run_length;
+        self.previous_success = 0;
+
+        for (i, row) in self.bin_summ.iter_mut().enumerate() {
+            for (k, &escape) in INIT_BIN_ESCAPE.iter().enumerate() {
+                let value = (BIN_SCALE - escape / (i as u32 + 2)) as u16;
+                for m in (0..64).step_by(8) {
+                    row[k + m] = value;
+                }
+            }
+        }
+        for (i, row) in self.see.iter_mut().enumerate() {
+            row.fill(See::new(5 * i as u16 + 10));
+        }
+    }
+
+    /// Whether `context` points to a context, not null or into the text.
+    fn valid_context(&self, context: u32) -> bool {
+        let memory = self.memory();
+        context > memory.text && (context as usize) < memory.data.len()
+    }
+
+    /// Decode a symbol, or `None` if the data is corrupt.
+    pub fn decode_char(&mut self, bits: &mut BitReader) -> Option<u8> {
+        if self.memory.is_none() || !self.valid_context(self.min_context) {
+            return None;
+        }
+        if self.memory().num_stats(self.min_context) == 1 {
+            self.decode_bin_symbol();
+        } else {
+            let stats = self.memory().stats(self.min_context);
+            if !self.valid_context(stats) || !self.decode_symbol1() {
+                return None;
+            }
+        }
+        self.coder.decode();
+        while self.found_state == 0 {
+            self.coder.normalize(bits);
+            loop {
+                self.order_fall += 1;
+                self.min_context = self.memory().suffix(self.min_context);
+                if !self.valid_context(self.min_context) {
+                    return None;
+                }
+                if self.memory().num_stats(self.min_context) != self.num_masked
+                {
+                    break;
+                }
+            }
+            if !self.decode_symbol2() {
+                return None;
+            }
+            self.coder.decode();
+        }
+        let memory = self.memory();
+        let symbol = memory.symbol(self.found_state);
+        let successor = memory.successor(self.found_state);
+        if self.order_fall == 0 && successor > memory.text {
+            self.min_context = successor;
+            self.max_context = successor;
+        } else {
+            self.update_model();
+            if self.escape_count == 0 {
+                self.escape_count = 1;
+                self.char_mask = [0; 256];
+            }
+        }
+        self.coder.normalize(bits);
+        Some(symbol)
+    }
+
+    /// Decode in a context with more than one symbol.
+    fn decode_symbol1(&mut self) -> bool {
+        let context = self.min_context;
+        let memory = self.memory.as_mut().unwrap();
+        self.coder.scale = memory.summ_freq(context);
+        let mut state = memory.stats(context);
+        let count = self.coder.count();
+        if count >= self.coder.scale {
+            return false;
+        }
+        let mut high = memory.freq(state);
+        if count < high {
+            self.coder.high_count = high;
+            self.previous_success = u32::from(2 * high > self.coder.scale);
+            self.run_length += self.previous_success as i32;
+            self.found_state = state;
+            high += 4;
+            memory.set_freq(state, high);
+            memory.set_summ_freq(context, memory.summ_freq(context) + 4);
+            if high > MAX_FREQ {
+                self.rescale();
+            }
+            self.coder.low_count = 0;
+            return true;
+        }
+        if self.found_state == 0 {
+            return false;
+        }
+        self.previous_success = 0;
+        let num_stats = memory.num_stats(context);
+        let mut i = num_stats - 1;
+        loop {
+            state += STATE_SIZE;
+            high += memory.freq(state);
+            if high > count {
+                break;
+            }
+            i -= 1;
+            if i == 0 {
+                // Escape to the suffix, with all these symbols masked
+                let found_symbol = memory.symbol(self.found_state);
+                self.high_bits_flag =
+                    u32::from(self.high_bits_flags[usize::from(found_symbol)]);
+                self.coder.low_count = high;
+                self.coder.high_count = self.coder.scale;
+                self.num_masked = num_stats;
+                self.found_state = 0;
+                for i in 0..num_stats {
+                    let symbol = memory.symbol(state - i * STATE_SIZE);
+                    self.char_mask[usize::from(symbol)] = self.escape_count;
+                }
+                return true;
+            }
+        }
+        self.coder.low_count = high - memory.freq(state);
+        self.coder.high_count = high;
+        self.update1(state);
+        true
+    }
+
+    /// Decode in a context with one symbol.
+    fn decode_bin_symbol(&mut self) {
+        let memory = self.memory.as_ref().unwrap();
+        let state = one_state(self.min_context);
+        let symbol = memory.symbol(state);
+        let freq = memory.freq(state);
+        let found_symbol = memory.symbol(self.found_state);
+        self.high_bits_flag =
+            u32::from(self.high_bits_flags[usize::from(found_symbol)]);
+        let suffix_stats = memory.num_stats(memory.suffix(self.min_context));
+        let column = self.previous_success
+            + u32::from(self.ns_to_bs_index[suffix_stats as usize - 1])
+            + self.high_bits_flag
+            + 2 * u32::from(self.high_bits_flags[usize::from(symbol)])
+            + ((self.run_length >> 26) & 0x20) as u32;
+        let summ = u32::from(self.bin_summ[freq as usize - 1][column as usize]);
+        let mean = (summ + (1 << (PERIOD_BITS - 2))) >> PERIOD_BITS;
+        let new_summ;
+        if self.coder.shift_count(14) < summ {
+            self.found_state = state;
+            self.memory_mut()
+                .set_freq(state, freq + u32::from(freq < 128));
+            self.coder.low_count = 0;
+            self.coder.high_count = summ;
+            new_summ = summ + INTERVAL - mean;
+            self.previous_success = 1;
+            self.run_length += 1;
+        } else {
+            self.coder.low_count = summ;
+            self.coder.high_count = BIN_SCALE;
+            new_summ = summ - mean;
+            self.init_escape = u32::from(EXP_ESCAPE[(new_summ >> 10) as usize]);
+            self.num_masked = 1;
+            self.char_mask[usize::from(symbol)] = self.escape_count;
+            self.previous_success = 0;
+            self.found_state = 0;
+        }
+        self.bin_summ[freq as usize - 1][column as usize] = new_summ as u16;
+    }
+
+    /// Decode in a suffix context, after escaping from `num_masked` symbols.
+    fn decode_symbol2(&mut self) -> bool {
+        let context = self.min_context;
+        let num_stats = self.memory().num_stats(context);
+        let difference = num_stats.saturating_sub(self.num_masked);
+        if difference == 0 {
+            return false;
+        }
+        let see = self.make_escape_freq(difference);
+
+        let memory = self.memory.as_ref().unwrap();
+        let end = memory.stats(context) + num_stats * STATE_SIZE;
+        let mut states = [0; 256];
+        let mut state = memory.stats(context);
+        let mut high = 0;
+        for slot in states.iter_mut().take(difference as usize) {
+            while self.char_mask[usize::from(memory.symbol(state))]
+                == self.escape_count
+            {
+                state += STATE_SIZE;
+                if state >= end {
+                    return false;
+                }
+            }
+            high += memory.freq(state);
+            *slot = state;
+            state += STATE_SIZE;
+        }
+        let states = &states[..difference as usize];
+
+        self.coder.scale += high;
+        let count = self.coder.count();
+        if count >= self.coder.scale {
+            return false;
+        }
+        if count < high {
+            let mut high = 0;
+            let mut found = states[0];
+            for &state in states {
+                found = state;
+                high += memory.freq(state);
+                if high > count {
+                    break;
+                }
+            }
+            self.coder.low_count = high - memory.freq(found);
+            self.coder.high_count = high;
+            if let Some((i, j)) = see {
+                self.see[i][j].update();
+            }
+            self.update2(found);
+        } else {
+            self.coder.low_count = high;
+            self.coder.high_count = self.coder.scale;
+            for &state in states {
+                let symbol = memory.symbol(state);
+                self.char_mask[usize::from(symbol)] = self.escape_count;
+            }
+            if let Some((i, j)) = see {
+                let see = &mut self.see[i][j];
+                see.summ = see.summ.wrapping_add(self.coder.scale as u16);
+            }
+            self.num_masked = num_stats;
+        }
+        true
+    }
+
+    /// Set the coder's scale to the escape frequency, returning which
+    /// estimator it came from, if any.
+    fn make_escape_freq(&mut self, difference: u32) -> Option<(usize, usize)> {
+        let memory = self.memory();
+        let context = self.min_context;
+        let num_stats = memory.num_stats(context);
+        if num_stats == 256 {
+            self.coder.scale = 1;
+            return None;
+        }
+        let suffix_stats = memory.num_stats(memory.suffix(context));
+        let i = usize::from(self.ns_to_index[difference as usize - 1]);
+        let j = usize::from(
+            (difference as i32) < suffix_stats as i32 - num_stats as i32,
+        ) + 2 * usize::from(memory.summ_freq(context) < 11 * num_stats)
+            + 4 * usize::from(self.num_masked > difference)
+            + self.high_bits_flag as usize;
+        self.coder.scale = self.see[i][j].mean();
+        Some((i, j))
+    }
+
+    fn update1(&mut self, state: u32) {
+        let context = self.min_context;
+        let memory = self.memory_mut();
+        memory.set_freq(state, memory.freq(state) + 4);
+        memory.set_summ_freq(context, memory.summ_freq(context) + 4);
+        self.found_state = state;
+        let memory = self.memory_mut();
+        if memory.freq(state) > memory.freq(state - STATE_SIZE) {
+            memory.swap_states(state, state - STATE_SIZE);
+            self.found_state = state - STATE_SIZE;
+            if self.memory().freq(self.found_state) > MAX_FREQ {
+                self.rescale();
+            }
+        }
+    }
+
+    fn update2(&mut self, state: u32) {
+        let context = self.min_context;
+        self.found_state = state;
+        let memory = self.memory_mut();
+        memory.set_freq(state, memory.freq(state) + 4);
+        memory.set_summ_freq(context, memory.summ_freq(context) + 4);
+        if memory.freq(state) > MAX_FREQ {
+            self.rescale();
+        }
+        self.escape_count = self.escape_count.wrapping_add(1);
+        self.run_length = self.init_run_length;
+    }
+
+    /// Halve the frequencies of the current context's symbols, dropping the
+    /// ones that reach zero.
+    fn rescale(&mut self) {
+        let context = self.min_context;
+        let adder = u32::from(self.order_fall != 0);
+        let memory = self.memory.as_mut().unwrap();
+        let old_stats = memory.num_stats(context);
+        let front = memory.stats(context);
+
+        // Move the found state to the front
+        let mut state = self.found_state;
+        while state != front {
+            memory.swap_states(state, state - STATE_SIZE);
+            state -= STATE_SIZE;
+        }
+        memory.set_freq(front, memory.freq(front) + 4);
+        memory.set_summ_freq(context, memory.summ_freq(context) + 4);
+        let mut escape_freq =
+            memory.summ_freq(context) as i32 - memory.freq(front) as i32;
+        let freq = (memory.freq(front) + adder) >> 1;
+        memory.set_freq(front, freq);
+        let mut summ_freq = freq;
+
+        let mut state = front;
+        for _ in 1..old_stats {
+            state += STATE_SIZE;
+            escape_freq -= memory.freq(state) as i32;
+            let freq = (memory.freq(state) + adder) >> 1;
+            memory.set_freq(state, freq);
+            summ_freq += freq;
+            if freq > memory.freq(state - STATE_SIZE) {
+                // Keep the states sorted by frequency
+                let moved = memory.state(state);
+                let mut at = state;
+                loop {
+                    memory.set_state(at, memory.state(at - STATE_SIZE));
+                    at -= STATE_SIZE;
+                    if at == front || freq <= memory.freq(at - STATE_SIZE) {
+                        break;
+                    }
+                }
+                memory.set_state(at, moved);
+            }
+        }
+
+        if memory.freq(state) == 0 {
+            let mut zeros = 0u32;
+            while memory.freq(state) == 0 {
+                zeros += 1;
+                state -= STATE_SIZE;
+            }
+            escape_freq += zeros as i32;
+            let num_stats = old_stats - zeros;
+            memory.set_num_stats(context, num_stats);
+            if num_stats == 1 {
+                let mut first = memory.state(front);
+                loop {
+                    first[1] -= first[1] >> 1;
+                    escape_freq >>= 1;
+                    if escape_freq <= 1 {
+                        break;
+                    }
+                }
+                memory.free_units(front, old_stats.div_ceil(2));
+                self.found_state = one_state(context);
+                memory.set_state(self.found_state, first);
+                return;
+            }
+        }
+        let num_stats = memory.num_stats(context);
+        escape_freq -= escape_freq >> 1;
+        memory
+            .set_summ_freq(context, summ_freq.wrapping_add(escape_freq as u32));
+        let (old_units, new_units) =
+            (old_stats.div_ceil(2), num_stats.div_ceil(2));
+        if old_units != new_units {
+            let front = memory.shrink_units(front, old_units, new_units);
+            memory.set_stats(context, front);
+        }
+        self.found_state = memory.stats(context);
+    }
+
+    /// Create the contexts for the found symbol following the current ones,
+    /// up to the maximum order.
+    fn create_successors(&mut self, skip: bool, hint: u32) -> u32 {
+        let memory = self.memory.as_mut().unwrap();
+        let found_symbol = memory.symbol(self.found_state);
+        let up_branch = memory.successor(self.found_state);
+        let mut context = self.min_context;
+        let mut states = [0u32; MAX_ORDER];
+        let mut count = 0;
+
+        if !skip {
+            states[count] = self.found_state;
+            count += 1;
+        }
+        let mut hint = hint;
+        // A context without a suffix is the root
+        if skip || memory.suffix(context) != 0 {
+            loop {
+                context = memory.suffix(context);
+                let state = if hint != 0 {
+                    std::mem::take(&mut hint)
+                } else if memory.num_stats(context) == 1 {
+                    one_state(context)
+                } else {
+                    let mut state = memory.stats(context);
+                    while memory.symbol(state) != found_symbol {
+                        state += STATE_SIZE;
+                    }
+                    state
+                };
+                if memory.successor(state) != up_branch {
+                    context = memory.successor(state);
+                    break;
+                }
+                if count >= MAX_ORDER {
+                    return 0;
+                }
+                states[count] = state;
+                count += 1;
+                if memory.suffix(context) == 0 {
+                    break;
+                }
+            }
+        }
+        if count == 0 {
+            return context;
+        }
+
+        // The symbol following this one in the text
+        let up_symbol = memory.u8(up_branch);
+        let up_freq = if memory.num_stats(context) == 1 {
+            memory.freq(one_state(context))
+        } else {
+            if context <= memory.text {
+                return 0;
+            }
+            let mut state = memory.stats(context);
+            while memory.symbol(state) != up_symbol {
+                state += STATE_SIZE;
+            }
+            let freq = memory.freq(state) - 1;
+            let escape =
+                memory.summ_freq(context) - memory.num_stats(context) - freq;
+            1 + if 2 * freq <= escape {
+                u32::from(5 * freq > escape)
+            } else {
+                (2 * freq + 3 * escape - 1) / (2 * escape)
+            }
+        };
+        let up_successor = (up_branch + 1).to_le_bytes();
+        let up_state = [
+            up_symbol,
+            up_freq as u8,
+            up_successor[0],
+            up_successor[1],
+            up_successor[2],
+            up_successor[3],
+        ];
+
+        while count > 0 {
+            count -= 1;
+            let child = memory.alloc_context();
+            if child == 0 {
+                return 0;
+            }
+            memory.set_num_stats(child, 1);
+            memory.set_state(one_state(child), up_state);
+            memory.set_suffix(child, context);
+            memory.set_successor(states[count], child);
+            context = child;
+        }
+        context
+    }
+
+    fn update_model(&mut self) {
+        if !self.try_update_model() {
+            self.restart_model();
+            self.escape_count = 0;
+        }
+    }
+
+    /// Update the model after a symbol has been found, returning false if
+    /// the memory ran out.
+    fn try_update_model(&mut self) -> bool {
+        let found = self.found_state;
+        let memory = self.memory.as_mut().unwrap();
+        let found_symbol = memory.symbol(found);
+        let found_freq = memory.freq(found);
+        let mut found_successor = memory.successor(found);
+
+        let hint = self.update_suffix(found_symbol, found_freq);
+
+        if self.order_fall == 0 {
+            let context = self.create_successors(true, hint);
+            self.min_context = context;
+            self.max_context = context;
+            if context == 0 {
+                return false;
+            }
+            self.memory_mut().set_successor(found, context);
+            return true;
+        }
+
+        let memory = self.memory.as_mut().unwrap();
+        memory.set_u8(memory.text, found_symbol);
+        memory.text += 1;
+        let mut successor = memory.text;
+        if memory.text >= memory.fake_units_start {
+            return false;
+        }
+
+        if found_successor != 0 {
+            if found_successor <= memory.text {
+                found_successor = self.create_successors(false, hint);
+                if found_successor == 0 {
+                    return false;
+                }
+            }
+            self.order_fall -= 1;
+            if self.order_fall == 0 {
+                successor = found_successor;
+                if self.max_context != self.min_context {
+                    self.memory_mut().text -= 1;
+                }
+            }
+        } else {
+            memory.set_successor(found, successor);
+            found_successor = self.min_context;
+        }
+
+        let min_context = self.min_context;
+        let memory = self.memory.as_mut().unwrap();
+        let num_stats = memory.num_stats(min_context);
+        let min_summ = memory.summ_freq(min_context);
+        let escape = min_summ.wrapping_sub(num_stats + found_freq - 1);
+
+        let mut context = self.max_context;
+        while context != min_context {
+            let count = self.memory().num_stats(context);
+            if !self.grow_context(context, count, num_stats) {
+                return false;
+            }
+            let memory = self.memory.as_mut().unwrap();
+            let summ = memory.summ_freq(context);
+            let mut freq = 2 * found_freq * (summ + 6);
+            let total = escape.wrapping_add(summ);
+            if freq < 6 * total {
+                freq =
+                    1 + u32::from(freq > total) + u32::from(freq >= 4 * total);
+                memory.set_summ_freq(context, summ + 3);
+            } else {
+                freq = 4
+                    + u32::from(freq >= 9 * total)
+                    + u32::from(freq >= 12 * total)
+                    + u32::from(freq >= 15 * total);
+                memory.set_summ_freq(context, summ + freq);
+            }
+            let state = memory.stats(context) + count * STATE_SIZE;
+            let successor = successor.to_le_bytes();
+            memory.set_state(
+                state,
+                [
+                    found_symbol,
+                    freq as u8,
+                    successor[0],
+                    successor[1],
+                    successor[2],
+                    successor[3],
+                ],
+            );
+            memory.set_num_stats(context, count + 1);
+            context = memory.suffix(context);
+        }
+        self.max_context = found_successor;
+        self.min_context = found_successor;
+        true
+    }
+
+    /// Count the found symbol in the suffix of the current context too,
+    /// returning its state there, if there is a suffix.
+    fn update_suffix(&mut self, found_symbol: u8, found_freq: u32) -> u32 {
+        let memory = self.memory.as_mut().unwrap();
+        let suffix = memory.suffix(self.min_context);
+        if found_freq >= MAX_FREQ / 4 || suffix == 0 {
+            return 0;
+        }
+        if memory.num_stats(suffix) == 1 {
+            let state = one_state(suffix);
+            if memory.freq(state) < 32 {
+                memory.set_freq(state, memory.freq(state) + 1);
+            }
+            return state;
+        }
+        let mut state = memory.stats(suffix);
+        if memory.symbol(state) != found_symbol {
+            while memory.symbol(state) != found_symbol {
+                state += STATE_SIZE;
+            }
+            if memory.freq(state) >= memory.freq(state - STATE_SIZE) {
+                memory.swap_states(state, state - STATE_SIZE);
+                state -= STATE_SIZE;
+            }
+        }
+        if memory.freq(state) < MAX_FREQ - 9 {
+            memory.set_freq(state, memory.freq(state) + 2);
+            memory.set_summ_freq(suffix, memory.summ_freq(suffix) + 2);
+        }
+        state
+    }
+
+    /// Make room for one more state in a context with `count` of them,
+    /// moving a single state out of the context itself. Returns false if
+    /// the memory ran out.
+    fn grow_context(
+        &mut self,
+        context: u32,
+        count: u32,
+        min_count: u32,
+    ) -> bool {
+        let init_escape = self.init_escape;
+        let memory = self.memory.as_mut().unwrap();
+        if count == 1 {
+            let states = memory.alloc_units(1);
+            if states == 0 {
+                return false;
+            }
+            let mut only = memory.state(one_state(context));
+            memory.set_stats(context, states);
+            only[1] = if u32::from(only[1]) < MAX_FREQ / 4 - 1 {
+                only[1] * 2
+            } else {
+                (MAX_FREQ - 4) as u8
+            };
+            memory.set_state(states, only);
+            memory.set_summ_freq(
+                context,
+                u32::from(only[1]) + init_escape + u32::from(min_count > 3),
+            );
+            return true;
+        }
+        if count & 1 == 0 {
+            let states = memory.expand_units(memory.stats(context), count >> 1);
+            if states == 0 {
+                return false;
+            }
+            memory.set_stats(context, states);
+        }
+        let summ = memory.summ_freq(context);
+        let increase = u32::from(2 * count < min_count)
+            + 2 * u32::from(4 * count <= min_count && summ <= 8 * count);
+        memory.set_summ_freq(context, summ + increase);
+        true
+    }
+}
@@ -0,0 +1,805 @@
+//! RAR 2.9 compression, used by RAR 3.x and 4.x: LZ77 with Huffman coding
+//! or `PPMd`, and filters originally given as programs for a virtual machine.
+//!
+//! Only the standard filters that RAR itself produces are supported. They
+//! are recognised by the checksum of their program, as unrar does.
+
+use super::bits::{self, BitReader, Huffman};
+use super::ppm::Model;
+use super::{Error, Window};
+
+/// Sizes of the main, distance, low distance bits and repeated length codes
+const MAIN_CODES: usize = 299;
+const DISTANCE_CODES: usize = 60;
+const LOW_DISTANCE_CODES: usize = 17;
+const LENGTH_CODES: usize = 28;
+const TABLE_SIZE: usize =
+    MAIN_CODES + DISTANCE_CODES + LOW_DISTANCE_CODES + LENGTH_CODES;
+
+const LENGTH_BASES: [u8; 28] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 12, 14, 16, 20, 24, 28, 32, 40, 48, 56, 64,
+    80, 96, 112, 128, 160, 192, 224,
+];
+const LENGTH_BITS: [u8; 28] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5,
+    5, 5, 5,
+];
+/// How many distance slots there are with each number of extra bits
+const DISTANCE_BIT_COUNTS: [usize; 19] =
+    [4, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 14, 0, 12];
+const SHORT_DISTANCE_BASES: [u8; 8] = [0, 4, 8, 16, 32, 64, 128, 192];
+const SHORT_DISTANCE_BITS: [u8; 8] = [2, 2, 3, 4, 5, 6, 6, 6];
+
+/// The most filter programs and pending filters there may be.
+const MAX_FILTERS: usize = 8192;
+/// The size of the virtual machine's memory, which limits filter blocks.
+const VM_MEMORY: usize = 0x40000;
+
+/// The standard filters, identified by the length and CRC of their program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKind {
+    E8,
+    E8E9,
+    Itanium,
+    Delta,
+    Rgb,
+    Audio,
+    /// A program we can't run
+    Unknown,
+}
+
+impl FilterKind {
+    fn identify(code: &[u8]) -> Self {
+        let checksum = code.iter().skip(1).fold(0, |sum, &byte| sum ^ byte);
+        if code.first() != Some(&checksum) {
+            return Self::Unknown;
+        }
+        match (code.len(), crc32fast::hash(code)) {
+            (53, 0xad57_6887) => Self::E8,
+            (57, 0x3cd7_e57e) => Self::E8E9,
+            (120, 0x3769_893f) => Self::Itanium,
+            (29, 0x0e06_077d) => Self::Delta,
+            (149, 0x1c2c_5dc8) => Self::Rgb,
+            (216, 0xbc85_e701) => Self::Audio,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+struct Filter {
+    kind: FilterKind,
+    /// The offset in the file of the data the filter applies to
+    start: usize,
+    length: usize,
+    /// The initial registers of the virtual machine, which are the
+    /// filter's parameters
+    registers: [u32; 7],
+}
+
+struct Tables {
+    main: Huffman,
+    distance: Huffman,
+    low_distance: Huffman,
+    length: Huffman,
+}
+
+/// The decoder state, which carries over between files of a solid stream.
+pub struct Unpack {
+    window: Window,
+    tables: Option<Tables>,
+    /// Code lengths of the last tables, which new ones are relative to
+    old_lengths: [u8; TABLE_SIZE],
+    /// Whether the next file continues with the current tables
+    tables_read: bool,
+    ppm: Option<Model>,
+    /// Whether the current block is `PPMd` rather than LZ
+    ppm_block: bool,
+    ppm_escape: u8,
+    old_distances: [usize; 4],
+    last_length: usize,
+    low_distance_repeats: u32,
+    previous_low_distance: usize,
+    distance_bases: [usize; DISTANCE_CODES],
+    distance_bits: [u8; DISTANCE_CODES],
+    /// The filter programs seen so far
+    programs: Vec<FilterKind>,
+    /// The block length last used with each program
+    program_lengths: Vec<usize>,
+    last_program: usize,
+    filters: Vec<Filter>,
+}
+
+/// How decoding of a file ended.
+enum End {
+    /// The stream marked the end of the file
+    Marked,
+    /// The data ran out, or couldn't be decoded
+    Stopped,
+}
+
+impl Unpack {
+    pub fn new(window: usize) -> Self {
+        let mut distance_bases = [0; DISTANCE_CODES];
+        let mut distance_bits = [0; DISTANCE_CODES];
+        let mut slot = 0;
+        let mut distance = 0;
+        for (bits, &count) in DISTANCE_BIT_COUNTS.iter().enumerate() {
+            for _ in 0..count {
+                distance_bases[slot] = distance;
+                distance_bits[slot] = bits as u8;
+                distance += 1 << bits;
+                slot += 1;
+            }
+        }
+        Self {
+            window: Window::new(window),
+            tables: None,
+            old_lengths: [0; TABLE_SIZE],
+            tables_read: false,
+            ppm: None,
+            ppm_block: false,
+            ppm_escape: 2,
+            old_distances: [0; 4],
+            last_length: 0,
+            low_distance_repeats: 0,
+            previous_low_distance: 0,
+            distance_bases,
+            distance_bits,
+            programs: vec![],
+            program_lengths: vec![],
+            last_program: 0,
+            filters: vec![],
+        }
+    }
+
+    /// Unpack one file, continuing the previous one's stream if `solid`.
+    pub fn unpack(
+        &mut self,
+        data: &[u8],
+        size: Option<u64>,
+        solid: bool,
+    ) -> Result<Vec<u8>, Error> {
+        if !solid {
+            self.tables_read = false;
+            self.old_lengths = [0; TABLE_SIZE];
+            self.ppm_block = false;
+            self.ppm_escape = 2;
+            self.old_distances = [0; 4];
+            self.last_length = 0;
+            self.low_distance_repeats = 0;
+            self.previous_low_distance = 0;
+            self.programs.clear();
+            self.program_lengths.clear();
+            self.last_program = 0;
+        }
+        self.filters.clear();
+
+        let size = size.map(|size| size as usize);
+        let mut out = Vec::with_capacity(size.unwrap_or(0).min(1 << 28));
+        let mut bits = BitReader::new(data);
+        if (!solid || !self.tables_read) && !self.read_tables(&mut bits) {
+            return Err(Error::Corrupt("invalid Huffman tables"));
+        }
+        let end = loop {
+            if Self::overrun(&bits, &out, size) {
+                break End::Stopped;
+            }
+            let step = if self.ppm_block {
+                self.step_ppm(&mut bits, &mut out)
+            } else {
+                self.step_lz(&mut bits, &mut out)?
+            };
+            if let Some(end) = step {
+                break end;
+            }
+        };
+
+        // Several filters of the same block are applied one after another
+        for filter in std::mem::take(&mut self.filters) {
+            let range = filter.start..filter.start + filter.length;
+            if let Some(block) = out.get_mut(range) {
+                apply_filter(&filter, block)?;
+            }
+        }
+        match size {
+            Some(size) if out.len() >= size => out.truncate(size),
+            Some(_) => return Err(Error::Corrupt("unexpected end of data")),
+            None if matches!(end, End::Stopped) => {
+                return Err(Error::Corrupt("unexpected end of data"));
+            }
+            None => {}
+        }
+        Ok(out)
+    }
+
+    /// Whether decoding should stop, because the data ran out or because
+    /// corrupt data has produced more than the file's size.
+    fn overrun(bits: &BitReader, out: &[u8], size: Option<usize>) -> bool {
+        bits.exhausted()
+            || size.is_some_and(|size| out.len() > size + VM_MEMORY)
+    }
+
+    /// Decode one LZ symbol, returning how the file ended if it did.
+    fn step_lz(
+        &mut self,
+        bits: &mut BitReader,
+        out: &mut Vec<u8>,
+    ) -> Result<Option<End>, Error> {
+        let Some(tables) = &self.tables else {
+            return Err(Error::Corrupt("missing Huffman tables"));
+        };
+        let number = tables.main.decode(bits);
+        if number < 256 {
+            self.window.put(out, number as u8);
+        } else if number >= 271 {
+            let slot = number - 271;
+            let mut length = usize::from(LENGTH_BASES[slot]) + 3;
+            length += bits.bits(u32::from(LENGTH_BITS[slot])) as usize;
+
+            let slot = tables.distance.decode(bits);
+            let mut distance = self.distance_bases[slot] + 1;
+            let count = u32::from(self.distance_bits[slot]);
+            if slot > 9 {
+                if count > 4 {
+                    distance += ((bits.peek() >> (20 - count)) as usize) << 4;
+                    bits.skip(count - 4);
+                }
+                if self.low_distance_repeats > 0 {
+                    self.low_distance_repeats -= 1;
+                    distance += self.previous_low_distance;
+                } else {
+                    let low = tables.low_distance.decode(bits);
+                    if low == 16 {
+                        self.low_distance_repeats = 15;
+                        distance += self.previous_low_distance;
+                    } else {
+                        distance += low;
+                        self.previous_low_distance = low;
+                    }
+                }
+            } else {
+                distance += bits.bits(count) as usize;
+            }
+            if distance >= 0x2000 {
+                length += 1;
+                if distance >= 0x40000 {
+                    length += 1;
+                }
+            }
+            self.insert_distance(distance);
+            self.last_length = length;
+            self.window.copy(out, distance, length);
+        } else if number == 256 {
+            // End of block: "1" starts new tables, "00" ends the file
+            // and "01" ends it with new tables for the next one
+            let field = bits.peek();
+            if field & 0x8000 != 0 {
+                bits.skip(1);
+                self.tables_read = false;
+                if !self.read_tables(bits) {
+                    return Ok(Some(End::Stopped));
+                }
+            } else {
+                bits.skip(2);
+                self.tables_read = field & 0x4000 == 0;
+                return Ok(Some(End::Marked));
+            }
+        } else if number == 257 {
+            if !self.read_vm_code(bits, out.len()) {
+                return Ok(Some(End::Stopped));
+            }
+        } else if number == 258 {
+            if self.last_length != 0 {
+                let distance = self.old_distances[0];
+                self.window.copy(out, distance, self.last_length);
+            }
+        } else if number < 263 {
+            let index = number - 259;
+            let distance = self.old_distances[index];
+            self.old_distances.copy_within(0..index, 1);
+            self.old_distances[0] = distance;
+            let slot = tables.length.decode(bits);
+            let mut length = usize::from(LENGTH_BASES[slot]) + 2;
+            length += bits.bits(u32::from(LENGTH_BITS[slot])) as usize;
+            self.last_length = length;
+            self.window.copy(out, distance, length);
+        } else {
+            let slot = number - 263;
+            let mut distance = usize::from(SHORT_DISTANCE_BASES[slot]) + 1;
+            let count = u32::from(SHORT_DISTANCE_BITS[slot]);
+            distance += bits.bits(count) as usize;
+            self.insert_distance(distance);
+            self.last_length = 2;
+            self.window.copy(out, distance, 2);
+        }
+        Ok(None)
+    }
+
+    /// Decode one `PPMd` symbol, returning how the file ended if it did.
+    fn step_ppm(
+        &mut self,
+        bits: &mut BitReader,
+        out: &mut Vec<u8>,
+    ) -> Option<End> {
+        let Some(byte) = self.ppm_char(bits) else {
+            return Some(End::Stopped);
+        };
+        if byte != self.ppm_escape {
+            self.window.put(out, byte);
+            return None;
+        }
+        let Some(command) = self.ppm_char(bits) else {
+            return Some(End::Stopped);
+        };
+        match command {
+            // New tables, which may switch back to LZ
+            0 => {
+                if !self.read_tables(bits) {
+                    return Some(End::Stopped);
+                }
+            }
+            // End of file
+            2 => return Some(End::Marked),
+            3 => {
+                if !self.read_vm_code_ppm(bits, out.len()) {
+                    return Some(End::Stopped);
+                }
+            }
+            // A match with a 24 bit distance and 8 bit length
+            4 => {
+                let mut distance = 0;
+                for _ in 0..3 {
+                    let Some(byte) = self.ppm_char(bits) else {
+                        return Some(End::Stopped);
+                    };
+                    distance = (distance << 8) | usize::from(byte);
+                }
+                let Some(length) = self.ppm_char(bits) else {
+                    return Some(End::Stopped);
+                };
+                self.window
+                    .copy(out, distance + 2, usize::from(length) + 32);
+            }
+            // A run of the previous byte
+            5 => {
+                let Some(length) = self.ppm_char(bits) else {
+                    return Some(End::Stopped);
+                };
+                self.window.copy(out, 1, usize::from(length) + 4);
+            }
+            // The escape byte itself
+            _ => self.window.put(out, byte),
+        }
+        None
+    }
+
+    fn ppm_char(&mut self, bits: &mut BitReader) -> Option<u8> {
+        self.ppm.as_mut()?.decode_char(bits)
+    }
+
+    fn insert_distance(&mut self, distance: usize) {
+        self.old_distances.copy_within(0..3, 1);
+        self.old_distances[0] = distance;
+    }
+
+    /// Read the tables at the start of a block, which either starts `PPMd` or
+    /// has the Huffman tables for LZ.
+    fn read_tables(&mut self, bits: &mut BitReader) -> bool {
+        bits.align();
+        if bits.peek() & 0x8000 != 0 {
+            self.ppm_block = true;
+            let ppm = self.ppm.get_or_insert_with(Model::new);
+            return ppm.decode_init(bits, &mut self.ppm_escape);
+        }
+        self.ppm_block = false;
+        self.previous_low_distance = 0;
+        self.low_distance_repeats = 0;
+        if bits.peek() & 0x4000 == 0 {
+            self.old_lengths = [0; TABLE_SIZE];
+        }
+        bits.skip(2);
+
+        let mut lengths = [0; TABLE_SIZE];
+        if !bits::read_lengths(bits, &mut lengths, Some(&self.old_lengths))
+            || bits.exhausted()
+        {
+            return false;
+        }
+        self.old_lengths = lengths;
+        self.tables_read = true;
+        let (main, rest) = lengths.split_at(MAIN_CODES);
+        let (distance, rest) = rest.split_at(DISTANCE_CODES);
+        let (low_distance, length) = rest.split_at(LOW_DISTANCE_CODES);
+        self.tables = Some(Tables {
+            main: Huffman::new(main),
+            distance: Huffman::new(distance),
+            low_distance: Huffman::new(low_distance),
+            length: Huffman::new(length),
+        });
+        true
+    }
+
+    fn read_vm_code(&mut self, bits: &mut BitReader, pos: usize) -> bool {
+        let first = bits.bits(8) as u8;
+        let length = match (first & 7) + 1 {
+            7 => bits.bits(8) as usize + 7,
+            8 => bits.bits(16) as usize,
+            length => usize::from(length),
+        };
+        if length == 0 {
+            return false;
+        }
+        let code = (0..length).map(|_| bits.bits(8) as u8).collect::<Vec<_>>();
+        if bits.exhausted() {
+            return false;
+        }
+        self.add_vm_code(first, &code, pos)
+    }
+
+    fn read_vm_code_ppm(&mut self, bits: &mut BitReader, pos: usize) -> bool {
+        let Some(ppm) = &mut self.ppm else {
+            return false;
+        };
+        let mut next = || ppm.decode_char(bits).map(usize::from);
+        let Some(first) = next() else {
+            return false;
+        };
+        let length = match (first & 7) + 1 {
+            7 => match next() {
+                Some(length) => length + 7,
+                None => return false,
+            },
+            8 => match (next(), next()) {
+                (Some(high), Some(low)) => (high << 8) | low,
+                _ => return false,
+            },
+            length => length,
+        };
+        if length == 0 {
+            return false;
+        }
+        let mut code = Vec::with_capacity(length);
+        for _ in 0..length {
+            let Some(byte) = next() else {
+                return false;
+            };
+            code.push(byte as u8);
+        }
+        self.add_vm_code(first as u8, &code, pos)
+    }
+
+    /// Add a filter for the data from `pos` on, as described by `code`:
+    /// which program to run and its parameters, and the program itself if
+    /// it's a new one.
+    fn add_vm_code(&mut self, first: u8, code: &[u8], pos: usize) -> bool {
+        let mut input = BitReader::new(code);
+        let mut program = self.last_program;
+        if first & 0x80 != 0 {
+            program = read_vm_data(&mut input) as usize;
+            if program == 0 {
+                self.programs.clear();
+                self.program_lengths.clear();
+                self.filters.clear();
+            } else {
+                program -= 1;
+            }
+        }
+        if program > self.programs.len() || program > MAX_FILTERS {
+            return false;
+        }
+        self.last_program = program;
+        let new = program == self.programs.len();
+        if new {
+            self.program_lengths.push(0);
+        }
+        if self.filters.len() > MAX_FILTERS {
+            return false;
+        }
+
+        let mut start = read_vm_data(&mut input) as usize;
+        if first & 0x40 != 0 {
+            start += 258;
+        }
+        let length = if first & 0x20 != 0 {
+            let length = read_vm_data(&mut input) as usize;
+            self.program_lengths[program] = length;
+            length
+        } else {
+            self.program_lengths[program]
+        };
+        let mut registers = [0; 7];
+        registers[4] = length as u32;
+        if first & 0x10 != 0 {
+            let mask = input.bits(7);
+            for (i, register) in registers.iter_mut().enumerate() {
+                if mask & (1 << i) != 0 {
+                    *register = read_vm_data(&mut input);
+                }
+            }
+        }
+
+        if new {
+            let size = read_vm_data(&mut input) as usize;
+            if size == 0
+                || size >= 0x10000
+                || input.position() / 8 + size > code.len()
+            {
+                self.program_lengths.pop();
+                return false;
+            }
+            let program =
+                (0..size).map(|_| input.bits(8) as u8).collect::<Vec<_>>();
+            self.programs.push(FilterKind::identify(&program));
+        }
+        // Data for the program's global memory, which the standard filters
+        // don't use
+        if first & 0x08 != 0 && read_vm_data(&mut input) >= 0x2000 - 0x40 {
+            return false;
+        }
+
+        self.filters.push(Filter {
+            kind: self.programs[program],
+            start: pos + start,
+            length,
+            registers,
+        });
+        true
+    }
+}
+
+/// Read a number in the virtual machine's variable length encoding.
+fn read_vm_data(bits: &mut BitReader) -> u32 {
+    let data = bits.peek();
+    match data & 0xc000 {
+        0 => {
+            bits.skip(6);
+            (data >> 10) & 0xf
+        }
+        0x4000 => {
+            if data & 0x3c00 == 0 {
+                bits.skip(14);
+                0xffff_ff00 | ((data >> 2) & 0xff)
+            } else {
+                bits.skip(10);
+                (data >> 6) & 0xff
+            }
+        }
+        0x8000 => {
+            bits.skip(2);
+            bits.bits(16)
+        }
+        _ => {
+            bits.skip(2);
+            let high = bits.bits(16);
+            (high << 16) | bits.bits(16)
+        }
+    }
+}
+
+fn apply_filter(filter: &Filter, data: &mut [u8]) -> Result<(), Error> {
+    let registers = &filter.registers;
+    let offset = filter.start as u32;
+    let length = data.len();
+    if length > VM_MEMORY {
+        return Err(Error::Corrupt("filter block too long"));
+    }
+    match filter.kind {
+        FilterKind::E8 | FilterKind::E8E9 => {
+            e8(data, offset, filter.kind == FilterKind::E8E9);
+        }
+        FilterKind::Itanium => itanium(data, offset),
+        FilterKind::Delta => {
+            let channels = registers[0] as usize;
+            if channels == 0 || length > VM_MEMORY / 2 {
+                return Err(Error::Corrupt("invalid filter"));
+            }
+            let source = data.to_vec();
+            let mut source = source.iter();
+            for channel in 0..channels {
+                let mut previous = 0u8;
+                for byte in data.iter_mut().skip(channel).step_by(channels) {
+                    previous = previous
+                        .wrapping_sub(source.next().copied().unwrap_or(0));
+                    *byte = previous;
+                }
+            }
+        }
+        FilterKind::Rgb => {
+            let width = (registers[0] as usize).wrapping_sub(3);
+            let red = registers[1] as usize;
+            if !(3..=VM_MEMORY / 2).contains(&length)
+                || width > length
+                || red > 2
+            {
+                return Err(Error::Corrupt("invalid filter"));
+            }
+            rgb(data, width, red);
+        }
+        FilterKind::Audio => {
+            let channels = registers[0] as usize;
+            if channels == 0 || channels > 128 || length > VM_MEMORY / 2 {
+                return Err(Error::Corrupt("invalid filter"));
+            }
+            let source = data.to_vec();
+            let mut source = source.iter();
+            for channel in 0..channels {
+                audio_channel(data, &mut source, channel, channels);
+            }
+        }
+        FilterKind::Unknown => {
+            return Err(Error::Unsupported(
+                "RAR virtual machine filters".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Undo the x86 filter, which made the addresses of calls (and jumps, with
+/// `e9`) absolute.
+fn e8(data: &mut [u8], offset: u32, e9: bool) {
+    const FILE_SIZE: u32 = 0x100_0000;
+    let mut pos = 0;
+    while pos + 5 <= data.len() {
+        let opcode = data[pos];
+        pos += 1;
+        if opcode != 0xe8 && !(e9 && opcode == 0xe9) {
+            continue;
+        }
+        let offset = offset.wrapping_add(pos as u32);
+        let bytes = &mut data[pos..pos + 4];
+        let address = u32::from_le_bytes(bytes.try_into().unwrap());
+        if address & 0x8000_0000 != 0 {
+            if address.wrapping_add(offset) & 0x8000_0000 == 0 {
+                bytes.copy_from_slice(
+                    &address.wrapping_add(FILE_SIZE).to_le_bytes(),
+                );
+            }
+        } else if address.wrapping_sub(FILE_SIZE) & 0x8000_0000 != 0 {
+            bytes.copy_from_slice(&address.wrapping_sub(offset).to_le_bytes());
+        }
+        pos += 4;
+    }
+}
+
+/// Undo the Itanium filter, which made the addresses of branches absolute.
+fn itanium(data: &mut [u8], offset: u32) {
+    const MASKS: [u8; 16] = [4, 4, 6, 6, 0, 0, 7, 7, 4, 4, 0, 0, 4, 4, 0, 0];
+    let mut offset = offset >> 4;
+    let mut pos = 0;
+    while pos + 21 < data.len() {
+        let bundle = &mut data[pos..];
+        let template = i32::from(bundle[0] & 0x1f) - 0x10;
+        if template >= 0 {
+            let mask = MASKS[template as usize];
+            for slot in 0..3 {
+                if mask & (1 << slot) == 0 {
+                    continue;
+                }
+                let start = slot * 41 + 5;
+                if itanium_bits(bundle, start + 37, 4) == 5 {
+                    let address = itanium_bits(bundle, start + 13, 20);
+                    let address = address.wrapping_sub(offset) & 0xfffff;
+                    set_itanium_bits(bundle, address, start + 13, 20);
+                }
+            }
+        }
+        pos += 16;
+        offset = offset.wrapping_add(1);
+    }
+}
+
+/// Undo the RGB filter's prediction of each pixel from its neighbours, and
+/// the subtraction of green from the other two colours.
+fn rgb(data: &mut [u8], width: usize, red: usize) {
+    let source = data.to_vec();
+    let mut source = source.iter();
+    for channel in 0..3 {
+        let mut previous = 0u32;
+        for i in (channel..data.len()).step_by(3) {
+            let mut predicted = previous;
+            if i >= width + 3 {
+                let upper = u32::from(data[i - width]);
+                let upper_left = u32::from(data[i - width - 3]);
+                predicted =
+                    previous.wrapping_add(upper).wrapping_sub(upper_left);
+                let pa = (predicted.wrapping_sub(previous) as i32).abs();
+                let pb = (predicted.wrapping_sub(upper) as i32).abs();
+                let pc = (predicted.wrapping_sub(upper_left) as i32).abs();
+                predicted = if pa <= pb && pa <= pc {
+                    previous
+                } else if pb <= pc {
+                    upper
+                } else {
+                    upper_left
+                };
+            }
+            let byte = (predicted as u8)
+                .wrapping_sub(source.next().copied().unwrap_or(0));
+            data[i] = byte;
+            previous = u32::from(byte);
+        }
+    }
+    for i in (red..data.len() - 2).step_by(3) {
+        let green = data[i + 1];
+        data[i] = data[i].wrapping_add(green);
+        data[i + 2] = data[i + 2].wrapping_add(green);
+    }
+}
+
+/// Undo the audio filter's prediction for one channel.
+fn audio_channel<'a>(
+    data: &mut [u8],
+    source: &mut impl Iterator<Item = &'a u8>,
+    channel: usize,
+    channels: usize,
+) {
+    let mut previous_byte = 0u32;
+    let mut previous_delta = 0i32;
+    let mut differences = [0u32; 7];
+    let (mut d1, mut d2) = (0i32, 0i32);
+    let (mut k1, mut k2, mut k3) = (0i32, 0i32, 0i32);
+    for (count, i) in (channel..data.len()).step_by(channels).enumerate() {
+        let d3 = d2;
+        d2 = previous_delta - d1;
+        d1 = previous_delta;
+        let predicted =
+            (8 * previous_byte as i32 + k1 * d1 + k2 * d2 + k3 * d3) as u32;
+        let predicted = (predicted >> 3) & 0xff;
+        let byte = source.next().copied().unwrap_or(0);
+        let predicted = predicted.wrapping_sub(u32::from(byte));
+        data[i] = predicted as u8;
+        previous_delta = i32::from(predicted.wrapping_sub(previous_byte) as i8);
+        previous_byte = predicted & 0xff;
+
+        let d = i32::from(byte as i8) << 3;
+        for (difference, value) in differences.iter_mut().zip([
+            d,
+            d - d1,
+            d + d1,
+            d - d2,
+            d + d2,
+            d - d3,
+            d + d3,
+        ]) {
+            *difference = difference.wrapping_add(value.unsigned_abs());
+        }
+        if count.is_multiple_of(32) {
+            let mut minimum = differences[0];
+            let mut best = 0;
+            differences[0] = 0;
+            for (j, difference) in differences.iter_mut().enumerate().skip(1) {
+                if *difference < minimum {
+                    minimum = *difference;
+                    best = j;
+                }
+                *difference = 0;
+            }
+            match best {
+                1 if k1 >= -16 => k1 -= 1,
+                2 if k1 < 16 => k1 += 1,
+                3 if k2 >= -16 => k2 -= 1,
+                4 if k2 < 16 => k2 += 1,
+                5 if k3 >= -16 => k3 -= 1,
+                6 if k3 < 16 => k3 += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+fn itanium_bits(data: &[u8], start: usize, count: u32) -> u32 {
+    let pos = start / 8;
+    let bytes = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+    (u32::from_le_bytes(bytes) >> (start % 8)) & (u32::MAX >> (32 - count))
+}
+
+fn set_itanium_bits(data: &mut [u8], value: u32, start: usize, count: u32) {
+    let pos = start / 8;
+    let shift = start % 8;
+    let mask = !((u32::MAX >> (32 - count)) << shift);
+    let bytes = &mut data[pos..pos + 4];
+    let current = u32::from_le_bytes((&*bytes).try_into().unwrap());
+    let new = (current & mask) | (value << shift);
+    bytes.copy_from_slice(&new.to_le_bytes());
+}
@@ -0,0 +1,337 @@
+//! RAR 5.0 compression: LZ77 with Huffman coded blocks, and filters that
+//! undo transformations of executable code and tabular data.
+
+use super::bits::{self, BitReader, Huffman};
+use super::{Error, Window};
+
+/// Sizes of the main, distance, low distance bits and repeated length codes
+const MAIN_CODES: usize = 306;
+const DISTANCE_CODES: usize = 64;
+const LOW_DISTANCE_CODES: usize = 16;
+const LENGTH_CODES: usize = 44;
+const TABLE_SIZE: usize =
+    MAIN_CODES + DISTANCE_CODES + LOW_DISTANCE_CODES + LENGTH_CODES;
+
+/// The longest block a filter may apply to.
+const MAX_FILTER_LENGTH: usize = 0x40_0000;
+
+struct Tables {
+    main: Huffman,
+    distance: Huffman,
+    low_distance: Huffman,
+    length: Huffman,
+}
+
+struct BlockHeader {
+    /// The bit position at which the block ends
+    end: usize,
+    last: bool,
+    has_tables: bool,
+}
+
+enum FilterKind {
+    Delta { channels: usize },
+    E8,
+    E8E9,
+    Arm,
+}
+
+struct Filter {
+    kind: FilterKind,
+    /// The offset in the file of the data the filter applies to
+    start: usize,
+    length: usize,
+}
+
+/// The decoder state, which carries over between files of a solid stream.
+pub struct Unpack {
+    window: Window,
+    tables: Option<Tables>,
+    old_distances: [usize; 4],
+    last_length: usize,
+}
+
+impl Unpack {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: Window::new(window),
+            tables: None,
+            old_distances: [0; 4],
+            last_length: 0,
+        }
+    }
+
+    /// Unpack one file, continuing the previous one's stream if `solid`.
+    pub fn unpack(
+        &mut self,
+        data: &[u8],
+        size: Option<u64>,
+        solid: bool,
+    ) -> Result<Vec<u8>, Error> {
+        if !solid {
+            self.tables = None;
+            self.old_distances = [0; 4];
+            self.last_length = 0;
+        }
+        let size = size.map(|size| size as usize);
+        let mut out = Vec::with_capacity(size.unwrap_or(0).min(1 << 28));
+        let mut filters = vec![];
+        let mut bits = BitReader::new(data);
+        let mut block = self.read_block(&mut bits)?;
+
+        'blocks: loop {
+            while bits.position() >= block.end {
+                if block.last {
+                    break 'blocks;
+                }
+                block = self.read_block(&mut bits)?;
+            }
+            if bits.exhausted() {
+                return Err(Error::Corrupt("unexpected end of data"));
+            }
+            // Don't let corrupt data run on forever
+            if size.is_some_and(|size| out.len() > size + MAX_FILTER_LENGTH) {
+                break;
+            }
+            let Some(tables) = &self.tables else {
+                return Err(Error::Corrupt("missing Huffman tables"));
+            };
+
+            let slot = tables.main.decode(&mut bits);
+            if slot < 256 {
+                self.window.put(&mut out, slot as u8);
+            } else if slot >= 262 {
+                let length = slot_to_length(&mut bits, slot - 262);
+                let distance = read_distance(tables, &mut bits);
+                self.copy_match(&mut out, distance, length);
+            } else if slot == 256 {
+                let filter = read_filter(&mut bits, out.len())?;
+                if filter.length <= MAX_FILTER_LENGTH {
+                    filters.push(filter);
+                }
+            } else if slot == 257 {
+                if self.last_length != 0 {
+                    let distance = self.old_distances[0];
+                    self.window.copy(&mut out, distance, self.last_length);
+                }
+            } else {
+                let index = slot - 258;
+                let distance = self.old_distances[index];
+                self.old_distances.copy_within(0..index, 1);
+                self.old_distances[0] = distance;
+                let slot = tables.length.decode(&mut bits);
+                let length = slot_to_length(&mut bits, slot);
+                self.last_length = length;
+                self.window.copy(&mut out, distance, length);
+            }
+        }
+
+        for filter in filters {
+            if let Some(block) =
+                out.get_mut(filter.start..filter.start + filter.length)
+            {
+                apply_filter(&filter, block);
+            }
+        }
+        if let Some(size) = size {
+            if out.len() < size {
+                return Err(Error::Corrupt("unexpected end of data"));
+            }
+            out.truncate(size);
+        }
+        Ok(out)
+    }
+
+    /// Copy a match, which becomes the most recent distance.
+    fn copy_match(
+        &mut self,
+        out: &mut Vec<u8>,
+        distance: usize,
+        length: usize,
+    ) {
+        // Longer distances imply longer matches
+        let length = length
+            + usize::from(distance > 0x100)
+            + usize::from(distance > 0x2000)
+            + usize::from(distance > 0x40000);
+        self.old_distances.copy_within(0..3, 1);
+        self.old_distances[0] = distance;
+        self.last_length = length;
+        self.window.copy(out, distance, length);
+    }
+
+    /// Read the header at the start of each block, and the Huffman tables
+    /// following it if it has them.
+    fn read_block(
+        &mut self,
+        bits: &mut BitReader,
+    ) -> Result<BlockHeader, Error> {
+        bits.align();
+        let flags = bits.bits(8);
+        let checksum = bits.bits(8);
+        let count = ((flags >> 3) & 3) + 1;
+        if count == 4 {
+            return Err(Error::Corrupt("invalid block header"));
+        }
+        let mut size = 0;
+        for i in 0..count {
+            size |= bits.bits(8) << (i * 8);
+        }
+        if (0x5a ^ flags ^ size ^ (size >> 8) ^ (size >> 16)) & 0xff != checksum
+        {
+            return Err(Error::Corrupt("block header checksum mismatch"));
+        }
+        if bits.exhausted() {
+            return Err(Error::Corrupt("unexpected end of data"));
+        }
+        // The last byte has between 1 and 8 bits of the block in it
+        let end = (bits.position() + size as usize * 8)
+            .saturating_sub(8 - ((flags & 7) + 1) as usize);
+        let header = BlockHeader {
+            end,
+            last: flags & 0x40 != 0,
+            has_tables: flags & 0x80 != 0,
+        };
+
+        if header.has_tables {
+            let mut lengths = [0; TABLE_SIZE];
+            if !bits::read_lengths(bits, &mut lengths, None) {
+                return Err(Error::Corrupt("invalid Huffman tables"));
+            }
+            let (main, rest) = lengths.split_at(MAIN_CODES);
+            let (distance, rest) = rest.split_at(DISTANCE_CODES);
+            let (low_distance, length) = rest.split_at(LOW_DISTANCE_CODES);
+            self.tables = Some(Tables {
+                main: Huffman::new(main),
+                distance: Huffman::new(distance),
+                low_distance: Huffman::new(low_distance),
+                length: Huffman::new(length),
+            });
+        }
+        Ok(header)
+    }
+}
+
+fn slot_to_length(bits: &mut BitReader, slot: usize) -> usize {
+    if slot < 8 {
+        return slot + 2;
+    }
+    let count = slot as u32 / 4 - 1;
+    let length = 2 + ((4 | (slot & 3)) << count);
+    length + bits.bits(count) as usize
+}
+
+fn read_distance(tables: &Tables, bits: &mut BitReader) -> usize {
+    let slot = tables.distance.decode(bits);
+    let mut distance = 1;
+    let mut count = 0;
+    if slot < 4 {
+        distance += slot;
+    } else {
+        count = slot as u32 / 2 - 1;
+        distance += (2 | (slot & 1)) << count;
+    }
+    if count >= 4 {
+        if count > 4 {
+            let high = bits.peek32() >> (36 - count);
+            distance += (high as usize) << 4;
+            bits.skip(count - 4);
+        }
+        distance += tables.low_distance.decode(bits);
+    } else if count > 0 {
+        distance += (bits.peek32() >> (32 - count)) as usize;
+        bits.skip(count);
+    }
+    distance
+}
+
+/// Read a filter, which applies from `pos` on.
+fn read_filter(bits: &mut BitReader, pos: usize) -> Result<Filter, Error> {
+    let start = pos + read_filter_data(bits);
+    let length = read_filter_data(bits);
+    let kind = match bits.bits(3) {
+        0 => FilterKind::Delta {
+            channels: bits.bits(5) as usize + 1,
+        },
+        1 => FilterKind::E8,
+        2 => FilterKind::E8E9,
+        3 => FilterKind::Arm,
+        _ => return Err(Error::Corrupt("unknown filter")),
+    };
+    Ok(Filter {
+        kind,
+        start,
+        length,
+    })
+}
+
+/// Read a filter's start or length: a two bit byte count and that many
+/// bytes, least significant first.
+fn read_filter_data(bits: &mut BitReader) -> usize {
+    let count = bits.bits(2) + 1;
+    let mut data = 0;
+    for i in 0..count {
+        data |= (bits.bits(8) as usize) << (i * 8);
+    }
+    data
+}
+
+fn apply_filter(filter: &Filter, data: &mut [u8]) {
+    match filter.kind {
+        FilterKind::Delta { channels } => {
+            // The bytes of each channel were stored together
+            let source = data.to_vec();
+            let mut source = source.iter();
+            for channel in 0..channels {
+                let mut previous = 0u8;
+                for byte in data.iter_mut().skip(channel).step_by(channels) {
+                    previous = previous
+                        .wrapping_sub(source.next().copied().unwrap_or(0));
+                    *byte = previous;
+                }
+            }
+        }
+        FilterKind::E8 | FilterKind::E8E9 => {
+            const FILE_SIZE: u32 = 0x100_0000;
+            let e9 = matches!(filter.kind, FilterKind::E8E9);
+            let mut pos = 0;
+            // Calls and jumps have a four byte address after the opcode
+            while pos + 5 <= data.len() {
+                let opcode = data[pos];
+                pos += 1;
+                if opcode != 0xe8 && !(e9 && opcode == 0xe9) {
+                    continue;
+                }
+                let offset = ((filter.start + pos) as u32) % FILE_SIZE;
+                let bytes = &mut data[pos..pos + 4];
+                let address = u32::from_le_bytes(bytes.try_into().unwrap());
+                if address & 0x8000_0000 != 0 {
+                    if address.wrapping_add(offset) & 0x8000_0000 == 0 {
+                        bytes.copy_from_slice(
+                            &address.wrapping_add(FILE_SIZE).to_le_bytes(),
+                        );
+                    }
+                } else if address.wrapping_sub(FILE_SIZE) & 0x8000_0000 != 0 {
+                    bytes.copy_from_slice(
+                        &address.wrapping_sub(offset).to_le_bytes(),
+                    );
+                }
+                pos += 4;
+            }
+        }
+        FilterKind::Arm => {
+            for (i, instruction) in data.chunks_exact_mut(4).enumerate() {
+                // BL with the "always" condition
+                if instruction[3] != 0xeb {
+                    continue;
+                }
+                let offset = u32::from(instruction[0])
+                    | (u32::from(instruction[1]) << 8)
+                    | (u32::from(instruction[2]) << 16);
+                let position = (filter.start + i * 4) as u32;
+                let offset = offset.wrapping_sub(position / 4);
+                instruction[..3].copy_from_slice(&offset.to_le_bytes()[..3]);
+            }
+        }
+    }
+}
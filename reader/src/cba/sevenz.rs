@@ -0,0 +1,133 @@
+//! Reading 7z archives.
+//!
+//! 7z compresses files together in blocks, so reading one means
+//! decompressing every file before it in its block. Those files are handed
+//! back along with the one that was asked for, so that they can be kept
+//! instead of being decompressed again.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use sevenz_rust::{BlockDecoder, SevenZArchiveEntry};
+
+pub struct Archive<R> {
+    reader: R,
+    archive: sevenz_rust::Archive,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> Result<Self, sevenz_rust::Error> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let archive = sevenz_rust::Archive::read(&mut reader, len, &[])?;
+        Ok(Self { reader, archive })
+    }
+
+    pub fn entries(&self) -> &[SevenZArchiveEntry] {
+        &self.archive.files
+    }
+
+    /// Read the file at `index`. The other files that are decompressed
+    /// along with it are given to `other` with their indices: the ones
+    /// before it in its block, and the ones after it until `ahead` bytes of
+    /// them have been read.
+    pub fn read(
+        &mut self,
+        index: usize,
+        ahead: usize,
+        other: &mut dyn FnMut(usize, Vec<u8>),
+    ) -> Result<Vec<u8>, sevenz_rust::Error> {
+        let map = &self.archive.stream_map;
+        // Empty files have no block
+        let Some(block) = map.file_folder_index[index] else {
+            return Ok(vec![]);
+        };
+        let mut current = map.folder_first_file_index[block];
+        let mut data = None;
+        let mut read_ahead = 0;
+        let decoder =
+            BlockDecoder::new(block, &self.archive, &[], &mut self.reader);
+        decoder.for_each_entries(&mut |entry, reader| {
+            // The files before it have to be read to get past them
+            let mut contents = vec![];
+            reader.read_to_end(&mut contents)?;
+            if current == index {
+                data = Some(contents);
+            } else if !entry.is_directory {
+                if data.is_some() {
+                    read_ahead += contents.len();
+                }
+                other(current, contents);
+            }
+            current += 1;
+            Ok(data.is_none() || read_ahead < ahead)
+        })?;
+        Ok(data.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// The files of `solid.cb7`, which are all in one block.
+    const SOLID: &[(&str, usize, u32)] = &[
+        ("01.txt", 3000, 0x8056_c0f9),
+        ("02.txt", 2000, 0x71a6_0cb0),
+        ("03.txt", 2500, 0x28e7_5a18),
+        ("04.txt", 1000, 0x4a51_ea11),
+    ];
+
+    fn open() -> Archive<File> {
+        Archive::new(File::open("tests/7z/solid.cb7").unwrap()).unwrap()
+    }
+
+    /// Read the file at `index`, returning the indices of the other files
+    /// that were read along with it.
+    fn read(
+        archive: &mut Archive<File>,
+        index: usize,
+        ahead: usize,
+    ) -> Vec<usize> {
+        let mut others = vec![];
+        let data = archive
+            .read(index, ahead, &mut |i, data| {
+                let (_, size, crc) = SOLID[i];
+                assert_eq!(size, data.len());
+                assert_eq!(crc, crc32fast::hash(&data));
+                others.push(i);
+            })
+            .unwrap();
+        let (_, size, crc) = SOLID[index];
+        assert_eq!(size, data.len());
+        assert_eq!(crc, crc32fast::hash(&data));
+        others
+    }
+
+    #[test]
+    fn solid() {
+        let mut archive = open();
+        assert_eq!(1, archive.archive.folders.len());
+        let names = archive
+            .entries()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<_>>();
+        let expected = SOLID.iter().map(|&(name, ..)| name).collect::<Vec<_>>();
+        assert_eq!(expected, names);
+        for index in 0..SOLID.len() {
+            let others = read(&mut archive, index, 0);
+            assert_eq!((0..index).collect::<Vec<_>>(), others);
+        }
+    }
+
+    #[test]
+    fn read_ahead() {
+        let mut archive = open();
+        // It stops once `ahead` bytes of files after it have been read
+        assert_eq!(vec![0, 2], read(&mut archive, 1, 1));
+        assert_eq!(vec![0, 2], read(&mut archive, 1, 2500));
+        assert_eq!(vec![0, 2, 3], read(&mut archive, 1, 2501));
+        assert_eq!(vec![0, 1, 2], read(&mut archive, 3, usize::MAX));
+    }
+}
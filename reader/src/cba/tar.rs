@@ -0,0 +1,56 @@
+//! Reading tar archives.
+//!
+//! The archive is scanned once when it's opened, recording where each
+//! file's data starts, so that reading a page is a seek and a read rather
+//! than a walk over every header before it.
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// A file in the archive.
+pub struct Entry {
+    pub name: String,
+    /// Where the file's data starts in the archive
+    offset: u64,
+    pub size: u64,
+}
+
+pub struct Archive<R> {
+    reader: R,
+    entries: Vec<Entry>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn new(mut reader: R) -> std::io::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut entries = vec![];
+        let mut archive = ::tar::Archive::new(&mut reader);
+        for entry in archive.entries_with_seek()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            entries.push(Entry {
+                name: entry.path()?.to_string_lossy().into_owned(),
+                offset: entry.raw_file_position(),
+                size: entry.size(),
+            });
+        }
+        Ok(Self { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Read the file at `index`.
+    pub fn read(&mut self, index: usize) -> std::io::Result<Vec<u8>> {
+        let entry = &self.entries[index];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        let mut data = vec![];
+        (&mut self.reader).take(entry.size).read_to_end(&mut data)?;
+        if (data.len() as u64) < entry.size {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,672 @@
+//! Subcommands that work on books from the command-line, without starting
+//! the server.
+//!
+//! A subcommand is the first argument, and takes its own flags, with a
+//! single "-" like the server's.
+
+use crate::library::{self, Kind};
+use crate::{Book, BookError};
+use epub::doc::{EpubDoc, NavPoint};
+use epub::editor::EpubEditor;
+use epub::metadata::Person;
+use epub::validate::{Report, Severity};
+use log::{debug, error, info, warn};
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+
+/// Run the subcommand named by the first of `args` and return its exit code,
+/// or [`None`] if there is no subcommand.
+pub fn run(args: &[String]) -> Option<i32> {
+    let (command, args) = args.split_first()?;
+    let code = match command.as_str() {
+        "edit" => edit(args),
+        "validate" => validate(args),
+        "info" => inspect(args, &[], info),
+        "toc" => inspect(args, &[], toc),
+        "ls" => inspect(args, &[], ls),
+        "cat" => inspect(args, &["chapter"], cat),
+        "extract" => inspect(args, &["resource"], extract),
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// The changes the `edit` subcommand makes to each book.
+#[derive(Debug, Default)]
+struct Edit {
+    title: Option<String>,
+    authors: Vec<String>,
+    series: Option<String>,
+    series_index: Option<String>,
+    remove_series: bool,
+    identifier: Option<String>,
+    identifiers: Vec<String>,
+    /// The media type and content of the new cover.
+    cover: Option<(String, Vec<u8>)>,
+    /// Where to write the edited book, instead of over the original.
+    output: Option<PathBuf>,
+}
+
+impl Edit {
+    /// Parse the flags of `edit`, and return the books to edit too.
+    fn parse(args: &[String]) -> Result<(Self, Vec<PathBuf>), String> {
+        let mut edit = Self::default();
+        let mut paths = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix('-') else {
+                paths.push(PathBuf::from(arg));
+                continue;
+            };
+            let flag = flag.to_lowercase();
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Expected a value for flag -{flag}"))
+            };
+            match flag.as_str() {
+                "title" => edit.title = Some(value()?),
+                "author" => edit.authors.push(value()?),
+                "series" => edit.series = Some(value()?),
+                "series-index" => edit.series_index = Some(value()?),
+                "no-series" => edit.remove_series = true,
+                "identifier" => edit.identifier = Some(value()?),
+                "add-identifier" => edit.identifiers.push(value()?),
+                "cover" => {
+                    let path = PathBuf::from(value()?);
+                    let mime = mime_guess::from_path(&path).first();
+                    let Some(mime) = mime.filter(|m| m.type_() == mime::IMAGE)
+                    else {
+                        return Err(format!(
+                            "\"{}\" isn't an image",
+                            path.display()
+                        ));
+                    };
+                    let content = std::fs::read(&path).map_err(|e| {
+                        format!("Couldn't read \"{}\": {e}", path.display())
+                    })?;
+                    edit.cover = Some((mime.to_string(), content));
+                }
+                "output" => edit.output = Some(PathBuf::from(value()?)),
+                _ => return Err(format!("Unrecognized flag \"{arg}\"!")),
+            }
+        }
+
+        if edit.remove_series
+            && (edit.series.is_some() || edit.series_index.is_some())
+        {
+            return Err(
+                "-no-series can't be combined with -series or -series-index"
+                    .into(),
+            );
+        }
+        if paths.is_empty() {
+            return Err("Expected the books to edit".into());
+        }
+        Ok((edit, paths))
+    }
+
+    /// Apply the changes to the book at `path`, and return where it was
+    /// written.
+    fn apply(&self, path: &Path) -> Result<PathBuf, BookError> {
+        let mut editor = EpubEditor::new(EpubDoc::new(path)?);
+        if let Some(title) = &self.title {
+            editor.title(title);
+        }
+        if !self.authors.is_empty() {
+            let authors = self
+                .authors
+                .iter()
+                .map(|name| Person {
+                    name: name.clone(),
+                    roles: vec!["aut".into()],
+                    ..Person::default()
+                })
+                .collect();
+            editor.creators(authors);
+        }
+        if self.remove_series {
+            editor.series(None);
+        } else if self.series.is_some() || self.series_index.is_some() {
+            let current = editor.doc().book_metadata.series().cloned();
+            let mut series = current.unwrap_or_default();
+            if let Some(name) = &self.series {
+                if *name != series.name {
+                    series.file_as = None;
+                }
+                series.name.clone_from(name);
+            }
+            if self.series_index.is_some() {
+                series.position.clone_from(&self.series_index);
+            }
+            if series.name.is_empty() {
+                warn!(
+                    "\"{}\" has no series to set the index of",
+                    path.display()
+                );
+            } else {
+                editor.series(Some(series));
+            }
+        }
+        if let Some(identifier) = &self.identifier {
+            editor.identifier(identifier);
+        }
+        for identifier in &self.identifiers {
+            editor.add_identifier(identifier);
+        }
+        if let Some((mime, content)) = &self.cover {
+            editor.cover_image(mime, content.clone());
+        }
+
+        // Write next to the target, so it can be renamed over it
+        let target = self.output.clone().unwrap_or_else(|| path.to_path_buf());
+        let file_name =
+            target.file_name().unwrap_or_default().to_string_lossy();
+        let temp = target.with_file_name(format!(".{file_name}.tmp"));
+        let written =
+            File::create(&temp)
+                .map_err(BookError::from)
+                .and_then(|file| {
+                    let file = editor.write(BufWriter::new(file))?;
+                    file.into_inner()
+                        .map_err(std::io::IntoInnerError::into_error)?;
+                    Ok(())
+                });
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&temp);
+            return Err(e);
+        }
+        drop(editor);
+        std::fs::rename(&temp, &target)?;
+        Ok(target)
+    }
+}
+
+/// Return the EPUBs among `paths` and in the directories of `paths`, or an
+/// error if one of `paths` is a book in another format.
+fn epub_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut books = vec![];
+    for path in library::book_files(paths) {
+        if Kind::of(&path) == Kind::Epub {
+            books.push(path);
+        } else if paths.contains(&path) {
+            return Err(format!("\"{}\" isn't an EPUB", path.display()));
+        } else {
+            debug!("Skipping \"{}\", which isn't an EPUB", path.display());
+        }
+    }
+    Ok(books)
+}
+
+/// Fix the metadata or the cover of EPUBs, in place or into `-output`.
+fn edit(args: &[String]) -> i32 {
+    let (edit, paths) = match Edit::parse(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            error!("FATAL: {e}");
+            crate::print_usage();
+            return 1;
+        }
+    };
+
+    let books = match epub_files(&paths) {
+        Ok(books) => books,
+        Err(e) => {
+            error!("FATAL: {e}");
+            return 1;
+        }
+    };
+    if edit.output.is_some() && books.len() != 1 {
+        error!("FATAL: -output can only be used with a single book");
+        return 1;
+    }
+
+    let mut code = 0;
+    for path in &books {
+        match edit.apply(path) {
+            Ok(target) => info!("Saved \"{}\"", target.display()),
+            Err(e) => {
+                error!("Failed to edit \"{}\": {e}", path.display());
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// Print the problems of EPUBs, and fail if any has errors.
+fn validate(args: &[String]) -> i32 {
+    if let Some(flag) = args.iter().find(|a| a.starts_with('-')) {
+        error!("FATAL: Unrecognized flag \"{flag}\"!");
+        crate::print_usage();
+        return 1;
+    }
+    if args.is_empty() {
+        error!("FATAL: Expected the books to validate");
+        crate::print_usage();
+        return 1;
+    }
+    let paths: Vec<_> = args.iter().map(PathBuf::from).collect();
+    let books = match epub_files(&paths) {
+        Ok(books) => books,
+        Err(e) => {
+            error!("FATAL: {e}");
+            return 1;
+        }
+    };
+
+    let mut code = 0;
+    for path in &books {
+        let report = match Report::new(path) {
+            Ok(report) => report,
+            Err(e) => {
+                error!("Failed to read \"{}\": {e}", path.display());
+                code = 1;
+                continue;
+            }
+        };
+        if report.problems.is_empty() {
+            println!("{}: ok", path.display());
+            continue;
+        }
+        let errors = report.errors().count();
+        let warnings = report.warnings().count();
+        println!("{}: {errors} errors, {warnings} warnings", path.display());
+        for problem in &report.problems {
+            let severity = match problem.severity() {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            println!("    {severity}: {problem}");
+        }
+        if errors > 0 {
+            code = 1;
+        }
+    }
+    code
+}
+
+/// The arguments of the subcommands that look into a single book.
+#[derive(Debug)]
+struct Inspect {
+    book: PathBuf,
+    /// The arguments after the book.
+    args: Vec<String>,
+    /// Where to write what's extracted, with "-" for stdout.
+    output: Option<PathBuf>,
+}
+
+impl Inspect {
+    /// Parse the book, the arguments named by `names` and the flags.
+    fn parse(args: &[String], names: &[&str]) -> Result<Self, String> {
+        let mut positional = vec![];
+        let mut output = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            // A lone "-" is stdout
+            let Some(flag) = arg.strip_prefix('-').filter(|f| !f.is_empty())
+            else {
+                positional.push(arg.clone());
+                continue;
+            };
+            match flag.to_lowercase().as_str() {
+                "output" => {
+                    let value = args.next().ok_or_else(|| {
+                        format!("Expected a value for flag -{flag}")
+                    })?;
+                    output = Some(PathBuf::from(value));
+                }
+                _ => return Err(format!("Unrecognized flag \"{arg}\"!")),
+            }
+        }
+
+        if positional.is_empty() {
+            return Err("Expected a book".into());
+        }
+        let book = PathBuf::from(positional.remove(0));
+        if let Some(name) = names.get(positional.len()) {
+            return Err(format!("Expected the {name} after the book"));
+        }
+        if positional.len() > names.len() {
+            return Err(format!(
+                "Unexpected argument \"{}\"",
+                positional[names.len()]
+            ));
+        }
+        Ok(Self {
+            book,
+            args: positional,
+            output,
+        })
+    }
+}
+
+/// Open the book named in `args` and run `command` on it, reporting its
+/// error if it fails.
+fn inspect(
+    args: &[String],
+    names: &[&str],
+    command: fn(&Inspect, &mut Book) -> Result<(), String>,
+) -> i32 {
+    let inspect = match Inspect::parse(args, names) {
+        Ok(inspect) => inspect,
+        Err(e) => {
+            error!("FATAL: {e}");
+            crate::print_usage();
+            return 1;
+        }
+    };
+    let mut book = match Book::open(&inspect.book) {
+        Ok(book) => book,
+        Err(e) => {
+            error!("Failed to open \"{}\": {e}", inspect.book.display());
+            return 1;
+        }
+    };
+    match command(&inspect, &mut book) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("{e}");
+            1
+        }
+    }
+}
+
+/// Write `output` and a line break to stdout. A closed pipe isn't an error,
+/// so the output can be cut short by `head` and the like.
+fn print(output: &[u8]) -> Result<(), String> {
+    let mut stdout = std::io::stdout().lock();
+    match stdout
+        .write_all(output)
+        .and_then(|()| stdout.write_all(b"\n"))
+    {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+            Err(format!("Couldn't write to stdout: {e}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), String> {
+    print(&serde_json::to_vec_pretty(value).expect("serializable value"))
+}
+
+/// The metadata printed by `info`.
+#[derive(Debug, serde::Serialize)]
+struct Info {
+    format: &'static str,
+    title: Option<String>,
+    authors: Vec<String>,
+    language: Option<String>,
+    identifier: Option<String>,
+    description: Option<String>,
+    publisher: Option<String>,
+    subjects: Vec<String>,
+    series: Option<String>,
+    series_index: Option<String>,
+    /// The number of chapters in the spine, or of pages in a comic.
+    pages: usize,
+    /// The path of the cover image in the book.
+    cover: Option<String>,
+}
+
+/// Print the metadata of the book.
+fn info(_: &Inspect, book: &mut Book) -> Result<(), String> {
+    let info = match book {
+        Book::Epub(epub) => {
+            let metadata = &epub.book_metadata;
+            let series = metadata.series();
+            Info {
+                format: "epub",
+                title: metadata.title().map(String::from),
+                authors: metadata
+                    .authors()
+                    .into_iter()
+                    .map(|p| p.name.clone())
+                    .collect(),
+                language: metadata.languages.first().cloned(),
+                identifier: epub.unique_identifier.clone(),
+                description: metadata.description.clone(),
+                publisher: metadata.publisher.clone(),
+                subjects: metadata
+                    .subjects
+                    .iter()
+                    .map(|s| s.value.clone())
+                    .collect(),
+                series: series.map(|s| s.name.clone()),
+                series_index: series.and_then(|s| s.position.clone()),
+                pages: epub.get_num_pages(),
+                cover: epub
+                    .get_cover_id()
+                    .and_then(|id| epub.resources.get(&id))
+                    .map(|(path, _)| path.to_string_lossy().into_owned()),
+            }
+        }
+        Book::Cba(cba) => {
+            let metadata = cba.metadata();
+            Info {
+                format: "cba",
+                title: metadata.and_then(|m| m.title.clone()),
+                authors: metadata
+                    .map(crate::cba::Metadata::authors)
+                    .unwrap_or_default(),
+                language: metadata.and_then(|m| m.language.clone()),
+                identifier: None,
+                description: metadata.and_then(|m| m.summary.clone()),
+                publisher: None,
+                subjects: vec![],
+                series: metadata.and_then(|m| m.series.clone()),
+                series_index: metadata.and_then(|m| m.number.clone()),
+                pages: cba.page_count(),
+                cover: cba.cover_name().map(String::from),
+            }
+        }
+    };
+    print_json(&info)
+}
+
+/// An entry of the table of contents printed by `toc`.
+#[derive(Debug, serde::Serialize)]
+struct TocEntry {
+    label: String,
+    /// The path in the book, with the fragment if there is one.
+    path: String,
+    /// The spine index this entry points to, if it's in the spine.
+    page: Option<usize>,
+    children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    fn from_navpoints(
+        epub: &EpubDoc<Cursor<Vec<u8>>>,
+        navpoints: &[NavPoint],
+    ) -> Vec<Self> {
+        navpoints
+            .iter()
+            .map(|nav| {
+                let path = nav.content.to_string_lossy().into_owned();
+                let file = path.split_once('#').map_or(&*path, |(p, _)| p);
+                Self {
+                    page: epub.resource_uri_to_chapter(&PathBuf::from(file)),
+                    label: nav.label.clone(),
+                    path,
+                    children: Self::from_navpoints(epub, &nav.children),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Print the table of contents of the book, which is empty for comics.
+fn toc(_: &Inspect, book: &mut Book) -> Result<(), String> {
+    let toc = match book {
+        Book::Epub(epub) => TocEntry::from_navpoints(epub, &epub.toc),
+        Book::Cba(_) => vec![],
+    };
+    print_json(&toc)
+}
+
+/// A file in a book, as listed by `ls`.
+#[derive(Debug, serde::Serialize)]
+struct Resource {
+    /// The manifest id, for EPUBs.
+    id: Option<String>,
+    path: String,
+    mime: String,
+    /// The size once decompressed, if the archive records it.
+    size: Option<u64>,
+    /// The spine index, or the page number of comics.
+    page: Option<usize>,
+}
+
+/// Print the resources of an EPUB, or the pages of a comic.
+fn ls(_: &Inspect, book: &mut Book) -> Result<(), String> {
+    let mut resources = vec![];
+    match book {
+        Book::Epub(epub) => {
+            let mut ids = epub.resources.keys().cloned().collect::<Vec<_>>();
+            ids.sort_by(|l, r| epub.resources[l].0.cmp(&epub.resources[r].0));
+            for id in ids {
+                let (path, mime) = epub.resources[&id].clone();
+                resources.push(Resource {
+                    size: epub.get_resource_size(&id),
+                    page: epub.resource_id_to_chapter(&id),
+                    id: Some(id),
+                    path: path.to_string_lossy().into_owned(),
+                    mime,
+                });
+            }
+        }
+        Book::Cba(cba) => {
+            for page in 0..cba.page_count() {
+                let path = cba.page_name(page).unwrap_or_default().to_string();
+                resources.push(Resource {
+                    id: None,
+                    mime: mime_guess::from_path(&path)
+                        .first_or_octet_stream()
+                        .to_string(),
+                    path,
+                    size: cba.page_size(page),
+                    page: Some(page),
+                });
+            }
+        }
+    }
+    print_json(&resources)
+}
+
+/// Find the manifest id of `name`, which is "cover", an id, or a path in
+/// the book.
+fn epub_resource(
+    epub: &EpubDoc<Cursor<Vec<u8>>>,
+    name: &str,
+) -> Option<String> {
+    if name == "cover" {
+        return epub.get_cover_id();
+    }
+    if epub.resources.contains_key(name) {
+        return Some(name.to_string());
+    }
+    epub.resources
+        .iter()
+        .find(|(_, (path, _))| path == Path::new(name))
+        .map(|(id, _)| id.clone())
+}
+
+/// Print the text of a chapter, given by its spine index, manifest id or
+/// path.
+fn cat(inspect: &Inspect, book: &mut Book) -> Result<(), String> {
+    let name = &inspect.args[0];
+    let Book::Epub(epub) = book else {
+        return Err("Comic books have no text".into());
+    };
+    let chapter = name
+        .parse::<usize>()
+        .ok()
+        .or_else(|| {
+            let id = epub_resource(epub, name)?;
+            epub.resource_id_to_chapter(&id)
+        })
+        .ok_or_else(|| format!("There is no chapter \"{name}\""))?;
+    let text = epub
+        .chapter_text(chapter)
+        .ok_or_else(|| format!("Chapter {chapter} has no readable text"))?;
+    print(text.trim_end().as_bytes())
+}
+
+/// A resource written by `extract`.
+#[derive(Debug, serde::Serialize)]
+struct Extracted {
+    /// The path in the book.
+    resource: PathBuf,
+    output: PathBuf,
+    mime: String,
+    size: usize,
+}
+
+/// Write a resource of the book, given as for [`cat`] or as "cover", to
+/// `-output` or to its file name in the current directory. Comic pages are
+/// given by page number or file name.
+fn extract(inspect: &Inspect, book: &mut Book) -> Result<(), String> {
+    let name = &inspect.args[0];
+    let not_found = || format!("There is no resource \"{name}\"");
+    let (path, data, mime) = match book {
+        Book::Epub(epub) => {
+            let id = epub_resource(epub, name).ok_or_else(not_found)?;
+            let path = epub.resources[&id].0.clone();
+            let (data, mime) = epub.get_resource(&id).ok_or_else(|| {
+                format!("Couldn't read \"{}\"", path.display())
+            })?;
+            (path, data, mime)
+        }
+        Book::Cba(cba) => {
+            let page = if name == "cover" {
+                None
+            } else {
+                let page = name.parse::<usize>().ok().or_else(|| {
+                    (0..cba.page_count()).find(|&p| {
+                        cba.page_name(p).is_some_and(|p| {
+                            p == name
+                                || Path::new(p).file_name()
+                                    == Some(name.as_ref())
+                        })
+                    })
+                });
+                Some(page.ok_or_else(not_found)?)
+            };
+            let path = match page {
+                Some(page) => cba.page_name(page),
+                None => cba.cover_name(),
+            }
+            .map(PathBuf::from)
+            .ok_or_else(not_found)?;
+            let read = match page {
+                Some(page) => cba.page(page),
+                None => cba.cover(),
+            };
+            let (data, mime) = read.map_err(|e| e.to_string())?;
+            (path, data, mime.to_string())
+        }
+    };
+
+    let output = inspect
+        .output
+        .clone()
+        .or_else(|| path.file_name().map(PathBuf::from))
+        .ok_or_else(not_found)?;
+    if output == Path::new("-") {
+        return std::io::stdout()
+            .write_all(&data)
+            .map_err(|e| format!("Couldn't write to stdout: {e}"));
+    }
+    std::fs::write(&output, &data)
+        .map_err(|e| format!("Couldn't write \"{}\": {e}", output.display()))?;
+
+    print_json(&Extracted {
+        resource: path,
+        output,
+        mime,
+        size: data.len(),
+    })
+}
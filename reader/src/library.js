@@ -0,0 +1,50 @@
+const shelf = document.getElementById("shelf");
+const filter_input = document.getElementById("filterinput");
+const sort_select = document.getElementById("sortselect");
+
+async function quit() {
+    if (confirm("Are you sure you want to stop the server?")) {
+        await fetch("/api/quit", { method: "POST" });
+        close();
+    }
+}
+
+// Show only the books whose title or authors contain the filter text.
+function filter_books() {
+    const text = filter_input.value.toLowerCase();
+    for (const book of shelf.children) {
+        const haystack = (
+            book.dataset.title +
+            " " +
+            book.dataset.authors
+        ).toLowerCase();
+        book.classList.toggle("hidden", !haystack.includes(text));
+    }
+    sessionStorage.setItem("shelf_filter", filter_input.value);
+}
+
+// Order the books by the field chosen in the sort selector.
+function sort_books() {
+    const key = sort_select.value;
+    const books = Array.from(shelf.children);
+    books.sort((a, b) =>
+        a.dataset[key].localeCompare(b.dataset[key], undefined, {
+            numeric: true,
+            sensitivity: "base",
+        }),
+    );
+    shelf.replaceChildren(...books);
+    localStorage.setItem("shelf_sort", key);
+}
+
+filter_input.addEventListener("input", filter_books);
+sort_select.addEventListener("change", sort_books);
+
+window.addEventListener("load", () => {
+    sort_select.value = localStorage.getItem("shelf_sort") ?? "title";
+    filter_input.value = sessionStorage.getItem("shelf_filter") ?? "";
+    sort_books();
+    filter_books();
+});
+
+const quit_button = quit;
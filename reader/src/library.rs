@@ -0,0 +1,349 @@
+//! Serving a collection of books from one server.
+//!
+//! The [`Library`] is built from the files and directories given on the
+//! command-line. Books are only fully opened once they're requested, and
+//! stay open, each with its own page state, until too many others have been
+//! opened since.
+
+use crate::{Book, BookState};
+use epub::doc::EpubDoc;
+use log::{debug, error, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// File extensions of the books we know how to read.
+const BOOK_EXTENSIONS: &[&str] = &["epub", "cbz", "cbr", "cb7", "cbt"];
+
+/// How many books are kept open at once. Opening another closes the least
+/// recently used one.
+const OPEN_BOOKS: usize = 4;
+
+/// The most bytes of cover images kept in memory for the bookshelf.
+const COVER_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Epub,
+    Cba,
+}
+
+impl Kind {
+    /// Guess the kind of book at `path` from its extension.
+    pub fn of(path: &Path) -> Self {
+        if path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("epub"))
+        {
+            Self::Epub
+        } else {
+            Self::Cba
+        }
+    }
+}
+
+/// A book in the library.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Identifies the book in urls. This is derived from the path, so it stays
+    /// the same across restarts as long as the file isn't moved.
+    pub id: String,
+    pub path: PathBuf,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub language: Option<String>,
+    pub identifier: Option<String>,
+    pub description: Option<String>,
+    /// Whether the book has a cover image to serve.
+    pub has_cover: bool,
+    /// When the file was last modified.
+    pub modified: SystemTime,
+}
+
+impl Entry {
+    /// Read the metadata of the book at `path`, without loading the whole
+    /// book.
+    pub fn read(path: &Path) -> Result<Self, crate::BookError> {
+        // Directories don't have extensions to strip
+        let file_name = if path.is_dir() {
+            path.file_name()
+        } else {
+            path.file_stem()
+        };
+        let file_name = file_name
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let canonical = std::fs::canonicalize(path);
+        let canonical = canonical.as_deref().unwrap_or(path);
+        let mut entry = Self {
+            id: format!(
+                "{:016x}",
+                crate::store::hash(canonical.as_os_str().as_encoded_bytes())
+            ),
+            path: path.to_path_buf(),
+            title: file_name,
+            authors: vec![],
+            language: None,
+            identifier: None,
+            description: None,
+            has_cover: true,
+            modified: std::fs::metadata(path)?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        };
+        if Kind::of(path) == Kind::Epub {
+            let epub = EpubDoc::new(path)?;
+            if let Some(title) = epub.mdata("title") {
+                entry.title = title;
+            }
+            entry.authors =
+                epub.metadata.get("creator").cloned().unwrap_or_default();
+            entry.language = epub.mdata("language");
+            entry.identifier = epub.mdata("identifier");
+            entry.description = epub.mdata("description");
+            entry.has_cover = epub.get_cover_id().is_some();
+        } else if let Some(metadata) =
+            crate::cba::CBAReader::read(path)?.metadata()
+        {
+            // Without metadata the file name is the best title there is
+            if let Some(title) = metadata.display_title() {
+                entry.title = title;
+            }
+            entry.authors = metadata.authors();
+            entry.language.clone_from(&metadata.language);
+            entry.description.clone_from(&metadata.summary);
+        }
+
+        Ok(entry)
+    }
+
+    /// Whether the book is a single file that can be downloaded, rather
+    /// than a directory.
+    pub fn downloadable(&self) -> bool {
+        self.path.is_file()
+    }
+
+    /// The mime type of the book file.
+    pub fn mime(&self) -> &'static str {
+        let extension = self
+            .path
+            .extension()
+            .and_then(|x| x.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("epub") => "application/epub+zip",
+            Some("cbz") => "application/vnd.comicbook+zip",
+            Some("cbr") => "application/vnd.comicbook-rar",
+            Some("cb7") => "application/x-cb7",
+            Some("cbt") => "application/x-cbt",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+pub struct Library {
+    pub entries: Vec<Entry>,
+    /// Books that have been opened, by id.
+    books: HashMap<String, BookState>,
+    /// The ids of `books`, from the least to the most recently used.
+    recent: Vec<String>,
+    /// The most recently requested cover images, by book id, so that the
+    /// books don't have to be opened again for them.
+    covers: crate::cba::Cache<String>,
+    /// The mime type of the cover of each book whose cover was requested,
+    /// or [`None`] if it has none.
+    cover_types: HashMap<String, Option<String>>,
+    /// Don't restore saved reading positions when opening books.
+    ignore_position: bool,
+    /// How many pages of comics are kept in memory and read ahead.
+    pub comic_cache: crate::cba::CacheConfig,
+}
+
+impl Library {
+    /// Build a library from `paths`, which may be books or directories to
+    /// search for books.
+    pub fn new(paths: &[PathBuf], ignore_position: bool) -> Self {
+        let entries = book_files(paths)
+            .iter()
+            .filter_map(|path| match Entry::read(path) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    warn!("Skipping \"{}\": {e}", path.display());
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        debug!("Found {} books", entries.len());
+
+        Self {
+            entries,
+            books: HashMap::new(),
+            recent: vec![],
+            covers: crate::cba::Cache::new(COVER_CACHE_SIZE),
+            cover_types: HashMap::new(),
+            ignore_position,
+            comic_cache: crate::cba::CacheConfig::default(),
+        }
+    }
+
+    pub fn entry(&self, id: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Get the book with `id`, opening it if it isn't already.
+    pub fn open(&mut self, id: &str) -> Option<&mut BookState> {
+        if !self.books.contains_key(id) {
+            let entry = self.entry(id)?.clone();
+            debug!("Opening \"{}\"", entry.path.display());
+            match BookState::open(entry, self.ignore_position) {
+                Ok(mut book) => {
+                    if let Book::Cba(cba) = &mut book.book {
+                        cba.set_cache(self.comic_cache);
+                    }
+                    self.books.insert(id.to_string(), book);
+                }
+                Err(e) => {
+                    error!("Failed to open book {id}: {e}");
+                    return None;
+                }
+            }
+        }
+
+        self.recent.retain(|recent| recent != id);
+        self.recent.push(id.to_string());
+        if self.recent.len() > OPEN_BOOKS {
+            let closed = self.recent.remove(0);
+            debug!("Closing book {closed}");
+            self.books.remove(&closed);
+        }
+        self.books.get_mut(id)
+    }
+
+    /// The cover image and its mime type of the book with `id`.
+    pub fn cover(&mut self, id: &str) -> Option<(Arc<[u8]>, String)> {
+        match self.cover_types.get(id) {
+            Some(None) => return None,
+            Some(Some(mime)) => {
+                if let Some(data) = self.covers.get(&id.to_string()) {
+                    return Some((data, mime.clone()));
+                }
+            }
+            None => {}
+        }
+
+        let cover = if let Some(book) = self.books.get_mut(id) {
+            cover(&mut book.book)
+        } else {
+            let entry = self.entry(id)?;
+            match Book::open(&entry.path) {
+                Ok(mut book) => cover(&mut book),
+                Err(e) => {
+                    // It may be readable next time
+                    error!("Failed to open book {id}: {e}");
+                    return None;
+                }
+            }
+        };
+        let cover = cover.map(|(data, mime)| (Arc::<[u8]>::from(data), mime));
+        if let Some((data, _)) = &cover {
+            self.covers.insert(id.to_string(), Arc::clone(data));
+        }
+        self.cover_types.insert(
+            id.to_string(),
+            cover.as_ref().map(|(_, mime)| mime.clone()),
+        );
+        cover
+    }
+}
+
+fn cover(book: &mut Book) -> Option<(Vec<u8>, String)> {
+    match book {
+        Book::Epub(epub) => epub.get_cover(),
+        Book::Cba(cba) => cba
+            .cover()
+            .map(|(data, mime)| (data, mime.to_string()))
+            .ok(),
+    }
+}
+
+/// The books in `paths`, with directories searched for books.
+pub fn book_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() && !is_image_dir(path) {
+            find_books(path, &mut files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Recursively collect the books inside `dir` into `files`, in path order.
+fn find_books(dir: &Path, files: &mut Vec<PathBuf>) {
+    let mut paths = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            warn!("Couldn't read directory \"{}\": {e}", dir.display());
+            return;
+        }
+    };
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if is_image_dir(&path) {
+                files.push(path);
+            } else {
+                find_books(&path, files);
+            }
+        } else if is_book(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_book(path: &Path) -> bool {
+    path.extension().and_then(|x| x.to_str()).is_some_and(|x| {
+        BOOK_EXTENSIONS.iter().any(|e| x.eq_ignore_ascii_case(e))
+    })
+}
+
+/// Whether `dir` should be read as a comic: it has images in it, or only
+/// has subdirectories that do (chapters), and it has no books.
+pub fn is_image_dir(dir: &Path) -> bool {
+    /// The subdirectories of `dir`, and whether it has images and books.
+    fn scan(dir: &Path) -> (Vec<PathBuf>, bool, bool) {
+        let (mut dirs, mut images, mut books) = (vec![], false, false);
+        for path in std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+        {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_book(&path) {
+                books = true;
+            } else if crate::cba::is_image(&path) {
+                images = true;
+            }
+        }
+        (dirs, images, books)
+    }
+
+    let (dirs, images, books) = scan(dir);
+    if books {
+        return false;
+    }
+    images
+        || !dirs.is_empty()
+            && dirs.iter().all(|dir| {
+                let (_, images, books) = scan(dir);
+                images && !books
+            })
+}
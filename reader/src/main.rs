@@ -1,23 +1,30 @@
 #![feature(duration_constructors)]
 #![warn(clippy::pedantic)]
 
-use epub::doc::EpubDoc;
+use epub::doc::{EpubDoc, NavPoint};
+use epub::search::SearchMode;
 use log::{debug, error, info, warn};
 use rinja::Template;
 use slime::parser::{UnParser as _, ini};
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, Write};
 use std::process::exit;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 use tiny_http::{Header, Method, Request, Response, StatusCode};
 
+mod annotations;
 mod cba;
+mod cli;
+mod library;
+mod opds;
+mod store;
 
 pub const XHTML: &str = "application/xhtml+xml";
 pub const HTML: &str = "text/html";
 pub const JSON: &str = "application/json";
 pub const CSS: &str = "text/css";
 const READER_JS: &str = include_str!("reader.js");
+const LIBRARY_JS: &str = include_str!("library.js");
 
 #[allow(clippy::large_enum_variant)]
 enum Book {
@@ -36,30 +43,217 @@ impl From<EpubDoc<Cursor<Vec<u8>>>> for Book {
     }
 }
 
-/// Application state.
-struct State<'a> {
+/// A table of contents entry as served by `/api/toc`.
+#[derive(Debug, serde::Serialize)]
+struct TocEntry {
+    label: String,
+    /// The spine index this entry points to, if it's in the spine.
+    page: Option<usize>,
+    /// The reader url of this entry, including its fragment anchor.
+    url: String,
+    children: Vec<TocEntry>,
+}
+
+impl TocEntry {
+    /// Build entries for `navpoints`, with urls under the book url `base`.
+    fn from_navpoints(
+        epub: &EpubDoc<Cursor<Vec<u8>>>,
+        navpoints: &[NavPoint],
+        base: &str,
+    ) -> Vec<Self> {
+        navpoints
+            .iter()
+            .map(|nav| {
+                let content = nav.content.to_string_lossy();
+                let (path, fragment) = content
+                    .split_once('#')
+                    .map_or((&*content, None), |(p, f)| (p, Some(f)));
+                let page = epub
+                    .resource_uri_to_chapter(&std::path::PathBuf::from(path));
+                let url = match fragment {
+                    Some(fragment) => format!("{base}/{path}#{fragment}"),
+                    None => format!("{base}/{path}"),
+                };
+                Self {
+                    label: nav.label.clone(),
+                    page,
+                    url,
+                    children: Self::from_navpoints(epub, &nav.children, base),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A search hit as served by `/api/search`.
+#[derive(Debug, serde::Serialize)]
+struct SearchResult {
+    /// The spine index of the hit.
+    page: usize,
+    /// The reader url of the hit, including the closest fragment anchor.
+    url: String,
+    matched: String,
+    snippet: String,
+}
+
+/// A request to change the note of a highlight.
+#[derive(Debug, serde::Deserialize)]
+struct NoteUpdate {
+    note: Option<String>,
+}
+
+/// Errors from opening a book.
+#[derive(Debug, thiserror::Error)]
+enum BookError {
+    #[error("IO: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("EPUB: {0}")]
+    Epub(#[from] epub::doc::DocError),
+    #[error("CBA: {0}")]
+    Cba(#[from] cba::Error),
+}
+
+impl Book {
+    /// Open the book at `path`, as an EPUB if it has the extension of one, or
+    /// as a comic book archive otherwise.
+    fn open(path: &std::path::Path) -> Result<Self, BookError> {
+        match library::Kind::of(path) {
+            library::Kind::Epub => {
+                debug!("Reading {path:?} as EPUB");
+                let bookbuf = std::fs::read(path)?;
+                Ok(EpubDoc::from_reader(Cursor::new(bookbuf))?.into())
+            }
+            library::Kind::Cba => {
+                debug!("Reading {path:?} as Comic Book Archive");
+                Ok(cba::CBAReader::read(path)?.into())
+            }
+        }
+    }
+}
+
+/// An open book and where the reader is in it.
+struct BookState {
     book: Book,
-    socket_addr: std::net::SocketAddr,
-    css_variables: CSSVariables<'a>,
+    /// The library id of the book, which prefixes all of its urls.
+    id: String,
+    title: String,
     current_page: usize,
     page_count: usize,
+    store: store::Store,
 }
 
-impl State<'_> {
-    fn new(book: Book) -> Self {
+impl BookState {
+    /// Open the book described by `entry`, restoring its saved reading
+    /// position unless `ignore_position` is set.
+    fn open(
+        entry: library::Entry,
+        ignore_position: bool,
+    ) -> Result<Self, BookError> {
+        let mut book = Book::open(&entry.path)?;
+
+        let key = match &book {
+            Book::Epub(epub) => epub.unique_identifier.clone(),
+            Book::Cba(_) => None,
+        };
+        let key = match key {
+            Some(key) => key,
+            None => store::file_key(&entry.path)?,
+        };
+        let store = store::Store::open(&key);
+
+        if let Book::Cba(cba) = &mut book {
+            cba.set_page_order(store.data.page_order);
+        }
         let page_count = match &book {
             Book::Epub(epub) => epub.get_num_pages(),
             Book::Cba(cba) => cba.page_count(),
         };
-        Self {
+
+        let mut current_page = 0;
+        if let Some(position) = store.data.position {
+            if ignore_position {
+                debug!("Ignoring saved reading position {position:?}");
+            } else if position.page < page_count {
+                info!(
+                    "Restoring reading position of \"{}\" at page {}",
+                    entry.title,
+                    position.page + 1
+                );
+                current_page = position.page;
+            }
+        }
+
+        Ok(Self {
             book,
-            socket_addr: std::net::SocketAddr::new(
-                std::net::Ipv4Addr::LOCALHOST.into(),
-                0,
-            ),
-            css_variables: CSSVariables::default(),
-            current_page: 0,
+            id: entry.id,
+            title: entry.title,
+            current_page,
             page_count,
+            store,
+        })
+    }
+
+    /// The url prefix of this book's pages, resources and api.
+    fn base(&self) -> String {
+        format!("/book/{}", self.id)
+    }
+
+    /// Whether the comic is read right to left, as chosen by the reader or
+    /// else by the comic's metadata.
+    fn right_to_left(&self) -> bool {
+        let Book::Cba(cba) = &self.book else {
+            return false;
+        };
+        self.store.data.layout.right_to_left.unwrap_or_else(|| {
+            cba.metadata()
+                .is_some_and(|metadata| metadata.right_to_left)
+        })
+    }
+
+    /// The comic's layout, with the reading direction settled.
+    fn layout(&self) -> store::ComicLayout {
+        store::ComicLayout {
+            right_to_left: Some(self.right_to_left()),
+            ..self.store.data.layout
+        }
+    }
+
+    /// The pages shown alongside the current one, if this is a comic being
+    /// shown two pages at a time.
+    fn spread(&self) -> Option<std::ops::Range<usize>> {
+        match &self.book {
+            Book::Cba(cba)
+                if self.store.data.layout.spread
+                    && !self.store.data.layout.webtoon =>
+            {
+                Some(cba.spread(self.current_page))
+            }
+            _ => None,
+        }
+    }
+
+    /// The page to go to next, which is the start of the next spread when
+    /// showing two pages at a time.
+    fn next_page(&self) -> usize {
+        let next = match self.spread() {
+            Some(spread) => spread.end,
+            None => self.current_page + 1,
+        };
+        if next < self.page_count {
+            next
+        } else {
+            self.current_page
+        }
+    }
+
+    /// The page to go back to, which is the start of the previous spread
+    /// when showing two pages at a time.
+    fn previous_page(&self) -> usize {
+        match (&self.book, self.spread()) {
+            (Book::Cba(cba), Some(spread)) if spread.start > 0 => {
+                cba.spread(spread.start - 1).start
+            }
+            _ => self.current_page.saturating_sub(1),
         }
     }
 
@@ -70,6 +264,7 @@ impl State<'_> {
     ) -> Result<Response<Cursor<Vec<u8>>>, ()> {
         self.current_page = pred(self.current_page, self.page_count)
             .clamp(0, self.page_count - 1);
+        self.store.save_position(self.current_page, 0.0);
 
         match &mut self.book {
             Book::Epub(epub) => {
@@ -99,6 +294,32 @@ impl State<'_> {
     }
 }
 
+/// Application state.
+struct State<'a> {
+    library: library::Library,
+    socket_addr: std::net::SocketAddr,
+    css_variables: CSSVariables<'a>,
+    /// How comic pages are sized to the view.
+    fit: cba::FitMode,
+    /// Whether we're serving a bookshelf rather than a single book.
+    library_mode: bool,
+}
+
+impl State<'_> {
+    fn new(library: library::Library, library_mode: bool) -> Self {
+        Self {
+            library,
+            socket_addr: std::net::SocketAddr::new(
+                std::net::Ipv4Addr::LOCALHOST.into(),
+                0,
+            ),
+            css_variables: CSSVariables::default(),
+            fit: cba::FitMode::default(),
+            library_mode,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Config<'a> {
     open_in_browser: bool,
@@ -106,11 +327,22 @@ struct Config<'a> {
     bind_addr: &'a str,
     bind_port: u16,
     css_variables: CSSVariables<'a>,
+    /// How comic pages are sized to the view, set in the `[comic]` section.
+    fit: cba::FitMode,
+    /// How many comic pages are kept in memory and read ahead, set in the
+    /// `[comic]` section.
+    comic_cache: cba::CacheConfig,
+    /// Start at the first page instead of the saved reading position. This is
+    /// only set from the command-line.
+    ignore_position: bool,
+    /// Print the annotations of the book in this format and quit instead of
+    /// serving it. This is only set from the command-line.
+    export_annotations: Option<annotations::ExportFormat>,
 }
 
 impl Config<'_> {
     /// The number of INI fields when serialized.
-    pub const S_FIELDS: usize = 9;
+    pub const S_FIELDS: usize = 12;
     pub const DEFAULT_BIND_ADDR: &'static str = "localhost";
     pub const DEFAULT_BIND_PORT: u16 = 0;
 }
@@ -123,6 +355,10 @@ impl Default for Config<'_> {
             open_in_browser: false,
             kill_timeout: -1,
             css_variables: CSSVariables::default(),
+            fit: cba::FitMode::default(),
+            comic_cache: cba::CacheConfig::default(),
+            ignore_position: false,
+            export_annotations: None,
         }
     }
 }
@@ -157,6 +393,25 @@ impl<'a> From<&Config<'a>> for [ini::Pair<'a>; Config::S_FIELDS] {
             css_variables[2],
             css_variables[3],
             css_variables[4],
+            ini::Pair {
+                section: "comic",
+                key: "fit",
+                value: cfg.fit.as_str(),
+            },
+            ini::Pair {
+                section: "comic",
+                key: "cache_size_mb",
+                value: Box::leak(Box::new(
+                    (cfg.comic_cache.size / 1024 / 1024).to_string(),
+                )),
+            },
+            ini::Pair {
+                section: "comic",
+                key: "prefetch",
+                value: Box::leak(Box::new(
+                    cfg.comic_cache.prefetch.to_string(),
+                )),
+            },
         ]
     }
 }
@@ -186,6 +441,20 @@ impl<'a> TryFrom<ini::Parse<'a>> for Config<'a> {
                         |_| "Invalid boolean value for 'open_in_browser'",
                     )?;
                 }
+                ("comic", "fit") => {
+                    x.fit = value.parse().map_err(|_| "Invalid fit")?;
+                }
+                ("comic", "cache_size_mb") => {
+                    let mb: usize =
+                        value.parse().map_err(|_| "Invalid cache_size_mb")?;
+                    x.comic_cache.size = mb
+                        .checked_mul(1024 * 1024)
+                        .ok_or("Invalid cache_size_mb")?;
+                }
+                ("comic", "prefetch") => {
+                    x.comic_cache.prefetch =
+                        value.parse().map_err(|_| "Invalid prefetch")?;
+                }
                 ("css", "fg_color") => x.css_variables.fg_color = value,
                 ("css", "bg_color") => x.css_variables.bg_color = value,
                 ("css", "content_font_size_px") => {
@@ -213,6 +482,10 @@ struct Reader<'a> {
     stylesheet: &'a str,
     javascript: &'a str,
     page_url: &'a str,
+    /// The url prefix of the book.
+    base: &'a str,
+    /// Whether there's a bookshelf to go back to.
+    library: bool,
     current_page: usize,
     page_count: usize,
 }
@@ -224,8 +497,43 @@ struct CBReader<'a> {
     stylesheet: &'a str,
     javascript: &'a str,
     image_url: &'a str,
+    /// The url prefix of the book.
+    base: &'a str,
+    /// Whether there's a bookshelf to go back to.
+    library: bool,
     current_page: usize,
     page_count: usize,
+    page_order: cba::PageOrder,
+    layout: store::ComicLayout,
+    right_to_left: bool,
+    fit: cba::FitMode,
+}
+
+/// All the pages of a comic stacked in one scrolling strip.
+#[derive(Debug, Template)]
+#[template(ext = "xhtml", path = "cbstrip.xml")]
+struct CBStrip<'a> {
+    title: &'a str,
+    /// The urls of the page images, in reading order.
+    images: &'a [String],
+}
+
+/// The pages of a comic shown at once: one page, or two side by side.
+#[derive(Debug, Template)]
+#[template(ext = "xhtml", path = "cbpages.xml")]
+struct CBPages<'a> {
+    title: &'a str,
+    /// The urls of the page images, in reading order.
+    images: &'a [String],
+    right_to_left: bool,
+}
+
+#[derive(Debug, Template)]
+#[template(ext = "xhtml", escape = "html", path = "library.xml")]
+struct Shelf<'a> {
+    stylesheet: &'a str,
+    javascript: &'a str,
+    books: &'a [library::Entry],
 }
 
 #[derive(Debug, Clone, Default, Template)]
@@ -330,6 +638,19 @@ fn parse_args(config: &mut Config) -> Vec<String> {
                         }
                     }
                 }
+                "ignore-position" => config.ignore_position = true,
+                "export-annotations" => {
+                    let format = expect_next(args.next());
+                    match format.parse() {
+                        Ok(f) => config.export_annotations = Some(f),
+                        Err(e) => {
+                            error!(
+                                "FATAL: Invalid value for flag -{arg} \"{format}\": {e}"
+                            );
+                            was_error = true;
+                        }
+                    }
+                }
                 "bind-addr" => {
                     config.bind_addr =
                         Box::leak(Box::new(expect_next(args.next())));
@@ -392,7 +713,12 @@ fn print_usage() {
         .unwrap()
         .to_str()
         .expect("We made this from a utf8 string");
-    println!("Usage: {program_name} [flags] <epub>
+    println!("Usage: {program_name} [flags] <book or directory>...
+       {program_name} edit [edit flags] <book or directory>...
+       {program_name} validate <book or directory>...
+       {program_name} info|toc|ls <book>
+       {program_name} cat <book> <chapter>
+       {program_name} extract [-output <file>] <book> <resource>
     -usage              Display this message
     -open-in-browser    Opens the the bind url in the default application (web browser)
                         default: false
@@ -401,7 +727,31 @@ fn print_usage() {
     -bind-port          Set the bind port
                         default: '{default_bind_port}'
     -kill-timeout       Set the inactivity timeout, after which the server quits.
-                        default: -1 (disabled).",
+                        default: -1 (disabled).
+    -ignore-position    Start at the beginning of the book instead of the saved
+                        reading position
+    -export-annotations Print the highlights and notes of the book as 'markdown'
+                        or 'json' and quit
+
+Edit flags, to fix the metadata of EPUBs in place:
+    -title              Set the title
+    -author             Replace the authors, repeat it for each author
+    -series             Set the series
+    -series-index       Set the position in the series
+    -no-series          Remove the series
+    -identifier         Replace the unique identifier
+    -add-identifier     Add an identifier, such as an ISBN
+    -cover              Replace the cover with this image
+    -output             Write the edited book there instead of over the
+                        original, with a single book only
+
+Validate prints the structural problems of EPUBs, and exits with 1 if any
+has errors.
+
+Info, toc and ls print the metadata, the table of contents and the files of a
+book as JSON. Cat prints the text of a chapter, given by its spine index, id or
+path, and extract writes a file of the book, or its cover with 'cover', to its
+file name or to -output, with '-' for stdout.",
                         default_bind_addr = Config::DEFAULT_BIND_ADDR,
                         default_bind_port = Config::DEFAULT_BIND_PORT);
 }
@@ -426,12 +776,33 @@ fn main() {
         Response::from_string(status.to_string())
             .with_status_code(StatusCode(status))
     }
+    fn response_json<T: serde::Serialize>(
+        value: &T,
+    ) -> Response<Cursor<Vec<u8>>> {
+        let data = serde_json::to_vec(value).expect("serializable value");
+        Response::from_data(data)
+            .with_header(Header::from_bytes(b"Content-Type", JSON).unwrap())
+    }
+
+    fn response_xml(
+        body: String,
+        content_type: &str,
+    ) -> Response<Cursor<Vec<u8>>> {
+        Response::from_string(body).with_header(
+            Header::from_bytes(b"Content-Type", content_type).unwrap(),
+        )
+    }
 
     env_logger::Builder::from_env("READER_LOG")
         .filter_level(log::LevelFilter::Debug)
         .write_style(env_logger::fmt::WriteStyle::Always)
         .init();
 
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args) {
+        exit(code);
+    }
+
     let config = Config::default();
     let Some(config_home) = slime::xdg::Dirs::config_home_dir() else {
         error!("FATAL: Couldn't find config directory!");
@@ -492,71 +863,56 @@ fn main() {
         config
     };
 
-    let mut positionals = parse_args(&mut config).into_iter();
-    let Some(book_arg) = positionals.next() else {
+    let positionals = parse_args(&mut config);
+    if positionals.is_empty() {
         error!(
-            "FATAL: Expected an EPUB file to be provided as the first positional argument"
+            "FATAL: Expected an EPUB file or a directory of books to be provided as a positional argument"
         );
         print_usage();
         exit(1);
-    };
-
-    let book_path: &std::path::Path = book_arg.as_ref();
-    let book: Book = if book_path.extension().is_some_and(|x| {
-        x.to_str()
-            .map(str::to_lowercase)
-            .is_some_and(|x| x == "epub")
-    }) {
-        debug!("Reading {book_path:?} as EPUB");
-        match std::fs::File::open(&book_arg).and_then(|mut f| {
-            let mut bookbuf = vec![];
-            f.read_to_end(&mut bookbuf)?;
-            Ok(bookbuf)
-        }) {
-            Ok(book) => match EpubDoc::from_reader(Cursor::new(book)) {
-                Ok(b) => b.into(),
-                Err(e) => {
-                    error!(
-                        "FATAL: Failed to read provided file \"{book_arg}\": {e}"
-                    );
-                    exit(1);
-                }
-            },
-            Err(e) => {
-                error!(
-                    "FATAL: Failed to open provided book file \"{book_arg}\": {e}"
-                );
-                exit(1);
-            }
-        }
-    } else {
-        debug!("Reading {book_path:?} as Comic Book Archive");
-        match cba::CBAReader::read(book_arg.as_ref()) {
-            Ok(cba) => cba.into(),
-            Err(e) => {
-                error!(
-                    "FATAL: failed to read provided book \"{book_arg}\": {e}"
-                );
-                exit(1);
-            }
+    }
+    let paths = positionals
+        .iter()
+        .map(std::path::PathBuf::from)
+        .collect::<Vec<_>>();
+    for path in &paths {
+        if !path.exists() {
+            error!("FATAL: \"{}\" doesn't exist", path.display());
+            exit(1);
         }
-    };
+    }
 
-    let book_title = match &book {
-        Book::Epub(epub) => epub
-            .metadata
-            .get("title")
-            .into_iter()
-            .flatten()
-            .map(String::as_str)
-            .next()
-            .unwrap_or("Missing Title")
-            .to_string(),
-        Book::Cba(_cba) => String::from("Missing Title"),
-    };
+    // A single book is served on its own, anything else gets a bookshelf
+    let library_mode = paths.len() > 1
+        || paths[0].is_dir() && !library::is_image_dir(&paths[0]);
+    let mut library = library::Library::new(&paths, config.ignore_position);
+    library.comic_cache = config.comic_cache;
+    if library.entries.is_empty() {
+        error!("FATAL: Found no books to read");
+        exit(1);
+    }
+    if !library_mode {
+        // Fail early if the only book can't be read
+        let id = library.entries[0].id.clone();
+        let Some(book) = library.open(&id) else {
+            error!(
+                "FATAL: Failed to read provided book \"{}\"",
+                positionals[0]
+            );
+            exit(1);
+        };
+        if let Some(format) = config.export_annotations {
+            print!("{}", annotations::export(book, format));
+            exit(0);
+        }
+    } else if config.export_annotations.is_some() {
+        error!("FATAL: Annotations can only be exported from a single book");
+        exit(1);
+    }
 
-    let mut state = State::new(book);
+    let mut state = State::new(library, library_mode);
     state.css_variables = config.css_variables;
+    state.fit = config.fit;
     let server =
         match tiny_http::Server::http((config.bind_addr, config.bind_port)) {
             Ok(s) => s,
@@ -641,284 +997,719 @@ fn main() {
             request.method(),
             request_url
         );
-        let response = match (request_url.as_str(), request.method()) {
-            ("/" | "/reader", &Method::Get) => {
-                // Redirect to the current page
-                let page_url = match &mut state.book {
-                    Book::Epub(epub) => {
-                        assert!(epub.set_current_page(state.current_page));
-                        epub.get_current_path().unwrap()
-                    }
-                    Book::Cba(cba) => {
-                        assert!(
-                            cba.set_current_page(state.current_page).is_some()
+        let response = if let Some((id, rest)) = split_book_url(&request_url) {
+            let Some(book) = state.library.open(id) else {
+                respond(request, rcode(404));
+                continue;
+            };
+            let base = book.base();
+            match (rest, request.method()) {
+                ("/", &Method::Get) => {
+                    // Redirect to the current page
+                    let page_url = match &mut book.book {
+                        Book::Epub(epub) => epub
+                            .set_current_page(book.current_page)
+                            .then(|| epub.get_current_path())
+                            .flatten(),
+                        Book::Cba(cba) => {
+                            cba.set_current_page(book.current_page).map(|_| {
+                                std::path::PathBuf::from(
+                                    cba.get_current_page().to_string(),
+                                )
+                            })
+                        }
+                    };
+                    let Some(page_url) = page_url else {
+                        error!(
+                            "Book \"{id}\" has no page {}",
+                            book.current_page
                         );
-                        std::path::PathBuf::from(
-                            cba.get_current_page().to_string(),
-                        )
-                    }
-                };
-                Response::from_data([])
-                    .with_status_code(StatusCode(307))
-                    .with_header(
-                        Header::from_bytes(
-                            b"location",
-                            page_url.as_os_str().as_encoded_bytes(),
+                        respond(request, rcode(500));
+                        continue;
+                    };
+                    let location = format!("{base}/{}", page_url.display());
+                    Response::from_data([])
+                        .with_status_code(StatusCode(307))
+                        .with_header(
+                            Header::from_bytes(b"location", location).unwrap(),
                         )
-                        .unwrap(),
-                    )
-            }
-            ("/api/quit", &Method::Post) => {
-                info!("Quitting at the request of the client");
-                quit = true;
-                rcode(200)
-            }
-            ("/api/keepalive", &Method::Post) => {
-                debug!("Got keepalive signal from client");
-                rcode(200)
-            }
-            ("/api/page", &Method::Post) => {
-                let mut req_body = String::new();
-                // TODO: Stop unwrapping and error handle properly
-                if let Err(_e) =
-                    request.as_reader().read_to_string(&mut req_body)
-                {
-                    respond(request, response_invalid_utf8());
-                    continue;
                 }
+                ("/api/keepalive", &Method::Post) => {
+                    debug!("Got keepalive signal from client");
+                    let mut req_body = String::new();
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
+                    }
 
-                match req_body.trim() {
-                    "+" => match state.change_page(|page, page_count| {
-                        if page + 1 < page_count {
-                            page + 1
-                        } else {
-                            page
-                        }
-                    }) {
-                        Ok(r) => r,
-                        Err(()) => rcode(500),
-                    },
-                    "-" => {
-                        match state.change_page(|page, _| {
-                            if page != 0 { page - 1 } else { page }
-                        }) {
-                            Ok(r) => r,
-                            Err(()) => rcode(500),
+                    // The client may report where it is in the current page
+                    if !req_body.trim().is_empty() {
+                        match serde_json::from_str::<store::Position>(&req_body)
+                        {
+                            Ok(position)
+                                if position.page == book.current_page =>
+                            {
+                                book.store.save_position(
+                                    position.page,
+                                    position.scroll.clamp(0.0, 1.0),
+                                );
+                            }
+                            Ok(position) => {
+                                debug!(
+                                    "Ignoring position on a stale page: {position:?}"
+                                );
+                            }
+                            Err(e) => {
+                                warn!("Invalid position in keepalive: {e}");
+                                respond(request, rcode(400));
+                                continue;
+                            }
                         }
                     }
-                    _ => {
-                        let Ok(page) = req_body.parse::<usize>() else {
+                    rcode(200)
+                }
+                ("/api/position", &Method::Get) => {
+                    response_json(&book.store.data.position)
+                }
+                ("/api/highlights", &Method::Get) => {
+                    response_json(&book.store.data.highlights)
+                }
+                ("/api/highlights", &Method::Post) => {
+                    let mut req_body = String::new();
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
+                    }
+                    let highlight = match serde_json::from_str::<store::Highlight>(
+                        &req_body,
+                    ) {
+                        Ok(h)
+                            if matches!(book.book, Book::Epub(_))
+                                && h.page < book.page_count
+                                && h.start < h.end
+                                && !h.text.is_empty() =>
+                        {
+                            h
+                        }
+                        Ok(h) => {
+                            warn!("Ignoring invalid highlight: {h:?}");
                             respond(request, rcode(400));
                             continue;
-                        };
-                        let Ok(r) = state.change_page(|_, _| page.max(1) - 1)
-                        else {
-                            respond(request, rcode(500));
+                        }
+                        Err(e) => {
+                            warn!("Invalid highlight: {e}");
+                            respond(request, rcode(400));
                             continue;
-                        };
-                        r
-                    }
+                        }
+                    };
+                    let highlight = book.store.add_highlight(highlight);
+                    debug!("Saved highlight {}", highlight.id);
+                    response_json(highlight)
                 }
-            }
-            ("/api/font-size", &Method::Post) => {
-                let mut req_body = String::new();
-                // TODO: Stop unwrapping and error handle properly
-                if let Err(_e) =
-                    request.as_reader().read_to_string(&mut req_body)
+                (url, method @ (&Method::Put | &Method::Delete))
+                    if url.starts_with("/api/highlights/") =>
                 {
-                    respond(request, response_invalid_utf8());
-                    continue;
+                    let Ok(id) =
+                        url.strip_prefix("/api/highlights/").unwrap().parse()
+                    else {
+                        respond(request, rcode(404));
+                        continue;
+                    };
+                    let found = if method == &Method::Delete {
+                        book.store.remove_highlight(id)
+                    } else {
+                        let mut req_body = String::new();
+                        if let Err(_e) =
+                            request.as_reader().read_to_string(&mut req_body)
+                        {
+                            respond(request, response_invalid_utf8());
+                            continue;
+                        }
+                        match serde_json::from_str::<NoteUpdate>(&req_body) {
+                            Ok(update) => {
+                                book.store.set_highlight_note(id, update.note)
+                            }
+                            Err(e) => {
+                                warn!("Invalid note update: {e}");
+                                respond(request, rcode(400));
+                                continue;
+                            }
+                        }
+                    };
+                    if found { rcode(200) } else { rcode(404) }
                 }
-                match req_body.trim() {
-                    "+" => {
-                        state.css_variables.content_font_size_px += 2;
-                        debug!(
-                            "Increasing font size from {} to {}",
-                            state.css_variables.content_font_size_px - 2,
-                            state.css_variables.content_font_size_px
-                        );
-                        rcode(200)
+                ("/api/bookmarks", &Method::Get) => {
+                    response_json(&book.store.data.bookmarks)
+                }
+                ("/api/bookmarks", &Method::Post) => {
+                    let mut req_body = String::new();
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
                     }
-                    "-" => {
-                        if state.css_variables.content_font_size_px - 2 != 0 {
-                            state.css_variables.content_font_size_px -= 2;
-                        };
-                        debug!(
-                            "Decreasing font size from {} to {}",
-                            state.css_variables.content_font_size_px + 2,
-                            state.css_variables.content_font_size_px
-                        );
-                        rcode(200)
+                    let mut bookmark = match serde_json::from_str::<
+                        store::Bookmark,
+                    >(&req_body)
+                    {
+                        Ok(b)
+                            if b.page < book.page_count
+                                && (0.0..=1.0).contains(&b.scroll) =>
+                        {
+                            b
+                        }
+                        Ok(b) => {
+                            warn!("Ignoring invalid bookmark: {b:?}");
+                            respond(request, rcode(400));
+                            continue;
+                        }
+                        Err(e) => {
+                            warn!("Invalid bookmark: {e}");
+                            respond(request, rcode(400));
+                            continue;
+                        }
+                    };
+                    bookmark.label = bookmark.label.trim().to_string();
+                    if bookmark.label.is_empty() {
+                        bookmark.label = annotations::toc_title(
+                            &book.book,
+                            bookmark.page,
+                            bookmark.anchor.as_deref(),
+                        )
+                        .unwrap_or_else(|| {
+                            format!("Page {}", bookmark.page + 1)
+                        });
                     }
-                    _ => rcode(400),
+                    let bookmark = book.store.add_bookmark(bookmark);
+                    debug!("Saved bookmark {}", bookmark.id);
+                    response_json(bookmark)
                 }
-            }
-            ("/api/invert-text-color", &Method::Post) => {
-                std::mem::swap(
-                    &mut state.css_variables.fg_color,
-                    &mut state.css_variables.bg_color,
-                );
-                debug!("Inverted content styles: {:?}", state.css_variables);
-                debug!("Inverted text color");
-                rcode(200)
-            }
-            ("/api/content-width", &Method::Post) => {
-                let mut req_body = String::new();
-                // TODO: Stop unwrapping and error handle properly
-                if let Err(_e) =
-                    request.as_reader().read_to_string(&mut req_body)
+                (url, &Method::Delete)
+                    if url.starts_with("/api/bookmarks/") =>
                 {
-                    respond(request, response_invalid_utf8());
-                    continue;
+                    let found = url
+                        .strip_prefix("/api/bookmarks/")
+                        .unwrap()
+                        .parse()
+                        .is_ok_and(|id| book.store.remove_bookmark(id));
+                    if found { rcode(200) } else { rcode(404) }
                 }
-
-                match req_body.trim() {
-                    "+" => {
-                        state.css_variables.content_width += 1.0;
-                        debug!(
-                            "Increased content width to {}",
-                            state.css_variables.content_width
-                        );
-                        rcode(200)
+                ("/api/metadata", &Method::Get) => match &book.book {
+                    Book::Cba(cba) => response_json(&cba.metadata()),
+                    Book::Epub(_) => rcode(404),
+                },
+                ("/api/layout", &Method::Get) => match &book.book {
+                    Book::Cba(_) => response_json(&book.layout()),
+                    Book::Epub(_) => rcode(404),
+                },
+                ("/api/layout", &Method::Post) => {
+                    let mut req_body = String::new();
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
                     }
-                    "-" => {
-                        if state.css_variables.content_width > 20.0 {
-                            state.css_variables.content_width -= 1.0;
+                    if !matches!(book.book, Book::Cba(_)) {
+                        respond(request, rcode(404));
+                        continue;
+                    }
+                    match serde_json::from_str::<store::ComicLayout>(&req_body)
+                    {
+                        Ok(layout) => {
+                            debug!("Changing comic layout to {layout:?}");
+                            book.store.save_layout(layout);
+                            rcode(200)
+                        }
+                        Err(e) => {
+                            warn!("Invalid comic layout: {e}");
+                            rcode(400)
                         }
-                        debug!(
-                            "Increased content width to {}",
-                            state.css_variables.content_width
-                        );
-                        rcode(200)
                     }
-                    _ => rcode(400),
                 }
-            }
-            (content, &Method::Get) if content.starts_with("/content/") => {
-                let content = content.strip_prefix("/content/").unwrap();
-                match &mut state.book {
-                    Book::Epub(epub) => {
-                        let (Some(data), Some(mime)) = (
-                            epub.get_resource_by_path(content),
-                            epub.get_resource_mime_by_path(content),
-                        ) else {
-                            request
-                                .respond(
-                                    Response::from_string("404")
-                                        .with_status_code(StatusCode(404)),
-                                )
-                                .unwrap();
+                ("/api/page-order", &Method::Get) => match &book.book {
+                    Book::Cba(_) => response_json(&book.store.data.page_order),
+                    Book::Epub(_) => rcode(404),
+                },
+                ("/api/page-order", &Method::Post) => {
+                    let mut req_body = String::new();
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
+                    }
+                    let Book::Cba(cba) = &mut book.book else {
+                        respond(request, rcode(404));
+                        continue;
+                    };
+                    match req_body.trim().parse::<cba::PageOrder>() {
+                        Ok(order) => {
+                            debug!("Ordering pages by {order:?}");
+                            cba.set_page_order(order);
+                            book.page_count = cba.page_count();
+                            book.current_page = book
+                                .current_page
+                                .min(book.page_count.saturating_sub(1));
+                            book.store.save_page_order(order);
+                            rcode(200)
+                        }
+                        Err(e) => {
+                            warn!("Invalid page order: {e}");
+                            rcode(400)
+                        }
+                    }
+                }
+                (url, &Method::Get)
+                    if url.split('?').next() == Some("/api/annotations") =>
+                {
+                    let query = parse_query(url);
+                    let format = match query.get("format").map(|f| f.parse()) {
+                        Some(Ok(f)) => f,
+                        Some(Err(_)) => {
+                            respond(request, rcode(400));
                             continue;
-                        };
-
-                        let data = if mime == XHTML || mime == HTML {
-                            let content_styles = ContentStyles {
-                                variables: state.css_variables,
-                            }
-                            .render()
-                            .unwrap();
-                            debug!("rendered content styles: {content_styles}");
-                            let data = std::str::from_utf8(&data).unwrap();
-                            // TODO: make paragraph numbers usable!!!!
-                            fix_content(data, &content_styles, false)
-                                .into_bytes()
-                        } else {
-                            data
-                        };
-                        Response::from_data(data).with_header(
+                        }
+                        None => annotations::ExportFormat::Markdown,
+                    };
+                    let extension = match format {
+                        annotations::ExportFormat::Markdown => "md",
+                        annotations::ExportFormat::Json => "json",
+                    };
+                    let disposition = format!(
+                        "attachment; filename*=UTF-8''{}",
+                        percent_encoding::utf8_percent_encode(
+                            &format!("{}.{extension}", book.title.trim()),
+                            percent_encoding::NON_ALPHANUMERIC
+                        )
+                    );
+                    Response::from_string(annotations::export(book, format))
+                        .with_header(
+                            Header::from_bytes(b"Content-Type", format.mime())
+                                .unwrap(),
+                        )
+                        .with_header(
                             Header::from_bytes(
-                                b"Content-Type",
-                                mime.as_bytes(),
+                                b"Content-Disposition",
+                                disposition,
                             )
-                            .expect("no header?"),
+                            .unwrap(),
                         )
+                }
+                ("/api/page", &Method::Post) => {
+                    let mut req_body = String::new();
+                    // TODO: Stop unwrapping and error handle properly
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
                     }
-                    Book::Cba(cba) => {
-                        let Ok(page_num) = content.parse::<usize>() else {
-                            error!(
-                                "Failed to parse page number from /content url (\"{content}\")"
-                            );
-                            respond(
-                                request,
-                                Response::new_empty(StatusCode(404)),
-                            );
+
+                    match req_body.trim() {
+                        "+" => {
+                            let next = book.next_page();
+                            match book.change_page(|_, _| next) {
+                                Ok(r) => r,
+                                Err(()) => rcode(500),
+                            }
+                        }
+                        "-" => {
+                            let previous = book.previous_page();
+                            match book.change_page(|_, _| previous) {
+                                Ok(r) => r,
+                                Err(()) => rcode(500),
+                            }
+                        }
+                        _ => {
+                            let Ok(page) = req_body.parse::<usize>() else {
+                                respond(request, rcode(400));
+                                continue;
+                            };
+                            let Ok(r) =
+                                book.change_page(|_, _| page.max(1) - 1)
+                            else {
+                                respond(request, rcode(500));
+                                continue;
+                            };
+                            r
+                        }
+                    }
+                }
+                ("/api/toc", &Method::Get) => {
+                    let toc = match &book.book {
+                        Book::Epub(epub) => {
+                            TocEntry::from_navpoints(epub, &epub.toc, &base)
+                        }
+                        Book::Cba(_) => vec![],
+                    };
+                    response_json(&toc)
+                }
+                (url, &Method::Get)
+                    if url.split('?').next() == Some("/api/search") =>
+                {
+                    let query = parse_query(url);
+                    let Some(q) = query.get("q").filter(|q| !q.is_empty())
+                    else {
+                        respond(request, rcode(400));
+                        continue;
+                    };
+                    let mode = match query.get("mode").map(String::as_str) {
+                        Some("regex") => SearchMode::Regex,
+                        Some("text") | None => SearchMode::Text,
+                        Some(_) => {
+                            respond(request, rcode(400));
                             continue;
-                        };
+                        }
+                    };
 
-                        match cba.page(page_num) {
-                            Ok((contents, mime)) => {
-                                Response::from_data(contents).with_header(
-                                    Header::from_bytes(
-                                        b"Content-Type",
-                                        mime.to_string().as_bytes(),
-                                    )
-                                    .expect("no head?"),
-                                )
+                    match &mut book.book {
+                        Book::Epub(epub) => match epub.search(q, mode) {
+                            Ok(matches) => {
+                                debug!(
+                                    "Found {} matches for {q:?}",
+                                    matches.len()
+                                );
+                                let results = matches
+                                    .into_iter()
+                                    .filter_map(|m| {
+                                        let id =
+                                            &epub.spine.get(m.chapter)?.idref;
+                                        let path = epub
+                                            .resources
+                                            .get(id)?
+                                            .0
+                                            .to_str()?;
+                                        let url = match m.anchor {
+                                            Some(anchor) => {
+                                                format!(
+                                                    "{base}/{path}#{anchor}"
+                                                )
+                                            }
+                                            None => format!("{base}/{path}"),
+                                        };
+                                        Some(SearchResult {
+                                            page: m.chapter,
+                                            url,
+                                            matched: m.matched,
+                                            snippet: m.snippet,
+                                        })
+                                    })
+                                    .collect::<Vec<_>>();
+                                response_json(&results)
                             }
                             Err(e) => {
+                                warn!("Search for {q:?} failed: {e}");
+                                Response::from_string(e.to_string())
+                                    .with_status_code(StatusCode(400))
+                            }
+                        },
+                        Book::Cba(_) => {
+                            response_json(&Vec::<SearchResult>::new())
+                        }
+                    }
+                }
+                (url, &Method::Get) if url.starts_with("/pages/") => {
+                    let Book::Cba(cba) = &book.book else {
+                        respond(request, rcode(404));
+                        continue;
+                    };
+                    let Some(page_num) = url
+                        .strip_prefix("/pages/")
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|&n| n < book.page_count)
+                    else {
+                        respond(request, rcode(404));
+                        continue;
+                    };
+                    let pages = if book.store.data.layout.spread {
+                        cba.spread(page_num)
+                    } else {
+                        page_num..page_num + 1
+                    };
+                    let images = pages
+                        .map(|page| format!("{}/content/{page}", book.base()))
+                        .collect::<Vec<_>>();
+                    let pages = CBPages {
+                        title: &book.title,
+                        images: &images,
+                        right_to_left: book.right_to_left(),
+                    };
+                    Response::from_string(pages.render().expect("pages"))
+                        .with_header(
+                            Header::from_bytes(b"Content-Type", XHTML).unwrap(),
+                        )
+                }
+                ("/strip", &Method::Get) => {
+                    if !matches!(book.book, Book::Cba(_)) {
+                        respond(request, rcode(404));
+                        continue;
+                    }
+                    let images = (0..book.page_count)
+                        .map(|page| format!("{}/content/{page}", book.base()))
+                        .collect::<Vec<_>>();
+                    let strip = CBStrip {
+                        title: &book.title,
+                        images: &images,
+                    };
+                    Response::from_string(strip.render().expect("strip"))
+                        .with_header(
+                            Header::from_bytes(b"Content-Type", XHTML).unwrap(),
+                        )
+                }
+                (content, &Method::Get) if content.starts_with("/content/") => {
+                    let content = content.strip_prefix("/content/").unwrap();
+                    match &mut book.book {
+                        Book::Epub(epub) => {
+                            let (Some(data), Some(mime)) = (
+                                epub.get_resource_by_path(content),
+                                epub.get_resource_mime_by_path(content),
+                            ) else {
+                                request
+                                    .respond(
+                                        Response::from_string("404")
+                                            .with_status_code(StatusCode(404)),
+                                    )
+                                    .unwrap();
+                                continue;
+                            };
+
+                            let data = if mime == XHTML || mime == HTML {
+                                let content_styles = ContentStyles {
+                                    variables: state.css_variables,
+                                }
+                                .render()
+                                .unwrap();
+                                debug!(
+                                    "rendered content styles: {content_styles}"
+                                );
+                                let Ok(data) = std::str::from_utf8(&data)
+                                else {
+                                    error!(
+                                        "Resource \"{content}\" is not valid UTF-8"
+                                    );
+                                    respond(request, rcode(500));
+                                    continue;
+                                };
+                                let page = epub.resource_uri_to_chapter(
+                                    &std::path::PathBuf::from(content),
+                                );
+                                let highlights = book
+                                    .store
+                                    .data
+                                    .highlights
+                                    .iter()
+                                    .filter(|h| Some(h.page) == page)
+                                    .collect::<Vec<_>>();
+                                // TODO: make paragraph numbers usable!!!!
+                                match fix_content(
+                                    data,
+                                    &content_styles,
+                                    false,
+                                    &highlights,
+                                ) {
+                                    Ok(fixed) => fixed.into_bytes(),
+                                    Err(e) => {
+                                        error!(
+                                            "XML parsing error in \"{content}\": {e}"
+                                        );
+                                        respond(request, rcode(500));
+                                        continue;
+                                    }
+                                }
+                            } else {
+                                data
+                            };
+                            Response::from_data(data).with_header(
+                                Header::from_bytes(
+                                    b"Content-Type",
+                                    mime.as_bytes(),
+                                )
+                                .expect("no header?"),
+                            )
+                        }
+                        Book::Cba(cba) => {
+                            let Ok(page_num) = content.parse::<usize>() else {
                                 error!(
-                                    "Failed to get page \"{page_num}\" from CBA: {e}"
+                                    "Failed to parse page number from /content url (\"{content}\")"
                                 );
                                 respond(
                                     request,
-                                    Response::new_empty(StatusCode(500)),
+                                    Response::new_empty(StatusCode(404)),
                                 );
                                 continue;
+                            };
+
+                            match cba.page(page_num) {
+                                Ok((contents, mime)) => {
+                                    Response::from_data(contents).with_header(
+                                        Header::from_bytes(
+                                            b"Content-Type",
+                                            mime.to_string().as_bytes(),
+                                        )
+                                        .expect("no head?"),
+                                    )
+                                }
+                                Err(e) => {
+                                    error!(
+                                        "Failed to get page \"{page_num}\" from CBA: {e}"
+                                    );
+                                    respond(
+                                        request,
+                                        Response::new_empty(StatusCode(500)),
+                                    );
+                                    continue;
+                                }
                             }
                         }
                     }
                 }
-            }
-            (req_url, &Method::Get) => {
-                let req_url =
-                    std::path::PathBuf::from(req_url.trim_start_matches('/'));
-
-                match &mut state.book {
-                    Book::Epub(epub) => {
-                        let abs_url = if req_url.starts_with(&epub.root_base) {
-                            req_url
-                        } else {
-                            epub.root_base.join(req_url)
-                        };
-
-                        if let Some(idx) =
-                            epub.resource_uri_to_chapter(&abs_url)
-                        {
-                            if idx != state.current_page {
-                                state.current_page = idx;
-                                debug!(
-                                    "Set page to {} / {}",
-                                    state.current_page + 1,
-                                    state.page_count
+                (req_url, &Method::Get) => {
+                    let req_url = std::path::PathBuf::from(
+                        req_url.trim_start_matches('/'),
+                    );
+
+                    match &mut book.book {
+                        Book::Epub(epub) => {
+                            let abs_url =
+                                if req_url.starts_with(&epub.root_base) {
+                                    req_url
+                                } else {
+                                    epub.root_base.join(req_url)
+                                };
+
+                            if let Some(idx) =
+                                epub.resource_uri_to_chapter(&abs_url)
+                            {
+                                if idx != book.current_page {
+                                    book.current_page = idx;
+                                    book.store
+                                        .save_position(book.current_page, 0.0);
+                                    debug!(
+                                        "Set page to {} / {}",
+                                        book.current_page + 1,
+                                        book.page_count
+                                    );
+                                }
+                                assert!(
+                                    epub.set_current_page(book.current_page),
+                                    "{} should be valid",
+                                    book.current_page
                                 );
+                                let Some(page_path) = epub.get_current_path()
+                                else {
+                                    respond(request, rcode(500));
+                                    continue;
+                                };
+                                let page_url = std::path::PathBuf::from(&base)
+                                    .join("content")
+                                    .join(page_path);
+                                let page_url = page_url.to_str().unwrap();
+
+                                let stylesheet = ReaderStyles {
+                                    variables: state.css_variables,
+                                }
+                                .render()
+                                .unwrap();
+                                let rv = Reader {
+                                    title: &book.title,
+                                    stylesheet: &stylesheet,
+                                    javascript: READER_JS,
+                                    page_url,
+                                    base: &base,
+                                    library: state.library_mode,
+
+                                    current_page: book.current_page + 1,
+                                    page_count: book.page_count,
+                                };
+                                Response::from_string(
+                                    rv.render().expect("thing inside thing"),
+                                )
+                                .with_header(
+                                    Header::from_bytes(b"Content-Type", XHTML)
+                                        .unwrap(),
+                                )
+                            } else {
+                                let (Some(data), Some(mime)) = (
+                                    epub.get_resource_by_path(&abs_url),
+                                    epub.get_resource_mime_by_path(&abs_url),
+                                ) else {
+                                    request.respond(rcode(404)).unwrap();
+                                    continue;
+                                };
+
+                                Response::from_data(data).with_header(
+                                    Header::from_bytes(
+                                        b"Content-Type",
+                                        mime.as_bytes(),
+                                    )
+                                    .expect("no header?"),
+                                )
                             }
-                            assert!(
-                                epub.set_current_page(state.current_page),
-                                "{} should be valid",
-                                state.current_page
-                            );
-                            let Some(page_path) = epub.get_current_path()
+                        }
+                        Book::Cba(cba) => {
+                            let Some(req_url) = req_url.to_str() else {
+                                respond(
+                                    request,
+                                    Response::new_empty(StatusCode(404)),
+                                );
+                                continue;
+                            };
+                            // Only pages that exist may be saved as the
+                            // position, since the book is reopened at it
+                            let Some(page_num) = req_url
+                                .parse::<usize>()
+                                .ok()
+                                .filter(|&n| n < book.page_count)
                             else {
-                                respond(request, rcode(500));
+                                respond(
+                                    request,
+                                    Response::new_empty(StatusCode(404)),
+                                );
                                 continue;
                             };
-                            let page_url = std::path::PathBuf::from("/content")
-                                .join(page_path);
-                            let page_url = page_url.to_str().unwrap();
+                            if page_num != book.current_page {
+                                book.current_page = page_num;
+                                book.store
+                                    .save_position(book.current_page, 0.0);
+                            }
+                            cba.set_current_page(book.current_page);
 
                             let stylesheet = ReaderStyles {
                                 variables: state.css_variables,
                             }
                             .render()
                             .unwrap();
-                            let rv = Reader {
-                                title: &book_title,
+                            // The images are shown in pages of their own, so
+                            // that they can be laid out and sized
+                            let image_url = std::path::PathBuf::from(&base);
+                            let image_url = if book.store.data.layout.webtoon {
+                                image_url.join("strip")
+                            } else {
+                                image_url
+                                    .join("pages")
+                                    .join(page_num.to_string())
+                            };
+                            let image_url = image_url.to_str().unwrap();
+
+                            let rv = CBReader {
+                                title: &book.title,
                                 stylesheet: &stylesheet,
                                 javascript: READER_JS,
-                                page_url,
-
-                                current_page: state.current_page + 1,
-                                page_count: state.page_count,
+                                image_url,
+                                base: &base,
+                                library: state.library_mode,
+                                current_page: book.current_page + 1,
+                                page_count: book.page_count,
+                                page_order: book.store.data.page_order,
+                                layout: book.store.data.layout,
+                                right_to_left: book.right_to_left(),
+                                fit: state.fit,
                             };
                             Response::from_string(
                                 rv.render().expect("thing inside thing"),
@@ -927,83 +1718,327 @@ fn main() {
                                 Header::from_bytes(b"Content-Type", XHTML)
                                     .unwrap(),
                             )
-                        } else {
-                            let (Some(data), Some(mime)) = (
-                                epub.get_resource_by_path(&abs_url),
-                                epub.get_resource_mime_by_path(&abs_url),
-                            ) else {
-                                request.respond(rcode(404)).unwrap();
-                                continue;
+                        }
+                    }
+                }
+                _ => rcode(404),
+            }
+        } else {
+            match (request_url.as_str(), request.method()) {
+                ("/" | "/reader", &Method::Get) if state.library_mode => {
+                    let stylesheet = ReaderStyles {
+                        variables: state.css_variables,
+                    }
+                    .render()
+                    .unwrap();
+                    let shelf = Shelf {
+                        stylesheet: &stylesheet,
+                        javascript: LIBRARY_JS,
+                        books: &state.library.entries,
+                    };
+                    Response::from_string(shelf.render().expect("shelf"))
+                        .with_header(
+                            Header::from_bytes(b"Content-Type", XHTML).unwrap(),
+                        )
+                }
+                ("/" | "/reader", &Method::Get) => {
+                    // Only one book is being served, so go straight to it
+                    let location =
+                        format!("/book/{}/", state.library.entries[0].id);
+                    Response::from_data([])
+                        .with_status_code(StatusCode(307))
+                        .with_header(
+                            Header::from_bytes(b"location", location).unwrap(),
+                        )
+                }
+                ("/api/quit", &Method::Post) => {
+                    info!("Quitting at the request of the client");
+                    quit = true;
+                    rcode(200)
+                }
+                ("/api/font-size", &Method::Post) => {
+                    let mut req_body = String::new();
+                    // TODO: Stop unwrapping and error handle properly
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
+                    }
+                    match req_body.trim() {
+                        "+" => {
+                            state.css_variables.content_font_size_px += 2;
+                            debug!(
+                                "Increasing font size from {} to {}",
+                                state.css_variables.content_font_size_px - 2,
+                                state.css_variables.content_font_size_px
+                            );
+                            rcode(200)
+                        }
+                        "-" => {
+                            if state.css_variables.content_font_size_px - 2 != 0
+                            {
+                                state.css_variables.content_font_size_px -= 2;
                             };
+                            debug!(
+                                "Decreasing font size from {} to {}",
+                                state.css_variables.content_font_size_px + 2,
+                                state.css_variables.content_font_size_px
+                            );
+                            rcode(200)
+                        }
+                        _ => rcode(400),
+                    }
+                }
+                ("/api/invert-text-color", &Method::Post) => {
+                    std::mem::swap(
+                        &mut state.css_variables.fg_color,
+                        &mut state.css_variables.bg_color,
+                    );
+                    debug!(
+                        "Inverted content styles: {:?}",
+                        state.css_variables
+                    );
+                    debug!("Inverted text color");
+                    rcode(200)
+                }
+                ("/api/fit", &Method::Get) => response_json(&state.fit),
+                ("/api/fit", &Method::Post) => {
+                    let mut req_body = String::new();
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
+                    }
 
-                            Response::from_data(data).with_header(
-                                Header::from_bytes(
-                                    b"Content-Type",
-                                    mime.as_bytes(),
-                                )
-                                .expect("no header?"),
-                            )
+                    match req_body.trim().parse() {
+                        Ok(fit) => {
+                            state.fit = fit;
+                            debug!("Fitting comic pages to {fit:?}");
+                            rcode(200)
+                        }
+                        Err(e) => {
+                            warn!("Invalid fit mode: {e}");
+                            rcode(400)
                         }
                     }
-                    Book::Cba(cba) => {
-                        let Some(req_url) = req_url.to_str() else {
-                            respond(
-                                request,
-                                Response::new_empty(StatusCode(404)),
+                }
+                ("/api/content-width", &Method::Post) => {
+                    let mut req_body = String::new();
+                    // TODO: Stop unwrapping and error handle properly
+                    if let Err(_e) =
+                        request.as_reader().read_to_string(&mut req_body)
+                    {
+                        respond(request, response_invalid_utf8());
+                        continue;
+                    }
+
+                    match req_body.trim() {
+                        "+" => {
+                            state.css_variables.content_width += 1.0;
+                            debug!(
+                                "Increased content width to {}",
+                                state.css_variables.content_width
                             );
-                            continue;
-                        };
-                        let Ok(page_num) = req_url.parse::<usize>() else {
-                            respond(
-                                request,
-                                Response::new_empty(StatusCode(404)),
+                            rcode(200)
+                        }
+                        "-" => {
+                            if state.css_variables.content_width > 20.0 {
+                                state.css_variables.content_width -= 1.0;
+                            }
+                            debug!(
+                                "Increased content width to {}",
+                                state.css_variables.content_width
                             );
+                            rcode(200)
+                        }
+                        _ => rcode(400),
+                    }
+                }
+                ("/opds", &Method::Get) => response_xml(
+                    opds::Feed::root(&state.library).render().expect("feed"),
+                    opds::NAVIGATION,
+                ),
+                ("/opds/authors", &Method::Get) => response_xml(
+                    opds::Feed::authors(&state.library).render().expect("feed"),
+                    opds::NAVIGATION,
+                ),
+                (url, &Method::Get)
+                    if url.split('?').next() == Some("/opds/books") =>
+                {
+                    let query = parse_query(url);
+                    let feed = opds::Feed::books(
+                        &state.library,
+                        query.get("author").map(String::as_str),
+                    );
+                    response_xml(
+                        feed.render().expect("feed"),
+                        opds::ACQUISITION,
+                    )
+                }
+                (url, &Method::Get)
+                    if url.split('?').next() == Some("/opds/search") =>
+                {
+                    let query = parse_query(url);
+                    let Some(q) = query.get("q") else {
+                        respond(request, rcode(400));
+                        continue;
+                    };
+                    let feed = opds::Feed::search(&state.library, q);
+                    response_xml(
+                        feed.render().expect("feed"),
+                        opds::ACQUISITION,
+                    )
+                }
+                ("/opds/opensearch.xml", &Method::Get) => {
+                    // Search templates have to be absolute, so use the host
+                    // the client reached us by
+                    let origin = request
+                        .headers()
+                        .iter()
+                        .find(|h| h.field.equiv("Host"))
+                        .map_or_else(
+                            || format!("http://{}", state.socket_addr),
+                            |h| format!("http://{}", h.value),
+                        );
+                    let description = opds::OpenSearch { origin: &origin };
+                    response_xml(
+                        description.render().expect("opensearch"),
+                        opds::OPENSEARCH,
+                    )
+                }
+                (url, &Method::Get) if url.starts_with("/download/") => {
+                    let id = url.strip_prefix("/download/").unwrap();
+                    let Some(entry) = state
+                        .library
+                        .entry(id)
+                        .filter(|entry| entry.downloadable())
+                    else {
+                        respond(request, rcode(404));
+                        continue;
+                    };
+                    let file = match std::fs::File::open(&entry.path) {
+                        Ok(file) => file,
+                        Err(e) => {
+                            error!(
+                                "Failed to open \"{}\" for download: {e}",
+                                entry.path.display()
+                            );
+                            respond(request, rcode(500));
                             continue;
-                        };
-                        state.current_page = page_num;
-                        cba.set_current_page(state.current_page);
-
-                        let stylesheet = ReaderStyles {
-                            variables: state.css_variables,
                         }
-                        .render()
-                        .unwrap();
-                        let image_url = std::path::PathBuf::from("/content")
-                            .join(page_num.to_string());
-                        let image_url = image_url.to_str().unwrap();
-
-                        let rv = CBReader {
-                            title: &book_title,
-                            stylesheet: &stylesheet,
-                            javascript: READER_JS,
-                            image_url,
-                            current_page: state.current_page + 1,
-                            page_count: state.page_count,
-                        };
-                        Response::from_string(
-                            rv.render().expect("thing inside thing"),
+                    };
+                    let file_name = entry
+                        .path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let disposition = format!(
+                        "attachment; filename*=UTF-8''{}",
+                        percent_encoding::utf8_percent_encode(
+                            &file_name,
+                            percent_encoding::NON_ALPHANUMERIC
                         )
+                    );
+                    // Stream the file rather than reading it into memory
+                    let response = Response::from_file(file)
                         .with_header(
-                            Header::from_bytes(b"Content-Type", XHTML).unwrap(),
+                            Header::from_bytes(b"Content-Type", entry.mime())
+                                .unwrap(),
                         )
-                    }
+                        .with_header(
+                            Header::from_bytes(
+                                b"Content-Disposition",
+                                disposition,
+                            )
+                            .unwrap(),
+                        );
+                    respond(request, response);
+                    continue;
+                }
+                (url, &Method::Get) if url.starts_with("/cover/") => {
+                    let id = url.strip_prefix("/cover/").unwrap();
+                    let Some((data, mime)) = state.library.cover(id) else {
+                        respond(request, rcode(404));
+                        continue;
+                    };
+                    // Served from the shared buffer, so it isn't copied
+                    let length = data.len();
+                    let response = Response::new(
+                        StatusCode(200),
+                        vec![
+                            Header::from_bytes(b"Content-Type", mime).unwrap(),
+                            Header::from_bytes(
+                                b"Cache-Control",
+                                "max-age=3600",
+                            )
+                            .unwrap(),
+                        ],
+                        Cursor::new(data),
+                        Some(length),
+                        None,
+                    );
+                    respond(request, response);
+                    continue;
                 }
+                _ => rcode(404),
             }
-            _ => rcode(404),
         };
 
         respond(request, response);
     }
 }
 
-/// Add the `stylesheet` to the end of the XHTML Header found in `src`. This
-/// does nothing if `src` doesn't have an HTML header.
-fn fix_content(src: &str, stylesheet: &str, paragraph_numbers: bool) -> String {
+/// Split a `/book/<id>/...` url into the book id and the rest of the url,
+/// which always starts with a `/`.
+fn split_book_url(url: &str) -> Option<(&str, &str)> {
+    let url = url.strip_prefix("/book/")?;
+    match url.find(['/', '?']) {
+        Some(i) if url[i..].starts_with('/') => Some((&url[..i], &url[i..])),
+        Some(_) => None,
+        None => Some((url, "/")),
+    }
+}
+
+/// Parse the query string of `url` into its decoded key-value pairs.
+fn parse_query(url: &str) -> std::collections::HashMap<String, String> {
+    fn decode(s: &str) -> String {
+        let s = s.replace('+', " ");
+        percent_encoding::percent_decode_str(&s)
+            .decode_utf8_lossy()
+            .into_owned()
+    }
+
+    url.split_once('?')
+        .map(|(_, query)| query)
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+/// Add the `stylesheet` to the end of the XHTML Header found in `src`, and
+/// mark the passages of `highlights` in its body. The stylesheet isn't added
+/// if `src` doesn't have an HTML header. Fails if `src` isn't well-formed XML.
+fn fix_content(
+    src: &str,
+    stylesheet: &str,
+    paragraph_numbers: bool,
+    highlights: &[&store::Highlight],
+) -> Result<String, xmlparser::Error> {
     use xmlparser::{ElementEnd, Token};
     let mut output = String::with_capacity(src.len() + stylesheet.len());
     let mut in_paragraph = false;
     let mut in_body = false;
     let mut paragraph_count = 0usize;
+    let highlights = annotations::resolve(src, highlights.iter().copied());
+    // How many characters of body text have been written
+    let mut text_pos = 0usize;
 
     for token in xmlparser::Tokenizer::from(src) {
         match token {
@@ -1046,7 +2081,24 @@ fn fix_content(src: &str, stylesheet: &str, paragraph_numbers: bool) -> String {
                     output.push_str(&paragraph_count.to_string());
                     output.push_str(r#"</span>"#);
                 }
-                output.push_str(text.as_str());
+                annotations::push_marked_text(
+                    &mut output,
+                    text.as_str(),
+                    &mut text_pos,
+                    &highlights,
+                );
+            }
+            Ok(Token::Text { text, .. }) if in_body => {
+                annotations::push_marked_text(
+                    &mut output,
+                    text.as_str(),
+                    &mut text_pos,
+                    &highlights,
+                );
+            }
+            Ok(Token::Cdata { text, span }) if in_body => {
+                text_pos += text.as_str().chars().count();
+                output.push_str(span.as_str());
             }
             Ok(Token::ElementEnd {
                 end: ElementEnd::Close(_, ename),
@@ -1067,11 +2119,8 @@ fn fix_content(src: &str, stylesheet: &str, paragraph_numbers: bool) -> String {
             Ok(t) => {
                 output.push_str(t.span().as_str());
             }
-            Err(e) => {
-                error!("FATAL: XML parsing error: {e}");
-                exit(1);
-            }
+            Err(e) => return Err(e),
         }
     }
-    output
+    Ok(output)
 }
@@ -0,0 +1,203 @@
+//! OPDS 1.2 catalog feeds for the library.
+//!
+//! The catalog starts at `/opds`, a navigation feed linking to acquisition
+//! feeds of all books and of the books by each author. Searching is described
+//! by the `OpenSearch` document at `/opds/opensearch.xml`. Books link to their
+//! cover at `/cover/<id>` and their original file at `/download/<id>`.
+
+use crate::library::{Entry, Library};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use rinja::Template;
+use std::time::SystemTime;
+
+pub const NAVIGATION: &str =
+    "application/atom+xml;profile=opds-catalog;kind=navigation";
+pub const ACQUISITION: &str =
+    "application/atom+xml;profile=opds-catalog;kind=acquisition";
+pub const OPENSEARCH: &str = "application/opensearchdescription+xml";
+
+/// An entry of a navigation feed, linking to another feed.
+#[derive(Debug)]
+pub struct Link {
+    pub title: String,
+    pub href: String,
+    /// The type of the linked feed, either [`NAVIGATION`] or [`ACQUISITION`].
+    pub kind: &'static str,
+    pub content: String,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "opds.xml")]
+pub struct Feed<'a> {
+    pub id: String,
+    pub title: String,
+    pub updated: String,
+    pub self_url: String,
+    /// The type of this feed, either [`NAVIGATION`] or [`ACQUISITION`].
+    pub kind: &'static str,
+    pub links: Vec<Link>,
+    pub books: Vec<&'a Entry>,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "opensearch.xml")]
+pub struct OpenSearch<'a> {
+    /// The absolute url of the server, as search templates can't be relative.
+    pub origin: &'a str,
+}
+
+impl<'a> Feed<'a> {
+    /// The catalog root.
+    pub fn root(library: &'a Library) -> Self {
+        Self {
+            id: String::from("urn:epub-reader:root"),
+            title: String::from("Library"),
+            updated: updated(&library.entries),
+            self_url: String::from("/opds"),
+            kind: NAVIGATION,
+            links: vec![
+                Link {
+                    title: String::from("All Books"),
+                    href: String::from("/opds/books"),
+                    kind: ACQUISITION,
+                    content: format!("{} books", library.entries.len()),
+                },
+                Link {
+                    title: String::from("Authors"),
+                    href: String::from("/opds/authors"),
+                    kind: NAVIGATION,
+                    content: String::from("Books by author"),
+                },
+            ],
+            books: vec![],
+        }
+    }
+
+    /// A navigation feed with an entry for each author.
+    pub fn authors(library: &'a Library) -> Self {
+        let mut authors = library
+            .entries
+            .iter()
+            .flat_map(|e| &e.authors)
+            .collect::<Vec<_>>();
+        authors.sort_unstable();
+        authors.dedup();
+
+        let links = authors
+            .into_iter()
+            .map(|author| {
+                let count = library
+                    .entries
+                    .iter()
+                    .filter(|e| e.authors.contains(author))
+                    .count();
+                Link {
+                    title: author.clone(),
+                    href: format!(
+                        "/opds/books?author={}",
+                        utf8_percent_encode(author, NON_ALPHANUMERIC)
+                    ),
+                    kind: ACQUISITION,
+                    content: format!("{count} books"),
+                }
+            })
+            .collect();
+
+        Self {
+            id: String::from("urn:epub-reader:authors"),
+            title: String::from("Authors"),
+            updated: updated(&library.entries),
+            self_url: String::from("/opds/authors"),
+            kind: NAVIGATION,
+            links,
+            books: vec![],
+        }
+    }
+
+    /// An acquisition feed of every book, or only those by `author`.
+    pub fn books(library: &'a Library, author: Option<&str>) -> Self {
+        let books = library
+            .entries
+            .iter()
+            .filter(|e| author.is_none_or(|a| e.authors.iter().any(|x| x == a)))
+            .collect::<Vec<_>>();
+        let (id, title, self_url) = match author {
+            Some(author) => {
+                let encoded = utf8_percent_encode(author, NON_ALPHANUMERIC);
+                (
+                    format!("urn:epub-reader:author:{encoded}"),
+                    format!("Books by {author}"),
+                    format!("/opds/books?author={encoded}"),
+                )
+            }
+            None => (
+                String::from("urn:epub-reader:books"),
+                String::from("All Books"),
+                String::from("/opds/books"),
+            ),
+        };
+
+        Self {
+            id,
+            title,
+            updated: updated(books.iter().copied()),
+            self_url,
+            kind: ACQUISITION,
+            links: vec![],
+            books,
+        }
+    }
+
+    /// An acquisition feed of the books whose title, authors or description
+    /// contain `query`, ignoring case.
+    pub fn search(library: &'a Library, query: &str) -> Self {
+        let needle = query.to_lowercase();
+        let books = library
+            .entries
+            .iter()
+            .filter(|e| {
+                std::iter::once(&e.title)
+                    .chain(&e.authors)
+                    .chain(&e.description)
+                    .any(|s| s.to_lowercase().contains(&needle))
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            id: format!(
+                "urn:epub-reader:search:{}",
+                utf8_percent_encode(query, NON_ALPHANUMERIC)
+            ),
+            title: format!("Search results for \"{query}\""),
+            updated: updated(books.iter().copied()),
+            self_url: format!(
+                "/opds/search?q={}",
+                utf8_percent_encode(query, NON_ALPHANUMERIC)
+            ),
+            kind: ACQUISITION,
+            links: vec![],
+            books,
+        }
+    }
+}
+
+impl Entry {
+    /// When the book was last modified, as an RFC 3339 timestamp.
+    pub fn updated(&self) -> String {
+        timestamp(self.modified)
+    }
+}
+
+/// The last modification time of `books`, as an RFC 3339 timestamp.
+fn updated<'a>(books: impl IntoIterator<Item = &'a Entry>) -> String {
+    let modified = books
+        .into_iter()
+        .map(|e| e.modified)
+        .max()
+        .unwrap_or_else(SystemTime::now);
+    timestamp(modified)
+}
+
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
@@ -1,10 +1,23 @@
+// The url prefix of the book being read, e.g. "/book/0123456789abcdef".
+const BASE = document.body.dataset.base;
+// Whether the book is a comic read right to left, which swaps the arrow keys.
+const RIGHT_TO_LEFT = document.body.dataset.direction === "rtl";
+
 const API = {
     QUIT: "/api/quit",
-    KEEPALIVE: "/api/keepalive",
-    PAGE: "/api/page",
+    KEEPALIVE: BASE + "/api/keepalive",
+    PAGE: BASE + "/api/page",
     FONT_SIZE: "/api/font-size",
     INVERT_TEXT_COLOR: "/api/invert-text-color",
     CONTENT_WIDTH: "/api/content-width",
+    FIT: "/api/fit",
+    TOC: BASE + "/api/toc",
+    POSITION: BASE + "/api/position",
+    SEARCH: BASE + "/api/search",
+    HIGHLIGHTS: BASE + "/api/highlights",
+    BOOKMARKS: BASE + "/api/bookmarks",
+    PAGE_ORDER: BASE + "/api/page-order",
+    LAYOUT: BASE + "/api/layout",
 };
 
 async function api_quit() {
@@ -12,7 +25,15 @@ async function api_quit() {
 }
 
 async function api_keepalive() {
-    const response = await fetch(API.KEEPALIVE, { method: "POST" });
+    const response = await fetch(API.KEEPALIVE, {
+        method: "POST",
+        body: JSON.stringify(current_position()),
+    });
+}
+
+async function api_position() {
+    const response = await fetch(API.POSITION);
+    return await response.json();
 }
 
 async function api_page(action) {
@@ -24,6 +45,24 @@ async function api_page(action) {
     return text;
 }
 
+async function api_page_order() {
+    const response = await fetch(API.PAGE_ORDER);
+    return await response.json();
+}
+
+async function api_set_page_order(order) {
+    await fetch(API.PAGE_ORDER, { method: "POST", body: order });
+}
+
+async function api_layout() {
+    const response = await fetch(API.LAYOUT);
+    return await response.json();
+}
+
+async function api_set_layout(layout) {
+    await fetch(API.LAYOUT, { method: "POST", body: JSON.stringify(layout) });
+}
+
 async function api_font_size(action) {
     const response = await fetch(API.FONT_SIZE, {
         method: "POST",
@@ -51,6 +90,68 @@ async function api_content_width(action) {
     }
 }
 
+async function api_set_fit(mode) {
+    await fetch(API.FIT, { method: "POST", body: mode });
+}
+
+async function api_toc() {
+    const response = await fetch(API.TOC);
+    return await response.json();
+}
+
+async function api_search(query, regex) {
+    const params = new URLSearchParams({
+        q: query,
+        mode: regex ? "regex" : "text",
+    });
+    const response = await fetch(API.SEARCH + "?" + params);
+    if (!response.ok) {
+        throw new Error(await response.text());
+    }
+    return await response.json();
+}
+
+async function api_highlights() {
+    const response = await fetch(API.HIGHLIGHTS);
+    return await response.json();
+}
+
+async function api_add_highlight(highlight) {
+    const response = await fetch(API.HIGHLIGHTS, {
+        method: "POST",
+        body: JSON.stringify(highlight),
+    });
+    return await response.json();
+}
+
+async function api_set_note(id, note) {
+    await fetch(API.HIGHLIGHTS + "/" + id, {
+        method: "PUT",
+        body: JSON.stringify({ note: note }),
+    });
+}
+
+async function api_remove_highlight(id) {
+    await fetch(API.HIGHLIGHTS + "/" + id, { method: "DELETE" });
+}
+
+async function api_bookmarks() {
+    const response = await fetch(API.BOOKMARKS);
+    return await response.json();
+}
+
+async function api_add_bookmark(bookmark) {
+    const response = await fetch(API.BOOKMARKS, {
+        method: "POST",
+        body: JSON.stringify(bookmark),
+    });
+    return await response.json();
+}
+
+async function api_remove_bookmark(id) {
+    await fetch(API.BOOKMARKS + "/" + id, { method: "DELETE" });
+}
+
 async function quit() {
     if (confirm("Are you sure you want to stop the server?")) {
         await api_quit();
@@ -60,12 +161,17 @@ async function quit() {
 
 
 async function keybinds(key) {
+    if (comic_keybinds(key)) {
+        return;
+    }
     switch (key) {
         case "ArrowLeft":
-            location.href = "/" + (await api_page("-"));
+            location.href =
+                BASE + "/" + (await api_page(RIGHT_TO_LEFT ? "+" : "-"));
             break;
         case "ArrowRight":
-            location.href = "/" + (await api_page("+"));
+            location.href =
+                BASE + "/" + (await api_page(RIGHT_TO_LEFT ? "-" : "+"));
             break;
         case "=":
             await api_font_size("+");
@@ -87,6 +193,24 @@ async function keybinds(key) {
             await api_content_width("+");
             location.reload();
             break;
+        case "t":
+            toggle_toc();
+            break;
+        case "/":
+            toggle_search();
+            break;
+        case "h":
+            await highlight_selection();
+            break;
+        case "n":
+            toggle_notes();
+            break;
+        case "b":
+            await toggle_bookmark();
+            break;
+        case "B":
+            toggle_bookmarks();
+            break;
         case "q":
             await quit();
             break;
@@ -98,7 +222,7 @@ async function keybinds(key) {
 const frame = document.getElementById("pageframe");
 
 window.addEventListener("keydown", (event) => {
-    if (event.defaultPrevented) {
+    if (event.defaultPrevented || event.target instanceof HTMLInputElement) {
         return;
     }
     keybinds(event.key);
@@ -110,8 +234,291 @@ frame.addEventListener("keydown", (event) => {
     }
     keybinds(event.key);
 });
+// Scroll the content frame to the element named by the fragment anchor of the
+// reader url, if any.
+function scroll_to_hash() {
+    if (!location.hash) {
+        return;
+    }
+    const id = decodeURIComponent(location.hash.slice(1));
+    const element = frame.contentDocument.getElementById(id);
+    if (element) {
+        element.scrollIntoView();
+    }
+}
+
+window.addEventListener("hashchange", () => {
+    scroll_to_hash();
+    highlight_search_match();
+});
+
+// The zero-based index of the page being shown.
+function current_page() {
+    return Number(document.getElementById("pageinput").defaultValue) - 1;
+}
+
+// The element that scrolls the content frame.
+function frame_scroller() {
+    return frame.contentDocument.scrollingElement;
+}
+
+// Whether the frame holds a comic's pages stacked in one scrolling strip.
+function is_strip() {
+    return frame.contentDocument.body.classList.contains("strip");
+}
+
+// The page images of the strip, in order.
+function strip_pages() {
+    return frame.contentDocument.querySelectorAll("img.page");
+}
+
+// How far the page is scrolled, from 0 to 1. In a strip, this is how far the
+// current page is scrolled past the top of the view.
+function current_scroll() {
+    if (is_strip()) {
+        const rect = strip_pages()[current_page()].getBoundingClientRect();
+        const scroll = rect.height > 0 ? -rect.top / rect.height : 0;
+        return Math.min(Math.max(scroll, 0), 1);
+    }
+    const scroller = frame_scroller();
+    const height = scroller.scrollHeight - scroller.clientHeight;
+    return height > 0 ? scroller.scrollTop / height : 0;
+}
+
+// Scroll the page to `scroll`, as given by `current_scroll`.
+function scroll_to(scroll) {
+    const scroller = frame_scroller();
+    if (is_strip()) {
+        const page = strip_pages()[current_page()];
+        page.scrollIntoView();
+        scroller.scrollTop += scroll * page.getBoundingClientRect().height;
+        return;
+    }
+    scroller.scrollTop =
+        scroll * (scroller.scrollHeight - scroller.clientHeight);
+}
+
+function current_position() {
+    return {
+        page: current_page(),
+        scroll: current_scroll(),
+    };
+}
+
+// Scroll to the saved reading position, if it's in this page.
+async function restore_position() {
+    const position = await api_position();
+    if (position && position.page === current_page()) {
+        scroll_to(position.scroll);
+    }
+}
+
+// How comic pages are sized to the view, one of `FIT_MODES`. This is only set
+// when reading a comic.
+let fit = document.body.dataset.fit;
+const FIT_MODES = ["page", "width", "height", "original"];
+// How far comic pages are zoomed in from their fitted size. This is kept
+// while turning pages.
+let zoom = Number(sessionStorage.getItem("zoom")) || 1;
+
+// The comic pages in the frame that are sized by the fit mode and zoom.
+// Pages in a strip always fill its width.
+function fitted_pages() {
+    if (!fit || is_strip()) {
+        return [];
+    }
+    return Array.from(frame.contentDocument.querySelectorAll("img.page"));
+}
+
+// Size the comic pages in the frame to the fit mode and zoom.
+function apply_fit() {
+    const pages = fitted_pages();
+    if (pages.length === 0) {
+        return;
+    }
+    const view = frame.contentDocument.documentElement;
+    // Pages side by side are fitted together
+    const width = pages.reduce((sum, page) => sum + page.naturalWidth, 0);
+    const height = Math.max(...pages.map((page) => page.naturalHeight));
+    const scales = {
+        page: Math.min(view.clientWidth / width, view.clientHeight / height),
+        width: view.clientWidth / width,
+        height: view.clientHeight / height,
+        original: 1,
+    };
+    const scale = scales[fit] * zoom;
+    for (const page of pages) {
+        page.style.maxWidth = "none";
+        page.style.maxHeight = "none";
+        page.style.width = page.naturalWidth * scale + "px";
+        page.style.height = page.naturalHeight * scale + "px";
+    }
+}
+
+// Zoom the comic pages to `value`, keeping the middle of the view in place.
+function set_zoom(value) {
+    const scroller = frame_scroller();
+    const { scrollLeft, scrollTop, clientWidth, clientHeight } = scroller;
+    const x = (scrollLeft + clientWidth / 2) / scroller.scrollWidth;
+    const y = (scrollTop + clientHeight / 2) / scroller.scrollHeight;
+    zoom = Math.min(Math.max(value, 0.25), 8);
+    sessionStorage.setItem("zoom", zoom);
+    apply_fit();
+    scroller.scrollLeft = x * scroller.scrollWidth - clientWidth / 2;
+    scroller.scrollTop = y * scroller.scrollHeight - clientHeight / 2;
+}
+
+// Handle the keys that zoom comic pages, returning whether `key` was one.
+function comic_keybinds(key) {
+    if (fitted_pages().length === 0) {
+        return false;
+    }
+    switch (key) {
+        case "=":
+        case "+":
+            set_zoom(zoom * 1.25);
+            return true;
+        case "-":
+            set_zoom(zoom / 1.25);
+            return true;
+        case "0":
+            set_zoom(1);
+            return true;
+        default:
+            return false;
+    }
+}
+
+// Switch comic pages to the next fit mode, back at their fitted size.
+async function cycle_fit() {
+    fit = FIT_MODES[(FIT_MODES.indexOf(fit) + 1) % FIT_MODES.length];
+    await api_set_fit(fit);
+    document.getElementById("fit_button").textContent = "Fit: " + fit;
+    set_zoom(1);
+}
+
+// Zoom comic pages with the mouse wheel while holding Ctrl, and drag them
+// around to see the parts that are out of view.
+function setup_zoom() {
+    const doc = frame.contentDocument;
+    let drag = null;
+    doc.addEventListener("mousedown", (event) => {
+        if (event.button === 0) {
+            drag = { x: event.clientX, y: event.clientY };
+            event.preventDefault();
+        }
+    });
+    doc.addEventListener("mousemove", (event) => {
+        if (!drag) {
+            return;
+        }
+        const scroller = frame_scroller();
+        scroller.scrollLeft -= event.clientX - drag.x;
+        scroller.scrollTop -= event.clientY - drag.y;
+        drag = { x: event.clientX, y: event.clientY };
+    });
+    doc.addEventListener("mouseup", () => (drag = null));
+    doc.addEventListener("mouseleave", () => (drag = null));
+    doc.addEventListener(
+        "wheel",
+        (event) => {
+            if (event.ctrlKey) {
+                event.preventDefault();
+                set_zoom(event.deltaY < 0 ? zoom * 1.1 : zoom / 1.1);
+            }
+        },
+        { passive: false },
+    );
+    // Keys pressed after clicking the pages go to the frame
+    doc.addEventListener("keydown", (event) => keybinds(event.key));
+}
+
+window.addEventListener("resize", apply_fit);
+
+// Have the browser fetch the images of the next comic pages, which are
+// enough for a spread, while this one is being read.
+function prefetch_pages() {
+    const page_count = Number(document.body.dataset.pageCount);
+    for (const page of [current_page() + 1, current_page() + 2]) {
+        if (page < page_count) {
+            const link = document.createElement("link");
+            link.rel = "prefetch";
+            link.href = BASE + "/content/" + page;
+            document.head.appendChild(link);
+        }
+    }
+}
+
+// Make `page` the current page of the strip, as if it had been navigated to.
+async function set_strip_page(page) {
+    const input = document.getElementById("pageinput");
+    input.defaultValue = input.value = page + 1;
+    history.replaceState(null, "", BASE + "/" + page);
+    await api_page(String(page + 1));
+}
+
+// Load the images of the strip as they come near the view, and follow the
+// page that's being read.
+function setup_strip() {
+    const view = frame.contentWindow;
+    const loader = new view.IntersectionObserver(
+        (entries) => {
+            for (const entry of entries) {
+                if (entry.isIntersecting) {
+                    entry.target.src = entry.target.dataset.src;
+                    loader.unobserve(entry.target);
+                }
+            }
+        },
+        { rootMargin: "100% 0px" },
+    );
+    const pages = strip_pages();
+    for (const page of pages) {
+        loader.observe(page);
+    }
+
+    // The page being read is the one across the middle of the view
+    let shown = current_page();
+    view.addEventListener("scroll", () => {
+        const middle = view.innerHeight / 2;
+        const page = Array.prototype.findIndex.call(
+            pages,
+            (page) => page.getBoundingClientRect().bottom > middle,
+        );
+        if (page !== -1 && page !== shown) {
+            shown = page;
+            set_strip_page(page);
+        }
+    });
+}
+
+// Save where we are before leaving the page.
+window.addEventListener("pagehide", () => {
+    navigator.sendBeacon(API.KEEPALIVE, JSON.stringify(current_position()));
+});
+
 //TODO: move this to the server
 frame.addEventListener("load", () => {
+    if (is_strip()) {
+        setup_strip();
+    } else if (fitted_pages().length > 0) {
+        setup_zoom();
+        apply_fit();
+        prefetch_pages();
+    }
+    if (location.hash) {
+        scroll_to_hash();
+    } else if (!jump_to_bookmark()) {
+        restore_position();
+    }
+    highlight_search_match();
+    focus_highlight();
+
+    // Clicking a highlight shows its note
+    for (const mark of frame.contentDocument.querySelectorAll("mark.highlight")) {
+        mark.addEventListener("click", () => show_note(mark.dataset.highlight));
+    }
+
     // Replace every link with a corrected version of it.
     for (const link of frame.contentDocument.links) {
         if (link.href.includes("content/")) {
@@ -128,6 +535,521 @@ frame.addEventListener("load", () => {
     }, 30000);
 });
 
+function build_toc_list(entries) {
+    const list = document.createElement("ul");
+    for (const entry of entries) {
+        const item = document.createElement("li");
+        const link = document.createElement("a");
+        link.textContent = entry.label;
+        link.href = entry.url;
+        if (entry.page !== null) {
+            link.dataset.page = entry.page;
+        }
+
+        if (entry.children.length > 0) {
+            const details = document.createElement("details");
+            const summary = document.createElement("summary");
+            summary.appendChild(link);
+            details.appendChild(summary);
+            details.appendChild(build_toc_list(entry.children));
+            item.appendChild(details);
+        } else {
+            item.appendChild(link);
+        }
+        list.appendChild(item);
+    }
+    return list;
+}
+
+async function load_toc() {
+    const toc = document.getElementById("toc");
+    if (!toc) {
+        return;
+    }
+    toc.replaceChildren(build_toc_list(await api_toc()));
+
+    // The current entry is the first one for the last page at or before the
+    // current page.
+    let current = null;
+    for (const link of toc.querySelectorAll("a[data-page]")) {
+        const page = Number(link.dataset.page);
+        if (
+            page <= current_page() &&
+            (!current || page > Number(current.dataset.page))
+        ) {
+            current = link;
+        }
+    }
+    if (current) {
+        current.classList.add("current");
+        for (let e = current.parentElement; e !== toc; e = e.parentElement) {
+            if (e.tagName.toLowerCase() === "details") {
+                e.open = true;
+            }
+        }
+    }
+}
+
+function toggle_toc() {
+    const tocbar = document.getElementById("tocbar");
+    if (tocbar) {
+        tocbar.classList.toggle("hidden");
+    }
+}
+
+window.addEventListener("load", load_toc);
+
+function toggle_search() {
+    const searchbar = document.getElementById("searchbar");
+    if (!searchbar) {
+        return;
+    }
+    searchbar.classList.toggle("hidden");
+    hide_notes();
+    hide_bookmarks();
+    if (searchbar.classList.contains("hidden")) {
+        sessionStorage.removeItem("search_open");
+    } else {
+        sessionStorage.setItem("search_open", "true");
+        document.getElementById("searchinput").focus();
+    }
+}
+
+async function run_search() {
+    const query = document.getElementById("searchinput").value;
+    const regex = document.getElementById("searchregex").checked;
+    sessionStorage.setItem("search_query", query);
+    sessionStorage.setItem("search_regex", regex);
+
+    const results = document.getElementById("searchresults");
+    results.replaceChildren();
+    if (!query) {
+        return;
+    }
+
+    let matches;
+    try {
+        matches = await api_search(query, regex);
+    } catch (e) {
+        results.textContent = e.message;
+        return;
+    }
+    if (matches.length === 0) {
+        results.textContent = "No matches";
+        return;
+    }
+
+    for (const match of matches) {
+        const item = document.createElement("li");
+        const link = document.createElement("a");
+        link.href = match.url;
+        link.textContent = "p. " + (match.page + 1) + ": ";
+        link.addEventListener("click", (event) => {
+            sessionStorage.setItem("search_highlight", match.matched);
+            // Following a link to where we already are doesn't reload anything
+            if (link.href === location.href) {
+                event.preventDefault();
+                scroll_to_hash();
+                highlight_search_match();
+            }
+        });
+
+        // Mark the match inside its snippet
+        const snippet = document.createElement("span");
+        const start = match.snippet.indexOf(match.matched);
+        if (start === -1) {
+            snippet.textContent = match.snippet;
+        } else {
+            const mark = document.createElement("mark");
+            mark.textContent = match.matched;
+            snippet.append(
+                match.snippet.slice(0, start),
+                mark,
+                match.snippet.slice(start + match.matched.length),
+            );
+        }
+
+        item.append(link, snippet);
+        results.appendChild(item);
+    }
+}
+
+// Highlight the search match that was just followed, starting from the
+// fragment anchor it was found at.
+function highlight_search_match() {
+    const text = sessionStorage.getItem("search_highlight");
+    if (!text) {
+        return;
+    }
+    sessionStorage.removeItem("search_highlight");
+
+    const doc = frame.contentDocument;
+    const previous = doc.getElementById("search-highlight");
+    if (previous) {
+        previous.replaceWith(...previous.childNodes);
+    }
+
+    const anchor = location.hash
+        ? doc.getElementById(decodeURIComponent(location.hash.slice(1)))
+        : null;
+    const walker = doc.createTreeWalker(doc.body, NodeFilter.SHOW_TEXT);
+    if (anchor) {
+        walker.currentNode = anchor;
+    }
+    for (let node = walker.nextNode(); node; node = walker.nextNode()) {
+        const start = node.data.indexOf(text);
+        if (start === -1) {
+            continue;
+        }
+        const range = doc.createRange();
+        range.setStart(node, start);
+        range.setEnd(node, start + text.length);
+        const mark = doc.createElementNS("http://www.w3.org/1999/xhtml", "mark");
+        mark.id = "search-highlight";
+        range.surroundContents(mark);
+        mark.scrollIntoView({ block: "center" });
+        return;
+    }
+}
+
+// Keep the search panel open, with its results, across pages.
+window.addEventListener("load", () => {
+    if (!sessionStorage.getItem("search_open")) {
+        return;
+    }
+    const searchbar = document.getElementById("searchbar");
+    if (!searchbar) {
+        return;
+    }
+    searchbar.classList.remove("hidden");
+    document.getElementById("searchinput").value =
+        sessionStorage.getItem("search_query") ?? "";
+    document.getElementById("searchregex").checked =
+        sessionStorage.getItem("search_regex") === "true";
+    run_search();
+});
+
+// The offset, in characters, of the boundary point (`node`, `offset`) in the
+// text of the content's body. This is what the server anchors highlights by.
+function text_offset(node, offset) {
+    const doc = frame.contentDocument;
+    const range = doc.createRange();
+    range.setStart(doc.body, 0);
+    range.setEnd(node, offset);
+    return [...range.toString()].length;
+}
+
+// Save the text selected in the content as a highlight, with an optional note.
+async function highlight_selection() {
+    const selection = frame.contentWindow.getSelection();
+    if (!selection || selection.isCollapsed) {
+        return;
+    }
+    const range = selection.getRangeAt(0);
+    const text = range.toString();
+    if (!text.trim()) {
+        return;
+    }
+    const note = prompt("Note (optional):");
+    if (note === null) {
+        return;
+    }
+
+    const highlight = await api_add_highlight({
+        page: current_page(),
+        start: text_offset(range.startContainer, range.startOffset),
+        end: text_offset(range.endContainer, range.endOffset),
+        text: text,
+        note: note || null,
+    });
+    sessionStorage.setItem("focus_highlight", highlight.id);
+    await reload_content();
+    if (!document.getElementById("notesbar").classList.contains("hidden")) {
+        load_notes();
+    }
+}
+
+// Reload the content, so the server marks any changed highlights, coming back
+// to the same place.
+async function reload_content() {
+    await api_keepalive();
+    frame.contentWindow.location.reload();
+}
+
+// Scroll to the highlight that was just made or followed from the notes.
+function focus_highlight() {
+    const id = sessionStorage.getItem("focus_highlight");
+    if (!id) {
+        return;
+    }
+    sessionStorage.removeItem("focus_highlight");
+    const mark = frame.contentDocument.querySelector(
+        'mark[data-highlight="' + id + '"]',
+    );
+    if (mark) {
+        mark.scrollIntoView({ block: "center" });
+    }
+}
+
+function build_note(highlight) {
+    const item = document.createElement("li");
+    item.id = "note-" + highlight.id;
+
+    const link = document.createElement("a");
+    link.href = "#";
+    link.textContent = "p. " + (highlight.page + 1);
+    link.addEventListener("click", async (event) => {
+        event.preventDefault();
+        sessionStorage.setItem("focus_highlight", highlight.id);
+        if (highlight.page === current_page()) {
+            focus_highlight();
+        } else {
+            const page = await api_page(String(highlight.page + 1));
+            location.href = BASE + "/" + page;
+        }
+    });
+
+    const quote = document.createElement("blockquote");
+    quote.textContent = highlight.text;
+    item.append(link, quote);
+
+    if (highlight.note) {
+        const note = document.createElement("p");
+        note.textContent = highlight.note;
+        item.appendChild(note);
+    }
+
+    const edit = document.createElement("button");
+    edit.textContent = "Edit";
+    edit.addEventListener("click", async () => {
+        const note = prompt("Note:", highlight.note ?? "");
+        if (note !== null) {
+            await api_set_note(highlight.id, note || null);
+            await reload_content();
+            load_notes();
+        }
+    });
+    const remove = document.createElement("button");
+    remove.textContent = "Delete";
+    remove.addEventListener("click", async () => {
+        if (confirm("Delete this highlight?")) {
+            await api_remove_highlight(highlight.id);
+            await reload_content();
+            load_notes();
+        }
+    });
+    item.append(edit, remove);
+    return item;
+}
+
+async function load_notes() {
+    const notes = document.getElementById("notes");
+    const highlights = await api_highlights();
+    highlights.sort((a, b) => a.page - b.page || a.start - b.start);
+    notes.replaceChildren(...highlights.map(build_note));
+    if (highlights.length === 0) {
+        notes.textContent = "No highlights";
+    }
+}
+
+function hide_notes() {
+    const notesbar = document.getElementById("notesbar");
+    if (notesbar) {
+        notesbar.classList.add("hidden");
+        sessionStorage.removeItem("notes_open");
+    }
+}
+
+function toggle_notes() {
+    const notesbar = document.getElementById("notesbar");
+    if (!notesbar) {
+        return;
+    }
+    if (!notesbar.classList.contains("hidden")) {
+        hide_notes();
+        return;
+    }
+    // The notes take the place of the search panel
+    const searchbar = document.getElementById("searchbar");
+    searchbar.classList.add("hidden");
+    sessionStorage.removeItem("search_open");
+    hide_bookmarks();
+
+    notesbar.classList.remove("hidden");
+    sessionStorage.setItem("notes_open", "true");
+    return load_notes();
+}
+
+// Open the notes on the note of the highlight with `id`.
+async function show_note(id) {
+    if (document.getElementById("notesbar").classList.contains("hidden")) {
+        await toggle_notes();
+    } else {
+        await load_notes();
+    }
+    const item = document.getElementById("note-" + id);
+    if (item) {
+        item.classList.add("current");
+        item.scrollIntoView();
+    }
+}
+
+window.addEventListener("load", () => {
+    if (sessionStorage.getItem("notes_open")) {
+        toggle_notes();
+    }
+});
+
+// The id of the element nearest the top of the view, if one starts in the top
+// half of it. Bookmarks are anchored by this, as it stays put when the font
+// size changes.
+function current_anchor() {
+    const doc = frame.contentDocument;
+    const height = frame_scroller().clientHeight;
+    let anchor = null;
+    let anchor_top = height / 2;
+    for (const element of doc.querySelectorAll("body [id]")) {
+        const top = element.getBoundingClientRect().top;
+        if (top >= 0 && top < anchor_top && element.id !== "search-highlight") {
+            anchor = element.id;
+            anchor_top = top;
+        }
+    }
+    return anchor;
+}
+
+// Bookmark the current location, or remove the bookmark that's already there.
+async function toggle_bookmark() {
+    const position = current_position();
+    const anchor = current_anchor();
+    const existing = (await api_bookmarks()).find(
+        (bookmark) =>
+            bookmark.page === position.page &&
+            bookmark.anchor === anchor &&
+            (anchor !== null ||
+                Math.abs(bookmark.scroll - position.scroll) < 0.02),
+    );
+    if (existing) {
+        await api_remove_bookmark(existing.id);
+        console.log("Removed bookmark " + existing.label);
+    } else {
+        const bookmark = await api_add_bookmark({
+            page: position.page,
+            anchor: anchor,
+            scroll: position.scroll,
+        });
+        console.log("Added bookmark " + bookmark.label);
+    }
+
+    const bookmarksbar = document.getElementById("bookmarksbar");
+    if (bookmarksbar && !bookmarksbar.classList.contains("hidden")) {
+        load_bookmarks();
+    }
+}
+
+// Go to `bookmark`, which may be on another page.
+async function go_to_bookmark(bookmark) {
+    sessionStorage.setItem("jump_bookmark", JSON.stringify(bookmark));
+    if (bookmark.page === current_page()) {
+        jump_to_bookmark();
+    } else {
+        const page = await api_page(String(bookmark.page + 1));
+        location.href = BASE + "/" + page;
+    }
+}
+
+// Scroll to the bookmark that was just followed, returning whether there was
+// one.
+function jump_to_bookmark() {
+    const json = sessionStorage.getItem("jump_bookmark");
+    if (!json) {
+        return false;
+    }
+    sessionStorage.removeItem("jump_bookmark");
+
+    const bookmark = JSON.parse(json);
+    const element = bookmark.anchor
+        ? frame.contentDocument.getElementById(bookmark.anchor)
+        : null;
+    if (element) {
+        element.scrollIntoView();
+    } else {
+        scroll_to(bookmark.scroll);
+    }
+    return true;
+}
+
+function build_bookmark(bookmark) {
+    const item = document.createElement("li");
+
+    const link = document.createElement("a");
+    link.href = "#";
+    link.textContent = bookmark.label;
+    link.addEventListener("click", (event) => {
+        event.preventDefault();
+        go_to_bookmark(bookmark);
+    });
+
+    const page = document.createElement("span");
+    page.textContent = " p. " + (bookmark.page + 1) + " ";
+
+    const remove = document.createElement("button");
+    remove.textContent = "Delete";
+    remove.addEventListener("click", async () => {
+        await api_remove_bookmark(bookmark.id);
+        load_bookmarks();
+    });
+
+    item.append(link, page, remove);
+    return item;
+}
+
+async function load_bookmarks() {
+    const list = document.getElementById("bookmarks");
+    const bookmarks = await api_bookmarks();
+    bookmarks.sort((a, b) => a.page - b.page || a.scroll - b.scroll);
+    list.replaceChildren(...bookmarks.map(build_bookmark));
+    if (bookmarks.length === 0) {
+        list.textContent = "No bookmarks";
+    }
+}
+
+function hide_bookmarks() {
+    const bookmarksbar = document.getElementById("bookmarksbar");
+    if (bookmarksbar) {
+        bookmarksbar.classList.add("hidden");
+        sessionStorage.removeItem("bookmarks_open");
+    }
+}
+
+function toggle_bookmarks() {
+    const bookmarksbar = document.getElementById("bookmarksbar");
+    if (!bookmarksbar) {
+        return;
+    }
+    if (!bookmarksbar.classList.contains("hidden")) {
+        hide_bookmarks();
+        return;
+    }
+    // The bookmarks take the place of the search and notes panels
+    const searchbar = document.getElementById("searchbar");
+    if (searchbar) {
+        searchbar.classList.add("hidden");
+        sessionStorage.removeItem("search_open");
+    }
+    hide_notes();
+
+    bookmarksbar.classList.remove("hidden");
+    sessionStorage.setItem("bookmarks_open", "true");
+    return load_bookmarks();
+}
+
+window.addEventListener("load", () => {
+    if (sessionStorage.getItem("bookmarks_open")) {
+        toggle_bookmarks();
+    }
+});
+
 /*window.addEventListener("load", () => {
     // Load preferences from cookies;
 });*/
@@ -135,15 +1057,50 @@ frame.addEventListener("load", () => {
 // This is called by the reader.xml
 async function navigate_to_page() {
     const page = await api_page(document.getElementById("pageinput").value);
-    location.href = location.origin + "/" + page;
+    location.href = location.origin + BASE + "/" + page;
 }
 
 async function previous_page_button() {
-    location.href = "/" + (await api_page("-"));
+    location.href = BASE + "/" + (await api_page("-"));
 }
 
 async function next_page_button() {
-    location.href = "/" + (await api_page("+"));
+    location.href = BASE + "/" + (await api_page("+"));
 }
 const quit_button = quit;
 
+// Switch a comic between natural and plain string page order.
+async function toggle_page_order() {
+    const order = await api_page_order();
+    await api_set_page_order(order === "lexical" ? "natural" : "lexical");
+    location.reload();
+}
+
+// Switch a comic between showing one page and two pages side by side.
+async function toggle_spread() {
+    const layout = await api_layout();
+    layout.spread = !layout.spread;
+    await api_set_layout(layout);
+    location.reload();
+}
+
+// Switch a comic between paging and scrolling through one long strip.
+async function toggle_webtoon() {
+    const layout = await api_layout();
+    layout.webtoon = !layout.webtoon;
+    await api_set_layout(layout);
+    location.reload();
+}
+
+// Switch a comic between reading left to right and right to left.
+async function toggle_direction() {
+    const layout = await api_layout();
+    layout.right_to_left = !layout.right_to_left;
+    await api_set_layout(layout);
+    location.reload();
+}
+
+function shelf_button() {
+    location.href = "/";
+}
+
@@ -0,0 +1,340 @@
+//! Per-book reader state that persists across restarts.
+//!
+//! Each book gets its own JSON file in `$XDG_STATE_HOME/epub-reader`, named
+//! after a hash of the book's key (see [`Store::open`]).
+
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use std::hash::Hasher as _;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A reading position inside a book.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    /// The spine index (or comic page) being read.
+    pub page: usize,
+    /// How far the page is scrolled, from `0.0` (top) to `1.0` (bottom).
+    pub scroll: f64,
+}
+
+/// A highlighted passage in a chapter, with an optional note.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Highlight {
+    /// Identifies the highlight within its book. This is assigned when the
+    /// highlight is saved.
+    #[serde(default)]
+    pub id: u64,
+    /// The spine index of the chapter.
+    pub page: usize,
+    /// The offset, in characters, of the start of the passage in the text of
+    /// the chapter's body.
+    pub start: usize,
+    /// The offset, in characters, of the end of the passage.
+    pub end: usize,
+    /// The highlighted text. This is used to find the passage again if the
+    /// offsets no longer match it.
+    pub text: String,
+    #[serde(default)]
+    pub note: Option<String>,
+    /// When the highlight was saved, as an RFC 3339 timestamp.
+    #[serde(default)]
+    pub created: String,
+}
+
+/// A named place in a book.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    /// Identifies the bookmark within its book. This is assigned when the
+    /// bookmark is saved.
+    #[serde(default)]
+    pub id: u64,
+    /// The spine index (or comic page).
+    pub page: usize,
+    /// The id of the element of the chapter the bookmark is at, if any.
+    #[serde(default)]
+    pub anchor: Option<String>,
+    /// How far the page is scrolled, for when there's no anchor.
+    #[serde(default)]
+    pub scroll: f64,
+    #[serde(default)]
+    pub label: String,
+    /// When the bookmark was saved, as an RFC 3339 timestamp.
+    #[serde(default)]
+    pub created: String,
+}
+
+/// How a comic's pages are laid out.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(default)]
+pub struct ComicLayout {
+    /// Whether to show two pages side by side.
+    pub spread: bool,
+    /// Whether to stack all the pages in one scrolling strip, for long-strip
+    /// comics. This takes precedence over `spread`.
+    pub webtoon: bool,
+    /// Whether pages are read right to left. When this isn't set, the
+    /// comic's metadata decides.
+    pub right_to_left: Option<bool>,
+}
+
+/// Everything saved for a single book.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BookData {
+    pub position: Option<Position>,
+    pub highlights: Vec<Highlight>,
+    pub bookmarks: Vec<Bookmark>,
+    /// How a comic's pages are ordered.
+    pub page_order: crate::cba::PageOrder,
+    pub layout: ComicLayout,
+}
+
+pub struct Store {
+    /// Where `data` is saved, if there is somewhere to save it.
+    path: Option<PathBuf>,
+    pub data: BookData,
+}
+
+impl Store {
+    /// Load the saved state for the book identified by `key`.
+    ///
+    /// A missing or unreadable state file results in empty [`BookData`]. An
+    /// unparsable one is moved aside to a `.json.bad` file first, so that
+    /// saving doesn't overwrite it.
+    pub fn open(key: &str) -> Self {
+        let Some(state_home) = state_home_dir() else {
+            error!(
+                "Couldn't find state directory! Reader state won't be saved."
+            );
+            return Self {
+                path: None,
+                data: BookData::default(),
+            };
+        };
+        let path = state_home
+            .join("epub-reader")
+            .join(format!("{:016x}.json", hash(key.as_bytes())));
+        debug!(
+            "Using \"{}\" as the state file for \"{key}\"",
+            path.display()
+        );
+
+        let data = match std::fs::read(&path) {
+            Ok(contents) => match serde_json::from_slice(&contents) {
+                Ok(data) => data,
+                Err(e) => {
+                    // Moved aside rather than saved over, so what's in it
+                    // can still be recovered by hand
+                    let aside = path.with_extension("json.bad");
+                    warn!(
+                        "Ignoring invalid state file \"{}\", moving it to \"{}\": {e}",
+                        path.display(),
+                        aside.display()
+                    );
+                    if let Err(e) = std::fs::rename(&path, &aside) {
+                        error!(
+                            "Couldn't move invalid state file \"{}\": {e}",
+                            path.display()
+                        );
+                    }
+                    BookData::default()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                BookData::default()
+            }
+            Err(e) => {
+                error!("Couldn't read state file \"{}\": {e}", path.display());
+                BookData::default()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            data,
+        }
+    }
+
+    /// Save `page`, scrolled by `scroll`, as the reading position if it
+    /// changed.
+    pub fn save_position(&mut self, page: usize, scroll: f64) {
+        let position = Position { page, scroll };
+        if self.data.position != Some(position) {
+            self.data.position = Some(position);
+            self.save();
+        }
+    }
+
+    /// Save `order` as the comic's page order if it changed.
+    pub fn save_page_order(&mut self, order: crate::cba::PageOrder) {
+        if self.data.page_order != order {
+            self.data.page_order = order;
+            self.save();
+        }
+    }
+
+    /// Save `layout` as the comic's layout if it changed.
+    pub fn save_layout(&mut self, layout: ComicLayout) {
+        if self.data.layout != layout {
+            self.data.layout = layout;
+            self.save();
+        }
+    }
+
+    /// Save `highlight` under a new id, returning the saved highlight.
+    pub fn add_highlight(&mut self, mut highlight: Highlight) -> &Highlight {
+        highlight.id = self
+            .data
+            .highlights
+            .iter()
+            .map(|h| h.id + 1)
+            .max()
+            .unwrap_or(1);
+        highlight.note = highlight.note.filter(|n| !n.trim().is_empty());
+        highlight.created =
+            humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        self.data.highlights.push(highlight);
+        self.save();
+        self.data.highlights.last().expect("just pushed")
+    }
+
+    /// Replace the note of the highlight with `id`, returning whether there
+    /// is such a highlight.
+    pub fn set_highlight_note(
+        &mut self,
+        id: u64,
+        note: Option<String>,
+    ) -> bool {
+        let Some(highlight) =
+            self.data.highlights.iter_mut().find(|h| h.id == id)
+        else {
+            return false;
+        };
+        highlight.note = note.filter(|n| !n.trim().is_empty());
+        self.save();
+        true
+    }
+
+    /// Delete the highlight with `id`, returning whether there was one.
+    pub fn remove_highlight(&mut self, id: u64) -> bool {
+        let len = self.data.highlights.len();
+        self.data.highlights.retain(|h| h.id != id);
+        if self.data.highlights.len() == len {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    /// Save `bookmark` under a new id, returning the saved bookmark.
+    pub fn add_bookmark(&mut self, mut bookmark: Bookmark) -> &Bookmark {
+        bookmark.id = self
+            .data
+            .bookmarks
+            .iter()
+            .map(|b| b.id + 1)
+            .max()
+            .unwrap_or(1);
+        bookmark.created =
+            humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        self.data.bookmarks.push(bookmark);
+        self.save();
+        self.data.bookmarks.last().expect("just pushed")
+    }
+
+    /// Delete the bookmark with `id`, returning whether there was one.
+    pub fn remove_bookmark(&mut self, id: u64) -> bool {
+        let len = self.data.bookmarks.len();
+        self.data.bookmarks.retain(|b| b.id != id);
+        if self.data.bookmarks.len() == len {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    /// Write the current state to disk, logging any failure.
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let contents =
+            serde_json::to_vec_pretty(&self.data).expect("serializable state");
+        // Write next to the state file and rename it over it, so that being
+        // stopped halfway doesn't leave a truncated file behind
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{file_name}.tmp"));
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| {
+                let mut file = std::fs::File::create(&temp)?;
+                file.write_all(&contents)?;
+                file.sync_all()
+            })
+            .and_then(|()| std::fs::rename(&temp, path));
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&temp);
+            error!("Failed to save state to \"{}\": {e}", path.display());
+        }
+    }
+}
+
+/// Build a key for a book that has no identifier of its own by hashing the
+/// contents of the file at `path`, or the names and sizes of the files in
+/// it if it's a directory.
+pub fn file_key(path: &Path) -> std::io::Result<String> {
+    if path.is_dir() {
+        let mut hasher = fnv::FnvHasher::default();
+        hash_dir(path, &mut hasher)?;
+        return Ok(format!("dir:{:016x}", hasher.finish()));
+    }
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut hasher = fnv::FnvHasher::default();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[..n]);
+    }
+    Ok(format!("file:{:016x}", hasher.finish()))
+}
+
+fn hash_dir(dir: &Path, hasher: &mut fnv::FnvHasher) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(std::fs::DirEntry::file_name);
+    for entry in entries {
+        hasher.write(entry.file_name().as_encoded_bytes());
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            hash_dir(&entry.path(), hasher)?;
+        } else {
+            hasher.write_u64(metadata.len());
+        }
+    }
+    Ok(())
+}
+
+/// The FNV hash of `bytes`.
+pub fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// `$XDG_STATE_HOME`, defaulting to `$HOME/.local/state`.
+fn state_home_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".local").join("state"))
+        })
+}